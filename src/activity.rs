@@ -0,0 +1,144 @@
+const BUCKET_SECS: u64 = 10;
+const BUCKETS: u64 = 90;
+
+#[derive(Default, Clone, Copy)]
+pub struct Throughput {
+    pub count: u64,
+    pub errors: u64,
+    pub total_us: u64,
+    pub max_us: u64,
+}
+
+impl Throughput {
+    fn add(&mut self, elapsed_us: u64, failed: bool) {
+        self.count += 1;
+        self.errors += failed as u64;
+        self.total_us += elapsed_us;
+        self.max_us = self.max_us.max(elapsed_us);
+    }
+
+    fn merge(&mut self, other: &Throughput) {
+        self.count += other.count;
+        self.errors += other.errors;
+        self.total_us += other.total_us;
+        self.max_us = self.max_us.max(other.max_us);
+    }
+
+    pub fn avg_us(&self) -> u64 {
+        self.total_us.checked_div(self.count).unwrap_or(0)
+    }
+}
+
+#[derive(Default)]
+struct Bucket {
+    slot: u64,
+    commands: std::collections::HashMap<String, Throughput>,
+    tables: std::collections::HashMap<String, Throughput>,
+}
+
+pub struct ActivityReport {
+    pub window_secs: u64,
+    pub commands: Vec<(String, Throughput)>,
+    pub tables: Vec<(String, Throughput)>,
+}
+
+// The last BUCKETS * BUCKET_SECS seconds of activity. A bucket is reused once its slot comes
+// round again, so memory stays bounded without a cleanup task.
+pub struct ActivityHistory {
+    buckets: std::sync::Mutex<Vec<Bucket>>,
+}
+
+impl ActivityHistory {
+    pub fn new() -> Self {
+        ActivityHistory {
+            buckets: std::sync::Mutex::new((0..BUCKETS).map(|_| Bucket::default()).collect()),
+        }
+    }
+
+    pub fn max_window_secs() -> u64 {
+        BUCKETS * BUCKET_SECS
+    }
+
+    pub fn resolution_secs() -> u64 {
+        BUCKET_SECS
+    }
+
+    pub fn record(
+        &self,
+        command: &str,
+        table: Option<&str>,
+        elapsed: std::time::Duration,
+        failed: bool,
+    ) {
+        let slot = Self::current_slot();
+        let elapsed_us = elapsed.as_micros() as u64;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = &mut buckets[(slot % BUCKETS) as usize];
+        if bucket.slot != slot {
+            bucket.slot = slot;
+            bucket.commands.clear();
+            bucket.tables.clear();
+        }
+        bucket
+            .commands
+            .entry(command.to_string())
+            .or_default()
+            .add(elapsed_us, failed);
+        if let Some(table) = table {
+            bucket
+                .tables
+                .entry(table.to_string())
+                .or_default()
+                .add(elapsed_us, failed);
+        }
+    }
+
+    // Sums the buckets covering the last `window_secs`, busiest first.
+    pub fn top(&self, window_secs: u64, limit: usize) -> ActivityReport {
+        let slots = window_secs.div_ceil(BUCKET_SECS).clamp(1, BUCKETS);
+        let current = Self::current_slot();
+        let oldest = current.saturating_sub(slots - 1);
+
+        let mut commands: std::collections::HashMap<String, Throughput> =
+            std::collections::HashMap::new();
+        let mut tables: std::collections::HashMap<String, Throughput> =
+            std::collections::HashMap::new();
+        let buckets = self.buckets.lock().unwrap();
+        for bucket in buckets
+            .iter()
+            .filter(|bucket| bucket.slot >= oldest && bucket.slot <= current)
+        {
+            for (name, throughput) in &bucket.commands {
+                commands.entry(name.clone()).or_default().merge(throughput);
+            }
+            for (name, throughput) in &bucket.tables {
+                tables.entry(name.clone()).or_default().merge(throughput);
+            }
+        }
+
+        ActivityReport {
+            window_secs: slots * BUCKET_SECS,
+            commands: Self::busiest(commands, limit),
+            tables: Self::busiest(tables, limit),
+        }
+    }
+
+    fn busiest(
+        entries: std::collections::HashMap<String, Throughput>,
+        limit: usize,
+    ) -> Vec<(String, Throughput)> {
+        let mut entries: Vec<(String, Throughput)> = entries.into_iter().collect();
+        entries.sort_by(|a, b| b.1.count.cmp(&a.1.count).then_with(|| a.0.cmp(&b.0)));
+        entries.truncate(limit);
+        entries
+    }
+
+    fn current_slot() -> u64 {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        now / BUCKET_SECS
+    }
+}
@@ -0,0 +1,113 @@
+use std::io::Write;
+
+// syslog facility authpriv (10); severity notice for normal events, warning for failures.
+const SYSLOG_PRI_NOTICE: u8 = 10 * 8 + 5;
+const SYSLOG_PRI_WARNING: u8 = 10 * 8 + 4;
+
+#[derive(Default)]
+pub struct AuthEvents {
+    sender: Option<std::sync::mpsc::Sender<serde_json::Value>>,
+}
+
+enum Sink {
+    File(String),
+    Webhook(crate::helpers::webhook::Webhook),
+    Syslog(String),
+}
+
+impl AuthEvents {
+    pub fn new(config: &crate::helpers::configs::AuthEventsConfig) -> Result<Self, String> {
+        let mut sinks = Vec::new();
+        if let Some(path) = &config.file {
+            sinks.push(Sink::File(path.clone()));
+        }
+        if let Some(url) = &config.webhook {
+            sinks.push(Sink::Webhook(crate::helpers::webhook::Webhook::parse(url)?));
+        }
+        if let Some(address) = &config.syslog {
+            sinks.push(Sink::Syslog(address.clone()));
+        }
+        if sinks.is_empty() {
+            return Ok(AuthEvents::default());
+        }
+
+        // Delivery happens off the request path so a slow webhook never holds up a login.
+        let (sender, receiver) = std::sync::mpsc::channel::<serde_json::Value>();
+        std::thread::Builder::new()
+            .name("sharknado-auth-events".to_string())
+            .spawn(move || {
+                for event in receiver {
+                    for sink in &sinks {
+                        if let Err(e) = sink.deliver(&event) {
+                            eprintln!("auth event delivery failed: {}", e);
+                        }
+                    }
+                }
+            })
+            .map_err(|e| e.to_string())?;
+
+        Ok(AuthEvents {
+            sender: Some(sender),
+        })
+    }
+
+    pub fn emit(&self, event: &str, username: &str, source: &str, fields: serde_json::Value) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+
+        let mut record = serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            "event": event,
+            "username": username,
+            "source": source,
+        });
+        if let (Some(record), serde_json::Value::Object(fields)) = (record.as_object_mut(), fields)
+        {
+            record.extend(fields);
+        }
+        let _ = sender.send(record);
+    }
+}
+
+impl Sink {
+    fn deliver(&self, event: &serde_json::Value) -> Result<(), String> {
+        let line = event.to_string();
+        match self {
+            Sink::File(path) => {
+                let mut file = std::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|e| format!("{}: {}", path, e))?;
+                writeln!(file, "{}", line).map_err(|e| format!("{}: {}", path, e))
+            }
+            Sink::Webhook(webhook) => webhook.post(&line),
+            Sink::Syslog(address) => {
+                let failed = event["event"]
+                    .as_str()
+                    .is_some_and(|event| event.ends_with("_failure"));
+                let pri = if failed {
+                    SYSLOG_PRI_WARNING
+                } else {
+                    SYSLOG_PRI_NOTICE
+                };
+                let hostname = std::env::var("HOSTNAME").unwrap_or_else(|_| "-".to_string());
+                let message = format!(
+                    "<{}>1 {} {} sharknado {} AUTH - {}",
+                    pri,
+                    event["timestamp"].as_str().unwrap_or("-"),
+                    hostname,
+                    std::process::id(),
+                    line
+                );
+                let socket = std::net::UdpSocket::bind("0.0.0.0:0")
+                    .map_err(|e| format!("syslog {}: {}", address, e))?;
+                socket
+                    .send_to(message.as_bytes(), address.as_str())
+                    .map(|_| ())
+                    .map_err(|e| format!("syslog {}: {}", address, e))
+            }
+        }
+    }
+}
@@ -0,0 +1,463 @@
+use crate::error::Error;
+
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+struct CdcState {
+    // The last sequence number the broker acknowledged.
+    seq: u64,
+}
+
+// Reads committed entries from the WAL file. Progress is kept as a sequence number rather than a
+// byte offset, because VACUUM rewrites the file; the byte position only saves re-reading it.
+pub struct WalCursor {
+    log_path: std::path::PathBuf,
+    position: u64,
+    // The file the position points into. VACUUM renames a rewritten log into place, and a
+    // position in the old file means nothing in the new one.
+    file_id: Option<(u64, u64)>,
+    published: u64,
+}
+
+impl WalCursor {
+    pub fn new(log_path: std::path::PathBuf, published: u64) -> Self {
+        WalCursor {
+            log_path,
+            position: 0,
+            file_id: None,
+            published,
+        }
+    }
+
+    pub fn published(&self) -> u64 {
+        self.published
+    }
+
+    // Entries appended since the last call, with the position to move to once they are all
+    // published. Entries that are already published, legacy entries and checkpoints are skipped.
+    // Only the part of the file past the saved position is read.
+    pub async fn pending(&mut self) -> Result<(Vec<crate::logs::LogEntry>, u64), Error> {
+        let io_error = |e: std::io::Error| Error::Io(format!("{}: {}", self.log_path.display(), e));
+        let mut file = match tokio::fs::File::open(&self.log_path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+            Err(e) => return Err(io_error(e)),
+        };
+        let metadata = file.metadata().await.map_err(io_error)?;
+        let id = file_id(&metadata);
+        if metadata.len() < self.position || id != self.file_id {
+            self.position = 0;
+            self.file_id = id;
+        }
+
+        let mut start = self.position;
+        let bytes = read_from(&mut file, start).await.map_err(io_error)?;
+        let decoded = crate::logs::LogEntry::decode_available(&bytes);
+        // Where the file's identity cannot tell, a rewrite shows as an entry that does not
+        // decode at the saved position, or as a sequence gap there, since sequence numbers only
+        // skip ahead where VACUUM dropped entries. Either way it is read again from the start.
+        let rewritten = match &decoded {
+            Ok((entries, _)) => entries
+                .iter()
+                .map(|entry| entry.seq)
+                .find(|seq| *seq > 0)
+                .is_some_and(|seq| seq > self.published + 1),
+            Err(_) => true,
+        };
+        let (entries, consumed) = if start > 0 && rewritten {
+            start = 0;
+            let bytes = read_from(&mut file, start).await.map_err(io_error)?;
+            crate::logs::LogEntry::decode_available(&bytes).map_err(Error::Parse)?
+        } else {
+            decoded.map_err(Error::Parse)?
+        };
+
+        let mut pending = Vec::new();
+        for entry in entries {
+            if entry.seq <= self.published {
+                continue;
+            }
+            // A checkpoint stands in for entries VACUUM dropped before they were published;
+            // there is nothing left to send for them.
+            if entry.operation == "checkpoint" {
+                if pending.is_empty() {
+                    self.published = entry.seq;
+                }
+                continue;
+            }
+            pending.push(entry);
+        }
+        Ok((pending, start + consumed as u64))
+    }
+
+    pub fn advance(&mut self, seq: u64, position: Option<u64>) {
+        self.published = self.published.max(seq);
+        if let Some(position) = position {
+            self.position = position;
+        }
+    }
+}
+
+// Device and inode, which change when a new file is renamed over the log.
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+async fn read_from(file: &mut tokio::fs::File, start: u64) -> std::io::Result<Vec<u8>> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    file.seek(std::io::SeekFrom::Start(start)).await?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes).await?;
+    Ok(bytes)
+}
+
+enum Sink {
+    #[cfg(feature = "kafka")]
+    Kafka {
+        producer: rdkafka::producer::FutureProducer,
+        topic: String,
+    },
+    #[cfg(feature = "nats")]
+    Nats {
+        jetstream: async_nats::jetstream::Context,
+        subject: String,
+    },
+}
+
+impl Sink {
+    async fn connect(config: &crate::helpers::configs::CdcConfig) -> Result<Self, String> {
+        match (&config.kafka, &config.nats) {
+            (Some(kafka), None) => {
+                #[cfg(feature = "kafka")]
+                {
+                    let producer = rdkafka::config::ClientConfig::new()
+                        .set("bootstrap.servers", &kafka.brokers)
+                        .set("acks", "all")
+                        .set("enable.idempotence", "true")
+                        .create()
+                        .map_err(|e| format!("Kafka {}: {}", kafka.brokers, e))?;
+                    Ok(Sink::Kafka {
+                        producer,
+                        topic: kafka.topic.clone(),
+                    })
+                }
+                #[cfg(not(feature = "kafka"))]
+                Err(format!(
+                    "cannot publish to Kafka topic {} on {}: built without the kafka feature",
+                    kafka.topic, kafka.brokers
+                ))
+            }
+            (None, Some(nats)) => {
+                #[cfg(feature = "nats")]
+                {
+                    // A broker that is down at startup is retried like any later outage.
+                    let client = async_nats::ConnectOptions::new()
+                        .retry_on_initial_connect()
+                        .connect(&nats.url)
+                        .await
+                        .map_err(|e| format!("NATS {}: {}", nats.url, e))?;
+                    Ok(Sink::Nats {
+                        jetstream: async_nats::jetstream::new(client),
+                        subject: nats.subject.clone(),
+                    })
+                }
+                #[cfg(not(feature = "nats"))]
+                Err(format!(
+                    "cannot publish to NATS subject {} on {}: built without the nats feature",
+                    nats.subject, nats.url
+                ))
+            }
+            _ => Err("set exactly one of cdc.kafka or cdc.nats".to_string()),
+        }
+    }
+
+    // Returns once the broker has acknowledged the event. Each sink uses only some of the
+    // arguments, and a build without either feature uses none.
+    #[allow(unused_variables)]
+    async fn publish(&self, id: &str, key: &str, payload: String) -> Result<(), String> {
+        match *self {
+            #[cfg(feature = "kafka")]
+            Sink::Kafka {
+                ref producer,
+                ref topic,
+            } => {
+                let record = rdkafka::producer::FutureRecord::to(topic)
+                    .key(key)
+                    .payload(&payload);
+                producer
+                    .send(record, std::time::Duration::from_secs(30))
+                    .await
+                    .map(|_| ())
+                    .map_err(|(e, _)| format!("Kafka {}: {}", topic, e))
+            }
+            #[cfg(feature = "nats")]
+            Sink::Nats {
+                ref jetstream,
+                ref subject,
+            } => {
+                // JetStream drops a message id it has already stored, so a resend after a crash
+                // is not stored twice while it is within the stream's duplicate window.
+                let mut headers = async_nats::HeaderMap::new();
+                headers.insert("Nats-Msg-Id", id);
+                jetstream
+                    .publish_with_headers(subject.clone(), headers, payload.into())
+                    .await
+                    .map_err(|e| format!("NATS {}: {}", subject, e))?
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| format!("NATS {}: {}", subject, e))
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match *self {
+            #[cfg(feature = "kafka")]
+            Sink::Kafka { ref topic, .. } => format!("Kafka topic {}", topic),
+            #[cfg(feature = "nats")]
+            Sink::Nats { ref subject, .. } => format!("NATS subject {}", subject),
+        }
+    }
+}
+
+// Publishes every committed WAL entry, in sequence order, to Kafka or NATS. The acknowledged
+// sequence number is saved in <database>.cdc.json after each batch, so a restart resumes from
+// there; entries published but not yet saved are sent again, making delivery at-least-once.
+pub struct CdcExport {
+    database_name: String,
+    state_path: std::path::PathBuf,
+    cursor: WalCursor,
+    sink: Sink,
+}
+
+impl CdcExport {
+    pub async fn connect(
+        config: &crate::helpers::configs::CdcConfig,
+        database_name: String,
+        log_path: std::path::PathBuf,
+        state_path: std::path::PathBuf,
+    ) -> Result<Self, String> {
+        // Starting over from sequence 0 would publish the whole log again, so a state file that
+        // exists but cannot be read stops the export instead.
+        let state: CdcState = match std::fs::read_to_string(&state_path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| format!("{}: {}", state_path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => CdcState::default(),
+            Err(e) => return Err(format!("{}: {}", state_path.display(), e)),
+        };
+        let sink = Sink::connect(config).await?;
+
+        Ok(CdcExport {
+            database_name,
+            state_path,
+            cursor: WalCursor::new(log_path, state.seq),
+            sink,
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{} from sequence {}",
+            self.sink.describe(),
+            self.cursor.published() + 1
+        )
+    }
+
+    pub async fn catch_up(&mut self) -> Result<usize, Error> {
+        let saved = self.cursor.published();
+        let (entries, position) = self.cursor.pending().await?;
+
+        let mut published = 0;
+        let mut failed = None;
+        for entry in &entries {
+            let event = serde_json::json!({
+                "database": self.database_name,
+                "seq": entry.seq,
+                "op": entry.operation,
+                "table": entry.table,
+                "key": entry.key,
+                "value": entry.value,
+                "version": entry.version,
+                "meta": entry.meta,
+            });
+            let id = format!("{}:{}", self.database_name, entry.seq);
+            let key = format!("{}/{}", entry.table, entry.key);
+            if let Err(e) = self.sink.publish(&id, &key, event.to_string()).await {
+                failed = Some(Error::Io(e));
+                break;
+            }
+            self.cursor.advance(entry.seq, None);
+            published += 1;
+        }
+        // The position only moves once the whole batch is out, so a failed entry is read again.
+        if failed.is_none() {
+            self.cursor.advance(0, Some(position));
+        }
+
+        if self.cursor.published() != saved {
+            self.save()?;
+        }
+        match failed {
+            Some(e) => Err(e),
+            None => Ok(published),
+        }
+    }
+
+    // Written to a temporary file and renamed over the old one, so a crash mid-write never
+    // leaves a state file that cannot be read back.
+    fn save(&self) -> Result<(), Error> {
+        let contents = serde_json::to_string(&CdcState {
+            seq: self.cursor.published(),
+        })
+        .map_err(|e| Error::Parse(e.to_string()))?;
+        let temp_path = self.state_path.with_extension("json.tmp");
+        std::fs::write(&temp_path, contents)
+            .and_then(|()| std::fs::rename(&temp_path, &self.state_path))
+            .map_err(|e| Error::Io(format!("{}: {}", self.state_path.display(), e)))
+    }
+}
+
+pub async fn run(
+    mut export: CdcExport,
+    logger: crate::helpers::logging::Logger,
+    poll_interval: std::time::Duration,
+) {
+    let mut last_error: Option<Error> = None;
+    loop {
+        match export.catch_up().await {
+            Ok(published) => {
+                if published > 0 {
+                    logger
+                        .debug(&format!("CDC published {} log entries", published))
+                        .await;
+                }
+                if last_error.take().is_some() {
+                    logger.info("CDC export recovered").await;
+                }
+            }
+            Err(e) => {
+                if last_error.as_ref() != Some(&e) {
+                    logger.error(&format!("CDC export failed: {}", e)).await;
+                }
+                last_error = Some(e);
+            }
+        }
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logs::{LogEntry, ValueCodec};
+
+    fn append(path: &std::path::Path, entries: &[LogEntry]) {
+        use std::io::Write;
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        for entry in entries {
+            file.write_all(&entry.encode_with(ValueCodec::Json).unwrap())
+                .unwrap();
+        }
+    }
+
+    fn entry(seq: u64, key: &str) -> LogEntry {
+        let mut entry = LogEntry::checkpoint(seq);
+        entry.operation = "add".to_string();
+        entry.table = "orders".to_string();
+        entry.key = key.to_string();
+        entry.value = Some(serde_json::json!({ "n": seq }));
+        entry
+    }
+
+    async fn read(cursor: &mut WalCursor) -> Vec<u64> {
+        let (entries, position) = cursor.pending().await.unwrap();
+        let seqs: Vec<u64> = entries.iter().map(|entry| entry.seq).collect();
+        cursor.advance(seqs.last().copied().unwrap_or(0), Some(position));
+        seqs
+    }
+
+    #[tokio::test]
+    async fn resumes_after_the_published_sequence() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("db.log");
+        append(&log_path, &[entry(1, "a"), entry(2, "b"), entry(3, "c")]);
+
+        let mut cursor = WalCursor::new(log_path.clone(), 1);
+        assert_eq!(read(&mut cursor).await, [2, 3]);
+        assert_eq!(read(&mut cursor).await, Vec::<u64>::new());
+        append(&log_path, &[entry(4, "d")]);
+        assert_eq!(read(&mut cursor).await, [4]);
+
+        // A batch that is not fully published is read again from the first unpublished entry.
+        append(&log_path, &[entry(5, "e"), entry(6, "f")]);
+        cursor.pending().await.unwrap();
+        cursor.advance(5, None);
+        assert_eq!(read(&mut cursor).await, [6]);
+
+        // VACUUM rewrote the log with a checkpoint for the dropped tail, then writes went on.
+        std::fs::remove_file(&log_path).unwrap();
+        let filler: Vec<LogEntry> = (7..=40).map(|seq| entry(seq, "x")).collect();
+        append(&log_path, &[entry(2, "b"), LogEntry::checkpoint(6)]);
+        append(&log_path, &filler);
+        assert_eq!(read(&mut cursor).await, (7..=40).collect::<Vec<u64>>());
+    }
+
+    #[tokio::test]
+    async fn a_log_replaced_under_the_cursor_is_read_from_the_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("db.log");
+        // At the old position the new file holds what reads as the header of an endless frame.
+        let mut replaced = entry(2, "b");
+        replaced.value = Some(serde_json::json!("\u{1}~~~~"));
+        let mut bytes = replaced.encode_with(ValueCodec::Cbor).unwrap();
+        for seq in 3..=5 {
+            bytes.extend(entry(seq, "x").encode_with(ValueCodec::Cbor).unwrap());
+        }
+        std::fs::write(&log_path, &bytes).unwrap();
+        let position = 1 + bytes[1..].iter().position(|byte| *byte == 1).unwrap();
+        assert_eq!(&bytes[position + 1..position + 5], b"~~~~");
+
+        // Left by reading the file VACUUM renamed this one over.
+        let mut cursor = WalCursor::new(log_path.clone(), 1);
+        cursor.position = position as u64;
+        cursor.file_id = Some((0, 0));
+        assert_eq!(read(&mut cursor).await, [2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn an_unreadable_state_file_stops_the_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("db.cdc.json");
+        std::fs::write(&state_path, "{\"seq\":").unwrap();
+        let config = crate::helpers::configs::CdcConfig {
+            kafka: None,
+            nats: None,
+            poll_interval_ms: 200,
+        };
+
+        let connected = CdcExport::connect(
+            &config,
+            "db".to_string(),
+            dir.path().join("db.log"),
+            state_path.clone(),
+        )
+        .await;
+        let e = connected
+            .err()
+            .expect("connected with a corrupt state file");
+        assert!(e.starts_with(&state_path.display().to_string()), "{}", e);
+    }
+}
@@ -0,0 +1,172 @@
+pub const SLOT_COUNT: u16 = 16384;
+
+pub struct SlotRange {
+    pub first: u16,
+    pub last: u16,
+    pub address: String,
+}
+
+// Which node owns each hash slot. Every node is given the same map, and a key belongs to the node
+// owning its slot; there is no coordination between nodes beyond that shared config.
+pub struct ClusterMap {
+    node: String,
+    ranges: Vec<SlotRange>,
+}
+
+impl ClusterMap {
+    // None when no nodes are configured, which leaves sharding off.
+    pub fn from_config(
+        config: &crate::helpers::configs::ClusterConfig,
+    ) -> Result<Option<Self>, String> {
+        if config.nodes.is_empty() {
+            return Ok(None);
+        }
+        let node = config
+            .node
+            .clone()
+            .ok_or_else(|| "cluster.node must name this node's address".to_string())?;
+        if !config.nodes.iter().any(|entry| entry.address == node) {
+            return Err(format!(
+                "cluster.node {} is not listed in cluster.nodes",
+                node
+            ));
+        }
+
+        let mut ranges = Vec::new();
+        for entry in &config.nodes {
+            for [first, last] in &entry.slots {
+                if first > last || *last >= SLOT_COUNT {
+                    return Err(format!(
+                        "{}: slot range {}-{} must be ascending and below {}",
+                        entry.address, first, last, SLOT_COUNT
+                    ));
+                }
+                ranges.push(SlotRange {
+                    first: *first,
+                    last: *last,
+                    address: entry.address.clone(),
+                });
+            }
+        }
+        ranges.sort_by_key(|range| range.first);
+
+        // Every slot must have exactly one owner, or some keys could be written nowhere.
+        let mut next = 0u32;
+        for range in &ranges {
+            match (range.first as u32).cmp(&next) {
+                std::cmp::Ordering::Less => {
+                    return Err(format!("slot {} is assigned twice", range.first));
+                }
+                std::cmp::Ordering::Greater => {
+                    return Err(format!(
+                        "slots {}-{} are not assigned",
+                        next,
+                        range.first - 1
+                    ));
+                }
+                std::cmp::Ordering::Equal => next = range.last as u32 + 1,
+            }
+        }
+        if next < SLOT_COUNT as u32 {
+            return Err(format!(
+                "slots {}-{} are not assigned",
+                next,
+                SLOT_COUNT - 1
+            ));
+        }
+
+        Ok(Some(ClusterMap { node, ranges }))
+    }
+
+    pub fn slot(key: &str) -> u16 {
+        (crc32fast::hash(key.as_bytes()) % SLOT_COUNT as u32) as u16
+    }
+
+    // The slot and owner of a key this node does not hold; None when it is ours.
+    pub fn moved(&self, key: &str) -> Option<(u16, &str)> {
+        let slot = Self::slot(key);
+        let index = self.ranges.partition_point(|range| range.last < slot);
+        let owner = &self.ranges[index].address;
+        (*owner != self.node).then_some((slot, owner.as_str()))
+    }
+
+    pub fn node(&self) -> &str {
+        &self.node
+    }
+
+    pub fn ranges(&self) -> &[SlotRange] {
+        &self.ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::configs::{ClusterConfig, ClusterNodeConfig};
+
+    fn config(node: &str, nodes: &[(&str, &[[u16; 2]])]) -> ClusterConfig {
+        ClusterConfig {
+            node: Some(node.to_string()),
+            nodes: nodes
+                .iter()
+                .map(|(address, slots)| ClusterNodeConfig {
+                    address: address.to_string(),
+                    slots: slots.to_vec(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn keys_move_to_the_node_owning_their_slot() {
+        let map = ClusterMap::from_config(&config(
+            "a:1",
+            &[("a:1", &[[0, 8191]]), ("b:1", &[[8192, 16383]])],
+        ))
+        .unwrap()
+        .unwrap();
+
+        let mut moved = 0;
+        for n in 0..1000 {
+            let key = format!("user:{}", n);
+            let slot = ClusterMap::slot(&key);
+            match map.moved(&key) {
+                Some((moved_slot, owner)) => {
+                    assert_eq!((moved_slot, owner), (slot, "b:1"));
+                    assert!(slot >= 8192);
+                    moved += 1;
+                }
+                None => assert!(slot < 8192),
+            }
+        }
+        assert!(moved > 0 && moved < 1000);
+    }
+
+    #[test]
+    fn every_slot_needs_one_owner() {
+        assert!(
+            ClusterMap::from_config(&ClusterConfig::default())
+                .unwrap()
+                .is_none()
+        );
+        let error = |node: &str, nodes: &[(&str, &[[u16; 2]])]| {
+            ClusterMap::from_config(&config(node, nodes)).err().unwrap()
+        };
+        assert_eq!(
+            error("a:1", &[("a:1", &[[0, 100]])]),
+            "slots 101-16383 are not assigned"
+        );
+        assert_eq!(
+            error(
+                "a:1",
+                &[("a:1", &[[0, 100], [101, 16383]]), ("b:1", &[[50, 60]])]
+            ),
+            "slot 50 is assigned twice"
+        );
+        assert_eq!(
+            error("c:1", &[("a:1", &[[0, 16383]])]),
+            "cluster.node c:1 is not listed in cluster.nodes"
+        );
+        assert!(error("a:1", &[("a:1", &[[0, 16384]])]).contains("below 16384"));
+    }
+}
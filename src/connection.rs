@@ -1,28 +1,129 @@
+use crate::helpers::compression::Compression;
 use crate::helpers::messages::Messages;
+use crate::helpers::tokenizer;
 use std::sync::Arc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-use crate::engine::{QueryCondition, QueryOperator};
+use crate::engine::{QueryCondition, QueryExpr, QueryOperator, QueryOrder, WriteOptions};
+use crate::error::Error;
+use crate::helpers::tokenizer::ExpressionToken;
+
+type ExpressionTokens = std::iter::Peekable<std::vec::IntoIter<(ExpressionToken, usize)>>;
+
+const MAX_REPORTED_DISCREPANCIES: usize = 100;
+const DEFAULT_WAL_TAIL: usize = 20;
+const MAX_PREPARED_QUERIES: usize = 256;
+// Stands in for each `?` while a prepared query is parsed. A NUL cannot be typed into a query
+// line by accident, so no written value is mistaken for a placeholder.
+const PLACEHOLDER: &str = "\u{0}?";
+
+// A QUERY parsed once by PREPARE. Conditions whose value is PLACEHOLDER take EXECUTE's
+// parameters, in the order they were written.
+#[derive(Clone)]
+struct PreparedQuery {
+    table: String,
+    clauses: (QueryExpr, Option<QueryOrder>, Option<usize>),
+    parameters: usize,
+}
+const MAX_WAL_TAIL: usize = 1000;
+const DEFAULT_SCAN_COUNT: usize = 100;
+const MAX_SCAN_COUNT: usize = 10_000;
+const QUERY_GRAMMAR_VERSION: u32 = 1;
+const DEFAULT_TOP_MINUTES: u64 = 5;
+const TOP_ROWS: usize = 10;
+const READ_BUFFER_BYTES: usize = 8 * 1024;
+const STREAM_CHUNK_BYTES: usize = 64 * 1024;
+const STREAM_BUFFERED_CHUNKS: usize = 4;
+const TABLE_COMMANDS: [&str; 8] = [
+    "set", "get", "update", "delete", "query", "scan", "vacuum", "truncate",
+];
+const WRITE_COMMANDS: [&str; 12] = [
+    "set", "update", "delete", "persist", "import", "copy", "swap", "rename", "vacuum", "truncate",
+    "drop", "eval",
+];
+// Commands that replace or remove a table's rows wholesale, refused while another connection
+// holds a row lock in it.
+const TABLE_REPLACING_COMMANDS: [&str; 6] =
+    ["import", "copy", "swap", "rename", "truncate", "drop"];
+// How often WAIT and READ AFTER check whether this node has reached their sequence number.
+const SEQUENCE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(10);
+// Commands whose responses name tables but never carry row data.
+const NAMESPACE_ECHO_COMMANDS: [&str; 6] =
+    ["explain", "truncate", "drop", "lease", "lock", "unlock"];
+
+// A streamed QUERY's output: rows as they are read, then END or the error that stopped them.
+enum StreamChunk {
+    Rows(String),
+    End(String),
+}
+
 pub struct TCPServer {
-    pub listener: tokio::net::TcpListener,
+    pub listeners: Vec<tokio::net::TcpListener>,
     logger: crate::helpers::logging::Logger,
     engine: Arc<crate::engine::Engine>,
     user_manager: Arc<crate::user_manager::UserManager>,
+    command_timeout: Option<std::time::Duration>,
+    login_timeout: Option<std::time::Duration>,
+    max_unauthenticated_per_ip: usize,
+    max_request_bytes: usize,
+    unauthenticated: std::sync::Mutex<std::collections::HashMap<std::net::IpAddr, usize>>,
+    started_at: std::time::Instant,
+    trace_prefix: String,
+    next_trace: std::sync::atomic::AtomicU64,
+    standby: Option<Arc<tokio::sync::Mutex<crate::standby::StandbyRestore>>>,
+    standby_active: Arc<std::sync::atomic::AtomicBool>,
+    maintenance_notice: Arc<std::sync::RwLock<Option<String>>>,
+    default_maintenance_notice: String,
+    maintenance_rejected_writes: std::sync::atomic::AtomicU64,
+    health_requires_auth: bool,
+    compression_algorithms: Vec<Compression>,
+    compression_min_size: usize,
+    request_compression: crate::helpers::compression::CompressionStats,
+    response_compression: crate::helpers::compression::CompressionStats,
+    connection_compression: std::sync::Mutex<std::collections::HashMap<String, Compression>>,
+    activity: crate::activity::ActivityHistory,
+    leases: crate::leases::TableLeases,
+    row_locks: Arc<crate::row_locks::RowLocks>,
+    row_lock_ttl: std::time::Duration,
+    row_lock_wait: std::time::Duration,
+    strict_by_default: bool,
+    max_result_rows: Option<usize>,
+    scripting_enabled: bool,
+    script_max_instructions: u64,
+    script_max_memory_bytes: usize,
+    connection_strict: std::sync::Mutex<std::collections::HashMap<String, bool>>,
+    prepared_queries: std::sync::Mutex<
+        std::collections::HashMap<String, std::collections::HashMap<String, PreparedQuery>>,
+    >,
+    json_connections: std::sync::Mutex<std::collections::HashSet<String>>,
+    stream_connections: std::sync::Mutex<std::collections::HashSet<String>>,
+    trace_connections: std::sync::Mutex<std::collections::HashSet<String>>,
+    pending_streams: std::sync::Mutex<
+        std::collections::HashMap<String, tokio::sync::mpsc::Receiver<StreamChunk>>,
+    >,
+    sessions: crate::sessions::Sessions,
+    ops_limiter: crate::quotas::OpsLimiter,
+    cluster: Option<crate::cluster::ClusterMap>,
 }
 
 impl TCPServer {
     pub async fn new(
-        host: String,
-        port: u16,
+        configs: &crate::helpers::configs::Config,
         logger: crate::helpers::logging::Logger,
+        engine_logger: crate::helpers::logging::Logger,
         database_name: String,
         user_manager: Arc<crate::user_manager::UserManager>,
+        seed_path: Option<String>,
     ) -> Self {
-        let listener = tokio::net::TcpListener::bind((host.as_str(), port))
-            .await
-            .unwrap();
+        let mut listeners = Vec::new();
+        for address in configs.server.listen_addresses() {
+            let listener = tokio::net::TcpListener::bind(address.as_str())
+                .await
+                .unwrap_or_else(|e| panic!("Failed to listen on {}: {}", address, e));
+            listeners.push(listener);
+        }
 
-        let local_data_path = Self::get_local_storage_path();
+        let local_data_path = Self::get_local_storage_path(configs.storage.data_dir.as_deref());
 
         logger
             .info(&format!("Database name: {}", database_name))
@@ -31,25 +132,475 @@ impl TCPServer {
             .info(&format!("Database storage path: {}", local_data_path))
             .await;
 
-        let engine = Arc::new(crate::engine::Engine::new(database_name, local_data_path));
+        let codec = match crate::logs::ValueCodec::from_str(&configs.storage.codec) {
+            Some(codec) => codec,
+            None => {
+                logger
+                    .error(&format!(
+                        "Invalid storage codec '{}', falling back to json",
+                        configs.storage.codec
+                    ))
+                    .await;
+                crate::logs::ValueCodec::Json
+            }
+        };
+        logger.info(&format!("Storage codec: {}", codec)).await;
+
+        let wal_options = crate::logs::WalOptions {
+            codec,
+            max_batch_delay: std::time::Duration::from_micros(configs.wal.max_batch_delay_us),
+            max_batch_entries: configs.wal.max_batch_entries,
+            sync: configs.wal.sync,
+        };
+
+        let storage: Box<dyn crate::storage::StorageBackend> =
+            match configs.storage.mode.to_lowercase().as_str() {
+                "memory" => {
+                    logger
+                        .info("Storage mode: memory, data will not survive a restart")
+                        .await;
+                    Box::new(crate::storage::MemoryStorage::default())
+                }
+                mode => {
+                    if mode != "wal" {
+                        logger
+                            .error(&format!(
+                                "Invalid storage mode '{}', falling back to wal",
+                                configs.storage.mode
+                            ))
+                            .await;
+                    }
+                    Box::new(crate::logs::LogStorageSetup::new(
+                        std::path::Path::new(&local_data_path)
+                            .join(format!("{}.log", database_name)),
+                        wal_options,
+                    ))
+                }
+            };
+        let dead_letter_path = configs
+            .triggers
+            .dead_letter_path
+            .clone()
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(|| {
+                std::path::Path::new(&local_data_path)
+                    .join(format!("{}.triggers.dead.jsonl", database_name))
+            });
+        let mut engine = crate::engine::Engine::new(database_name, local_data_path, storage);
+        engine.set_trigger_policy(crate::triggers::RetryPolicy {
+            max_attempts: configs.triggers.max_attempts.max(1),
+            backoff: std::time::Duration::from_millis(configs.triggers.retry_backoff_ms),
+            dead_letter_path: Some(dead_letter_path),
+            queue_size: configs.triggers.queue_size,
+        });
+        engine.set_idempotency_window(std::time::Duration::from_secs(
+            configs.idempotency.window_secs,
+        ));
+        engine.set_logger(engine_logger.clone());
+        if configs.stats.enabled {
+            engine.enable_stats_sampling(configs.stats.sample_rate, configs.stats.max_rows);
+        }
+        let engine = Arc::new(engine);
+
+        // Serving an empty index on top of a log that could not be read would look like data
+        // loss to clients, and writes appended after it would bury the entries it holds.
+        if let Err(e) = engine.replay_log().await {
+            let message = format!("Failed to replay log: {}", e);
+            engine_logger.error(&message).await;
+            engine_logger.flush();
+            panic!("{}", message);
+        }
+
+        // Registered after replay so rebuilding tables from the log fires nothing.
+        for (table, triggers) in &configs.triggers.tables {
+            for trigger in triggers {
+                let added = crate::triggers::TriggerTarget::from_config(trigger)
+                    .and_then(|target| engine.triggers().add(table, &trigger.name, target));
+                match added {
+                    Ok(()) => {
+                        logger
+                            .info(&format!("Trigger {} on {}", trigger.name, table))
+                            .await;
+                    }
+                    Err(e) => {
+                        logger
+                            .error(&format!(
+                                "Invalid trigger {} on {}: {}",
+                                trigger.name, table, e
+                            ))
+                            .await;
+                    }
+                }
+            }
+        }
+
+        for (table, shadow_table) in &configs.shadow_tables {
+            match engine.set_shadow_table(table.clone(), shadow_table.clone()) {
+                Ok(()) => {
+                    logger
+                        .info(&format!(
+                            "Mirroring writes on {} to shadow table {}",
+                            table, shadow_table
+                        ))
+                        .await;
+                }
+                Err(e) => {
+                    logger
+                        .error(&format!("Invalid shadow table for {}: {}", table, e))
+                        .await;
+                }
+            }
+        }
+
+        for (table, rules) in &configs.redactions {
+            for (field_path, action) in rules {
+                match crate::engine::RedactionAction::from_str(action) {
+                    Some(action) => engine.set_redaction(table.clone(), field_path.clone(), action),
+                    None => {
+                        logger
+                            .error(&format!(
+                                "Invalid redaction action '{}' for {}.{}",
+                                action, table, field_path
+                            ))
+                            .await;
+                    }
+                }
+            }
+        }
+
+        for (role, filters) in &configs.row_filters {
+            for (table, condition) in filters {
+                let filter = crate::user_manager::UserRole::from_str(role)
+                    .ok_or_else(|| format!("unknown role '{}'", role))
+                    .and_then(|role| {
+                        Self::parse_query_expression(condition, configs.query.strict)
+                            .map(|filter| (role, filter))
+                            .map_err(|e| e.to_string())
+                    });
+                match filter {
+                    Ok((role, filter)) => {
+                        engine.set_role_filter(&role, table.clone(), filter);
+                    }
+                    Err(e) => {
+                        logger
+                            .error(&format!(
+                                "Invalid row filter for {} on {}: {}",
+                                role, table, e
+                            ))
+                            .await;
+                    }
+                }
+            }
+        }
+
+        for (table, indexes) in &configs.range_indexes {
+            for fields in indexes {
+                engine
+                    .create_range_index(table.clone(), fields.clone())
+                    .await;
+            }
+        }
+
+        if let Some(seed_path) = seed_path {
+            let seed_mode = match crate::engine::SeedMode::from_str(&configs.seed.mode) {
+                Some(mode) => mode,
+                None => {
+                    logger
+                        .error(&format!(
+                            "Invalid seed mode '{}', falling back to empty",
+                            configs.seed.mode
+                        ))
+                        .await;
+                    crate::engine::SeedMode::Empty
+                }
+            };
+            match engine
+                .seed_from_path(std::path::Path::new(&seed_path), seed_mode)
+                .await
+            {
+                Ok(seeded) => {
+                    for (table, count) in seeded {
+                        logger
+                            .info(&format!(
+                                "Seeded table {} with {} rows ({})",
+                                table, count, seed_mode
+                            ))
+                            .await;
+                    }
+                }
+                Err(e) => {
+                    logger
+                        .error(&format!(
+                            "Failed to load seed data from {}: {}",
+                            seed_path, e
+                        ))
+                        .await;
+                }
+            }
+        }
+
+        let standby_active = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let standby = match &configs.standby.archive_dir {
+            Some(archive_dir) => {
+                let state_path = std::path::Path::new(&engine.database_path)
+                    .join(format!("{}.standby.json", engine.database_name));
+                let mut restore = crate::standby::StandbyRestore::new(
+                    std::path::PathBuf::from(archive_dir),
+                    state_path,
+                );
+                match restore.catch_up(&engine).await {
+                    Ok(applied) => {
+                        logger
+                            .info(&format!(
+                                "Standby mode: restored {} log entries from {}",
+                                applied, archive_dir
+                            ))
+                            .await;
+                    }
+                    Err(e) => {
+                        logger
+                            .error(&format!("Standby restore failed: {}", e))
+                            .await;
+                    }
+                }
+
+                standby_active.store(true, std::sync::atomic::Ordering::SeqCst);
+                let restore = Arc::new(tokio::sync::Mutex::new(restore));
+                tokio::spawn(crate::standby::run(
+                    restore.clone(),
+                    engine.clone(),
+                    standby_active.clone(),
+                    logger.module("sharknado::standby"),
+                    std::time::Duration::from_millis(configs.standby.poll_interval_ms.max(1)),
+                ));
+                Some(restore)
+            }
+            None => None,
+        };
+
+        if configs.cdc.kafka.is_some() || configs.cdc.nats.is_some() {
+            let database_path = std::path::Path::new(&engine.database_path);
+            let export = if configs.storage.mode.eq_ignore_ascii_case("memory") {
+                Err("CDC export needs the WAL, which memory storage does not keep".to_string())
+            } else {
+                crate::cdc::CdcExport::connect(
+                    &configs.cdc,
+                    engine.database_name.clone(),
+                    database_path.join(format!("{}.log", engine.database_name)),
+                    database_path.join(format!("{}.cdc.json", engine.database_name)),
+                )
+                .await
+            };
+            match export {
+                Ok(export) => {
+                    logger
+                        .info(&format!("CDC export to {}", export.describe()))
+                        .await;
+                    tokio::spawn(crate::cdc::run(
+                        export,
+                        logger.module("sharknado::cdc"),
+                        std::time::Duration::from_millis(configs.cdc.poll_interval_ms.max(1)),
+                    ));
+                }
+                Err(e) => {
+                    logger.error(&format!("CDC export disabled: {}", e)).await;
+                }
+            }
+        }
 
-        if let Err(e) = engine.replay_log() {
-            eprintln!("Failed to replay log: {}", e);
+        let maintenance_notice = Arc::new(std::sync::RwLock::new(
+            configs
+                .maintenance
+                .enabled
+                .then(|| configs.maintenance.notice.clone()),
+        ));
+
+        let mut retention_policies = Vec::new();
+        for (table, policy) in &configs.retention.tables {
+            match crate::retention::RetentionPolicy::from_config(table, policy) {
+                Ok(policy) => {
+                    logger
+                        .info(&format!("Retention on {}: {}", table, policy))
+                        .await;
+                    if let Some((age_field, max_age)) = policy.expiry() {
+                        engine.set_expiry(table.clone(), age_field.to_string(), max_age);
+                    }
+                    retention_policies.push(policy);
+                }
+                Err(e) => {
+                    logger
+                        .error(&format!("Invalid retention policy for {}: {}", table, e))
+                        .await;
+                }
+            }
+        }
+        // Serving keys this node does not own would split them across nodes, so a bad map is fatal.
+        let cluster = crate::cluster::ClusterMap::from_config(&configs.cluster)
+            .unwrap_or_else(|e| panic!("Invalid cluster config: {}", e));
+        if let Some(cluster) = &cluster {
+            let owned: usize = cluster
+                .ranges()
+                .iter()
+                .filter(|range| range.address == cluster.node())
+                .map(|range| (range.last - range.first) as usize + 1)
+                .sum();
+            logger
+                .info(&format!(
+                    "Sharding enabled: {} owns {} of {} slots",
+                    cluster.node(),
+                    owned,
+                    crate::cluster::SLOT_COUNT
+                ))
+                .await;
+        }
+
+        if !retention_policies.is_empty() {
+            tokio::spawn(crate::retention::run(
+                retention_policies,
+                engine.clone(),
+                standby_active.clone(),
+                maintenance_notice.clone(),
+                logger.module("sharknado::retention"),
+                std::time::Duration::from_secs(configs.retention.interval_secs.max(1)),
+            ));
+        }
+
+        let mut compression_algorithms = Vec::new();
+        if configs.compression.enabled {
+            for name in &configs.compression.algorithms {
+                match Compression::from_str(name) {
+                    Some(compression) if !compression_algorithms.contains(&compression) => {
+                        compression_algorithms.push(compression)
+                    }
+                    Some(_) => {}
+                    None => {
+                        logger
+                            .error(&format!(
+                                "Ignoring unknown compression algorithm '{}'",
+                                name
+                            ))
+                            .await;
+                    }
+                }
+            }
         }
 
+        let command_timeout = match configs.server.command_timeout_ms {
+            0 => None,
+            timeout_ms => Some(std::time::Duration::from_millis(timeout_ms)),
+        };
+
         TCPServer {
-            listener,
+            listeners,
             logger,
             engine,
             user_manager,
+            command_timeout,
+            login_timeout: match configs.server.login_timeout_ms {
+                0 => None,
+                timeout_ms => Some(std::time::Duration::from_millis(timeout_ms)),
+            },
+            max_unauthenticated_per_ip: configs.server.max_unauthenticated_per_ip,
+            max_request_bytes: configs.server.max_request_bytes,
+            unauthenticated: std::sync::Mutex::new(std::collections::HashMap::new()),
+            started_at: std::time::Instant::now(),
+            trace_prefix: format!("{:08x}", chrono::Utc::now().timestamp() as u32),
+            next_trace: std::sync::atomic::AtomicU64::new(1),
+            standby,
+            standby_active,
+            maintenance_notice,
+            default_maintenance_notice: configs.maintenance.notice.clone(),
+            maintenance_rejected_writes: std::sync::atomic::AtomicU64::new(0),
+            health_requires_auth: configs.health.require_auth,
+            compression_algorithms,
+            compression_min_size: configs.compression.min_size,
+            request_compression: Default::default(),
+            response_compression: Default::default(),
+            connection_compression: std::sync::Mutex::new(std::collections::HashMap::new()),
+            activity: crate::activity::ActivityHistory::new(),
+            leases: crate::leases::TableLeases::new(),
+            row_locks: Arc::new(crate::row_locks::RowLocks::new()),
+            row_lock_ttl: std::time::Duration::from_millis(configs.row_locks.ttl_ms.max(1)),
+            row_lock_wait: std::time::Duration::from_millis(configs.row_locks.wait_ms),
+            strict_by_default: configs.query.strict,
+            max_result_rows: (configs.query.max_result_rows > 0)
+                .then_some(configs.query.max_result_rows),
+            scripting_enabled: configs.scripting.enabled,
+            script_max_instructions: configs.scripting.max_instructions,
+            script_max_memory_bytes: configs.scripting.max_memory_bytes,
+            connection_strict: std::sync::Mutex::new(std::collections::HashMap::new()),
+            prepared_queries: std::sync::Mutex::new(std::collections::HashMap::new()),
+            json_connections: std::sync::Mutex::new(std::collections::HashSet::new()),
+            stream_connections: std::sync::Mutex::new(std::collections::HashSet::new()),
+            trace_connections: std::sync::Mutex::new(std::collections::HashSet::new()),
+            pending_streams: std::sync::Mutex::new(std::collections::HashMap::new()),
+            sessions: crate::sessions::Sessions::new(),
+            ops_limiter: crate::quotas::OpsLimiter::new(),
+            cluster,
+        }
+    }
+
+    // A client may tag a command as `TRACE <id> <command>`; otherwise an id is generated. The
+    // start-time prefix keeps generated ids from repeating across restarts.
+    fn split_trace<'a>(&self, command: &'a str) -> Result<(String, &'a str), String> {
+        let (first, rest) = command
+            .split_once(char::is_whitespace)
+            .unwrap_or((command, ""));
+        // `TRACE`, `TRACE ON` and `TRACE OFF` carry no command, so they are commands themselves.
+        if !first.eq_ignore_ascii_case("trace") || !rest.trim().contains(char::is_whitespace) {
+            let sequence = self
+                .next_trace
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            return Ok((format!("{}-{:x}", self.trace_prefix, sequence), command));
+        }
+
+        let (trace_id, command) = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| Messages::ERROR_TRACE_ARGS.to_string())?;
+        let valid = trace_id.len() <= 64
+            && trace_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid || command.trim().is_empty() {
+            return Err(Messages::ERROR_TRACE_ARGS.to_string());
+        }
+        Ok((trace_id.to_string(), command.trim_start()))
+    }
+
+    // The applied sequence only moves past a write once it is durable and in memory, on a
+    // primary and on a standby restoring the primary's log alike, so once it has a read sees
+    // every write up to that point. Returns this node's applied sequence, or the response to
+    // send on timeout.
+    async fn wait_for_sequence(
+        &self,
+        sequence: u64,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<u64, String> {
+        loop {
+            let reached = self.engine.applied_sequence();
+            if reached >= sequence {
+                return Ok(reached);
+            }
+            if deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline) {
+                return Err(Messages::wait_timeout(sequence, reached));
+            }
+            tokio::time::sleep(SEQUENCE_POLL_INTERVAL).await;
         }
     }
 
-    fn get_local_storage_path() -> String {
+    fn command_deadline(&self) -> Option<std::time::Instant> {
+        self.command_timeout
+            .map(|timeout| std::time::Instant::now() + timeout)
+    }
+
+    pub fn get_local_storage_path(data_dir: Option<&str>) -> String {
         use std::env;
         use std::path::PathBuf;
 
-        let app_data_dir = if cfg!(target_os = "windows") {
+        let app_data_dir = if let Some(data_dir) = data_dir {
+            PathBuf::from(data_dir)
+        } else if cfg!(target_os = "windows") {
             env::var("APPDATA")
                 .map(|path| PathBuf::from(path).join("sharknado"))
                 .unwrap_or_else(|_| PathBuf::from("./data"))
@@ -83,47 +634,424 @@ impl TCPServer {
         app_data_dir.to_string_lossy().to_string()
     }
 
+    // A namespaced user sees their tables under the names they gave; the prefix is taken back off
+    // the tables the command named wherever an error or echo shows them, but never out of row data
+    // or values that merely contain it.
     async fn parse_command(&self, command: &str, connection_id: &str) -> String {
-        let parts: Vec<&str> = command.trim().splitn(4, ' ').collect();
+        let response = self.dispatch_command(command, connection_id).await;
+        let prefix = self
+            .user_manager
+            .get_connection_user(connection_id)
+            .and_then(|user| user.table_prefix());
+        let cmd = command.split_whitespace().next().unwrap_or_default();
+        match prefix {
+            Some(prefix)
+                if response.starts_with("ERROR")
+                    || NAMESPACE_ECHO_COMMANDS
+                        .iter()
+                        .any(|echo| echo.eq_ignore_ascii_case(cmd)) =>
+            {
+                let Ok(tokens) = Self::command_tokens(command) else {
+                    return response;
+                };
+                let cmd = tokens[0].to_lowercase();
+                let mut tables: Vec<&str> = tokens
+                    .get(Self::named_tables(&cmd, &tokens))
+                    .unwrap_or_default()
+                    .iter()
+                    .map(String::as_str)
+                    .collect();
+                if cmd == "eval"
+                    && let Some(declared) = tokens.get(1)
+                {
+                    tables.extend(declared.split(',').filter(|table| !table.is_empty()));
+                }
+                Self::unprefix_tables(&response, &prefix, &tables)
+            }
+            _ => response,
+        }
+    }
+
+    // Replaces each prefixed table name with the name the user gave, only where it stands as a
+    // whole name rather than inside a longer word or value.
+    fn unprefix_tables(response: &str, prefix: &str, tables: &[&str]) -> String {
+        let is_name_char = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        let mut response = response.to_string();
+        for table in tables {
+            let prefixed = format!("{}{}", prefix, table);
+            let mut unprefixed = String::with_capacity(response.len());
+            let mut rest = response.as_str();
+            while let Some(at) = rest.find(&prefixed) {
+                let end = at + prefixed.len();
+                unprefixed.push_str(&rest[..at]);
+                let standalone =
+                    !unprefixed.ends_with(is_name_char) && !rest[end..].starts_with(is_name_char);
+                unprefixed.push_str(if standalone { table } else { &prefixed });
+                rest = &rest[end..];
+            }
+            unprefixed.push_str(rest);
+            response = unprefixed;
+        }
+        response
+    }
+
+    fn command_tokens(command: &str) -> Result<Vec<String>, String> {
+        let mut words = command.split_whitespace().map(str::to_lowercase);
+        let max_tokens = match (words.next().as_deref(), words.next().as_deref()) {
+            (Some("delete"), Some("from")) => 5,
+            (Some("copy"), Some("table")) => 6,
+            (Some("set" | "update"), _) => 4,
+            (Some("query"), _) => 3,
+            (Some("prepare"), _) => 5,
+            (Some("execute"), _) => 3,
+            (Some("eval"), _) => 3,
+            (Some("explain"), Some("query")) => 4,
+            (Some("filter"), Some(arg)) if arg.starts_with("role=") => 4,
+            (Some("maintenance"), Some("on")) => 3,
+            (Some("read"), Some("after")) => 4,
+            _ => usize::MAX,
+        };
+        tokenizer::tokenize_n(command, max_tokens).map_err(|err| Messages::syntax_error(&err))
+    }
+
+    async fn dispatch_command(&self, command: &str, connection_id: &str) -> String {
+        let logger = self.connection_logger(connection_id);
+        let mut tokens = match Self::command_tokens(command) {
+            Ok(tokens) => tokens,
+            Err(err) => return err,
+        };
 
-        if parts.is_empty() {
+        if tokens.is_empty() {
             return Messages::ERROR_EMPTY_COMMAND.to_string();
         }
 
-        let cmd = parts[0].to_lowercase();
+        let cmd = tokens[0].to_lowercase();
 
-        match cmd.as_str() {
-            "login" => {
-                if parts.len() != 3 {
-                    return Messages::ERROR_LOGIN_ARGS.to_string();
-                }
+        let user = self.user_manager.get_connection_user(connection_id);
+        // Prefixed before anything else reads the table names, so leases, permissions and the
+        // engine only ever see the namespaced table.
+        if let Some(prefix) = user.as_ref().and_then(|user| user.table_prefix()) {
+            let tables = Self::named_tables(&cmd, &tokens);
+            for table in tokens.get_mut(tables).unwrap_or_default() {
+                table.insert_str(0, &prefix);
+            }
+        }
+        let parts: Vec<&str> = tokens.iter().map(String::as_str).collect();
+        if let Some(user) = &user
+            && let Some(max_ops_per_sec) = user.quotas.max_ops_per_sec
+            && !self.ops_limiter.admit(&user.username, max_ops_per_sec)
+        {
+            return Messages::quota_ops(max_ops_per_sec);
+        }
+        // A default admin still on its shipped password may only read until PASSWD is run; that
+        // includes minting users or tokens, which would outlive the rotation.
+        if user.as_ref().is_some_and(|user| user.must_change_password)
+            && (WRITE_COMMANDS.contains(&cmd.as_str()) || cmd == "user" || cmd == "token")
+        {
+            return Messages::ERROR_PASSWORD_CHANGE_REQUIRED.to_string();
+        }
 
-                let username = parts[1];
-                let password = parts[2];
+        if self.user_manager.is_connection_authenticated(connection_id)
+            && let Some(cluster) = &self.cluster
+            && let Some((slot, owner)) =
+                Self::addressed_key(&cmd, &parts).and_then(|key| cluster.moved(key))
+        {
+            return Messages::moved(slot, owner);
+        }
 
-                match self
-                    .user_manager
-                    .authenticate_connection(connection_id, username, password)
+        if WRITE_COMMANDS.contains(&cmd.as_str()) {
+            if self
+                .standby_active
+                .load(std::sync::atomic::Ordering::SeqCst)
+            {
+                return Messages::ERROR_STANDBY_READ_ONLY.to_string();
+            }
+            if let Some(notice) = self.maintenance_notice.read().unwrap().as_deref() {
+                self.maintenance_rejected_writes
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                return Messages::maintenance_read_only(notice);
+            }
+            if let Err(reason) = self.engine.check_wal().await {
+                return Messages::wal_read_only(&reason.to_string());
+            }
+            if self.user_manager.is_connection_authenticated(connection_id) {
+                for table in Self::written_tables(&cmd, &parts) {
+                    if let Err(err) = self.leases.check_write(table, connection_id) {
+                        return Self::lease_error(table, err);
+                    }
+                }
+                if let Some(key) = Self::addressed_key(&cmd, &parts)
+                    && let Err(err) = self.row_locks.check_write(parts[1], key, connection_id)
                 {
-                    Ok(()) => {
-                        self.logger
+                    return Self::row_lock_error(parts[1], key, err);
+                }
+                if TABLE_REPLACING_COMMANDS.contains(&cmd.as_str()) {
+                    for table in Self::written_tables(&cmd, &parts) {
+                        if let Err((key, err)) = self.row_locks.check_table(table, connection_id) {
+                            return Self::row_lock_error(table, &key, err);
+                        }
+                    }
+                }
+            }
+        }
+
+        match cmd.as_str() {
+            "login" => {
+                let (name, password) = match parts.as_slice() {
+                    [_, username, password] => (username.to_string(), Some(password.to_string())),
+                    [_, token] => (token.to_string(), None),
+                    _ => return Messages::ERROR_LOGIN_ARGS.to_string(),
+                };
+                // Password hashing is slow on purpose, so it runs off the async workers.
+                let user_manager = self.user_manager.clone();
+                let id = connection_id.to_string();
+                let result = tokio::task::spawn_blocking(move || match password {
+                    Some(password) => user_manager
+                        .authenticate_connection(&id, &name, &password)
+                        .map(|()| name),
+                    None => user_manager.authenticate_connection_token(&id, &name),
+                })
+                .await
+                .unwrap_or_else(|err| Err(err.into()));
+
+                match result {
+                    Ok(username) => {
+                        logger
                             .info(&format!(
                                 "User {} logged in from {}",
                                 username, connection_id
                             ))
                             .await;
+                        if self
+                            .user_manager
+                            .get_connection_user(connection_id)
+                            .is_some_and(|user| user.must_change_password)
+                        {
+                            logger
+                                .warning(&format!("User {} must change their password", username))
+                                .await;
+                            return Messages::LOGIN_SUCCESS_CHANGE_PASSWORD.to_string();
+                        }
                         Messages::LOGIN_SUCCESS.to_string()
                     }
+                    Err(err @ Error::Quota(_)) => {
+                        logger
+                            .info(&format!("Login from {} refused: {}", connection_id, err))
+                            .await;
+                        Messages::error(&err)
+                    }
                     Err(_) => Messages::ERROR_INVALID_CREDENTIALS.to_string(),
                 }
             }
             "logout" => {
                 self.user_manager.logout_connection(connection_id);
-                self.logger
+                logger
                     .info(&format!("User logged out from {}", connection_id))
                     .await;
                 Messages::LOGOUT_SUCCESS.to_string()
             }
+            "passwd" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+                if parts.len() != 3 {
+                    return Messages::ERROR_PASSWD_ARGS.to_string();
+                }
+
+                let user_manager = self.user_manager.clone();
+                let id = connection_id.to_string();
+                let (old_password, new_password) = (parts[1].to_string(), parts[2].to_string());
+                let changed = tokio::task::spawn_blocking(move || {
+                    user_manager.change_password(&id, &old_password, &new_password)
+                })
+                .await
+                .unwrap_or_else(|err| Err(err.into()));
+                match changed {
+                    Ok(()) => {
+                        logger.info("Password changed").await;
+                        Messages::PASSWORD_CHANGED.to_string()
+                    }
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "user" => {
+                let Some(admin) = self
+                    .user_manager
+                    .get_connection_user(connection_id)
+                    .filter(|user| user.role == crate::user_manager::UserRole::Admin)
+                else {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                };
+
+                let subcommand = parts.get(1).map(|part| part.to_lowercase());
+                let result = match (subcommand.as_deref(), parts.get(2..).unwrap_or_default()) {
+                    (Some("list"), []) => {
+                        let users = self.user_manager.list_users();
+                        if users.is_empty() {
+                            return Messages::USERS_NONE.to_string();
+                        }
+                        let mut response = Messages::user_list_header(users.len());
+                        for user in users {
+                            response.push_str(&Messages::user_list_item(
+                                &user.username,
+                                &user.role.to_string(),
+                                &user.created_at,
+                                user.namespace.as_deref(),
+                                &user.quotas.to_string(),
+                            ));
+                        }
+                        return response;
+                    }
+                    (Some("create"), [username, password, role]) => {
+                        let Some(role) = crate::user_manager::UserRole::from_str(role) else {
+                            return Messages::ERROR_INVALID_ROLE.to_string();
+                        };
+                        self.user_manager
+                            .create_user_as(
+                                &admin.username,
+                                connection_id,
+                                username.to_string(),
+                                password.to_string(),
+                                role,
+                            )
+                            .map(|()| Messages::USER_CREATED)
+                    }
+                    (Some("create"), _) => return Messages::ERROR_USER_CREATE_ARGS.to_string(),
+                    (Some("delete"), [username]) => self
+                        .user_manager
+                        .delete_user_as(&admin.username, connection_id, username)
+                        .map(|()| Messages::USER_DELETED),
+                    (Some("delete"), _) => return Messages::ERROR_USER_DELETE_ARGS.to_string(),
+                    (Some("update"), [username, field, value]) => self
+                        .user_manager
+                        .update_user_as(
+                            &admin.username,
+                            connection_id,
+                            username,
+                            &field.to_lowercase(),
+                            value,
+                        )
+                        .map(|()| Messages::USER_UPDATED),
+                    (Some("update"), _) => return Messages::ERROR_USER_UPDATE_ARGS.to_string(),
+                    _ => return Messages::ERROR_USER_ARGS.to_string(),
+                };
+
+                match result {
+                    Ok(response) => {
+                        logger
+                            .info(&format!("USER {} {}", parts[1].to_uppercase(), parts[2]))
+                            .await;
+                        response.to_string()
+                    }
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "sessions" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                let sessions = self.sessions.list();
+                let now = chrono::Utc::now();
+                let mut response = Messages::session_list_header(sessions.len());
+                for session in sessions {
+                    let username = self
+                        .user_manager
+                        .get_connection_user(&session.connection_id)
+                        .map(|user| user.username);
+                    response.push_str(&Messages::session_list_item(
+                        &session.connection_id,
+                        username.as_deref(),
+                        &session
+                            .connected_at
+                            .format("%Y-%m-%d %H:%M:%S UTC")
+                            .to_string(),
+                        (now - session.connected_at).num_seconds(),
+                        session.last_command.as_deref(),
+                    ));
+                }
+                response
+            }
+            "kill" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+                let [_, target] = parts.as_slice() else {
+                    return Messages::ERROR_KILL_ARGS.to_string();
+                };
+
+                if !self.sessions.kill(target) {
+                    return Messages::session_missing(target);
+                }
+                logger.info(&format!("Killed session {}", target)).await;
+                Messages::session_killed(target)
+            }
+            "token" => {
+                let Some(user) = self.user_manager.get_connection_user(connection_id) else {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                };
+                let is_admin = user.role == crate::user_manager::UserRole::Admin;
+                // Users manage their own tokens; admins may name another user.
+                let owner = match parts.as_slice() {
+                    [_, action, ..] if action.eq_ignore_ascii_case("create") => None,
+                    [_, action, target] if action.eq_ignore_ascii_case("list") => Some(*target),
+                    [_, action, _, target] if action.eq_ignore_ascii_case("revoke") => {
+                        Some(*target)
+                    }
+                    _ => None,
+                };
+                if owner.is_some_and(|owner| owner != user.username && !is_admin) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+                let owner = owner.unwrap_or(&user.username);
+
+                match parts.as_slice() {
+                    [_, action, name] if action.eq_ignore_ascii_case("create") => {
+                        match self.user_manager.create_token(connection_id, owner, name) {
+                            Ok(token) => {
+                                logger.info(&format!("Created token {}", name)).await;
+                                Messages::token_created(name, &token)
+                            }
+                            Err(err) => Messages::error(&err),
+                        }
+                    }
+                    [_, action] | [_, action, _] if action.eq_ignore_ascii_case("list") => {
+                        let Some(target) = self.user_manager.get_user(owner) else {
+                            return Messages::ERROR_USER_NOT_FOUND.to_string();
+                        };
+                        if target.tokens.is_empty() {
+                            return Messages::TOKENS_NONE.to_string();
+                        }
+                        let mut response = String::new();
+                        for token in target.tokens {
+                            response.push_str(&Messages::token_list_item(
+                                &token.name,
+                                &token.created_at,
+                            ));
+                        }
+                        response
+                    }
+                    [_, action, name] | [_, action, name, _]
+                        if action.eq_ignore_ascii_case("revoke") =>
+                    {
+                        match self.user_manager.revoke_token(
+                            &user.username,
+                            connection_id,
+                            owner,
+                            name,
+                        ) {
+                            Ok(()) => {
+                                logger
+                                    .info(&format!("Revoked token {} of {}", name, owner))
+                                    .await;
+                                Messages::TOKEN_REVOKED.to_string()
+                            }
+                            Err(err) => Messages::error(&err),
+                        }
+                    }
+                    _ => Messages::ERROR_TOKEN_ARGS.to_string(),
+                }
+            }
             "whoami" => {
                 if let Some(user) = self.user_manager.get_connection_user(connection_id) {
                     Messages::user_whoami_response(&user.username, &user.role.to_string())
@@ -143,18 +1071,54 @@ impl TCPServer {
                 let key = parts[2].to_string();
                 let json_value = parts[3];
 
-                match serde_json::from_str(json_value) {
-                    Ok(value) => {
-                        self.engine.add_row(table, key, value).await;
-                        self.logger
+                let (value, options, return_old) = match Self::parse_write_value(json_value) {
+                    Ok(parsed) => parsed,
+                    Err(err) => return err,
+                };
+
+                if key.eq_ignore_ascii_case("auto") {
+                    if self.cluster.is_some() {
+                        return Messages::ERROR_CLUSTER_AUTO.to_string();
+                    }
+                    if options.if_exists || options.if_version.is_some() || return_old {
+                        return Messages::ERROR_AUTO_OPTIONS.to_string();
+                    }
+                    return match self
+                        .engine
+                        .add_row_auto(table.clone(), value, options)
+                        .await
+                    {
+                        Ok(key) => {
+                            logger
+                                .debug(&format!("SET operation: {} {} {}", table, key, json_value))
+                                .await;
+                            Messages::generated_key(&key)
+                        }
+                        Err(err) => Messages::error(&err),
+                    };
+                }
+
+                let Some(user) = self.user_manager.get_connection_user(connection_id) else {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                };
+                if let Err(err) = self.engine.check_writable_as(&user.role, &table, &key) {
+                    return Messages::error(&err);
+                }
+                match self
+                    .engine
+                    .add_row(table.clone(), key, value, options)
+                    .await
+                {
+                    Ok(previous) => {
+                        logger
                             .debug(&format!(
                                 "SET operation: {} {} {}",
                                 parts[1], parts[2], json_value
                             ))
                             .await;
-                        Messages::SUCCESS_OK.to_string()
+                        self.write_response(connection_id, &table, previous, return_old)
                     }
-                    Err(_) => Messages::ERROR_INVALID_JSON.to_string(),
+                    Err(err) => Messages::error(&err),
                 }
             }
             "get" => {
@@ -162,21 +1126,45 @@ impl TCPServer {
                     return Messages::ERROR_NOT_AUTHENTICATED.to_string();
                 }
 
-                if parts.len() != 3 {
+                if parts.len() != 3 && parts.len() != 4 {
                     return Messages::ERROR_GET_ARGS.to_string();
                 }
                 let table = parts[1].to_string();
                 let key = parts[2].to_string();
+                let field_path = parts.get(3).copied();
 
-                match self.engine.get_row(table.clone(), key.clone()) {
-                    Some(value) => {
-                        self.logger
+                let role = match self.user_manager.get_connection_user(connection_id) {
+                    Some(user) => user.role,
+                    None => return Messages::ERROR_NOT_AUTHENTICATED.to_string(),
+                };
+
+                let started = std::time::Instant::now();
+                let row = self.engine.get_row_as(&role, table.clone(), key.clone());
+                self.engine.sample_read(
+                    "GET",
+                    &table,
+                    "key = ?",
+                    started.elapsed(),
+                    row.is_some() as usize,
+                );
+
+                // With a field path only that part of the document is sent back.
+                let found = row.as_ref().and_then(|row| {
+                    let value = match field_path {
+                        Some(field_path) => self.engine.get_nested_value(&row.value, field_path)?,
+                        None => &row.value,
+                    };
+                    Some((value.to_string(), row.version))
+                });
+                match found {
+                    Some((value, version)) => {
+                        logger
                             .debug(&format!("GET operation: {} {} -> found", table, key))
                             .await;
-                        format!("{}\n", value.to_string())
+                        Messages::row_with_version(&value, version)
                     }
                     None => {
-                        self.logger
+                        logger
                             .debug(&format!("GET operation: {} {} -> not found", table, key))
                             .await;
                         Messages::SUCCESS_NULL.to_string()
@@ -191,144 +1179,2739 @@ impl TCPServer {
                 if parts.len() != 4 {
                     return Messages::ERROR_UPDATE_ARGS.to_string();
                 }
+
+                if parts[2].eq_ignore_ascii_case("set")
+                    && let Some((patch, condition_str)) = Self::split_set_where(parts[3])
+                {
+                    let table = parts[1].to_string();
+                    let filter = match Self::parse_query_expression(
+                        condition_str,
+                        self.strict_syntax(connection_id),
+                    ) {
+                        Ok(filter) => filter,
+                        Err(err) => return Messages::error(&err),
+                    };
+
+                    let Some(user) = self.user_manager.get_connection_user(connection_id) else {
+                        return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                    };
+                    let writable = |key: &str| {
+                        self.row_locks
+                            .check_write(&table, key, connection_id)
+                            .map_err(|err| err.into_error(&table, key))
+                    };
+                    let updated = match self
+                        .engine
+                        .update_rows_where(
+                            &user.role,
+                            table.clone(),
+                            filter,
+                            patch,
+                            &writable,
+                            self.command_deadline(),
+                        )
+                        .await
+                    {
+                        Ok(updated) => updated,
+                        Err(err) => return Messages::error(&err),
+                    };
+                    logger
+                        .debug(&format!(
+                            "UPDATE WHERE operation: {} {} -> {} rows",
+                            table, condition_str, updated
+                        ))
+                        .await;
+                    return Messages::rows_updated(updated);
+                }
+
                 let table = parts[1].to_string();
                 let key = parts[2].to_string();
                 let json_value = parts[3];
 
-                match serde_json::from_str(json_value) {
-                    Ok(value) => {
-                        self.engine.update_row(table, key, value).await;
-                        self.logger
+                let (value, options, return_old) = match Self::parse_write_value(json_value) {
+                    Ok(parsed) => parsed,
+                    Err(err) => return err,
+                };
+                // UPDATE already requires the key to exist; NX and XX belong to SET.
+                if options.if_absent || options.if_exists {
+                    return Messages::unexpected_write_option(if options.if_absent {
+                        "NX"
+                    } else {
+                        "XX"
+                    });
+                }
+
+                let Some(user) = self.user_manager.get_connection_user(connection_id) else {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                };
+                if let Err(err) = self.engine.check_writable_as(&user.role, &table, &key) {
+                    return Messages::error(&err);
+                }
+                match self
+                    .engine
+                    .update_row(table.clone(), key, value, options)
+                    .await
+                {
+                    Ok(previous) => {
+                        logger
                             .debug(&format!(
                                 "UPDATE operation: {} {} {}",
                                 parts[1], parts[2], json_value
                             ))
                             .await;
+                        self.write_response(connection_id, &table, previous, return_old)
+                    }
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "delete" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+
+                if parts.len() > 1 && parts[1].eq_ignore_ascii_case("from") {
+                    if parts.len() != 5 || !parts[3].eq_ignore_ascii_case("where") {
+                        return Messages::ERROR_DELETE_WHERE_ARGS.to_string();
+                    }
+                    let table = parts[2].to_string();
+
+                    let filter = match Self::parse_query_expression(
+                        parts[4],
+                        self.strict_syntax(connection_id),
+                    ) {
+                        Ok(filter) => filter,
+                        Err(err) => return Messages::error(&err),
+                    };
+
+                    let Some(user) = self.user_manager.get_connection_user(connection_id) else {
+                        return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                    };
+                    let writable = |key: &str| {
+                        self.row_locks
+                            .check_write(&table, key, connection_id)
+                            .map_err(|err| err.into_error(&table, key))
+                    };
+                    let deleted = match self
+                        .engine
+                        .remove_rows_where(
+                            &user.role,
+                            table.clone(),
+                            filter,
+                            &writable,
+                            self.command_deadline(),
+                        )
+                        .await
+                    {
+                        Ok(deleted) => deleted,
+                        Err(err) => return Messages::error(&err),
+                    };
+                    logger
+                        .debug(&format!(
+                            "DELETE WHERE operation: {} {} -> {} rows",
+                            table, parts[4], deleted
+                        ))
+                        .await;
+                    return Messages::rows_deleted(deleted);
+                }
+
+                let return_old = match parts.as_slice() {
+                    [_, _, _] => false,
+                    [_, _, _, option] if option.eq_ignore_ascii_case("returnold") => true,
+                    _ => return Messages::ERROR_DELETE_ARGS.to_string(),
+                };
+                let table = parts[1].to_string();
+                let key = parts[2].to_string();
+
+                let Some(user) = self.user_manager.get_connection_user(connection_id) else {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                };
+                if let Err(err) = self.engine.check_writable_as(&user.role, &table, &key) {
+                    return Messages::error(&err);
+                }
+                let previous = match self.engine.remove_row(table.clone(), key.clone()).await {
+                    Ok(previous) => previous,
+                    Err(err) => return Messages::error(&err),
+                };
+                logger
+                    .debug(&format!("DELETE operation: {} {}", table, key))
+                    .await;
+                self.write_response(connection_id, &table, previous, return_old)
+            }
+            "persist" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+
+                let [_, table, key] = parts.as_slice() else {
+                    return Messages::ERROR_PERSIST_ARGS.to_string();
+                };
+                let Some(user) = self.user_manager.get_connection_user(connection_id) else {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                };
+                if let Err(err) = self.engine.check_writable_as(&user.role, table, key) {
+                    return Messages::error(&err);
+                }
+
+                match self
+                    .engine
+                    .persist_row(table.to_string(), key.to_string())
+                    .await
+                {
+                    Ok(()) => {
+                        logger
+                            .debug(&format!("PERSIST operation: {} {}", table, key))
+                            .await;
+                        Messages::SUCCESS_OK.to_string()
+                    }
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "query" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+
+                if parts.len() < 3 {
+                    return Messages::ERROR_QUERY_ARGS.to_string();
+                }
+                let table = parts[1].to_string();
+                let clauses =
+                    match self.parse_query_clauses(parts[2], self.strict_syntax(connection_id)) {
+                        Ok(clauses) => clauses,
+                        Err(err) => return err,
+                    };
+                self.run_query(connection_id, table, clauses).await
+            }
+            "prepare" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+
+                let [_, name, query, table, rest] = parts.as_slice() else {
+                    return Messages::ERROR_PREPARE_ARGS.to_string();
+                };
+                if !query.eq_ignore_ascii_case("query") {
+                    return Messages::ERROR_PREPARE_ARGS.to_string();
+                }
+
+                let (template, placeholders) = Self::mark_placeholders(rest);
+                let mut clauses =
+                    match self.parse_query_clauses(&template, self.strict_syntax(connection_id)) {
+                        Ok(clauses) => clauses,
+                        Err(err) => return err.replace(PLACEHOLDER, "?"),
+                    };
+                let mut parameters = 0;
+                Self::bind_parameters(&mut clauses.0, &mut |condition| {
+                    parameters += 1;
+                    Ok(condition.clone())
+                })
+                .expect("counting placeholders cannot fail");
+                if parameters != placeholders {
+                    return Messages::error(&Error::Parse(
+                        Messages::PLACEHOLDER_POSITION.to_string(),
+                    ));
+                }
+
+                let mut prepared = self.prepared_queries.lock().unwrap();
+                let queries = prepared.entry(connection_id.to_string()).or_default();
+                if queries.len() >= MAX_PREPARED_QUERIES && !queries.contains_key(*name) {
+                    return Messages::prepared_limit(MAX_PREPARED_QUERIES);
+                }
+                queries.insert(
+                    name.to_string(),
+                    PreparedQuery {
+                        table: table.to_string(),
+                        clauses,
+                        parameters,
+                    },
+                );
+                Messages::prepared(name, parameters)
+            }
+            "execute" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+
+                let (name, values) = match parts.as_slice() {
+                    [_, name] => (*name, ""),
+                    [_, name, values] => (*name, *values),
+                    _ => return Messages::ERROR_EXECUTE_ARGS.to_string(),
+                };
+                let prepared = self
+                    .prepared_queries
+                    .lock()
+                    .unwrap()
+                    .get(connection_id)
+                    .and_then(|queries| queries.get(name))
+                    .cloned();
+                let Some(PreparedQuery {
+                    table,
+                    mut clauses,
+                    parameters,
+                }) = prepared
+                else {
+                    return Messages::error(&Error::NotFound(Messages::no_prepared_query(name)));
+                };
+
+                // Each parameter is one JSON value, so it can only ever be a value: quotes and
+                // keywords inside it are never read as query syntax.
+                let values: Result<Vec<serde_json::Value>, _> =
+                    serde_json::Deserializer::from_str(values)
+                        .into_iter::<serde_json::Value>()
+                        .collect();
+                let values = match values {
+                    Ok(values) => values,
+                    Err(e) => {
+                        return Messages::error(&Error::Parse(Messages::invalid_parameter(
+                            &e.to_string(),
+                        )));
+                    }
+                };
+                if values.len() != parameters {
+                    return Messages::error(&Error::InvalidArgument(Messages::parameter_count(
+                        name,
+                        parameters,
+                        values.len(),
+                    )));
+                }
+
+                let mut values = values.into_iter();
+                if let Err(err) = Self::bind_parameters(&mut clauses.0, &mut |condition| {
+                    Self::condition_with_value(
+                        &condition.field_path,
+                        condition.operator.clone(),
+                        values.next().unwrap_or_default(),
+                    )
+                }) {
+                    return Messages::error(&err);
+                }
+                self.run_query(connection_id, table, clauses).await
+            }
+            "eval" => {
+                let Some(user) = self.user_manager.get_connection_user(connection_id) else {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                };
+                if !self.scripting_enabled {
+                    return Messages::ERROR_SCRIPTING_DISABLED.to_string();
+                }
+                if self.cluster.is_some() {
+                    return Messages::ERROR_CLUSTER_EVAL.to_string();
+                }
+                let [_, tables, source] = parts.as_slice() else {
+                    return Messages::ERROR_EVAL_ARGS.to_string();
+                };
+
+                // The script names tables as the user does; a namespace is applied here since
+                // the declared list is not a single table argument.
+                let prefix = user.table_prefix().unwrap_or_default();
+                let tables: std::collections::HashMap<String, String> = tables
+                    .split(',')
+                    .filter(|table| !table.is_empty())
+                    .map(|table| (table.to_string(), format!("{}{}", prefix, table)))
+                    .collect();
+                if tables.is_empty() {
+                    return Messages::ERROR_EVAL_ARGS.to_string();
+                }
+                for table in tables.values() {
+                    if let Err(err) = self.leases.check_write(table, connection_id) {
+                        return Self::lease_error(table, err);
+                    }
+                }
+
+                let locked: Vec<String> = tables.values().cloned().collect();
+                let script = crate::scripting::ScriptRun {
+                    role: user.role,
+                    tables,
+                    strict: self.strict_syntax(connection_id),
+                    max_instructions: self.script_max_instructions,
+                    max_memory_bytes: self.script_max_memory_bytes,
+                    deadline: self.command_deadline(),
+                    row_locks: self.row_locks.clone(),
+                    connection_id: connection_id.to_string(),
+                };
+                let engine = self.engine.clone();
+                let source = source.to_string();
+                let guards = self.engine.lock_for_script(&locked).await;
+                let (result, written) = match tokio::task::spawn_blocking(move || {
+                    crate::scripting::run(&engine, &script, &source)
+                })
+                .await
+                {
+                    Ok(ran) => ran,
+                    Err(err) => return Messages::error(&err.into()),
+                };
+                let committed = self.engine.commit_script(written).await;
+                drop(guards);
+
+                match (result, committed) {
+                    (Ok(value), Ok(())) => {
+                        logger.debug(&format!("EVAL on {}", locked.join(","))).await;
+                        Messages::script_result(&value)
+                    }
+                    (Err(err), _) | (Ok(_), Err(err)) => Messages::error(&err),
+                }
+            }
+            "explain" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+
+                if parts.len() != 4 || !parts[1].eq_ignore_ascii_case("query") {
+                    return Messages::ERROR_EXPLAIN_ARGS.to_string();
+                }
+                let table = parts[2];
+                let (filter, order, limit) =
+                    match self.parse_query_clauses(parts[3], self.strict_syntax(connection_id)) {
+                        Ok(clauses) => clauses,
+                        Err(err) => return err,
+                    };
+                let role = match self.user_manager.get_connection_user(connection_id) {
+                    Some(user) => user.role,
+                    None => return Messages::ERROR_NOT_AUTHENTICATED.to_string(),
+                };
+
+                // Only a WITHIN term over a range index narrows the rows read.
+                let mut response = Messages::explain_item("table", table);
+                let (index, rows_scanned) = match self.engine.indexed_keys(table, &filter) {
+                    Some(([first, second], keys)) => {
+                        (Messages::range_index(&first, &second), keys.len())
+                    }
+                    None => ("none (full scan)".to_string(), self.engine.table_len(table)),
+                };
+                response.push_str(&Messages::explain_item("index", &index));
+                response.push_str(&Messages::explain_item(
+                    "rows_scanned",
+                    &rows_scanned.to_string(),
+                ));
+                if let Some(role_filter) = self.engine.role_filter(&role, table) {
+                    response.push_str(&Messages::explain_item(
+                        "role_filter",
+                        &role_filter.to_string(),
+                    ));
+                }
+                if let Some(order) = &order {
+                    let direction = if order.descending { "DESC" } else { "ASC" };
+                    response.push_str(&Messages::explain_item(
+                        "order_by",
+                        &format!(
+                            "{} {} (sorted after filtering)",
+                            order.field_path, direction
+                        ),
+                    ));
+                }
+                if let Some(limit) = limit {
+                    response.push_str(&Messages::explain_item("limit", &limit.to_string()));
+                }
+                response.push_str(Messages::EXPLAIN_CONDITIONS);
+                for line in filter.tree() {
+                    response.push_str(&Messages::explain_tree_line(&line));
+                }
+                response
+            }
+            "scan" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+
+                let count = match parts.as_slice() {
+                    [_, _, _] => DEFAULT_SCAN_COUNT,
+                    [_, _, _, option, count] if option.eq_ignore_ascii_case("count") => {
+                        match count.parse::<usize>() {
+                            Ok(count) if count > 0 && count <= MAX_SCAN_COUNT => count,
+                            _ => return Messages::ERROR_SCAN_COUNT.to_string(),
+                        }
+                    }
+                    _ => return Messages::ERROR_SCAN_ARGS.to_string(),
+                };
+                let table = parts[1].to_string();
+                let after = match Self::decode_cursor(parts[2]) {
+                    Some(after) => after,
+                    None => return Messages::ERROR_SCAN_CURSOR.to_string(),
+                };
+
+                let role = match self.user_manager.get_connection_user(connection_id) {
+                    Some(user) => {
+                        if let Some(max_rows) = user.quotas.max_result_rows
+                            && count > max_rows
+                        {
+                            return Messages::quota_rows(count, max_rows);
+                        }
+                        user.role
+                    }
+                    None => return Messages::ERROR_NOT_AUTHENTICATED.to_string(),
+                };
+
+                let started = std::time::Instant::now();
+                let engine = self.engine.clone();
+                let scan_table = table.clone();
+                let deadline = self.command_deadline();
+                let (rows, next_cursor) = match tokio::task::spawn_blocking(move || {
+                    engine.scan_page_as(&role, scan_table, after.as_deref(), count, deadline)
+                })
+                .await
+                {
+                    Ok(Ok(page)) => page,
+                    Ok(Err(err)) => return Messages::error(&err),
+                    Err(err) => return Messages::error(&err.into()),
+                };
+                self.engine
+                    .sample_read("SCAN", &table, "", started.elapsed(), rows.len());
+
+                let next_cursor = next_cursor
+                    .map(|key| Self::encode_cursor(&key))
+                    .unwrap_or_else(|| "0".to_string());
+                let mut response = Messages::scan_cursor(&next_cursor, rows.len());
+                for (key, value) in rows {
+                    response.push_str(&Messages::query_result_item(&key, &value.to_string()));
+                }
+                response
+            }
+            "filter" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                if parts.len() == 2 && parts[1].eq_ignore_ascii_case("list") {
+                    let filters = self.engine.list_role_filters();
+                    if filters.is_empty() {
+                        return Messages::FILTER_NONE.to_string();
+                    }
+                    let mut response = String::new();
+                    for (role, table, condition) in filters {
+                        response.push_str(&Messages::filter_list_item(&role, &table, &condition));
+                    }
+                    return response;
+                }
+
+                if parts.len() == 5 && parts[1].eq_ignore_ascii_case("clear") {
+                    let role = match self.parse_role_selector(parts[2]) {
+                        Ok(role) => role,
+                        Err(err) => return err,
+                    };
+                    if !parts[3].eq_ignore_ascii_case("on") {
+                        return Messages::ERROR_FILTER_ARGS.to_string();
+                    }
+                    return if self.engine.clear_role_filter(&role, parts[4]) {
+                        Messages::SUCCESS_OK.to_string()
+                    } else {
+                        Messages::ERROR_NO_FILTER.to_string()
+                    };
+                }
+
+                if parts.len() != 4 || !parts[2].eq_ignore_ascii_case("on") {
+                    return Messages::ERROR_FILTER_ARGS.to_string();
+                }
+                let role = match self.parse_role_selector(parts[1]) {
+                    Ok(role) => role,
+                    Err(err) => return err,
+                };
+                let (table, condition_str) = match parts[3].split_once(':') {
+                    Some((table, condition_str)) => (table.trim().to_string(), condition_str),
+                    None => return Messages::ERROR_FILTER_ARGS.to_string(),
+                };
+
+                let filter = match Self::parse_query_expression(
+                    condition_str.trim(),
+                    self.strict_syntax(connection_id),
+                ) {
+                    Ok(filter) => filter,
+                    Err(err) => return Messages::error(&err),
+                };
+
+                logger
+                    .info(&format!(
+                        "Row filter for role {} on {}: {}",
+                        role,
+                        table,
+                        condition_str.trim()
+                    ))
+                    .await;
+                self.engine.set_role_filter(&role, table, filter);
+                Messages::SUCCESS_OK.to_string()
+            }
+            "redact" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                if parts.len() == 2 && parts[1].eq_ignore_ascii_case("list") {
+                    let redactions = self.engine.list_redactions();
+                    if redactions.is_empty() {
+                        return Messages::REDACT_NONE.to_string();
+                    }
+                    let mut response = String::new();
+                    for (table, field_path, action) in redactions {
+                        response.push_str(&Messages::redaction_list_item(
+                            &table,
+                            &field_path,
+                            &action.to_string(),
+                        ));
+                    }
+                    return response;
+                }
+
+                if parts.len() != 4 {
+                    return Messages::ERROR_REDACT_ARGS.to_string();
+                }
+                let table = parts[1].to_string();
+                let field_path = parts[2].to_string();
+
+                if parts[3].eq_ignore_ascii_case("off") {
+                    return if self.engine.clear_redaction(&table, &field_path) {
+                        Messages::SUCCESS_OK.to_string()
+                    } else {
+                        Messages::ERROR_NO_REDACTION.to_string()
+                    };
+                }
+
+                match crate::engine::RedactionAction::from_str(parts[3]) {
+                    Some(action) => {
+                        logger
+                            .info(&format!(
+                                "Redaction on {}.{}: {}",
+                                table, field_path, action
+                            ))
+                            .await;
+                        self.engine.set_redaction(table, field_path, action);
+                        Messages::SUCCESS_OK.to_string()
+                    }
+                    None => Messages::ERROR_REDACT_ARGS.to_string(),
+                }
+            }
+            "index" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                if parts.len() == 2 && parts[1].eq_ignore_ascii_case("list") {
+                    let indexes = self.engine.list_range_indexes();
+                    if indexes.is_empty() {
+                        return Messages::INDEX_NONE.to_string();
+                    }
+                    let mut response = String::new();
+                    for (table, [first, second], rows) in indexes {
+                        response
+                            .push_str(&Messages::range_index_item(&table, &first, &second, rows));
+                    }
+                    return response;
+                }
+
+                let off = parts.len() == 5 && parts[4].eq_ignore_ascii_case("off");
+                if parts.len() != 4 && !off {
+                    return Messages::ERROR_INDEX_ARGS.to_string();
+                }
+                let table = parts[1].to_string();
+                let fields = [parts[2].to_string(), parts[3].to_string()];
+
+                if off {
+                    return if self.engine.drop_range_index(&table, &fields) {
+                        Messages::SUCCESS_OK.to_string()
+                    } else {
+                        Messages::ERROR_NO_INDEX.to_string()
+                    };
+                }
+
+                logger
+                    .info(&format!(
+                        "Range index on {}: {}",
+                        table,
+                        Messages::range_index(&fields[0], &fields[1])
+                    ))
+                    .await;
+                if self.engine.create_range_index(table, fields).await {
+                    Messages::SUCCESS_OK.to_string()
+                } else {
+                    Messages::ERROR_INDEX_EXISTS.to_string()
+                }
+            }
+            "shadow" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                if parts.len() == 2 && parts[1].eq_ignore_ascii_case("list") {
+                    let shadow_tables = self.engine.list_shadow_tables();
+                    if shadow_tables.is_empty() {
+                        return Messages::SHADOW_NONE.to_string();
+                    }
+                    let mut response = String::new();
+                    for (table, shadow_table) in shadow_tables {
+                        response.push_str(&Messages::shadow_list_item(&table, &shadow_table));
+                    }
+                    return response;
+                }
+
+                if parts.len() != 3 {
+                    return Messages::ERROR_SHADOW_ARGS.to_string();
+                }
+                let table = parts[1].to_string();
+
+                if parts[2].eq_ignore_ascii_case("off") {
+                    return match self.engine.remove_shadow_table(&table) {
+                        Some(shadow_table) => {
+                            logger
+                                .info(&format!(
+                                    "Stopped mirroring {} to shadow table {}",
+                                    table, shadow_table
+                                ))
+                                .await;
+                            Messages::SUCCESS_OK.to_string()
+                        }
+                        None => Messages::ERROR_NO_SHADOW_TABLE.to_string(),
+                    };
+                }
+
+                let shadow_table = parts[2].to_string();
+                match self
+                    .engine
+                    .set_shadow_table(table.clone(), shadow_table.clone())
+                {
+                    Ok(()) => {
+                        logger
+                            .info(&format!(
+                                "Mirroring writes on {} to shadow table {}",
+                                table, shadow_table
+                            ))
+                            .await;
+                        Messages::SUCCESS_OK.to_string()
+                    }
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "trigger" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                let subcommand = parts.get(1).map(|word| word.to_lowercase());
+                match (subcommand.as_deref(), parts.len()) {
+                    (Some("list"), 2) => {
+                        let triggers = self.engine.triggers().list();
+                        if triggers.is_empty() {
+                            return Messages::TRIGGER_NONE.to_string();
+                        }
+                        let mut response = String::new();
+                        for trigger in triggers {
+                            response.push_str(&Messages::trigger_list_item(&trigger));
+                        }
+                        response
+                    }
+                    // Command triggers run programs on the server, so they can only come from
+                    // the config file; over TCP a trigger is always a webhook.
+                    (Some("add"), 5) => {
+                        let (table, name) = (parts[2], parts[3]);
+                        let target = match crate::helpers::webhook::Webhook::parse(parts[4]) {
+                            Ok(webhook) => crate::triggers::TriggerTarget::Webhook(webhook),
+                            Err(e) => return Messages::error(&Error::InvalidArgument(e)),
+                        };
+                        match self.engine.triggers().add(table, name, target) {
+                            Ok(()) => {
+                                logger
+                                    .info(&format!(
+                                        "Added trigger {} on {} -> {}",
+                                        name, table, parts[4]
+                                    ))
+                                    .await;
+                                Messages::SUCCESS_OK.to_string()
+                            }
+                            Err(e) => Messages::error(&Error::Conflict(e)),
+                        }
+                    }
+                    (Some("remove"), 4) => {
+                        let (table, name) = (parts[2], parts[3]);
+                        if self.engine.triggers().remove(table, name) {
+                            logger
+                                .info(&format!("Removed trigger {} on {}", name, table))
+                                .await;
+                            Messages::SUCCESS_OK.to_string()
+                        } else {
+                            Messages::ERROR_NO_TRIGGER.to_string()
+                        }
+                    }
+                    _ => Messages::ERROR_TRIGGER_ARGS.to_string(),
+                }
+            }
+            "import" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                let format = match parts.as_slice() {
+                    [_, _, into, _] if into.eq_ignore_ascii_case("into") => {
+                        Some(crate::import::ImportFormat::Jsonl)
+                    }
+                    [_, _, into, _, format_keyword, format]
+                        if into.eq_ignore_ascii_case("into")
+                            && format_keyword.eq_ignore_ascii_case("format") =>
+                    {
+                        crate::import::ImportFormat::from_str(format)
+                    }
+                    _ => None,
+                };
+                let Some(format) = format else {
+                    return Messages::ERROR_IMPORT_ARGS.to_string();
+                };
+                let path = parts[1];
+                let table = parts[3].to_string();
+
+                match self
+                    .engine
+                    .import_table(table.clone(), std::path::Path::new(path), format)
+                    .await
+                {
+                    Ok(count) => {
+                        logger
+                            .info(&format!(
+                                "Imported {} rows from {} into {}",
+                                count, path, table
+                            ))
+                            .await;
+                        Messages::rows_imported(count)
+                    }
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "swap" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                if parts.len() != 4 || !parts[1].eq_ignore_ascii_case("table") {
+                    return Messages::ERROR_SWAP_ARGS.to_string();
+                }
+                let table = parts[2].to_string();
+                let other = parts[3].to_string();
+
+                match self.engine.swap_tables(table.clone(), other.clone()).await {
+                    Ok(()) => {
+                        logger
+                            .info(&format!("Swapped tables {} and {}", table, other))
+                            .await;
                         Messages::SUCCESS_OK.to_string()
                     }
-                    Err(_) => Messages::ERROR_INVALID_JSON.to_string(),
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "copy" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
                 }
+
+                let filter = match parts.as_slice() {
+                    [_, keyword, _, _] if keyword.eq_ignore_ascii_case("table") => QueryExpr::all(),
+                    [_, keyword, _, _, where_keyword, conditions]
+                        if keyword.eq_ignore_ascii_case("table")
+                            && where_keyword.eq_ignore_ascii_case("where") =>
+                    {
+                        match Self::parse_query_expression(
+                            conditions,
+                            self.strict_syntax(connection_id),
+                        ) {
+                            Ok(filter) => filter,
+                            Err(err) => return Messages::error(&err),
+                        }
+                    }
+                    _ => return Messages::ERROR_COPY_ARGS.to_string(),
+                };
+                let table = parts[2].to_string();
+                let destination = parts[3].to_string();
+
+                match self
+                    .engine
+                    .copy_table(
+                        table.clone(),
+                        destination.clone(),
+                        filter,
+                        self.command_deadline(),
+                    )
+                    .await
+                {
+                    Ok(count) => {
+                        logger
+                            .info(&format!(
+                                "Copied {} rows from {} to {}",
+                                count, table, destination
+                            ))
+                            .await;
+                        Messages::rows_copied(count)
+                    }
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "rename" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                if parts.len() != 4 || !parts[1].eq_ignore_ascii_case("table") {
+                    return Messages::ERROR_RENAME_ARGS.to_string();
+                }
+                let table = parts[2].to_string();
+                let new_name = parts[3].to_string();
+
+                match self
+                    .engine
+                    .rename_table(table.clone(), new_name.clone())
+                    .await
+                {
+                    Ok(()) => {
+                        logger
+                            .info(&format!("Renamed table {} to {}", table, new_name))
+                            .await;
+                        Messages::SUCCESS_OK.to_string()
+                    }
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "truncate" => {
+                if parts.len() != 2 {
+                    return Messages::ERROR_TRUNCATE_ARGS.to_string();
+                }
+                let table = parts[1].to_string();
+                if !self.may_clear_table(connection_id, &table) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                match self.engine.truncate_table(table.clone()).await {
+                    Ok(rows) => {
+                        logger
+                            .info(&format!("Truncated {}: removed {} rows", table, rows))
+                            .await;
+                        Messages::table_truncated(&table, rows)
+                    }
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "drop" => {
+                if parts.len() != 3 || !parts[1].eq_ignore_ascii_case("table") {
+                    return Messages::ERROR_DROP_ARGS.to_string();
+                }
+                let table = parts[2].to_string();
+                if !self.may_clear_table(connection_id, &table) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                match self.engine.drop_table(table.clone()).await {
+                    Ok(rows) => {
+                        logger
+                            .info(&format!("Dropped table {} ({} rows)", table, rows))
+                            .await;
+                        Messages::table_dropped(&table, rows)
+                    }
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "vacuum" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                if parts.len() != 2 {
+                    return Messages::ERROR_VACUUM_ARGS.to_string();
+                }
+                let table = parts[1].to_string();
+
+                match self.engine.vacuum_table(table.clone()).await {
+                    Ok(stats) => {
+                        let reclaimed = stats.bytes_before - stats.bytes_after;
+                        logger
+                            .info(&format!(
+                                "Vacuumed {}: removed {} entries, reclaimed {} bytes",
+                                table, stats.entries_removed, reclaimed
+                            ))
+                            .await;
+                        Messages::vacuum_result(&table, stats.entries_removed, reclaimed)
+                    }
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "lease" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+
+                let action = parts.get(1).map(|action| action.to_lowercase());
+                match (action.as_deref(), parts.len()) {
+                    (Some("acquire"), 5) => {
+                        let (table, owner) = (parts[2], parts[3]);
+                        let ttl_secs = match parts[4].parse::<u64>() {
+                            Ok(ttl_secs) if ttl_secs > 0 => ttl_secs,
+                            _ => return Messages::ERROR_LEASE_ARGS.to_string(),
+                        };
+                        match self.leases.acquire(
+                            table,
+                            owner,
+                            std::time::Duration::from_secs(ttl_secs),
+                            connection_id,
+                        ) {
+                            Ok(()) => {
+                                logger
+                                    .debug(&format!(
+                                        "LEASE ACQUIRE: {} by {} for {}s",
+                                        table, owner, ttl_secs
+                                    ))
+                                    .await;
+                                Messages::lease_acquired(table, owner, ttl_secs)
+                            }
+                            Err(err) => Self::lease_error(table, err),
+                        }
+                    }
+                    (Some("release"), 3) => {
+                        let table = parts[2];
+                        let force = self.user_manager.is_connection_admin(connection_id);
+                        match self.leases.release(table, connection_id, force) {
+                            Ok(owner) => {
+                                logger
+                                    .info(&format!("Released lease on {} held by {}", table, owner))
+                                    .await;
+                                Messages::lease_released(table, &owner)
+                            }
+                            Err(err) => Self::lease_error(table, err),
+                        }
+                    }
+                    _ => Messages::ERROR_LEASE_ARGS.to_string(),
+                }
+            }
+            "lock" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+                let [_, table, key] = parts.as_slice() else {
+                    return Messages::ERROR_LOCK_ARGS.to_string();
+                };
+
+                let deadline = std::time::Instant::now() + self.row_lock_wait;
+                match self
+                    .row_locks
+                    .lock(table, key, connection_id, self.row_lock_ttl, deadline)
+                    .await
+                {
+                    Ok(()) => {
+                        logger.debug(&format!("LOCK: {} {}", table, key)).await;
+                        Messages::row_locked(
+                            table,
+                            key,
+                            self.row_lock_ttl.as_secs_f64().ceil() as u64,
+                        )
+                    }
+                    Err(err) => Self::row_lock_error(table, key, err),
+                }
+            }
+            "unlock" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+                match parts.as_slice() {
+                    [_] => Messages::row_locks_released(self.row_locks.unlock_all(connection_id)),
+                    [_, table, key] => match self.row_locks.unlock(table, key, connection_id) {
+                        Ok(()) => {
+                            logger.debug(&format!("UNLOCK: {} {}", table, key)).await;
+                            Messages::SUCCESS_OK.to_string()
+                        }
+                        Err(err) => Self::row_lock_error(table, key, err),
+                    },
+                    _ => Messages::ERROR_UNLOCK_ARGS.to_string(),
+                }
+            }
+            "promote" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                let Some(standby) = &self.standby else {
+                    return Messages::ERROR_NOT_STANDBY.to_string();
+                };
+                let mut restore = standby.lock().await;
+                if !self
+                    .standby_active
+                    .load(std::sync::atomic::Ordering::SeqCst)
+                {
+                    return Messages::ERROR_NOT_STANDBY.to_string();
+                }
+
+                match restore.catch_up(&self.engine).await {
+                    Ok(applied) => {
+                        self.standby_active
+                            .store(false, std::sync::atomic::Ordering::SeqCst);
+                        logger
+                            .info(&format!(
+                                "Promoted from standby after restoring {} final entries from {}",
+                                applied,
+                                restore.archive_dir().display()
+                            ))
+                            .await;
+                        Messages::promoted(applied)
+                    }
+                    Err(err) => Messages::error(&err),
+                }
+            }
+            "sequence" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+                if parts.len() != 1 {
+                    return Messages::ERROR_SEQUENCE_ARGS.to_string();
+                }
+                Messages::sequence(self.engine.applied_sequence())
+            }
+            "wait" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+                let sequence = match parts.get(1).map(|sequence| sequence.parse::<u64>()) {
+                    Some(Ok(sequence)) if parts.len() <= 3 => sequence,
+                    _ => return Messages::ERROR_WAIT_ARGS.to_string(),
+                };
+                let deadline = match parts.get(2).map(|timeout| timeout.parse::<u64>()) {
+                    Some(Ok(timeout_ms)) => Some(
+                        std::time::Instant::now() + std::time::Duration::from_millis(timeout_ms),
+                    ),
+                    Some(Err(_)) => return Messages::ERROR_WAIT_ARGS.to_string(),
+                    None => self.command_deadline(),
+                };
+                match self.wait_for_sequence(sequence, deadline).await {
+                    Ok(reached) => Messages::sequence(reached),
+                    Err(response) => response,
+                }
+            }
+            "read" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+                let sequence = match parts.get(2).map(|sequence| sequence.parse::<u64>()) {
+                    Some(Ok(sequence))
+                        if parts.len() == 4 && parts[1].eq_ignore_ascii_case("after") =>
+                    {
+                        sequence
+                    }
+                    _ => return Messages::ERROR_READ_AFTER_ARGS.to_string(),
+                };
+                let command = parts[3];
+                let inner = command
+                    .split_whitespace()
+                    .next()
+                    .unwrap_or_default()
+                    .to_lowercase();
+                if WRITE_COMMANDS.contains(&inner.as_str()) || inner == "read" {
+                    return Messages::ERROR_READ_AFTER_WRITE.to_string();
+                }
+
+                if let Err(response) = self
+                    .wait_for_sequence(sequence, self.command_deadline())
+                    .await
+                {
+                    return response;
+                }
+                Box::pin(self.parse_command(command, connection_id)).await
+            }
+            "consistency" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                if parts.len() != 2 || !parts[1].eq_ignore_ascii_case("check") {
+                    return Messages::ERROR_CONSISTENCY_ARGS.to_string();
+                }
+
+                let report = match self.engine.check_consistency().await {
+                    Ok(report) => report,
+                    Err(err) => return Messages::error(&err),
+                };
+                let problems = report.discrepancies.len() + report.checksum_failures;
+                logger
+                    .info(&format!(
+                        "Consistency check: {} tables, {} rows, {} problems",
+                        report.tables, report.rows, problems
+                    ))
+                    .await;
+
+                let mut response = Messages::consistency_header(report.tables, report.rows);
+                if report.checksum_failures > 0 {
+                    response.push_str(&Messages::consistency_checksum_failures(
+                        report.checksum_failures,
+                    ));
+                }
+                for discrepancy in report.discrepancies.iter().take(MAX_REPORTED_DISCREPANCIES) {
+                    response.push_str(&Messages::consistency_item(discrepancy));
+                }
+                if report.discrepancies.len() > MAX_REPORTED_DISCREPANCIES {
+                    response.push_str(&Messages::consistency_truncated(
+                        report.discrepancies.len() - MAX_REPORTED_DISCREPANCIES,
+                    ));
+                }
+                if problems == 0 {
+                    response.push_str(Messages::CONSISTENCY_OK);
+                } else {
+                    response.push_str(&Messages::consistency_problems(problems));
+                }
+                response
+            }
+            "wal" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                let action = parts.get(1).map(|action| action.to_lowercase());
+                let tail = match (action.as_deref(), parts.get(2)) {
+                    (Some("tail"), None) if parts.len() == 2 => Some(DEFAULT_WAL_TAIL),
+                    (Some("tail"), Some(count)) if parts.len() == 3 => {
+                        match count.parse::<usize>() {
+                            Ok(count) if count > 0 => Some(count.min(MAX_WAL_TAIL)),
+                            _ => return Messages::ERROR_WAL_ARGS.to_string(),
+                        }
+                    }
+                    (Some("stats"), None) if parts.len() == 2 => None,
+                    _ => return Messages::ERROR_WAL_ARGS.to_string(),
+                };
+
+                let (entries, bytes) = match self.engine.wal_entries().await {
+                    Ok(log) => log,
+                    Err(err) => return Messages::error(&err),
+                };
+
+                if let Some(count) = tail {
+                    let shown = &entries[entries.len().saturating_sub(count)..];
+                    let mut response = Messages::wal_tail_header(shown.len(), entries.len());
+                    for entry in shown {
+                        response.push_str(&Messages::wal_entry(&entry.describe()));
+                    }
+                    return response;
+                }
+
+                let mut operations: std::collections::BTreeMap<&str, usize> =
+                    std::collections::BTreeMap::new();
+                for entry in &entries {
+                    *operations.entry(&entry.operation).or_default() += 1;
+                }
+                let checksum_failures = entries
+                    .iter()
+                    .filter(|entry| entry.verify_checksum() == Some(false))
+                    .count();
+                let written = entries.iter().filter_map(|entry| entry.meta);
+                let oldest = written.clone().map(|meta| meta.updated_at).min();
+                let newest = written.map(|meta| meta.updated_at).max();
+                let timestamp = |at: Option<chrono::DateTime<chrono::Utc>>| {
+                    at.map(|at| at.to_rfc3339())
+                        .unwrap_or_else(|| "-".to_string())
+                };
+
+                let mut response = String::new();
+                response.push_str(&Messages::metric_item(
+                    "location",
+                    &self.engine.storage.location(),
+                ));
+                response.push_str(&Messages::metric_item("bytes", &bytes.to_string()));
+                response.push_str(&Messages::metric_item(
+                    "entries",
+                    &entries.len().to_string(),
+                ));
+                for (operation, count) in operations {
+                    response.push_str(&Messages::metric_item(
+                        &format!("entries_{}", operation),
+                        &count.to_string(),
+                    ));
+                }
+                response.push_str(&Messages::metric_item(
+                    "last_seq",
+                    &self.engine.storage.last_sequence().to_string(),
+                ));
+                response.push_str(&Messages::metric_item(
+                    "checksum_failures",
+                    &checksum_failures.to_string(),
+                ));
+                response.push_str(&Messages::metric_item("oldest_write", &timestamp(oldest)));
+                response.push_str(&Messages::metric_item("newest_write", &timestamp(newest)));
+                response
+            }
+            "cluster" => {
+                if !self.user_manager.is_connection_authenticated(connection_id) {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+
+                if parts.len() != 2 || !parts[1].eq_ignore_ascii_case("slots") {
+                    return Messages::ERROR_CLUSTER_ARGS.to_string();
+                }
+                let Some(cluster) = &self.cluster else {
+                    return Messages::ERROR_CLUSTER_DISABLED.to_string();
+                };
+                cluster
+                    .ranges()
+                    .iter()
+                    .map(|range| {
+                        Messages::cluster_slots(
+                            range.first,
+                            range.last,
+                            &range.address,
+                            range.address == cluster.node(),
+                        )
+                    })
+                    .collect()
+            }
+            "maintenance" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                let admin = self
+                    .user_manager
+                    .get_connection_user(connection_id)
+                    .map(|user| user.username)
+                    .unwrap_or_default();
+                match parts.as_slice() {
+                    [_] => match self.maintenance_notice.read().unwrap().as_deref() {
+                        Some(notice) => Messages::maintenance_status(notice),
+                        None => Messages::MAINTENANCE_OFF.to_string(),
+                    },
+                    [_, switch, notice @ ..]
+                        if switch.eq_ignore_ascii_case("on") && notice.len() <= 1 =>
+                    {
+                        let notice = match notice.first() {
+                            Some(notice) => notice.trim_matches('"').to_string(),
+                            None => self.default_maintenance_notice.clone(),
+                        };
+                        logger
+                            .warning(&format!(
+                                "Maintenance mode enabled by {}: {}",
+                                admin, notice
+                            ))
+                            .await;
+                        let response = Messages::maintenance_status(&notice);
+                        *self.maintenance_notice.write().unwrap() = Some(notice);
+                        response
+                    }
+                    [_, switch] if switch.eq_ignore_ascii_case("off") => {
+                        *self.maintenance_notice.write().unwrap() = None;
+                        logger
+                            .warning(&format!("Maintenance mode disabled by {}", admin))
+                            .await;
+                        Messages::MAINTENANCE_OFF.to_string()
+                    }
+                    _ => Messages::ERROR_MAINTENANCE_ARGS.to_string(),
+                }
+            }
+            "metrics" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                let wal = self.engine.storage.metrics();
+                let mut response = String::new();
+                response.push_str(&Messages::metric_item(
+                    "wal_batches",
+                    &wal.batches.to_string(),
+                ));
+                response.push_str(&Messages::metric_item(
+                    "wal_requests",
+                    &wal.requests.to_string(),
+                ));
+                response.push_str(&Messages::metric_item("wal_bytes", &wal.bytes.to_string()));
+                response.push_str(&Messages::metric_item(
+                    "wal_last_seq",
+                    &self.engine.storage.last_sequence().to_string(),
+                ));
+                response.push_str(&Messages::metric_item(
+                    "wal_applied_seq",
+                    &self.engine.applied_sequence().to_string(),
+                ));
+                response.push_str(&Messages::metric_item(
+                    "wal_avg_batch_size",
+                    &format!("{:.2}", wal.avg_batch_size),
+                ));
+                response.push_str(&Messages::metric_item(
+                    "wal_avg_write_us",
+                    &wal.avg_write_us.to_string(),
+                ));
+                response.push_str(&Messages::metric_item(
+                    "wal_batch_window_us",
+                    &wal.window_us.to_string(),
+                ));
+                response.push_str(&Messages::metric_item(
+                    "wal_degraded",
+                    if self.engine.wal_degraded().is_some() {
+                        "1"
+                    } else {
+                        "0"
+                    },
+                ));
+                let maintenance = self.maintenance_notice.read().unwrap().is_some();
+                response.push_str(&Messages::metric_item(
+                    "maintenance_mode",
+                    if maintenance { "1" } else { "0" },
+                ));
+                response.push_str(&Messages::metric_item(
+                    "maintenance_rejected_writes",
+                    &self
+                        .maintenance_rejected_writes
+                        .load(std::sync::atomic::Ordering::Relaxed)
+                        .to_string(),
+                ));
+                for (direction, stats) in [
+                    ("request", &self.request_compression),
+                    ("response", &self.response_compression),
+                ] {
+                    let (frames, raw_bytes, wire_bytes, ratio) = stats.snapshot();
+                    response.push_str(&Messages::metric_item(
+                        &format!("compression_{}_frames", direction),
+                        &frames.to_string(),
+                    ));
+                    response.push_str(&Messages::metric_item(
+                        &format!("compression_{}_raw_bytes", direction),
+                        &raw_bytes.to_string(),
+                    ));
+                    response.push_str(&Messages::metric_item(
+                        &format!("compression_{}_wire_bytes", direction),
+                        &wire_bytes.to_string(),
+                    ));
+                    response.push_str(&Messages::metric_item(
+                        &format!("compression_{}_ratio", direction),
+                        &format!("{:.2}", ratio),
+                    ));
+                }
+                response
+            }
+            "top" => {
+                if !self.user_manager.is_connection_admin(connection_id) {
+                    return Messages::ERROR_INSUFFICIENT_PERMISSIONS.to_string();
+                }
+
+                let (section, minutes) = match parts.as_slice() {
+                    [_] => (None, None),
+                    [_, arg] if arg.parse::<u64>().is_ok() => (None, Some(*arg)),
+                    [_, section] => (Some(section.to_lowercase()), None),
+                    [_, section, minutes] => (Some(section.to_lowercase()), Some(*minutes)),
+                    _ => return Messages::ERROR_TOP_ARGS.to_string(),
+                };
+                if section
+                    .as_deref()
+                    .is_some_and(|section| section != "commands" && section != "tables")
+                {
+                    return Messages::ERROR_TOP_ARGS.to_string();
+                }
+                let max_minutes = crate::activity::ActivityHistory::max_window_secs() / 60;
+                let minutes = match minutes.map(str::parse::<u64>) {
+                    None => DEFAULT_TOP_MINUTES,
+                    Some(Ok(minutes)) if (1..=max_minutes).contains(&minutes) => minutes,
+                    Some(_) => return Messages::ERROR_TOP_ARGS.to_string(),
+                };
+
+                let report = self.activity.top(minutes * 60, TOP_ROWS);
+                let mut response = Messages::top_window(
+                    report.window_secs,
+                    crate::activity::ActivityHistory::resolution_secs(),
+                );
+                for (name, rows) in [("commands", &report.commands), ("tables", &report.tables)] {
+                    if section.as_deref().is_some_and(|section| section != name) {
+                        continue;
+                    }
+                    response.push_str(&Messages::top_section(name));
+                    for (key, throughput) in rows {
+                        response.push_str(&Messages::top_row(
+                            key,
+                            throughput.count,
+                            throughput.count as f64 / report.window_secs as f64,
+                            throughput.avg_us() as f64 / 1000.0,
+                            throughput.max_us as f64 / 1000.0,
+                            throughput.errors,
+                        ));
+                    }
+                }
+                response
+            }
+            "ping" => {
+                if self.health_requires_auth
+                    && !self.user_manager.is_connection_authenticated(connection_id)
+                {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+                Messages::PONG.to_string()
+            }
+            "health" => {
+                if self.health_requires_auth
+                    && !self.user_manager.is_connection_authenticated(connection_id)
+                {
+                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+                }
+
+                let mut response = String::new();
+                let wal_degraded = self.engine.wal_degraded();
+                let status = if wal_degraded.is_some() {
+                    "degraded"
+                } else {
+                    "ok"
+                };
+                response.push_str(&Messages::metric_item("status", status));
+                response.push_str(&Messages::metric_item(
+                    "uptime_secs",
+                    &self.started_at.elapsed().as_secs().to_string(),
+                ));
+                response.push_str(&Messages::metric_item(
+                    "database",
+                    &self.engine.database_name,
+                ));
+                let role = if self
+                    .standby_active
+                    .load(std::sync::atomic::Ordering::SeqCst)
+                {
+                    "standby"
+                } else {
+                    "standalone"
+                };
+                response.push_str(&Messages::metric_item("role", role));
+                response.push_str(&Messages::metric_item(
+                    "tables",
+                    &self.engine.table_count().to_string(),
+                ));
+                if let Some(notice) = self.maintenance_notice.read().unwrap().as_deref() {
+                    response.push_str(&Messages::metric_item("maintenance", notice));
+                }
+                if let Some(reason) = wal_degraded {
+                    response.push_str(&Messages::metric_item("wal_error", &reason));
+                }
+                response
+            }
+            "hello" => {
+                let offered: Vec<Compression> = match parts.as_slice() {
+                    [_] => Vec::new(),
+                    [_, option, names @ ..]
+                        if option.eq_ignore_ascii_case("compress") && !names.is_empty() =>
+                    {
+                        names
+                            .iter()
+                            .flat_map(|names| names.split(','))
+                            .filter_map(Compression::from_str)
+                            .collect()
+                    }
+                    _ => return Messages::ERROR_HELLO_ARGS.to_string(),
+                };
+
+                let compression =
+                    crate::helpers::compression::negotiate(&self.compression_algorithms, &offered);
+                let mut connection_compression = self.connection_compression.lock().unwrap();
+                match compression {
+                    Some(compression) => {
+                        connection_compression.insert(connection_id.to_string(), compression);
+                        Messages::hello_response(&compression.to_string())
+                    }
+                    None => {
+                        connection_compression.remove(connection_id);
+                        Messages::hello_response("none")
+                    }
+                }
+            }
+            "syntax" => {
+                let strict = match parts.as_slice() {
+                    [_] => {
+                        return Messages::syntax_mode(
+                            QUERY_GRAMMAR_VERSION,
+                            self.strict_syntax(connection_id),
+                        );
+                    }
+                    [_, mode] if mode.eq_ignore_ascii_case("lenient") => false,
+                    [_, mode] if mode.eq_ignore_ascii_case("strict") => true,
+                    [_, mode, version] if mode.eq_ignore_ascii_case("strict") => {
+                        if version.parse::<u32>() != Ok(QUERY_GRAMMAR_VERSION) {
+                            return Messages::unsupported_grammar_version(
+                                version,
+                                QUERY_GRAMMAR_VERSION,
+                            );
+                        }
+                        true
+                    }
+                    _ => return Messages::ERROR_SYNTAX_ARGS.to_string(),
+                };
+                self.connection_strict
+                    .lock()
+                    .unwrap()
+                    .insert(connection_id.to_string(), strict);
+                Messages::syntax_mode(QUERY_GRAMMAR_VERSION, strict)
+            }
+            "format" => {
+                let json = match parts.as_slice() {
+                    [_] => return Messages::response_format(self.json_responses(connection_id)),
+                    [_, format] if format.eq_ignore_ascii_case("text") => false,
+                    [_, format] if format.eq_ignore_ascii_case("json") => true,
+                    _ => return Messages::ERROR_FORMAT_ARGS.to_string(),
+                };
+                let mut json_connections = self.json_connections.lock().unwrap();
+                if json {
+                    json_connections.insert(connection_id.to_string());
+                } else {
+                    json_connections.remove(connection_id);
+                }
+                Messages::response_format(json)
+            }
+            "trace" => {
+                let tracing = match parts.as_slice() {
+                    [_] => return Messages::trace_mode(self.tracing(connection_id)),
+                    [_, mode] if mode.eq_ignore_ascii_case("on") => true,
+                    [_, mode] if mode.eq_ignore_ascii_case("off") => false,
+                    _ => return Messages::ERROR_TRACE_ARGS.to_string(),
+                };
+                let mut trace_connections = self.trace_connections.lock().unwrap();
+                if tracing {
+                    trace_connections.insert(connection_id.to_string());
+                } else {
+                    trace_connections.remove(connection_id);
+                }
+                Messages::trace_mode(tracing)
+            }
+            "stream" => {
+                let streaming = match parts.as_slice() {
+                    [_] => return Messages::stream_mode(self.streaming(connection_id)),
+                    [_, mode] if mode.eq_ignore_ascii_case("on") => true,
+                    [_, mode] if mode.eq_ignore_ascii_case("off") => false,
+                    _ => return Messages::ERROR_STREAM_ARGS.to_string(),
+                };
+                let mut stream_connections = self.stream_connections.lock().unwrap();
+                if streaming {
+                    stream_connections.insert(connection_id.to_string());
+                } else {
+                    stream_connections.remove(connection_id);
+                }
+                Messages::stream_mode(streaming)
+            }
+            "help" => Messages::TCP_HELP_TEXT.to_string(),
+            _ => Messages::unknown_command(&cmd),
+        }
+    }
+
+    fn split_json_value(rest: &str) -> Option<(serde_json::Value, &str)> {
+        let mut stream = serde_json::Deserializer::from_str(rest).into_iter::<serde_json::Value>();
+        let value = stream.next()?.ok()?;
+        Some((value, rest[stream.byte_offset()..].trim()))
+    }
+
+    // Returns the value, its write options and whether RETURNOLD asked for the replaced row.
+    fn parse_write_value(rest: &str) -> Result<(serde_json::Value, WriteOptions, bool), String> {
+        let (value, remainder) = match Self::split_json_value(rest) {
+            Some(parsed) => parsed,
+            None => return Err(Messages::ERROR_INVALID_JSON.to_string()),
+        };
+
+        let mut options = WriteOptions::default();
+        let mut return_old = false;
+        let tokens = tokenizer::tokenize(remainder).map_err(|err| Messages::syntax_error(&err))?;
+        let mut tokens = tokens.iter();
+
+        while let Some(token) = tokens.next() {
+            if token.eq_ignore_ascii_case("if")
+                && tokens
+                    .next()
+                    .is_some_and(|token| token.eq_ignore_ascii_case("version"))
+            {
+                let version = tokens
+                    .next()
+                    .and_then(|version| version.parse::<u64>().ok())
+                    .ok_or_else(|| Messages::ERROR_IF_VERSION_ARGS.to_string())?;
+                options.if_version = Some(version);
+            } else if token.eq_ignore_ascii_case("idempotent") {
+                let idempotency_key = tokens
+                    .next()
+                    .ok_or_else(|| Messages::ERROR_IDEMPOTENT_ARGS.to_string())?;
+                options.idempotency_key = Some(idempotency_key.clone());
+            } else if token.eq_ignore_ascii_case("nx") {
+                options.if_absent = true;
+            } else if token.eq_ignore_ascii_case("xx") {
+                options.if_exists = true;
+            } else if token.eq_ignore_ascii_case("returnold") {
+                return_old = true;
+            } else {
+                return Err(Messages::unexpected_write_option(token));
+            }
+        }
+
+        if options.if_absent && options.if_exists {
+            return Err(Messages::ERROR_NX_XX.to_string());
+        }
+        Ok((value, options, return_old))
+    }
+
+    // With RETURNOLD the replaced row follows OK, as the writer's role may read it.
+    fn write_response(
+        &self,
+        connection_id: &str,
+        table: &str,
+        previous: Option<std::sync::Arc<crate::engine::Row>>,
+        return_old: bool,
+    ) -> String {
+        if !return_old {
+            return Messages::SUCCESS_OK.to_string();
+        }
+        let previous = previous
+            .zip(self.user_manager.get_connection_user(connection_id))
+            .and_then(|(row, user)| self.engine.row_as(&user.role, table, &row));
+        Messages::old_value(previous.map(|row| row.value.to_string()).as_deref())
+    }
+
+    fn split_set_where(rest: &str) -> Option<(serde_json::Value, &str)> {
+        let (patch, remainder) = Self::split_json_value(rest)?;
+
+        if remainder.len() < 6 || !remainder[..6].eq_ignore_ascii_case("where ") {
+            return None;
+        }
+        Some((patch, remainder[6..].trim()))
+    }
+
+    fn encode_cursor(key: &str) -> String {
+        key.bytes().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn decode_cursor(cursor: &str) -> Option<Option<String>> {
+        if cursor == "0" {
+            return Some(None);
+        }
+        if cursor.is_empty() || !cursor.len().is_multiple_of(2) {
+            return None;
+        }
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|position| u8::from_str_radix(cursor.get(position..position + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        String::from_utf8(bytes).ok().map(Some)
+    }
+
+    fn split_limit(rest: &str) -> Result<(&str, Option<usize>), String> {
+        let lowered = rest.to_ascii_lowercase();
+        let (remaining, count) = if lowered.starts_with("limit ") {
+            ("", &rest[6..])
+        } else {
+            match lowered.rfind(" limit ") {
+                Some(position) => (rest[..position].trim(), &rest[position + 7..]),
+                None => return Ok((rest, None)),
+            }
+        };
+
+        match count.trim().parse::<usize>() {
+            Ok(count) => Ok((remaining, Some(count))),
+            Err(_) => Err(Messages::ERROR_LIMIT_ARGS.to_string()),
+        }
+    }
+
+    fn split_order_by(rest: &str) -> Result<(&str, Option<QueryOrder>), String> {
+        let lowered = rest.to_ascii_lowercase();
+        let (conditions, clause) = if lowered.starts_with("order by ") {
+            ("", &rest[9..])
+        } else {
+            match lowered.rfind(" order by ") {
+                Some(position) => (rest[..position].trim(), &rest[position + 10..]),
+                None => return Ok((rest, None)),
+            }
+        };
+
+        let clause: Vec<&str> = clause.split_whitespace().collect();
+        let descending = match clause.get(1).map(|direction| direction.to_lowercase()) {
+            None => false,
+            Some(direction) if direction == "asc" => false,
+            Some(direction) if direction == "desc" => true,
+            Some(_) => return Err(Messages::ERROR_ORDER_BY_ARGS.to_string()),
+        };
+        if clause.is_empty() || clause.len() > 2 {
+            return Err(Messages::ERROR_ORDER_BY_ARGS.to_string());
+        }
+
+        Ok((
+            conditions,
+            Some(QueryOrder {
+                field_path: clause[0].to_string(),
+                descending,
+            }),
+        ))
+    }
+
+    fn parse_role_selector(&self, selector: &str) -> Result<crate::user_manager::UserRole, String> {
+        match selector.strip_prefix("role=") {
+            Some(role) => crate::user_manager::UserRole::from_str(role)
+                .ok_or_else(|| Messages::ERROR_INVALID_ROLE.to_string()),
+            None => Err(Messages::ERROR_FILTER_ARGS.to_string()),
+        }
+    }
+
+    fn parse_query_clauses(
+        &self,
+        rest: &str,
+        strict: bool,
+    ) -> Result<(QueryExpr, Option<QueryOrder>, Option<usize>), String> {
+        // A JSON filter ends where its document does, so clauses are only looked for after it.
+        if rest.starts_with('{') {
+            let Some((filter, remainder)) = Self::split_json_value(rest) else {
+                return Err(Messages::error(&Self::invalid_json_filter(rest)));
+            };
+            let (remainder, limit) = Self::split_limit(remainder)?;
+            let (remainder, order) = Self::split_order_by(remainder)?;
+            if !remainder.is_empty() {
+                return Err(Messages::error(&Error::Parse(
+                    Messages::json_filter_trailing(remainder),
+                )));
+            }
+            let filter = Self::parse_json_filter(&filter).map_err(|err| Messages::error(&err))?;
+            return Ok((filter, order, limit));
+        }
+
+        let (rest, limit) = Self::split_limit(rest)?;
+        let (conditions_str, order) = Self::split_order_by(rest)?;
+
+        let filter = if conditions_str.is_empty() && (order.is_some() || limit.is_some()) {
+            QueryExpr::all()
+        } else {
+            Self::parse_query_expression(conditions_str, strict)
+                .map_err(|err| Messages::error(&err))?
+        };
+        Ok((filter, order, limit))
+    }
+
+    fn strict_syntax(&self, connection_id: &str) -> bool {
+        self.connection_strict
+            .lock()
+            .unwrap()
+            .get(connection_id)
+            .copied()
+            .unwrap_or(self.strict_by_default)
+    }
+
+    pub fn parse_query_expression(input: &str, strict: bool) -> Result<QueryExpr, Error> {
+        if input.trim_start().starts_with('{') {
+            let filter =
+                serde_json::from_str(input).map_err(|_| Self::invalid_json_filter(input))?;
+            return Self::parse_json_filter(&filter);
+        }
+        let mut tokens = tokenizer::tokenize_expression(input)
+            .map_err(|err| Error::Parse(err.to_string()))?
+            .into_iter()
+            .peekable();
+        let expr = Self::parse_or_expression(&mut tokens, strict)?;
+        match tokens.next() {
+            None => Ok(expr),
+            Some((ExpressionToken::Close, position)) => Err(Self::parse_error(
+                Messages::ERROR_UNBALANCED_PARENTHESES,
+                position,
+                strict,
+            )),
+            Some((token, position)) => Err(Self::parse_error(
+                &Messages::expected_condition(&token.to_string()),
+                position,
+                strict,
+            )),
+        }
+    }
+
+    fn parse_error(message: &str, position: usize, strict: bool) -> Error {
+        Error::Parse(if strict {
+            Messages::strict_error(message, position)
+        } else {
+            message.to_string()
+        })
+    }
+
+    // OR binds loosest, then AND, then NOT, matching the usual precedence. Strict mode refuses to
+    // rely on that and wants parentheses whenever AND and OR meet at the same level.
+    fn parse_or_expression(
+        tokens: &mut ExpressionTokens,
+        strict: bool,
+    ) -> Result<QueryExpr, Error> {
+        let (first, mut mixed) = Self::parse_and_expression(tokens, strict)?;
+        let mut terms = vec![first];
+        let mut first_or = None;
+        while let Some((_, position)) = tokens.next_if(|(token, _)| *token == ExpressionToken::Or) {
+            first_or.get_or_insert(position);
+            let (term, bare_and) = Self::parse_and_expression(tokens, strict)?;
+            mixed |= bare_and;
+            terms.push(term);
+        }
+        if let (true, true, Some(position)) = (strict, mixed, first_or) {
+            return Err(Error::Parse(Messages::strict_error(
+                Messages::STRICT_MIXED_AND_OR,
+                position,
+            )));
+        }
+        Ok(if terms.len() == 1 {
+            terms.remove(0)
+        } else {
+            QueryExpr::Or(terms)
+        })
+    }
+
+    fn parse_and_expression(
+        tokens: &mut ExpressionTokens,
+        strict: bool,
+    ) -> Result<(QueryExpr, bool), Error> {
+        let mut terms = vec![Self::parse_unary_expression(tokens, strict)?];
+        while tokens
+            .next_if(|(token, _)| *token == ExpressionToken::And)
+            .is_some()
+        {
+            terms.push(Self::parse_unary_expression(tokens, strict)?);
+        }
+        Ok(if terms.len() == 1 {
+            (terms.remove(0), false)
+        } else {
+            (QueryExpr::And(terms), true)
+        })
+    }
+
+    fn parse_unary_expression(
+        tokens: &mut ExpressionTokens,
+        strict: bool,
+    ) -> Result<QueryExpr, Error> {
+        match tokens.next() {
+            Some((ExpressionToken::Not, _)) => Ok(QueryExpr::Not(Box::new(
+                Self::parse_unary_expression(tokens, strict)?,
+            ))),
+            Some((ExpressionToken::Open, position)) => {
+                let expr = Self::parse_or_expression(tokens, strict)?;
+                match tokens.next() {
+                    Some((ExpressionToken::Close, _)) => Ok(expr),
+                    _ => Err(Self::parse_error(
+                        Messages::ERROR_UNBALANCED_PARENTHESES,
+                        position,
+                        strict,
+                    )),
+                }
+            }
+            Some((ExpressionToken::Condition(condition), position)) => {
+                if let Some(within) = Self::parse_within_condition(&condition)? {
+                    return Ok(QueryExpr::Within(within));
+                }
+                let condition = if strict {
+                    Self::parse_strict_condition(&condition, position)?
+                } else {
+                    Self::parse_single_condition(&condition)?
+                };
+                Ok(QueryExpr::Condition(condition))
             }
-            "delete" => {
-                if !self.user_manager.is_connection_authenticated(connection_id) {
-                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+            Some((token, position)) => Err(Self::parse_error(
+                &Messages::expected_condition(&token.to_string()),
+                position,
+                strict,
+            )),
+            None => Err(Error::Parse(Messages::expected_condition("end of input"))),
+        }
+    }
+
+    fn parse_single_condition(condition_str: &str) -> Result<crate::engine::QueryCondition, Error> {
+        // Word operators (contains, regex, ...) always come right after the field path.
+        if let Some((field, rest)) = condition_str.trim().split_once(char::is_whitespace) {
+            let rest = rest.trim_start();
+            let (word, value_str) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            if let Some(operator) = QueryOperator::from_word(word) {
+                let value_str = value_str.trim();
+                if value_str.is_empty() {
+                    return Err(Error::Parse(Messages::invalid_word_condition(
+                        operator.symbol(),
+                    )));
                 }
+                return Self::build_condition(field, operator, value_str);
+            }
+        }
 
-                if parts.len() != 3 {
-                    return Messages::ERROR_DELETE_ARGS.to_string();
+        if let Some((offset, symbol)) = tokenizer::find_operator(condition_str) {
+            let operator = QueryOperator::from_symbol(symbol)
+                .ok_or_else(|| Error::Parse(Messages::unsupported_operator(symbol)))?;
+            return Self::build_condition(
+                condition_str[..offset].trim(),
+                operator,
+                condition_str[offset + symbol.len()..].trim(),
+            );
+        }
+
+        Err(Error::Parse(Messages::invalid_condition(condition_str)))
+    }
+
+    fn invalid_json_filter(input: &str) -> Error {
+        let reason = match serde_json::Deserializer::from_str(input)
+            .into_iter::<serde_json::Value>()
+            .next()
+        {
+            Some(Err(e)) => e.to_string(),
+            _ => "expected one JSON object".to_string(),
+        };
+        Error::Parse(Messages::invalid_json_filter(&reason))
+    }
+
+    // `{"age": {"$gt": 18}, "status": "active"}`: each field holds a value to equal, or an object
+    // of `$` operators that must all hold. `$and` and `$or` take a list of filters, `$not` one.
+    fn parse_json_filter(filter: &serde_json::Value) -> Result<QueryExpr, Error> {
+        let serde_json::Value::Object(fields) = filter else {
+            return Err(Error::Parse(Messages::JSON_FILTER_OBJECT.to_string()));
+        };
+
+        let mut exprs = Vec::new();
+        for (key, value) in fields {
+            match key.as_str() {
+                "$and" | "$or" => {
+                    let serde_json::Value::Array(filters) = value else {
+                        return Err(Error::Parse(Messages::json_filter_list(key)));
+                    };
+                    let filters = filters
+                        .iter()
+                        .map(Self::parse_json_filter)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    exprs.push(if key == "$and" {
+                        QueryExpr::And(filters)
+                    } else {
+                        QueryExpr::Or(filters)
+                    });
                 }
-                let table = parts[1].to_string();
-                let key = parts[2].to_string();
+                "$not" => exprs.push(QueryExpr::Not(Box::new(Self::parse_json_filter(value)?))),
+                operator if operator.starts_with('$') => {
+                    return Err(Error::Parse(Messages::json_filter_operator(operator)));
+                }
+                field => match value {
+                    serde_json::Value::Object(operators)
+                        if operators.keys().any(|key| key.starts_with('$')) =>
+                    {
+                        for (operator, operand) in operators {
+                            let operator = Self::json_filter_operator(operator)?;
+                            exprs.push(QueryExpr::Condition(Self::condition_with_value(
+                                field,
+                                operator,
+                                operand.clone(),
+                            )?));
+                        }
+                    }
+                    value => exprs.push(QueryExpr::Condition(Self::condition_with_value(
+                        field,
+                        QueryOperator::Equals,
+                        value.clone(),
+                    )?)),
+                },
+            }
+        }
 
-                self.engine.remove_row(table.clone(), key.clone()).await;
-                self.logger
-                    .debug(&format!("DELETE operation: {} {}", table, key))
-                    .await;
-                Messages::SUCCESS_OK.to_string()
+        Ok(match exprs.len() {
+            1 => exprs.pop().unwrap(),
+            _ => QueryExpr::And(exprs),
+        })
+    }
+
+    fn json_filter_operator(operator: &str) -> Result<QueryOperator, Error> {
+        let symbol = match operator {
+            "$eq" => "=",
+            "$ne" => "!=",
+            "$gt" => ">",
+            "$gte" => ">=",
+            "$lt" => "<",
+            "$lte" => "<=",
+            _ => "",
+        };
+        QueryOperator::from_symbol(symbol)
+            .or_else(|| {
+                operator
+                    .strip_prefix('$')
+                    .and_then(QueryOperator::from_word)
+            })
+            .ok_or_else(|| Error::Parse(Messages::json_filter_operator(operator)))
+    }
+
+    // `WITHIN <field1> <min1> <max1> <field2> <min2> <max2>`, the same in either mode. None when
+    // the condition is an ordinary comparison, such as one on a field named `within`.
+    fn parse_within_condition(
+        condition_str: &str,
+    ) -> Result<Option<crate::engine::WithinCondition>, Error> {
+        let words: Vec<&str> = condition_str.split_whitespace().collect();
+        let Some((first, rest)) = words.split_first() else {
+            return Ok(None);
+        };
+        if !first.eq_ignore_ascii_case("within")
+            || tokenizer::find_operator(condition_str).is_some()
+            || rest
+                .first()
+                .is_some_and(|word| QueryOperator::from_word(word).is_some())
+        {
+            return Ok(None);
+        }
+        let [first, min1, max1, second, min2, max2] = rest else {
+            return Err(Error::Parse(Messages::WITHIN_ARGS.to_string()));
+        };
+        let bound = |bound: &str| match bound.parse::<f64>() {
+            Ok(number) if !number.is_nan() => Ok(number),
+            _ => Err(Error::Parse(Messages::within_bound(bound))),
+        };
+        Ok(Some(crate::engine::WithinCondition {
+            fields: [first.to_string(), second.to_string()],
+            bounds: [(bound(min1)?, bound(max1)?), (bound(min2)?, bound(max2)?)],
+        }))
+    }
+
+    // Strict conditions are exactly `<field> <operator> <literal>`, where a text literal must be
+    // quoted. `position` is where the condition starts in the filter, for error messages.
+    fn parse_strict_condition(
+        condition_str: &str,
+        position: usize,
+    ) -> Result<crate::engine::QueryCondition, Error> {
+        let at = |reason: &str, offset: usize| {
+            Error::Parse(Messages::strict_error(
+                reason,
+                position + condition_str[..offset].chars().count(),
+            ))
+        };
+
+        let field_end = condition_str
+            .find(|c: char| c.is_whitespace() || "=!<>\"".contains(c))
+            .unwrap_or(condition_str.len());
+        if field_end == 0 {
+            return Err(at(Messages::STRICT_EXPECTED_FIELD, 0));
+        }
+
+        let rest = condition_str[field_end..].trim_start();
+        let operator_start = condition_str.len() - rest.len();
+        let symbol = [">=", "<=", "!=", "=", ">", "<"]
+            .into_iter()
+            .find(|symbol| rest.starts_with(symbol));
+        let (operator, operator_len) = match symbol {
+            Some(symbol) => (QueryOperator::from_symbol(symbol), symbol.len()),
+            None => {
+                let word = rest.split(char::is_whitespace).next().unwrap_or_default();
+                (QueryOperator::from_word(word), word.len())
             }
-            "query" => {
-                if !self.user_manager.is_connection_authenticated(connection_id) {
-                    return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+        };
+        let Some(operator) = operator else {
+            return Err(at(Messages::STRICT_EXPECTED_OPERATOR, operator_start));
+        };
+
+        let value_str = rest[operator_len..].trim();
+        let value_start = condition_str.len() - rest[operator_len..].trim_start().len();
+        if value_str.is_empty() {
+            return Err(at(Messages::STRICT_MISSING_VALUE, value_start));
+        }
+        if !Self::is_strict_literal(value_str) {
+            return Err(at(Messages::STRICT_UNQUOTED_VALUE, value_start));
+        }
+
+        Self::build_condition(&condition_str[..field_end], operator, value_str)
+    }
+
+    fn is_strict_literal(value_str: &str) -> bool {
+        if let Some(quoted) = value_str.strip_prefix('"') {
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => {
+                        chars.next();
+                    }
+                    '"' => return chars.as_str().is_empty(),
+                    _ => {}
                 }
+            }
+            return false;
+        }
+        // f64 parsing also accepts inf and NaN and overflows to infinity; strict wants a finite number.
+        matches!(value_str, "true" | "false" | "null")
+            || (value_str.parse::<f64>().is_ok_and(f64::is_finite)
+                && value_str
+                    .chars()
+                    .all(|c| c.is_ascii_digit() || "+-.eE".contains(c)))
+    }
 
-                if parts.len() < 3 {
-                    return Messages::ERROR_QUERY_ARGS.to_string();
+    fn build_condition(
+        field: &str,
+        operator: QueryOperator,
+        value_str: &str,
+    ) -> Result<crate::engine::QueryCondition, Error> {
+        Self::condition_with_value(field, operator, Self::parse_condition_value(value_str))
+    }
+
+    fn condition_with_value(
+        field: &str,
+        operator: QueryOperator,
+        value: serde_json::Value,
+    ) -> Result<crate::engine::QueryCondition, Error> {
+        let pattern = if operator == QueryOperator::Regex {
+            let source = match &value {
+                serde_json::Value::String(source) => source.clone(),
+                other => other.to_string(),
+            };
+            // Parse errors span several lines with a caret diagram; the last line names the problem.
+            Some(regex::Regex::new(&source).map_err(|e| {
+                let err = e.to_string();
+                let reason = err.lines().last().unwrap_or_default().trim();
+                Error::Parse(Messages::invalid_regex(
+                    &source,
+                    reason.trim_start_matches("error: "),
+                ))
+            })?)
+        } else {
+            None
+        };
+        Ok(QueryCondition {
+            field_path: field.to_string(),
+            operator,
+            value,
+            pattern,
+        })
+    }
+
+    // Swaps each `?` standing alone as a condition value for a quoted PLACEHOLDER, skipping
+    // quoted text, and counts them. A `?` inside a bare word, as in a regex, is left alone.
+    fn mark_placeholders(filter: &str) -> (String, usize) {
+        let mut template = String::with_capacity(filter.len());
+        let mut placeholders = 0;
+        let mut chars = filter.chars().peekable();
+        let mut previous = ' ';
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            if quoted {
+                template.push(c);
+                match c {
+                    '\\' => template.extend(chars.next()),
+                    '"' => quoted = false,
+                    _ => {}
                 }
-                let table = parts[1].to_string();
-                let conditions_str = parts[2..].join(" ");
+            } else if c == '?'
+                && (previous.is_whitespace() || "=!<>(".contains(previous))
+                && chars
+                    .peek()
+                    .is_none_or(|next| next.is_whitespace() || *next == ')')
+            {
+                template.push_str(&format!("\"{}\"", PLACEHOLDER));
+                placeholders += 1;
+            } else {
+                quoted = c == '"';
+                template.push(c);
+            }
+            previous = c;
+        }
+        (template, placeholders)
+    }
 
-                let conditions = match self.parse_single_condition(&conditions_str) {
-                    Ok(cond) => vec![cond],
-                    Err(err) => return Messages::query_error(&err),
-                };
+    // Rebuilds every placeholder condition in written order with `bind`.
+    fn bind_parameters(
+        expr: &mut QueryExpr,
+        bind: &mut impl FnMut(&QueryCondition) -> Result<QueryCondition, Error>,
+    ) -> Result<(), Error> {
+        match expr {
+            QueryExpr::Condition(condition) if condition.value == PLACEHOLDER => {
+                *condition = bind(condition)?;
+            }
+            QueryExpr::Condition(_) | QueryExpr::Within(_) => {}
+            QueryExpr::Not(inner) => Self::bind_parameters(inner, bind)?,
+            QueryExpr::And(exprs) | QueryExpr::Or(exprs) => {
+                for expr in exprs {
+                    Self::bind_parameters(expr, bind)?;
+                }
+            }
+        }
+        Ok(())
+    }
 
-                let results = self.engine.query_rows(table.clone(), conditions);
+    fn parse_condition_value(value_str: &str) -> serde_json::Value {
+        if value_str.starts_with('"') && value_str.ends_with('"') {
+            serde_json::Value::String(value_str.trim_matches('"').to_string())
+        } else if let Ok(num) = value_str.parse::<i64>() {
+            serde_json::json!(num)
+        } else if let Ok(num) = value_str.parse::<f64>() {
+            serde_json::json!(num)
+        } else if value_str == "true" || value_str == "false" {
+            serde_json::Value::Bool(value_str == "true")
+        } else if value_str == "null" {
+            serde_json::Value::Null
+        } else {
+            serde_json::Value::String(value_str.to_string())
+        }
+    }
 
-                if results.is_empty() {
-                    self.logger
-                        .debug(&format!("QUERY operation: {} -> 0 results", table))
-                        .await;
-                    Messages::QUERY_NO_RESULTS.to_string()
-                } else {
-                    self.logger
-                        .debug(&format!(
-                            "QUERY operation: {} -> {} results",
-                            table,
-                            results.len()
-                        ))
-                        .await;
-                    let mut response = Messages::query_results_header(results.len());
-                    for (key, value) in results {
-                        response.push_str(&Messages::query_result_item(&key, &value.to_string()));
+    // Passwords never reach the logs; only the command and the user name are kept.
+    fn mask_credentials(command: &str) -> std::borrow::Cow<'_, str> {
+        let mut words = command.split_whitespace();
+        match words.next().map(str::to_lowercase).as_deref() {
+            Some("login") => match (words.next(), words.next()) {
+                (Some(username), Some(_)) => format!("LOGIN {} ***", username).into(),
+                (Some(_), None) => "LOGIN ***".into(),
+                _ => command.into(),
+            },
+            Some("passwd") => "PASSWD *** ***".into(),
+            Some("user") => {
+                let words: Vec<&str> = words.collect();
+                match words.as_slice() {
+                    [action, username, _, ..] if action.eq_ignore_ascii_case("create") => {
+                        format!("USER {} {} ***", action, username).into()
+                    }
+                    [action, username, field, _] if field.eq_ignore_ascii_case("password") => {
+                        format!("USER {} {} {} ***", action, username, field).into()
                     }
-                    response
+                    _ => command.into(),
                 }
             }
-            "help" => Messages::TCP_HELP_TEXT.to_string(),
-            _ => Messages::unknown_command(&cmd),
+            _ => command.into(),
         }
     }
 
-    fn parse_single_condition(
+    fn json_responses(&self, connection_id: &str) -> bool {
+        self.json_connections
+            .lock()
+            .unwrap()
+            .contains(connection_id)
+    }
+
+    fn streaming(&self, connection_id: &str) -> bool {
+        self.stream_connections
+            .lock()
+            .unwrap()
+            .contains(connection_id)
+    }
+
+    // Reads the rows of a streamed QUERY on a blocking thread and queues them in chunks of about
+    // STREAM_CHUNK_BYTES for handle_connection to send after the header. The queue is bounded,
+    // so a slow client holds back the reader rather than the whole result piling up in memory.
+    async fn run_query(
         &self,
-        condition_str: &str,
-    ) -> Result<crate::engine::QueryCondition, String> {
-        if condition_str.contains("contains") {
-            let parts: Vec<&str> = condition_str.split(" contains ").collect();
-            if parts.len() != 2 {
-                return Err(Messages::ERROR_INVALID_CONTAINS.to_string());
-            }
-            let field = parts[0].trim().to_string();
-            let value_str = parts[1].trim().trim_matches('"');
-            return Ok(QueryCondition {
-                field_path: field,
-                operator: QueryOperator::Contains,
-                value: serde_json::Value::String(value_str.to_string()),
-            });
+        connection_id: &str,
+        table: String,
+        (filter, order, limit): (QueryExpr, Option<QueryOrder>, Option<usize>),
+    ) -> String {
+        let logger = self.connection_logger(connection_id);
+        let mut shape = filter.shape();
+        if let Some(order) = &order {
+            shape = format!("{} ORDER BY {}", shape, order.field_path)
+                .trim()
+                .to_string();
         }
 
-        let operators = [">=", "<=", "!=", "=", ">", "<"];
-        for op in &operators {
-            if condition_str.contains(op) {
-                let parts: Vec<&str> = condition_str.splitn(2, op).collect();
-                if parts.len() != 2 {
-                    continue;
-                }
-
-                let field = parts[0].trim().to_string();
-                let value_str = parts[1].trim();
-
-                let operator = match *op {
-                    "=" => QueryOperator::Equals,
-                    "!=" => QueryOperator::NotEquals,
-                    ">" => QueryOperator::GreaterThan,
-                    "<" => QueryOperator::LessThan,
-                    ">=" => QueryOperator::GreaterThanOrEqual,
-                    "<=" => QueryOperator::LessThanOrEqual,
-                    _ => return Err(Messages::unsupported_operator(op)),
-                };
-
-                let value = if value_str.starts_with('"') && value_str.ends_with('"') {
-                    serde_json::Value::String(value_str.trim_matches('"').to_string())
-                } else if let Ok(num) = value_str.parse::<f64>() {
-                    serde_json::json!(num)
-                } else if value_str == "true" || value_str == "false" {
-                    serde_json::Value::Bool(value_str == "true")
-                } else if value_str == "null" {
-                    serde_json::Value::Null
-                } else {
-                    serde_json::Value::String(value_str.to_string())
-                };
+        let Some(user) = self.user_manager.get_connection_user(connection_id) else {
+            return Messages::ERROR_NOT_AUTHENTICATED.to_string();
+        };
+        if self.streaming(connection_id) {
+            logger
+                .debug(&format!("QUERY operation: {} -> streaming", table))
+                .await;
+            return self.start_query_stream(
+                connection_id,
+                user,
+                table,
+                (filter, order, limit),
+                shape,
+            );
+        }
+        let role = user.role;
 
-                return Ok(QueryCondition {
-                    field_path: field,
-                    operator,
-                    value,
-                });
+        let started = std::time::Instant::now();
+        let engine = self.engine.clone();
+        let query_table = table.clone();
+        let deadline = self.command_deadline();
+        let mut results = match tokio::task::spawn_blocking(move || {
+            engine.query_rows_as(&role, query_table, filter, order, deadline)
+        })
+        .await
+        {
+            Ok(Ok(results)) => results,
+            Ok(Err(err)) => return Messages::error(&err),
+            Err(err) => return Messages::error(&err.into()),
+        };
+        self.engine
+            .sample_read("QUERY", &table, &shape, started.elapsed(), results.len());
+        if let Some(limit) = limit {
+            results.truncate(limit);
+        }
+        if let Some(max_rows) = user.quotas.max_result_rows
+            && results.len() > max_rows
+        {
+            return Messages::quota_rows(results.len(), max_rows);
+        }
+        // Streamed results are sent as they are read, so only a buffered response is capped.
+        if let Some(max_rows) = self.max_result_rows
+            && results.len() > max_rows
+        {
+            logger
+                .warning(&format!(
+                    "QUERY operation: {} -> {} results, over the {} row limit",
+                    table,
+                    results.len(),
+                    max_rows
+                ))
+                .await;
+            return Messages::result_too_large(results.len(), max_rows);
+        }
+
+        if results.is_empty() {
+            logger
+                .debug(&format!("QUERY operation: {} -> 0 results", table))
+                .await;
+            Messages::QUERY_NO_RESULTS.to_string()
+        } else {
+            logger
+                .debug(&format!(
+                    "QUERY operation: {} -> {} results",
+                    table,
+                    results.len()
+                ))
+                .await;
+            let mut response = Messages::query_results_header(results.len());
+            for (key, value) in results {
+                response.push_str(&Messages::query_result_item(&key, &value.to_string()));
+            }
+            response
+        }
+    }
+
+    fn start_query_stream(
+        &self,
+        connection_id: &str,
+        user: crate::user_manager::User,
+        table: String,
+        (filter, order, limit): (QueryExpr, Option<QueryOrder>, Option<usize>),
+        shape: String,
+    ) -> String {
+        let (sender, receiver) = tokio::sync::mpsc::channel(STREAM_BUFFERED_CHUNKS);
+        let engine = self.engine.clone();
+        let json = self.json_responses(connection_id);
+        let deadline = self.command_deadline();
+        let max_rows = user.quotas.max_result_rows;
+        tokio::task::spawn_blocking(move || {
+            let started = std::time::Instant::now();
+            let mut chunk = String::new();
+            let mut sent = 0;
+            let mut over_quota = false;
+            let result = engine.stream_rows_as(
+                &user.role,
+                table.clone(),
+                filter,
+                order,
+                limit,
+                deadline,
+                |key, value| {
+                    if max_rows.is_some_and(|max_rows| sent >= max_rows) {
+                        over_quota = true;
+                        return false;
+                    }
+                    sent += 1;
+                    if json {
+                        chunk.push_str(&Messages::stream_json_row(&key, &value));
+                    } else {
+                        chunk.push_str(&Messages::query_result_item(&key, &value.to_string()));
+                    }
+                    chunk.len() < STREAM_CHUNK_BYTES
+                        || sender
+                            .blocking_send(StreamChunk::Rows(std::mem::take(&mut chunk)))
+                            .is_ok()
+                },
+            );
+            engine.sample_read("QUERY", &table, &shape, started.elapsed(), sent);
+            if !chunk.is_empty() {
+                let _ = sender.blocking_send(StreamChunk::Rows(chunk));
+            }
+            let end = match result {
+                _ if over_quota => Messages::quota_rows(sent + 1, sent),
+                Ok(count) => Messages::stream_end(count),
+                Err(err) => Messages::error(&err),
+            };
+            let _ = sender.blocking_send(StreamChunk::End(end));
+        });
+        self.pending_streams
+            .lock()
+            .unwrap()
+            .insert(connection_id.to_string(), receiver);
+        Messages::QUERY_STREAM_HEADER.to_string()
+    }
+
+    async fn send_stream(
+        &self,
+        stream: &mut tokio::net::TcpStream,
+        connection_id: &str,
+        compression: Option<Compression>,
+        trace_id: Option<&str>,
+        mut receiver: tokio::sync::mpsc::Receiver<StreamChunk>,
+    ) -> std::io::Result<()> {
+        while let Some(chunk) = receiver.recv().await {
+            let chunk = match chunk {
+                StreamChunk::Rows(rows) => rows,
+                StreamChunk::End(end) => self.render_response(connection_id, &end, trace_id),
+            };
+            stream
+                .write_all(&self.frame_response(compression, &chunk))
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Errors carry the trace id so a failure can be found in the server log. After TRACE ON,
+    // every other response ends with it on a line of its own.
+    fn render_response(
+        &self,
+        connection_id: &str,
+        response: &str,
+        trace_id: Option<&str>,
+    ) -> String {
+        let is_error = response.starts_with("ERROR");
+        let trace_id = trace_id.filter(|_| is_error || self.tracing(connection_id));
+        if self.json_responses(connection_id) {
+            return Messages::json_response(response, trace_id);
+        }
+        match trace_id {
+            Some(trace_id) if is_error => Messages::with_trace(response, trace_id),
+            Some(trace_id) => format!("{}{}", response, Messages::trace_line(trace_id)),
+            None => response.to_string(),
+        }
+    }
+
+    fn tracing(&self, connection_id: &str) -> bool {
+        self.trace_connections
+            .lock()
+            .unwrap()
+            .contains(connection_id)
+    }
+
+    async fn read_before(
+        stream: &mut tokio::net::TcpStream,
+        buffer: &mut [u8],
+        deadline: Option<tokio::time::Instant>,
+    ) -> Option<std::io::Result<usize>> {
+        match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, stream.read(buffer))
+                .await
+                .ok(),
+            None => Some(stream.read(buffer).await),
+        }
+    }
+
+    fn frame_response(&self, compression: Option<Compression>, response: &str) -> Vec<u8> {
+        match compression {
+            Some(compression) => {
+                let frame = crate::helpers::compression::encode_frame(
+                    compression,
+                    self.compression_min_size,
+                    response.as_bytes(),
+                );
+                self.response_compression
+                    .record(response.len(), frame.len());
+                frame
+            }
+            None => response.as_bytes().to_vec(),
+        }
+    }
+
+    // Commands end with a newline, so a long one is collected over as many reads as it takes, up
+    // to max_request_bytes. Past the limit the rest of the line is read and thrown away rather
+    // than taken for the next command. A client that disconnects ends its last command.
+    async fn read_request_line(
+        &self,
+        stream: &mut tokio::net::TcpStream,
+        buffer: &mut [u8],
+        received: usize,
+        deadline: Option<tokio::time::Instant>,
+    ) -> Result<String, String> {
+        let mut request = buffer[..received].to_vec();
+        let mut too_large = request.len() > self.max_request_bytes;
+        while !request.ends_with(b"\n") {
+            let n = match Self::read_before(stream, buffer, deadline).await {
+                Some(Ok(n)) if n > 0 => n,
+                _ => break,
+            };
+            if too_large {
+                request.clear();
+            }
+            request.extend_from_slice(&buffer[..n]);
+            too_large |= request.len() > self.max_request_bytes;
+        }
+        if too_large {
+            return Err(Messages::request_too_large(self.max_request_bytes));
+        }
+        Ok(String::from_utf8_lossy(&request).into_owned())
+    }
+
+    // `received` holds the header line and whatever part of the body arrived with it.
+    async fn read_request_frame(
+        &self,
+        stream: &mut tokio::net::TcpStream,
+        received: &[u8],
+    ) -> Result<String, &'static str> {
+        let header_end = received
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .ok_or(Messages::ERROR_INVALID_REQUEST_FRAME)?;
+        let header = String::from_utf8_lossy(&received[..header_end]);
+        let (compression, length) =
+            crate::helpers::compression::parse_frame_header(header.trim_end())
+                .filter(|(_, length)| *length <= self.max_request_bytes)
+                .ok_or(Messages::ERROR_INVALID_REQUEST_FRAME)?;
+
+        let mut body = received[header_end + 1..].to_vec();
+        let received_len = body.len();
+        body.resize(length, 0);
+        if received_len < length {
+            stream
+                .read_exact(&mut body[received_len..])
+                .await
+                .map_err(|_| Messages::ERROR_INVALID_REQUEST_FRAME)?;
+        }
+
+        let decoded = match compression {
+            Some(compression) => compression
+                .decompress_limited(&body, self.max_request_bytes as u64)
+                .map_err(|_| Messages::ERROR_INVALID_REQUEST_FRAME)?,
+            None => body,
+        };
+        self.request_compression
+            .record(decoded.len(), header_end + 1 + length);
+        String::from_utf8(decoded).map_err(|_| Messages::ERROR_INVALID_REQUEST_FRAME)
+    }
+
+    // Connections count against their IP until they log in, so a client that opens sockets
+    // and never authenticates cannot hold more than a few of them.
+    fn admit_unauthenticated(&self, ip: std::net::IpAddr) -> bool {
+        let mut unauthenticated = self.unauthenticated.lock().unwrap();
+        let count = unauthenticated.entry(ip).or_insert(0);
+        if self.max_unauthenticated_per_ip > 0 && *count >= self.max_unauthenticated_per_ip {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    fn release_unauthenticated(&self, ip: std::net::IpAddr) {
+        let mut unauthenticated = self.unauthenticated.lock().unwrap();
+        if let Some(count) = unauthenticated.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                unauthenticated.remove(&ip);
             }
         }
+    }
+
+    // Unknown commands are counted under one name, and tables only for logged-in connections, so
+    // stray input cannot fill the history with made-up names.
+    fn record_activity(
+        &self,
+        command: &str,
+        connection_id: &str,
+        response: &str,
+        elapsed: std::time::Duration,
+    ) {
+        let words: Vec<String> = command
+            .split_whitespace()
+            .take(3)
+            .map(str::to_lowercase)
+            .collect();
+        let Some(name) = words.first() else {
+            return;
+        };
+        let name = if response == Messages::unknown_command(name) {
+            "unknown"
+        } else {
+            name.as_str()
+        };
+        let table = match (name, words.get(1).map(String::as_str)) {
+            ("delete", Some("from"))
+            | ("explain", Some("query"))
+            | ("drop", Some("table"))
+            | ("rename", Some("table"))
+            | ("copy", Some("table")) => command.split_whitespace().nth(2),
+            (name, _) if TABLE_COMMANDS.contains(&name) => command.split_whitespace().nth(1),
+            _ => None,
+        }
+        .filter(|_| self.user_manager.is_connection_authenticated(connection_id));
+
+        self.activity
+            .record(name, table, elapsed, response.starts_with("ERROR"));
+    }
+
+    // The key a single-row command reads or writes. Table-wide commands address no key and always
+    // run against the rows this node holds.
+    fn addressed_key<'a>(cmd: &str, parts: &[&'a str]) -> Option<&'a str> {
+        let key = *parts.get(2)?;
+        match cmd {
+            "get" | "persist" | "lock" | "unlock" => Some(key),
+            "set" if !key.eq_ignore_ascii_case("auto") => Some(key),
+            "update"
+                if !(key.eq_ignore_ascii_case("set")
+                    && parts
+                        .get(3)
+                        .is_some_and(|rest| Self::split_set_where(rest).is_some())) =>
+            {
+                Some(key)
+            }
+            "delete" if !parts[1].eq_ignore_ascii_case("from") => Some(key),
+            _ => None,
+        }
+    }
+
+    // Where the tables are in each command a user (not an admin) may run.
+    fn named_tables(cmd: &str, tokens: &[String]) -> std::ops::Range<usize> {
+        let second_word = |word: &str| {
+            tokens
+                .get(1)
+                .is_some_and(|token| token.eq_ignore_ascii_case(word))
+        };
+        match cmd {
+            "set" | "get" | "update" | "persist" | "query" | "scan" | "truncate" | "lock"
+            | "unlock" => 1..2,
+            "delete" if second_word("from") => 2..3,
+            "delete" => 1..2,
+            "explain" if second_word("query") => 2..3,
+            "drop" if second_word("table") => 2..3,
+            "lease" => 2..3,
+            "prepare" => 3..4,
+            _ => 0..0,
+        }
+    }
+
+    fn written_tables<'a, 'b>(cmd: &str, parts: &'b [&'a str]) -> &'b [&'a str] {
+        let tables = match cmd {
+            "delete"
+                if parts
+                    .get(1)
+                    .is_some_and(|word| word.eq_ignore_ascii_case("from")) =>
+            {
+                2..3
+            }
+            "import" | "copy" => 3..4,
+            "swap" | "rename" => 2..4,
+            // EVAL checks each of its declared tables itself.
+            "eval" => 0..0,
+            "drop" => 2..3,
+            _ => 1..2,
+        };
+        parts.get(tables).unwrap_or_default()
+    }
+
+    // The connection holding a table's lease owns it for the lease's lifetime.
+    fn may_clear_table(&self, connection_id: &str, table: &str) -> bool {
+        self.user_manager.is_connection_admin(connection_id)
+            || (self.user_manager.is_connection_authenticated(connection_id)
+                && self.leases.is_held_by(table, connection_id))
+    }
+
+    fn lease_error(table: &str, err: crate::leases::LeaseError) -> String {
+        match err {
+            crate::leases::LeaseError::Held {
+                owner,
+                remaining_secs,
+            } => Messages::lease_held(table, &owner, remaining_secs),
+            crate::leases::LeaseError::NotLeased => Messages::lease_missing(table),
+        }
+    }
+
+    fn row_lock_error(table: &str, key: &str, err: crate::row_locks::LockError) -> String {
+        match err {
+            crate::row_locks::LockError::Held { remaining_secs } => {
+                Messages::row_lock_held(table, key, remaining_secs)
+            }
+            crate::row_locks::LockError::NotLocked => Messages::row_lock_missing(table, key),
+        }
+    }
+
+    fn connection_logger(&self, connection_id: &str) -> crate::helpers::logging::Logger {
+        match self.user_manager.get_connection_user(connection_id) {
+            Some(user) => self
+                .logger
+                .with_fields(&[("conn", connection_id), ("user", &user.username)]),
+            None => self.logger.with_fields(&[("conn", connection_id)]),
+        }
+    }
 
-        Err(Messages::invalid_condition(condition_str))
+    fn end_connection(&self, connection_id: &str) {
+        self.user_manager.cleanup_connection(connection_id);
+        let mut connection_compression = self.connection_compression.lock().unwrap();
+        connection_compression.remove(connection_id);
+        self.connection_strict.lock().unwrap().remove(connection_id);
+        self.prepared_queries.lock().unwrap().remove(connection_id);
+        self.json_connections.lock().unwrap().remove(connection_id);
+        self.stream_connections
+            .lock()
+            .unwrap()
+            .remove(connection_id);
+        self.pending_streams.lock().unwrap().remove(connection_id);
+        self.trace_connections.lock().unwrap().remove(connection_id);
+        self.leases.end_connection(connection_id);
+        self.row_locks.unlock_all(connection_id);
     }
 
     pub async fn handle_connection(&self, mut stream: tokio::net::TcpStream) {
@@ -336,73 +3919,428 @@ impl TCPServer {
             .peer_addr()
             .unwrap_or_else(|_| "unknown".parse().unwrap());
         let connection_id = format!("{}", peer_addr);
+        let mut logger = self.connection_logger(&connection_id);
 
-        self.logger
+        logger
             .info(&format!("New connection from: {}", peer_addr))
             .await;
 
+        if !self.admit_unauthenticated(peer_addr.ip()) {
+            logger
+                .warning(&format!(
+                    "Rejected {}: too many unauthenticated connections from {}",
+                    peer_addr,
+                    peer_addr.ip()
+                ))
+                .await;
+            let _ = stream
+                .write_all(Messages::ERROR_TOO_MANY_UNAUTHENTICATED.as_bytes())
+                .await;
+            return;
+        }
+        let mut awaiting_login = true;
+        let login_deadline = self
+            .login_timeout
+            .map(|timeout| tokio::time::Instant::now() + timeout);
+
         let welcome_msg = Messages::AUTH_REQUIRED;
         if let Err(e) = stream.write_all(welcome_msg.as_bytes()).await {
-            self.logger
+            logger
                 .error(&format!("Failed to send welcome message: {}", e))
                 .await;
+            self.release_unauthenticated(peer_addr.ip());
             return;
         }
 
-        let mut buffer = [0; 1024];
+        let kill = self.sessions.register(&connection_id);
+        let mut buffer = vec![0; READ_BUFFER_BYTES];
 
         loop {
-            match stream.read(&mut buffer).await {
+            let read = tokio::select! {
+                _ = kill.notified() => {
+                    logger
+                        .info(&format!("Session {} killed by an admin", peer_addr))
+                        .await;
+                    let response = self.frame_response(
+                        self.connection_compression
+                            .lock()
+                            .unwrap()
+                            .get(&connection_id)
+                            .copied(),
+                        &self.render_response(&connection_id, Messages::SESSION_KILLED, None),
+                    );
+                    let _ = stream.write_all(&response).await;
+                    break;
+                }
+                read = Self::read_before(
+                    &mut stream,
+                    &mut buffer,
+                    login_deadline.filter(|_| awaiting_login),
+                ) => match read {
+                    Some(read) => read,
+                    None => {
+                        logger
+                            .info(&format!("Login timed out for {}", peer_addr))
+                            .await;
+                        let response = self.frame_response(
+                            self.connection_compression
+                                .lock()
+                                .unwrap()
+                                .get(&connection_id)
+                                .copied(),
+                            &self.render_response(
+                                &connection_id,
+                                Messages::ERROR_LOGIN_TIMEOUT,
+                                None,
+                            ),
+                        );
+                        let _ = stream.write_all(&response).await;
+                        break;
+                    }
+                },
+            };
+
+            match read {
                 Ok(0) => {
-                    self.user_manager.cleanup_connection(&connection_id);
-                    self.logger
+                    logger
                         .info(&format!("Connection closed: {}", peer_addr))
                         .await;
                     break;
                 }
                 Ok(n) => {
-                    let request = String::from_utf8_lossy(&buffer[..n]);
+                    let compression = self
+                        .connection_compression
+                        .lock()
+                        .unwrap()
+                        .get(&connection_id)
+                        .copied();
+
+                    // Once compression is negotiated a client may also send its commands framed.
+                    let request = if compression.is_some() && buffer[..n].starts_with(b"FRAME ") {
+                        self.read_request_frame(&mut stream, &buffer[..n])
+                            .await
+                            .map_err(str::to_string)
+                    } else {
+                        self.read_request_line(
+                            &mut stream,
+                            &mut buffer,
+                            n,
+                            login_deadline.filter(|_| awaiting_login),
+                        )
+                        .await
+                    };
+                    let request = match request {
+                        Ok(request) => request,
+                        Err(err) => {
+                            let response = self.frame_response(
+                                compression,
+                                &self.render_response(&connection_id, &err, None),
+                            );
+                            if stream.write_all(&response).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                    };
                     let command = request.trim();
 
                     if command.is_empty() {
                         continue;
                     }
 
-                    self.logger
-                        .debug(&format!("[{}] Received: {}", peer_addr, command))
-                        .await;
-
                     if command.to_lowercase() == "exit" {
-                        self.user_manager.cleanup_connection(&connection_id);
-                        let response = Messages::SUCCESS_GOODBYE;
-                        if let Err(e) = stream.write_all(response.as_bytes()).await {
-                            self.logger
+                        let response = self.frame_response(
+                            compression,
+                            &self.render_response(&connection_id, Messages::SUCCESS_GOODBYE, None),
+                        );
+                        if let Err(e) = stream.write_all(&response).await {
+                            logger
                                 .error(&format!("Failed to send response: {}", e))
                                 .await;
                         }
-                        self.logger
+                        logger
                             .info(&format!("Client {} disconnected", peer_addr))
                             .await;
                         break;
                     }
 
-                    let response = self.parse_command(command, &connection_id).await;
+                    let (response, trace_id) = match self.split_trace(command) {
+                        Ok((trace_id, command)) => {
+                            let response = crate::helpers::logging::TRACE_ID
+                                .scope(trace_id.clone(), async {
+                                    let masked = Self::mask_credentials(command);
+                                    self.sessions.record_command(&connection_id, &masked);
+                                    logger.debug(&format!("Received: {}", masked)).await;
+                                    let started = std::time::Instant::now();
+                                    let response =
+                                        self.parse_command(command, &connection_id).await;
+                                    let elapsed = started.elapsed();
+                                    self.record_activity(
+                                        command,
+                                        &connection_id,
+                                        &response,
+                                        elapsed,
+                                    );
+                                    logger
+                                        .debug(&Messages::command_duration(
+                                            command.split_whitespace().next().unwrap_or_default(),
+                                            elapsed,
+                                        ))
+                                        .await;
+                                    if response.starts_with("ERROR") {
+                                        logger
+                                            .debug(&format!("Responded: {}", response.trim_end()))
+                                            .await;
+                                    } else {
+                                        logger
+                                            .trace(&format!("Responded: {}", response.trim_end()))
+                                            .await;
+                                    }
+                                    response
+                                })
+                                .await;
+                            (response, Some(trace_id))
+                        }
+                        Err(err) => (err, None),
+                    };
+                    let pending_stream =
+                        self.pending_streams.lock().unwrap().remove(&connection_id);
+                    let response = self.frame_response(
+                        compression,
+                        &self.render_response(&connection_id, &response, trace_id.as_deref()),
+                    );
 
-                    if let Err(e) = stream.write_all(response.as_bytes()).await {
-                        self.logger
+                    if let Err(e) = stream.write_all(&response).await {
+                        logger
+                            .error(&format!("Failed to send response: {}", e))
+                            .await;
+                        break;
+                    }
+                    if let Some(receiver) = pending_stream
+                        && let Err(e) = self
+                            .send_stream(
+                                &mut stream,
+                                &connection_id,
+                                compression,
+                                trace_id.as_deref(),
+                                receiver,
+                            )
+                            .await
+                    {
+                        logger
                             .error(&format!("Failed to send response: {}", e))
                             .await;
                         break;
                     }
+
+                    if awaiting_login
+                        && self
+                            .user_manager
+                            .is_connection_authenticated(&connection_id)
+                    {
+                        awaiting_login = false;
+                        self.release_unauthenticated(peer_addr.ip());
+                    }
+                    logger = self.connection_logger(&connection_id);
                 }
                 Err(e) => {
-                    self.user_manager.cleanup_connection(&connection_id);
-                    self.logger
+                    logger
                         .error(&format!("Failed to read from socket: {}", e))
                         .await;
                     break;
                 }
             }
         }
+
+        self.end_connection(&connection_id);
+        self.sessions.end(&connection_id);
+        if awaiting_login {
+            self.release_unauthenticated(peer_addr.ip());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn condition(input: &str) -> QueryCondition {
+        match TCPServer::parse_query_expression(input, false).unwrap() {
+            QueryExpr::Condition(condition) => condition,
+            other => panic!("expected a single condition, got {:?}", other),
+        }
+    }
+
+    fn parse_error(input: &str, strict: bool) -> String {
+        match TCPServer::parse_query_expression(input, strict) {
+            Err(Error::Parse(message)) => message,
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn splits_conditions_at_the_first_operator_outside_quotes() {
+        let parsed = condition(r#"name = "a>=b""#);
+        assert_eq!(parsed.field_path, "name");
+        assert_eq!(parsed.operator, QueryOperator::Equals);
+        assert_eq!(parsed.value, serde_json::json!("a>=b"));
+
+        let parsed = condition(r#"age>="18""#);
+        assert_eq!(parsed.operator, QueryOperator::GreaterThanOrEqual);
+        assert_eq!(parsed.value, serde_json::json!("18"));
+
+        let parsed = condition("city != 東京");
+        assert_eq!(parsed.operator, QueryOperator::NotEquals);
+        assert_eq!(parsed.value, serde_json::json!("東京"));
+    }
+
+    #[test]
+    fn reports_malformed_filters() {
+        assert_eq!(
+            parse_error(r#"name = "bob"#, false),
+            "Unterminated double quote at position 8"
+        );
+        assert!(parse_error("(a = 1", false).contains("parenthes"));
+        assert!(parse_error("a = 1 and", false).contains("end of input"));
+        assert_eq!(
+            parse_error("a = 1 or b = 2 and c = 3", true),
+            Messages::strict_error(Messages::STRICT_MIXED_AND_OR, 6)
+        );
+        assert_eq!(
+            parse_error("名前 = 1 and = 2", true),
+            Messages::strict_error(Messages::STRICT_EXPECTED_FIELD, 11)
+        );
+    }
+
+    #[test]
+    fn parses_within_boxes() {
+        let within = match TCPServer::parse_query_expression("WITHIN lat -1.5 2 lng 3 4e1", true) {
+            Ok(QueryExpr::Within(within)) => within,
+            other => panic!("expected WITHIN, got {:?}", other),
+        };
+        assert_eq!(within.fields, ["lat", "lng"]);
+        assert_eq!(within.bounds, [(-1.5, 2.0), (3.0, 40.0)]);
+
+        // A field named within is still compared as usual.
+        assert_eq!(condition("within = 5").field_path, "within");
+        assert_eq!(
+            condition("within contains x").operator,
+            QueryOperator::Contains
+        );
+
+        assert_eq!(parse_error("WITHIN lat 1 2", false), Messages::WITHIN_ARGS);
+        assert_eq!(
+            parse_error("within lat 1 2 lng NaN 4", false),
+            Messages::within_bound("NaN")
+        );
+    }
+
+    #[test]
+    fn binds_parameters_only_as_values() {
+        let (template, placeholders) =
+            TCPServer::mark_placeholders(r#"age>? AND (name = "?" OR tag regex a?b OR city = ?)"#);
+        assert_eq!(placeholders, 2);
+        let mut filter = TCPServer::parse_query_expression(&template, false).unwrap();
+
+        let mut values = vec![serde_json::json!(18), serde_json::json!("x\" OR age>0")].into_iter();
+        TCPServer::bind_parameters(&mut filter, &mut |condition| {
+            TCPServer::condition_with_value(
+                &condition.field_path,
+                condition.operator.clone(),
+                values.next().unwrap(),
+            )
+        })
+        .unwrap();
+        assert_eq!(
+            filter.to_string(),
+            r#"age > 18 AND (name = "?" OR tag regex "a?b" OR city = "x\" OR age>0")"#
+        );
+    }
+
+    #[test]
+    fn parses_json_conditions() {
+        let filter = TCPServer::parse_query_expression(
+            r#"{"$or": [{"age": {"$gte": 18, "$lt": 65}}, {"name": {"$startswith": "A"}}], "role": "x\" OR 1=1"}"#,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            filter.to_string(),
+            r#"((age >= 18 AND age < 65) OR name startswith "A") AND role = "x\" OR 1=1""#
+        );
+
+        assert_eq!(
+            parse_error("[1]", false),
+            Messages::invalid_condition("[1]")
+        );
+        assert_eq!(
+            parse_error(r#"{"$xor": []}"#, false),
+            Messages::json_filter_operator("$xor")
+        );
+        assert_eq!(
+            parse_error(r#"{"age": {"$near": 1}}"#, false),
+            Messages::json_filter_operator("$near")
+        );
+        assert_eq!(
+            parse_error(r#"{"$or": {"a": 1}}"#, false),
+            Messages::json_filter_list("$or")
+        );
+    }
+
+    #[test]
+    fn finds_the_tables_a_command_names() {
+        let tables = |command: &str| {
+            let tokens: Vec<String> = command.split(' ').map(str::to_string).collect();
+            let range = TCPServer::named_tables(&tokens[0].to_lowercase(), &tokens);
+            tokens.get(range).unwrap_or_default().to_vec()
+        };
+        assert_eq!(tables("GET users u1"), ["users"]);
+        assert_eq!(tables("DELETE FROM users WHERE"), ["users"]);
+        assert_eq!(tables("DELETE users u1"), ["users"]);
+        assert_eq!(tables("EXPLAIN QUERY orders x"), ["orders"]);
+        assert_eq!(tables("PREPARE q QUERY orders x"), ["orders"]);
+        assert_eq!(tables("LEASE RELEASE orders"), ["orders"]);
+        assert!(tables("DROP orders").is_empty());
+        assert!(tables("LEASE").is_empty());
+        assert!(tables("PING").is_empty());
+    }
+
+    #[test]
+    fn takes_the_namespace_off_named_tables_only() {
+        let unprefix = |response: &str| TCPServer::unprefix_tables(response, "acme__", &["orders"]);
+        assert_eq!(
+            unprefix("ERROR: Table acme__orders not found"),
+            "ERROR: Table orders not found"
+        );
+        assert_eq!(unprefix("LEASED acme__orders 30"), "LEASED orders 30");
+        // A value that happens to contain the prefix, or a longer name, is left alone.
+        assert_eq!(
+            unprefix(r#"ERROR: Invalid value "acme__x acme__orders2 xacme__orders""#),
+            r#"ERROR: Invalid value "acme__x acme__orders2 xacme__orders""#
+        );
+        assert_eq!(
+            TCPServer::unprefix_tables("ERROR: acme__orders", "acme__", &[]),
+            "ERROR: acme__orders"
+        );
+    }
+
+    #[test]
+    fn random_filters_never_panic() {
+        let alphabet: Vec<char> = "ab =!<>()\"\\ANDORNTWIH~,.0-é🦈".chars().collect();
+        let mut seed = 0xf11e_u64;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize
+        };
+        for _ in 0..20_000 {
+            let len = next() % 32;
+            let input: String = (0..len)
+                .map(|_| alphabet[next() % alphabet.len()])
+                .collect();
+            let _ = TCPServer::parse_query_expression(&input, false);
+            let _ = TCPServer::parse_query_expression(&input, true);
+        }
     }
 }
@@ -1,7 +1,18 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 
-#[derive(Debug, Clone)]
+use crate::error::Error;
+use crate::user_manager::UserRole;
+
+pub const STATS_TABLE: &str = "_sharknado_stats";
+const DEADLINE_CHECK_INTERVAL: usize = 256;
+const WAL_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+pub const CREATED_AT_FIELD: &str = "__created_at__";
+pub const UPDATED_AT_FIELD: &str = "__updated_at__";
+pub const EXPIRES_AT_FIELD: &str = "__expires_at__";
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum QueryOperator {
     Equals,
     NotEquals,
@@ -10,6 +21,63 @@ pub enum QueryOperator {
     GreaterThanOrEqual,
     LessThanOrEqual,
     Contains,
+    IContains,
+    StartsWith,
+    EndsWith,
+    Regex,
+}
+
+impl QueryOperator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            QueryOperator::Equals => "=",
+            QueryOperator::NotEquals => "!=",
+            QueryOperator::GreaterThan => ">",
+            QueryOperator::LessThan => "<",
+            QueryOperator::GreaterThanOrEqual => ">=",
+            QueryOperator::LessThanOrEqual => "<=",
+            QueryOperator::Contains => "contains",
+            QueryOperator::IContains => "icontains",
+            QueryOperator::StartsWith => "startswith",
+            QueryOperator::EndsWith => "endswith",
+            QueryOperator::Regex => "regex",
+        }
+    }
+
+    pub fn from_symbol(symbol: &str) -> Option<QueryOperator> {
+        match symbol {
+            "=" => Some(QueryOperator::Equals),
+            "!=" => Some(QueryOperator::NotEquals),
+            ">" => Some(QueryOperator::GreaterThan),
+            "<" => Some(QueryOperator::LessThan),
+            ">=" => Some(QueryOperator::GreaterThanOrEqual),
+            "<=" => Some(QueryOperator::LessThanOrEqual),
+            _ => None,
+        }
+    }
+
+    pub fn from_word(word: &str) -> Option<QueryOperator> {
+        match word.to_lowercase().as_str() {
+            "contains" => Some(QueryOperator::Contains),
+            "icontains" => Some(QueryOperator::IContains),
+            "startswith" => Some(QueryOperator::StartsWith),
+            "endswith" => Some(QueryOperator::EndsWith),
+            "regex" => Some(QueryOperator::Regex),
+            _ => None,
+        }
+    }
+
+    fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            QueryOperator::Equals
+                | QueryOperator::NotEquals
+                | QueryOperator::GreaterThan
+                | QueryOperator::LessThan
+                | QueryOperator::GreaterThanOrEqual
+                | QueryOperator::LessThanOrEqual
+        )
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -17,223 +85,4861 @@ pub struct QueryCondition {
     pub field_path: String,
     pub operator: QueryOperator,
     pub value: serde_json::Value,
+    // Compiled once when the condition is parsed, then reused for every row in the scan.
+    pub pattern: Option<regex::Regex>,
+}
+
+impl QueryCondition {
+    pub fn shape(&self) -> String {
+        format!("{} {} ?", self.field_path, self.operator.symbol())
+    }
+}
+
+impl std::fmt::Display for QueryCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.field_path,
+            self.operator.symbol(),
+            self.value
+        )
+    }
+}
+
+// `WITHIN <field1> <min1> <max1> <field2> <min2> <max2>`: both fields hold numbers inside their
+// inclusive bounds, as in a latitude/longitude bounding box.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WithinCondition {
+    pub fields: [String; 2],
+    pub bounds: [(f64, f64); 2],
+}
+
+impl WithinCondition {
+    pub fn shape(&self) -> String {
+        format!("WITHIN {} ? ? {} ? ?", self.fields[0], self.fields[1])
+    }
+}
+
+impl std::fmt::Display for WithinCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "WITHIN {} {} {} {} {} {}",
+            self.fields[0],
+            self.bounds[0].0,
+            self.bounds[0].1,
+            self.fields[1],
+            self.bounds[1].0,
+            self.bounds[1].1
+        )
+    }
+}
+
+// An empty And matches every row, which is what a query without conditions means.
+#[derive(Debug, Clone)]
+pub enum QueryExpr {
+    Condition(QueryCondition),
+    Within(WithinCondition),
+    Not(Box<QueryExpr>),
+    And(Vec<QueryExpr>),
+    Or(Vec<QueryExpr>),
+}
+
+impl QueryExpr {
+    pub fn all() -> Self {
+        QueryExpr::And(Vec::new())
+    }
+
+    pub fn shape(&self) -> String {
+        self.render(&QueryCondition::shape, &WithinCondition::shape)
+    }
+
+    // One line per node, children indented two spaces under their operator.
+    pub fn tree(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        self.push_tree(0, &mut lines);
+        lines
+    }
+
+    fn push_tree(&self, depth: usize, lines: &mut Vec<String>) {
+        let indent = "  ".repeat(depth);
+        let (label, children) = match self {
+            QueryExpr::Condition(condition) => {
+                lines.push(format!("{}{}", indent, condition));
+                return;
+            }
+            QueryExpr::Within(within) => {
+                lines.push(format!("{}{}", indent, within));
+                return;
+            }
+            QueryExpr::And(terms) if terms.is_empty() => {
+                lines.push(format!("{}(all rows)", indent));
+                return;
+            }
+            QueryExpr::Not(inner) => ("NOT", std::slice::from_ref(inner.as_ref())),
+            QueryExpr::And(terms) => ("AND", terms.as_slice()),
+            QueryExpr::Or(terms) => ("OR", terms.as_slice()),
+        };
+        lines.push(format!("{}{}", indent, label));
+        for child in children {
+            child.push_tree(depth + 1, lines);
+        }
+    }
+
+    fn render(
+        &self,
+        condition: &dyn Fn(&QueryCondition) -> String,
+        within: &dyn Fn(&WithinCondition) -> String,
+    ) -> String {
+        let grouped = |expr: &QueryExpr| match expr {
+            QueryExpr::And(terms) | QueryExpr::Or(terms) if terms.len() > 1 => {
+                format!("({})", expr.render(condition, within))
+            }
+            _ => expr.render(condition, within),
+        };
+        match self {
+            QueryExpr::Condition(leaf) => condition(leaf),
+            QueryExpr::Within(leaf) => within(leaf),
+            QueryExpr::Not(inner) => format!("NOT {}", grouped(inner)),
+            QueryExpr::And(terms) => terms.iter().map(grouped).collect::<Vec<_>>().join(" AND "),
+            QueryExpr::Or(terms) => terms.iter().map(grouped).collect::<Vec<_>>().join(" OR "),
+        }
+    }
+}
+
+impl std::fmt::Display for QueryExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.render(&|condition| condition.to_string(), &|within| within
+                .to_string())
+        )
+    }
+}
+
+pub const REDACTION_MASK: &str = "********";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedactionAction {
+    Mask,
+    Drop,
+}
+
+impl RedactionAction {
+    pub fn from_str(action: &str) -> Option<RedactionAction> {
+        match action.to_lowercase().as_str() {
+            "mask" => Some(RedactionAction::Mask),
+            "drop" => Some(RedactionAction::Drop),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for RedactionAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RedactionAction::Mask => write!(f, "mask"),
+            RedactionAction::Drop => write!(f, "drop"),
+        }
+    }
+}
+
+// How seed rows meet a table that already has rows.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeedMode {
+    // Only empty tables are seeded, so a restart never changes existing data.
+    Empty,
+    // Seed rows are written over rows with the same key; other rows stay.
+    Merge,
+    // The table is truncated first, leaving exactly the seed rows.
+    Overwrite,
+}
+
+impl SeedMode {
+    pub fn from_str(mode: &str) -> Option<SeedMode> {
+        match mode.to_lowercase().as_str() {
+            "empty" => Some(SeedMode::Empty),
+            "merge" => Some(SeedMode::Merge),
+            "overwrite" => Some(SeedMode::Overwrite),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SeedMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SeedMode::Empty => write!(f, "empty"),
+            SeedMode::Merge => write!(f, "merge"),
+            SeedMode::Overwrite => write!(f, "overwrite"),
+        }
+    }
+}
+
+// A number in a range index, ordered by `f64::total_cmp`. -0.0 is folded into 0.0 so the order
+// agrees with the comparisons WITHIN makes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct IndexNumber(f64);
+
+impl IndexNumber {
+    fn new(number: f64) -> Self {
+        IndexNumber(number + 0.0)
+    }
+}
+
+impl Eq for IndexNumber {}
+
+impl PartialOrd for IndexNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IndexNumber {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+// Rows of a table ordered by two numeric fields, so WITHIN reads only the rows whose first field
+// is in range. Rows missing either field, or holding something other than a number there, are
+// left out, as WITHIN never matches them.
+struct RangeIndex {
+    fields: [String; 2],
+    entries: std::collections::BTreeSet<(IndexNumber, IndexNumber, String)>,
+    points: HashMap<String, [IndexNumber; 2]>,
+}
+
+impl RangeIndex {
+    fn new(fields: [String; 2]) -> Self {
+        RangeIndex {
+            fields,
+            entries: std::collections::BTreeSet::new(),
+            points: HashMap::new(),
+        }
+    }
+
+    fn set(&mut self, key: &str, point: Option<[IndexNumber; 2]>) {
+        if let Some([first, second]) = self.points.remove(key) {
+            self.entries.remove(&(first, second, key.to_string()));
+        }
+        if let Some([first, second]) = point {
+            self.entries.insert((first, second, key.to_string()));
+            self.points.insert(key.to_string(), [first, second]);
+        }
+    }
+
+    // Keys inside a WITHIN box, or None when the box is over other fields. The fields may be
+    // given in either order.
+    fn keys_within(&self, within: &WithinCondition) -> Option<Vec<String>> {
+        let [first, second] = &within.fields;
+        let [(min1, max1), (min2, max2)] = if self.fields == [first.clone(), second.clone()] {
+            within.bounds
+        } else if self.fields == [second.clone(), first.clone()] {
+            [within.bounds[1], within.bounds[0]]
+        } else {
+            return None;
+        };
+        let start = (
+            IndexNumber::new(min1),
+            IndexNumber(f64::NEG_INFINITY),
+            String::new(),
+        );
+        Some(
+            self.entries
+                .range(start..)
+                .take_while(|(first, _, _)| first.0 <= max1)
+                .filter(|(_, second, _)| min2 <= second.0 && second.0 <= max2)
+                .map(|(_, _, key)| key.clone())
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WriteOptions {
+    pub if_version: Option<u64>,
+    pub idempotency_key: Option<String>,
+    // SET NX and SET XX: write only when the key is absent, or only when it exists.
+    pub if_absent: bool,
+    pub if_exists: bool,
+}
+
+// A single-key write checked against the rows it replaces, with its log entries and the row
+// each target holds once it is applied (None for a remove).
+struct RowWrite {
+    // The operation its triggers see.
+    operation: &'static str,
+    table: String,
+    key: String,
+    previous: Option<Arc<Row>>,
+    entries: Vec<crate::logs::LogEntry>,
+    rows: Vec<(String, Option<Arc<Row>>)>,
+}
+
+// A script's write, already applied, and the rows it replaced in case it has to be undone.
+pub struct ScriptWrite {
+    write: RowWrite,
+    replaced: Vec<Option<Arc<Row>>>,
+    written: crate::logs::WalTicket,
+    _sequenced: Sequenced,
+}
+
+struct IdempotentWrite {
+    recorded_at: std::time::Instant,
+    fingerprint: (String, String, String),
+    // The row the write replaced, for SET ... RETURNOLD.
+    result: Result<Option<Arc<Row>>, Error>,
+}
+
+#[derive(Debug, Clone)]
+pub struct QueryOrder {
+    pub field_path: String,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RowMeta {
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    // Set by PERSIST: the table's retention max_age no longer applies. The next write clears it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub persist: bool,
+}
+
+impl RowMeta {
+    pub fn at(timestamp: chrono::DateTime<chrono::Utc>) -> Self {
+        RowMeta {
+            created_at: timestamp,
+            updated_at: timestamp,
+            persist: false,
+        }
+    }
+
+    pub fn touched(previous: Option<&RowMeta>) -> Self {
+        let now = chrono::Utc::now();
+        RowMeta {
+            created_at: previous.map(|meta| meta.created_at).unwrap_or(now),
+            updated_at: now,
+            persist: false,
+        }
+    }
+
+    fn field(&self, field_path: &str) -> Option<chrono::DateTime<chrono::Utc>> {
+        match field_path {
+            CREATED_AT_FIELD => Some(self.created_at),
+            UPDATED_AT_FIELD => Some(self.updated_at),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub value: serde_json::Value,
+    pub version: u64,
+    pub meta: RowMeta,
+}
+
+type Table = Arc<RwLock<HashMap<String, Arc<Row>>>>;
+
+pub type TableGuards = Vec<tokio::sync::OwnedRwLockWriteGuard<()>>;
+
+type KeyLocks = HashMap<(String, String), Arc<tokio::sync::Mutex<()>>>;
+
+// Which sequence numbers have been applied. Writes are logged in sequence order but finish in
+// any order, so the applied sequence only moves past a number once every write numbered at or
+// below it has been applied, or has failed and never will be.
+#[derive(Default)]
+struct SequenceTracker {
+    state: std::sync::Mutex<SequenceState>,
+}
+
+#[derive(Default)]
+struct SequenceState {
+    // Writes in flight per sequence number.
+    pending: std::collections::BTreeMap<u64, usize>,
+    issued: u64,
+    applied: u64,
+}
+
+impl SequenceTracker {
+    fn finish(&self, seq: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(count) = state.pending.get_mut(&seq) {
+            *count -= 1;
+            if *count == 0 {
+                state.pending.remove(&seq);
+            }
+        }
+        let applied = match state.pending.keys().next() {
+            Some(first) => first - 1,
+            None => state.issued,
+        };
+        state.applied = state.applied.max(applied);
+    }
+}
+
+// A logged write's place in the sequence, given up once the write is applied or abandoned.
+struct Sequenced {
+    tracker: Arc<SequenceTracker>,
+    seq: Option<u64>,
+}
+
+impl Drop for Sequenced {
+    fn drop(&mut self) {
+        if let Some(seq) = self.seq {
+            self.tracker.finish(seq);
+        }
+    }
+}
+
+// Held by a single-key write from before it reads the row until it is logged and applied. It
+// shares its tables with other single-key writes, so they are still logged in one batch, and
+// keeps out table-wide writes and other writes to the same key.
+struct KeyGuard<'a> {
+    engine: &'a Engine,
+    tables: Vec<tokio::sync::OwnedRwLockReadGuard<()>>,
+    keys: Vec<((String, String), tokio::sync::OwnedMutexGuard<()>)>,
+}
+
+impl Drop for KeyGuard<'_> {
+    fn drop(&mut self) {
+        let mut key_locks = self.engine.key_locks.lock().unwrap();
+        for (row, guard) in self.keys.drain(..) {
+            let lock = tokio::sync::OwnedMutexGuard::mutex(&guard).clone();
+            drop(guard);
+            // Only the map and this clone are left once nobody else is waiting for the key.
+            if Arc::strong_count(&lock) == 2 {
+                key_locks.remove(&row);
+            }
+        }
+        self.tables.clear();
+    }
+}
+
+pub type ScanPage = (Vec<(String, serde_json::Value)>, Option<String>);
+
+// Rows are shared behind Arc and writes replace them rather than editing in place, so a
+// snapshot only copies keys and pointers. Later writes never show up in an open iterator.
+pub struct TableIter {
+    rows: std::vec::IntoIter<(String, Arc<Row>)>,
+}
+
+impl Iterator for TableIter {
+    type Item = (String, Arc<Row>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.rows.size_hint()
+    }
+}
+
+impl ExactSizeIterator for TableIter {}
+
+pub struct ConsistencyReport {
+    pub tables: usize,
+    pub rows: usize,
+    pub checksum_failures: usize,
+    pub discrepancies: Vec<String>,
+}
+
+struct StatsSampler {
+    sample_rate: u64,
+    max_rows: u64,
+    reads_seen: AtomicU64,
+    next_id: AtomicU64,
 }
 
 pub struct Engine {
-    pub log_storage: crate::logs::LogStorageSetup,
+    pub storage: Box<dyn crate::storage::StorageBackend>,
     pub database_name: String,
     pub database_path: String,
-    index: Arc<RwLock<HashMap<String, HashMap<String, serde_json::Value>>>>,
+    index: RwLock<HashMap<String, Table>>,
+    last_version: AtomicU64,
+    table_locks: std::sync::Mutex<HashMap<String, Arc<tokio::sync::RwLock<()>>>>,
+    key_locks: std::sync::Mutex<KeyLocks>,
+    shadow_tables: RwLock<HashMap<String, String>>,
+    stats_sampler: Option<StatsSampler>,
+    role_filters: RwLock<HashMap<(String, String), QueryExpr>>,
+    redactions: RwLock<HashMap<String, Vec<(String, RedactionAction)>>>,
+    range_indexes: RwLock<HashMap<String, Vec<RangeIndex>>>,
+    // Each table's retention age field and max_age, which give its rows their expiry.
+    expiry: RwLock<HashMap<String, (String, chrono::Duration)>>,
+    idempotency_window: std::time::Duration,
+    idempotent_writes: std::sync::Mutex<HashMap<String, IdempotentWrite>>,
+    // The millisecond and sequence of the last generated key.
+    key_clock: std::sync::Mutex<(u64, u16)>,
+    logger: crate::helpers::logging::Logger,
+    wal_degraded: std::sync::Mutex<Option<(String, std::time::Instant)>>,
+    triggers: crate::triggers::Triggers,
+    sequences: Arc<SequenceTracker>,
 }
 
 impl Engine {
-    pub fn new(database_name: String, database_path: String) -> Self {
-        let log_storage = crate::logs::LogStorageSetup::new(
-            database_name.clone(),
-            std::path::PathBuf::from(database_path.clone()).join(format!("{}.log", database_name)),
-        );
+    pub fn new(
+        database_name: String,
+        database_path: String,
+        storage: Box<dyn crate::storage::StorageBackend>,
+    ) -> Self {
         Engine {
-            log_storage,
+            storage,
             database_name,
             database_path,
-            index: Arc::new(RwLock::new(HashMap::new())),
+            index: RwLock::new(HashMap::new()),
+            last_version: AtomicU64::new(0),
+            table_locks: std::sync::Mutex::new(HashMap::new()),
+            key_locks: std::sync::Mutex::new(HashMap::new()),
+            shadow_tables: RwLock::new(HashMap::new()),
+            stats_sampler: None,
+            role_filters: RwLock::new(HashMap::new()),
+            redactions: RwLock::new(HashMap::new()),
+            range_indexes: RwLock::new(HashMap::new()),
+            expiry: RwLock::new(HashMap::new()),
+            idempotency_window: std::time::Duration::from_secs(300),
+            idempotent_writes: std::sync::Mutex::new(HashMap::new()),
+            key_clock: std::sync::Mutex::new((0, 0)),
+            logger: crate::helpers::logging::Logger::new(
+                "sharknado::engine".to_string(),
+                crate::helpers::logging::LogLevel::NONE,
+                crate::helpers::logging::LogPath::Console,
+                false,
+                crate::helpers::logging::LogFormat::Text,
+            ),
+            wal_degraded: std::sync::Mutex::new(None),
+            triggers: crate::triggers::Triggers::default(),
+            sequences: Arc::new(SequenceTracker::default()),
         }
     }
 
-    pub async fn add_row(&self, table: String, key: String, values: serde_json::Value) {
-        let entry = crate::logs::LogEntry::new(
-            "add".to_string(),
-            table.clone(),
-            key.clone(),
-            Some(values.to_string()),
-            0,
-        );
-        self.log_storage.log_entry(entry).await;
-
-        let mut index = self.index.write().unwrap();
-        let table_map = index.entry(table).or_insert_with(HashMap::new);
-        table_map.insert(key, values);
+    pub fn table_count(&self) -> usize {
+        self.index.read().unwrap().len()
     }
 
-    pub fn get_row(&self, table: String, key: String) -> Option<serde_json::Value> {
+    fn table(&self, table: &str) -> Option<Table> {
         let index = self.index.read().unwrap();
-        index.get(&table)?.get(&key).cloned()
+        index.get(table).cloned()
     }
 
-    pub fn query_rows(
-        &self,
-        table: String,
-        conditions: Vec<QueryCondition>,
-    ) -> Vec<(String, serde_json::Value)> {
-        let index = self.index.read().unwrap();
-        let table_data = match index.get(&table) {
-            Some(data) => data,
-            None => return Vec::new(),
-        };
+    fn table_or_create(&self, table: &str) -> Table {
+        if let Some(table_map) = self.table(table) {
+            return table_map;
+        }
+        let mut index = self.index.write().unwrap();
+        index.entry(table.to_string()).or_default().clone()
+    }
 
-        table_data
-            .iter()
-            .filter(|(_, value)| self.matches_conditions(value, &conditions))
-            .map(|(key, value)| (key.clone(), value.clone()))
+    fn table_locks(&self, tables: &[String]) -> Vec<Arc<tokio::sync::RwLock<()>>> {
+        let mut tables: Vec<&String> = tables.iter().collect();
+        tables.sort();
+        tables.dedup();
+
+        let mut table_locks = self.table_locks.lock().unwrap();
+        tables
+            .into_iter()
+            .map(|table| table_locks.entry(table.clone()).or_default().clone())
             .collect()
     }
 
-    pub fn query_rows_with_limit(
-        &self,
-        table: String,
-        conditions: Vec<QueryCondition>,
-        limit: Option<usize>,
-    ) -> Vec<(String, serde_json::Value)> {
-        let index = self.index.read().unwrap();
-        let table_data = match index.get(&table) {
-            Some(data) => data,
-            None => return Vec::new(),
+    async fn lock_tables(&self, tables: &[String]) -> TableGuards {
+        let mut guards = Vec::new();
+        for lock in self.table_locks(tables) {
+            guards.push(lock.write_owned().await);
+        }
+        guards
+    }
+
+    async fn lock_key(&self, tables: &[String], key: &str) -> KeyGuard<'_> {
+        let mut guard = KeyGuard {
+            engine: self,
+            tables: Vec::new(),
+            keys: Vec::new(),
         };
+        for lock in self.table_locks(tables) {
+            guard.tables.push(lock.read_owned().await);
+        }
 
-        let mut results: Vec<(String, serde_json::Value)> = table_data
+        let mut rows: Vec<(String, String)> = tables
             .iter()
-            .filter(|(_, value)| self.matches_conditions(value, &conditions))
-            .map(|(key, value)| (key.clone(), value.clone()))
+            .map(|table| (table.clone(), key.to_string()))
             .collect();
-
-        if let Some(limit_count) = limit {
-            results.truncate(limit_count);
+        rows.sort();
+        rows.dedup();
+        for row in rows {
+            let lock = self
+                .key_locks
+                .lock()
+                .unwrap()
+                .entry(row.clone())
+                .or_default()
+                .clone();
+            guard.keys.push((row, lock.lock_owned().await));
         }
-
-        results
+        guard
     }
 
-    fn matches_conditions(&self, value: &serde_json::Value, conditions: &[QueryCondition]) -> bool {
-        conditions
-            .iter()
-            .all(|condition| self.matches_condition(value, condition))
+    pub fn set_logger(&mut self, logger: crate::helpers::logging::Logger) {
+        self.logger = logger;
     }
 
-    fn matches_condition(&self, value: &serde_json::Value, condition: &QueryCondition) -> bool {
-        let field_value = self.get_nested_value(value, &condition.field_path);
+    // Every write goes through here, so the applied sequence knows of it from the moment it is
+    // numbered. Hold the guard until the write is applied or abandoned.
+    fn append(&self, entries: Vec<crate::logs::LogEntry>) -> (crate::logs::WalTicket, Sequenced) {
+        let mut state = self.sequences.state.lock().unwrap();
+        let numbered = !entries.is_empty();
+        let written = self.storage.append(entries);
+        let seq = numbered.then(|| {
+            let seq = self.storage.last_sequence();
+            *state.pending.entry(seq).or_default() += 1;
+            state.issued = state.issued.max(seq);
+            seq
+        });
+        let sequenced = Sequenced {
+            tracker: self.sequences.clone(),
+            seq,
+        };
+        (written, sequenced)
+    }
 
-        match (&field_value, &condition.operator, &condition.value) {
-            (Some(field_val), QueryOperator::Equals, expected) => *field_val == expected,
-            (Some(field_val), QueryOperator::NotEquals, expected) => *field_val != expected,
-            (
-                Some(serde_json::Value::String(field_str)),
-                QueryOperator::Contains,
-                serde_json::Value::String(expected_str),
-            ) => field_str.contains(expected_str.as_str()),
-            (
-                Some(serde_json::Value::Number(field_num)),
-                QueryOperator::GreaterThan,
-                serde_json::Value::Number(expected_num),
-            ) => field_num.as_f64().unwrap_or(0.0) > expected_num.as_f64().unwrap_or(0.0),
-            (
-                Some(serde_json::Value::Number(field_num)),
-                QueryOperator::LessThan,
-                serde_json::Value::Number(expected_num),
-            ) => field_num.as_f64().unwrap_or(0.0) < expected_num.as_f64().unwrap_or(0.0),
-            (
-                Some(serde_json::Value::Number(field_num)),
-                QueryOperator::GreaterThanOrEqual,
-                serde_json::Value::Number(expected_num),
-            ) => field_num.as_f64().unwrap_or(0.0) >= expected_num.as_f64().unwrap_or(0.0),
-            (
-                Some(serde_json::Value::Number(field_num)),
-                QueryOperator::LessThanOrEqual,
-                serde_json::Value::Number(expected_num),
-            ) => field_num.as_f64().unwrap_or(0.0) <= expected_num.as_f64().unwrap_or(0.0),
-            (None, _, _) => false,
-            _ => false,
+    // Waits until the entries are durable. Hold the guard until they are applied.
+    async fn log_entries(&self, entries: Vec<crate::logs::LogEntry>) -> Result<Sequenced, Error> {
+        if entries.is_empty() {
+            return Ok(Sequenced {
+                tracker: self.sequences.clone(),
+                seq: None,
+            });
         }
+        let (written, sequenced) = self.append(entries);
+        self.check_logged(written.wait().await).await?;
+        Ok(sequenced)
     }
 
-    fn get_nested_value<'a>(
-        &self,
-        value: &'a serde_json::Value,
-        field_path: &str,
-    ) -> Option<&'a serde_json::Value> {
-        let path_parts: Vec<&str> = field_path.split('.').collect();
-        let mut current = value;
+    // The highest sequence number up to which every write is applied and durable. A read that
+    // starts once it has reached a write's number sees that write.
+    pub fn applied_sequence(&self) -> u64 {
+        self.sequences.state.lock().unwrap().applied
+    }
 
-        for part in path_parts {
-            match current {
-                serde_json::Value::Object(obj) => {
-                    current = obj.get(part)?;
+    // A write the WAL could not record will not survive a restart, so it is logged and
+    // reported to the caller, and the engine turns read-only until the WAL recovers.
+    async fn check_logged(&self, written: Result<(), crate::logs::WalError>) -> Result<(), Error> {
+        let Err(e) = written else {
+            return Ok(());
+        };
+        self.logger
+            .error(&format!(
+                "Failed to write {}: {}; refusing writes until it recovers",
+                self.storage.location(),
+                e
+            ))
+            .await;
+        *self.wal_degraded.lock().unwrap() = Some((e.to_string(), std::time::Instant::now()));
+        Err(Error::Wal(e))
+    }
+
+    pub fn wal_degraded(&self) -> Option<String> {
+        self.wal_degraded
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(reason, _)| reason.clone())
+    }
+
+    // While degraded, probes the WAL at most once per WAL_RETRY_INTERVAL and leaves degraded
+    // mode once the log can be opened and flushed again.
+    pub async fn check_wal(&self) -> Result<(), Error> {
+        {
+            let mut degraded = self.wal_degraded.lock().unwrap();
+            match degraded.as_mut() {
+                None => return Ok(()),
+                Some((reason, probed_at)) if probed_at.elapsed() < WAL_RETRY_INTERVAL => {
+                    return Err(Error::ReadOnly(reason.clone()));
                 }
-                _ => return None,
+                Some((_, probed_at)) => *probed_at = std::time::Instant::now(),
             }
         }
 
-        Some(current)
+        match self.storage.flush().await {
+            Ok(()) => {
+                *self.wal_degraded.lock().unwrap() = None;
+                self.logger
+                    .info(&format!(
+                        "{} is writable again, accepting writes",
+                        self.storage.location()
+                    ))
+                    .await;
+                Ok(())
+            }
+            Err(e) => {
+                if let Some((reason, _)) = self.wal_degraded.lock().unwrap().as_mut() {
+                    *reason = e.to_string();
+                }
+                Err(Error::ReadOnly(e.to_string()))
+            }
+        }
     }
 
-    pub async fn remove_row(&self, table: String, key: String) {
-        let entry =
-            crate::logs::LogEntry::new("remove".to_string(), table.clone(), key.clone(), None, 0);
-        self.log_storage.log_entry(entry).await;
+    pub fn set_trigger_policy(&mut self, policy: crate::triggers::RetryPolicy) {
+        self.triggers.set_policy(policy);
+    }
 
-        let mut index = self.index.write().unwrap();
-        if let Some(table_map) = index.get_mut(&table) {
-            table_map.remove(&key);
-        }
+    pub fn triggers(&self) -> &crate::triggers::Triggers {
+        &self.triggers
     }
 
-    pub async fn update_row(&self, table: String, key: String, values: serde_json::Value) {
-        let entry = crate::logs::LogEntry::new(
-            "update".to_string(),
-            table.clone(),
-            key.clone(),
-            Some(values.to_string()),
-            0,
-        );
-        self.log_storage.log_entry(entry).await;
+    pub fn set_idempotency_window(&mut self, window: std::time::Duration) {
+        self.idempotency_window = window;
+    }
 
-        let mut index = self.index.write().unwrap();
-        let table_map = index.entry(table).or_insert_with(HashMap::new);
-        table_map.insert(key, values);
+    pub fn set_role_filter(&self, role: &UserRole, table: String, filter: QueryExpr) {
+        let mut role_filters = self.role_filters.write().unwrap();
+        role_filters.insert((role.to_string(), table), filter);
     }
 
-    pub fn replay_log(&self) -> Result<(), Box<dyn std::error::Error>> {
-        use std::fs::File;
-        use std::io::{BufRead, BufReader};
+    pub fn clear_role_filter(&self, role: &UserRole, table: &str) -> bool {
+        let mut role_filters = self.role_filters.write().unwrap();
+        role_filters
+            .remove(&(role.to_string(), table.to_string()))
+            .is_some()
+    }
 
-        if !self.log_storage.log_file_path.exists() {
-            return Ok(());
-        }
+    pub fn list_role_filters(&self) -> Vec<(String, String, String)> {
+        let role_filters = self.role_filters.read().unwrap();
+        let mut filters: Vec<(String, String, String)> = role_filters
+            .iter()
+            .map(|((role, table), filter)| (role.clone(), table.clone(), filter.to_string()))
+            .collect();
+        filters.sort();
+        filters
+    }
 
-        let file = File::open(&self.log_storage.log_file_path)?;
-        let reader = BufReader::new(file);
+    pub fn role_filter(&self, role: &UserRole, table: &str) -> Option<QueryExpr> {
+        let role_filters = self.role_filters.read().unwrap();
+        role_filters
+            .get(&(role.to_string(), table.to_string()))
+            .cloned()
+    }
 
-        let mut index = self.index.write().unwrap();
+    pub fn set_redaction(&self, table: String, field_path: String, action: RedactionAction) {
+        let mut redactions = self.redactions.write().unwrap();
+        let rules = redactions.entry(table).or_default();
+        rules.retain(|(existing, _)| *existing != field_path);
+        rules.push((field_path, action));
+    }
 
-        for line in reader.lines() {
-            let line = line?;
-            let parts: Vec<&str> = line.split('|').collect();
+    pub fn clear_redaction(&self, table: &str, field_path: &str) -> bool {
+        let mut redactions = self.redactions.write().unwrap();
+        let rules = match redactions.get_mut(table) {
+            Some(rules) => rules,
+            None => return false,
+        };
+        let before = rules.len();
+        rules.retain(|(existing, _)| existing != field_path);
+        before != rules.len()
+    }
 
-            if parts.len() >= 3 {
-                let operation = parts[0];
-                let table = parts[1].to_string();
-                let key = parts[2].to_string();
-                let value = if parts.len() > 3 && !parts[3].is_empty() {
-                    serde_json::from_str(parts[3]).ok()
-                } else {
-                    None
-                };
+    pub fn list_redactions(&self) -> Vec<(String, String, RedactionAction)> {
+        let redactions = self.redactions.read().unwrap();
+        let mut rules: Vec<(String, String, RedactionAction)> = redactions
+            .iter()
+            .flat_map(|(table, rules)| {
+                rules
+                    .iter()
+                    .map(|(field_path, action)| (table.clone(), field_path.clone(), *action))
+            })
+            .collect();
+        rules.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+        rules
+    }
 
-                let table_map = index.entry(table.clone()).or_insert_with(HashMap::new);
+    // Indexes a table on two numeric fields for WITHIN. Returns false if the index exists.
+    pub async fn create_range_index(&self, table: String, fields: [String; 2]) -> bool {
+        let _table_guards = self.lock_tables(std::slice::from_ref(&table)).await;
+        {
+            let mut range_indexes = self.range_indexes.write().unwrap();
+            let indexes = range_indexes.entry(table.clone()).or_default();
+            if indexes.iter().any(|index| index.fields == fields) {
+                return false;
+            }
+            indexes.push(RangeIndex::new(fields));
+        }
+        self.reindex_table(&table);
+        true
+    }
 
-                match operation {
-                    "add" | "update" => {
-                        if let Some(val) = value {
-                            table_map.insert(key, val);
-                        }
+    pub fn drop_range_index(&self, table: &str, fields: &[String; 2]) -> bool {
+        let mut range_indexes = self.range_indexes.write().unwrap();
+        let Some(indexes) = range_indexes.get_mut(table) else {
+            return false;
+        };
+        let before = indexes.len();
+        indexes.retain(|index| index.fields != *fields);
+        let dropped = indexes.len() < before;
+        if indexes.is_empty() {
+            range_indexes.remove(table);
+        }
+        dropped
+    }
+
+    // Each range index as its table, its fields and the number of rows it holds.
+    pub fn list_range_indexes(&self) -> Vec<(String, [String; 2], usize)> {
+        let range_indexes = self.range_indexes.read().unwrap();
+        let mut indexes: Vec<(String, [String; 2], usize)> = range_indexes
+            .iter()
+            .flat_map(|(table, indexes)| {
+                indexes
+                    .iter()
+                    .map(|index| (table.clone(), index.fields.clone(), index.points.len()))
+            })
+            .collect();
+        indexes.sort();
+        indexes
+    }
+
+    // The keys a range index picks out for a WITHIN term the whole filter depends on, with the
+    // index's fields, or None when the filter needs a full scan. Callers still check the filter
+    // against each row, so a row written since the lookup is never returned wrongly.
+    pub fn indexed_keys(
+        &self,
+        table: &str,
+        filter: &QueryExpr,
+    ) -> Option<([String; 2], Vec<String>)> {
+        let terms = match filter {
+            QueryExpr::And(terms) => terms.as_slice(),
+            other => std::slice::from_ref(other),
+        };
+        let range_indexes = self.range_indexes.read().unwrap();
+        let indexes = range_indexes.get(table)?;
+        terms.iter().find_map(|term| {
+            let QueryExpr::Within(within) = term else {
+                return None;
+            };
+            indexes.iter().find_map(|index| {
+                index
+                    .keys_within(within)
+                    .map(|keys| (index.fields.clone(), keys))
+            })
+        })
+    }
+
+    fn index_point(
+        &self,
+        value: &serde_json::Value,
+        fields: &[String; 2],
+    ) -> Option<[IndexNumber; 2]> {
+        let number = |field_path: &String| {
+            self.get_nested_value(value, field_path)
+                .and_then(serde_json::Value::as_f64)
+                .map(IndexNumber::new)
+        };
+        Some([number(&fields[0])?, number(&fields[1])?])
+    }
+
+    // Keeps a table's range indexes in step with one row, given None once the row is gone.
+    // Called with the table's write lock held, so the index never runs behind the table.
+    fn index_row(&self, table: &str, key: &str, value: Option<&serde_json::Value>) {
+        if !self.range_indexes.read().unwrap().contains_key(table) {
+            return;
+        }
+        let mut range_indexes = self.range_indexes.write().unwrap();
+        for index in range_indexes.get_mut(table).into_iter().flatten() {
+            let point = value.and_then(|value| self.index_point(value, &index.fields));
+            index.set(key, point);
+        }
+    }
+
+    // Rebuilds a table's range indexes after its rows were replaced wholesale. Callers hold the
+    // table's lock, so no write lands while the index is rebuilt.
+    fn reindex_table(&self, table: &str) {
+        if !self.range_indexes.read().unwrap().contains_key(table) {
+            return;
+        }
+        let table_map = self.table(table);
+        let table_data = table_map
+            .as_ref()
+            .map(|table_map| table_map.read().unwrap());
+        let mut range_indexes = self.range_indexes.write().unwrap();
+        for index in range_indexes.get_mut(table).into_iter().flatten() {
+            let mut rebuilt = RangeIndex::new(index.fields.clone());
+            for (key, row) in table_data.iter().flat_map(|table_data| table_data.iter()) {
+                rebuilt.set(key, self.index_point(&row.value, &rebuilt.fields));
+            }
+            *index = rebuilt;
+        }
+    }
+
+    fn redactions_for(&self, role: &UserRole, table: &str) -> Vec<(String, RedactionAction)> {
+        if *role == UserRole::Admin {
+            return Vec::new();
+        }
+        let redactions = self.redactions.read().unwrap();
+        redactions.get(table).cloned().unwrap_or_default()
+    }
+
+    fn materialize(
+        &self,
+        mut value: serde_json::Value,
+        redactions: &[(String, RedactionAction)],
+    ) -> serde_json::Value {
+        for (field_path, action) in redactions {
+            let (parent_path, field) = match field_path.rsplit_once('.') {
+                Some((parent_path, field)) => (Some(parent_path), field),
+                None => (None, field_path.as_str()),
+            };
+            let parent = match parent_path {
+                Some(parent_path) => parent_path
+                    .split('.')
+                    .try_fold(&mut value, |current, part| current.get_mut(part)),
+                None => Some(&mut value),
+            };
+            if let Some(serde_json::Value::Object(fields)) = parent {
+                match action {
+                    RedactionAction::Drop => {
+                        fields.remove(field);
+                    }
+                    RedactionAction::Mask => {
+                        if let Some(field_value) = fields.get_mut(field) {
+                            *field_value = serde_json::Value::String(REDACTION_MASK.to_string());
+                        }
+                    }
+                }
+            }
+        }
+        value
+    }
+
+    pub fn get_row_as(&self, role: &UserRole, table: String, key: String) -> Option<Row> {
+        let row = self.get_row(table.clone(), key)?;
+        self.row_as(role, &table, &row)
+    }
+
+    // The row as a role may read it: hidden by its role filter, with its redactions applied.
+    pub fn row_as(&self, role: &UserRole, table: &str, row: &Row) -> Option<Row> {
+        if let Some(filter) = self.role_filter(role, table)
+            && self.matches_expr(table, &row.value, &row.meta, &filter) != Ok(true)
+        {
+            return None;
+        }
+        Some(Row {
+            value: self.materialize(row.value.clone(), &self.redactions_for(role, table)),
+            version: row.version,
+            meta: row.meta,
+        })
+    }
+
+    // Writes act as the role too: a row its filter hides can be neither overwritten nor removed.
+    pub fn check_writable_as(&self, role: &UserRole, table: &str, key: &str) -> Result<(), Error> {
+        match self.get_row(table.to_string(), key.to_string()) {
+            Some(row) if self.row_as(role, table, &row).is_none() => Err(Error::PermissionDenied(
+                format!("Row {} in {} is hidden from role {}", key, table, role),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn query_rows_as(
+        &self,
+        role: &UserRole,
+        table: String,
+        filter: QueryExpr,
+        order: Option<QueryOrder>,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<Vec<(String, serde_json::Value)>, Error> {
+        let role_filter = self.role_filter(role, &table);
+        let redactions = self.redactions_for(role, &table);
+
+        let table_map = match self.table(&table) {
+            Some(table_map) => table_map,
+            None => return Ok(Vec::new()),
+        };
+        let candidates = self.indexed_keys(&table, &filter).map(|(_, keys)| keys);
+        let table_data = table_map.read().unwrap();
+
+        let mut results: Vec<(String, serde_json::Value, RowMeta)> = Vec::new();
+        for (scanned, (key, row)) in candidate_rows(&table_data, candidates).enumerate() {
+            check_deadline(deadline, scanned)?;
+            if role_filter.as_ref().is_some_and(|filter| {
+                self.matches_expr(&table, &row.value, &row.meta, filter) != Ok(true)
+            }) {
+                continue;
+            }
+            let value = self.materialize(row.value.clone(), &redactions);
+            if self.matches_expr(&table, &value, &row.meta, &filter)? {
+                results.push((key.clone(), value, row.meta));
+            }
+        }
+
+        if let Some(order) = order {
+            results.sort_by(|(_, a_value, a_meta), (_, b_value, b_meta)| {
+                let ordering = self.compare_field(
+                    &table,
+                    (a_value, a_meta),
+                    (b_value, b_meta),
+                    &order.field_path,
+                );
+                if order.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            });
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|(key, value, _)| (key, value))
+            .collect())
+    }
+
+    // Hands matching rows to `emit` as they are found, for results too large to hold at once.
+    // Only the keys are copied up front; rows are read a batch at a time without holding the
+    // table while `emit` waits, so a row written mid-stream is seen as the stream reaches it.
+    // ORDER BY needs every match before the first one and falls back to a full query.
+    // Stops early when `emit` returns false. Returns the number of rows emitted.
+    #[allow(clippy::too_many_arguments)]
+    pub fn stream_rows_as(
+        &self,
+        role: &UserRole,
+        table: String,
+        filter: QueryExpr,
+        order: Option<QueryOrder>,
+        limit: Option<usize>,
+        deadline: Option<std::time::Instant>,
+        mut emit: impl FnMut(String, serde_json::Value) -> bool,
+    ) -> Result<usize, Error> {
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut emitted = 0;
+        if order.is_some() {
+            for (key, value) in self.query_rows_as(role, table, filter, order, deadline)? {
+                if emitted == limit || !emit(key, value) {
+                    break;
+                }
+                emitted += 1;
+            }
+            return Ok(emitted);
+        }
+
+        let role_filter = self.role_filter(role, &table);
+        let redactions = self.redactions_for(role, &table);
+        let Some(table_map) = self.table(&table) else {
+            return Ok(0);
+        };
+        let keys: Vec<String> = match self.indexed_keys(&table, &filter) {
+            Some((_, keys)) => keys,
+            None => table_map.read().unwrap().keys().cloned().collect(),
+        };
+
+        let mut scanned = 0;
+        for batch in keys.chunks(DEADLINE_CHECK_INTERVAL) {
+            let mut matches = Vec::new();
+            {
+                let table_data = table_map.read().unwrap();
+                for key in batch {
+                    check_deadline(deadline, scanned)?;
+                    scanned += 1;
+                    let Some(row) = table_data.get(key) else {
+                        continue;
+                    };
+                    if role_filter.as_ref().is_some_and(|filter| {
+                        self.matches_expr(&table, &row.value, &row.meta, filter) != Ok(true)
+                    }) {
+                        continue;
                     }
-                    "remove" => {
-                        table_map.remove(&key);
+                    let value = self.materialize(row.value.clone(), &redactions);
+                    if self.matches_expr(&table, &value, &row.meta, &filter)? {
+                        matches.push((key.clone(), value));
                     }
-                    _ => {}
                 }
             }
+            for (key, value) in matches {
+                if emitted == limit || !emit(key, value) {
+                    return Ok(emitted);
+                }
+                emitted += 1;
+            }
+        }
+        Ok(emitted)
+    }
+
+    pub fn scan_page_as(
+        &self,
+        role: &UserRole,
+        table: String,
+        after: Option<&str>,
+        count: usize,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<ScanPage, Error> {
+        let role_filter = self.role_filter(role, &table);
+        let redactions = self.redactions_for(role, &table);
+
+        let table_map = match self.table(&table) {
+            Some(table_map) => table_map,
+            None => return Ok((Vec::new(), None)),
+        };
+        let table_data = table_map.read().unwrap();
+
+        // Keep the count + 1 smallest keys past the cursor; the extra one says whether to go on.
+        let mut page: std::collections::BinaryHeap<&String> =
+            std::collections::BinaryHeap::with_capacity(count + 1);
+        for (scanned, (key, row)) in table_data.iter().enumerate() {
+            check_deadline(deadline, scanned)?;
+            if after.is_some_and(|after| key.as_str() <= after) {
+                continue;
+            }
+            if page.len() > count && page.peek().is_some_and(|largest| key >= *largest) {
+                continue;
+            }
+            if role_filter.as_ref().is_some_and(|filter| {
+                self.matches_expr(&table, &row.value, &row.meta, filter) != Ok(true)
+            }) {
+                continue;
+            }
+            page.push(key);
+            if page.len() > count + 1 {
+                page.pop();
+            }
+        }
+
+        let has_more = page.len() > count;
+        let mut keys = page.into_sorted_vec();
+        keys.truncate(count);
+        let next_cursor = if has_more {
+            keys.last().map(|key| key.to_string())
+        } else {
+            None
+        };
+
+        let rows = keys
+            .into_iter()
+            .map(|key| {
+                let value = self.materialize(table_data[key].value.clone(), &redactions);
+                (key.clone(), value)
+            })
+            .collect();
+        Ok((rows, next_cursor))
+    }
+
+    pub fn enable_stats_sampling(&mut self, sample_rate: u64, max_rows: u64) {
+        self.stats_sampler = Some(StatsSampler {
+            sample_rate: sample_rate.max(1),
+            max_rows: max_rows.max(1),
+            reads_seen: AtomicU64::new(0),
+            next_id: AtomicU64::new(0),
+        });
+    }
+
+    pub fn sample_read(
+        &self,
+        command: &str,
+        table: &str,
+        shape: &str,
+        latency: std::time::Duration,
+        rows: usize,
+    ) {
+        let sampler = match &self.stats_sampler {
+            Some(sampler) => sampler,
+            None => return,
+        };
+        if table == STATS_TABLE {
+            return;
+        }
+        if sampler.reads_seen.fetch_add(1, Ordering::Relaxed) % sampler.sample_rate != 0 {
+            return;
         }
 
+        let id = sampler.next_id.fetch_add(1, Ordering::Relaxed);
+        let stat = serde_json::json!({
+            "command": command,
+            "table": table,
+            "shape": shape,
+            "latency_us": latency.as_micros() as u64,
+            "rows": rows,
+            "timestamp": chrono::Utc::now().format("%Y-%m-%d %H:%M:%S%.3f UTC").to_string(),
+        });
+
+        let stats_table = self.table_or_create(STATS_TABLE);
+        let mut stats_table = stats_table.write().unwrap();
+        stats_table.insert(
+            format!("{:020}", id),
+            Arc::new(Row {
+                value: stat,
+                version: 0,
+                meta: RowMeta::at(chrono::Utc::now()),
+            }),
+        );
+        if id >= sampler.max_rows {
+            stats_table.remove(&format!("{:020}", id - sampler.max_rows));
+        }
+    }
+
+    pub fn set_shadow_table(&self, table: String, shadow_table: String) -> Result<(), Error> {
+        if table == shadow_table {
+            return Err(Error::InvalidArgument(
+                "A table cannot shadow itself".to_string(),
+            ));
+        }
+
+        let mut shadow_tables = self.shadow_tables.write().unwrap();
+        if shadow_tables.contains_key(&shadow_table) {
+            return Err(Error::Conflict(format!(
+                "Table {} already has a shadow table",
+                shadow_table
+            )));
+        }
+        if shadow_tables.values().any(|shadow| *shadow == table) {
+            return Err(Error::Conflict(format!(
+                "Table {} is already a shadow table",
+                table
+            )));
+        }
+
+        shadow_tables.insert(table, shadow_table);
         Ok(())
     }
+
+    pub fn remove_shadow_table(&self, table: &str) -> Option<String> {
+        let mut shadow_tables = self.shadow_tables.write().unwrap();
+        shadow_tables.remove(table)
+    }
+
+    pub fn list_shadow_tables(&self) -> Vec<(String, String)> {
+        let shadow_tables = self.shadow_tables.read().unwrap();
+        let mut pairs: Vec<(String, String)> = shadow_tables
+            .iter()
+            .map(|(table, shadow)| (table.clone(), shadow.clone()))
+            .collect();
+        pairs.sort();
+        pairs
+    }
+
+    fn write_targets(&self, table: &str) -> Vec<String> {
+        let shadow_tables = self.shadow_tables.read().unwrap();
+        let mut targets = vec![table.to_string()];
+        if let Some(shadow) = shadow_tables.get(table) {
+            targets.push(shadow.clone());
+        }
+        targets
+    }
+
+    fn next_version(&self) -> u64 {
+        self.last_version.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    async fn write_row(
+        &self,
+        operation: &str,
+        table: String,
+        key: String,
+        values: serde_json::Value,
+        options: WriteOptions,
+    ) -> Result<Option<Arc<Row>>, Error> {
+        let targets = self.write_targets(&table);
+        let _key_guard = self.lock_key(&targets, &key).await;
+
+        let fingerprint = (operation.to_string(), table.clone(), key.clone());
+        if let Some(idempotency_key) = &options.idempotency_key {
+            let mut idempotent_writes = self.idempotent_writes.lock().unwrap();
+            idempotent_writes
+                .retain(|_, write| write.recorded_at.elapsed() < self.idempotency_window);
+            if let Some(write) = idempotent_writes.get(idempotency_key) {
+                if write.fingerprint != fingerprint {
+                    return Err(Error::Conflict(format!(
+                        "Idempotency key {} was already used for a different write",
+                        idempotency_key
+                    )));
+                }
+                return write.result.clone();
+            }
+        }
+
+        let result = match self.prepare_write(operation, &targets, table, key, values, &options) {
+            Ok(write) => self.commit_row_write(write).await,
+            Err(e) => Err(e),
+        };
+
+        // A write the WAL did not take is not remembered, so retrying it can still land.
+        if let Some(idempotency_key) = options.idempotency_key
+            && !matches!(result, Err(Error::Wal(_)))
+        {
+            let mut idempotent_writes = self.idempotent_writes.lock().unwrap();
+            idempotent_writes.insert(
+                idempotency_key,
+                IdempotentWrite {
+                    recorded_at: std::time::Instant::now(),
+                    fingerprint,
+                    result: result.clone(),
+                },
+            );
+        }
+        result
+    }
+
+    fn prepare_write(
+        &self,
+        operation: &str,
+        targets: &[String],
+        table: String,
+        key: String,
+        values: serde_json::Value,
+        options: &WriteOptions,
+    ) -> Result<RowWrite, Error> {
+        let previous = self
+            .table(&table)
+            .and_then(|table_map| table_map.read().unwrap().get(&key).cloned());
+        let current_version = previous.as_ref().map_or(0, |row| row.version);
+        if let Some(expected_version) = options.if_version
+            && current_version != expected_version
+        {
+            return Err(Error::Conflict(format!(
+                "Version conflict: expected version {}, current version is {}",
+                expected_version, current_version
+            )));
+        }
+        if current_version == 0 && (operation == "update" || options.if_exists) {
+            return Err(Error::NotFound(format!(
+                "Key {} does not exist in table {}",
+                key, table
+            )));
+        }
+        if current_version != 0 && options.if_absent {
+            return Err(Error::Conflict(format!(
+                "Key {} already exists in table {}",
+                key, table
+            )));
+        }
+
+        let version = self.next_version();
+        let mut entries = Vec::with_capacity(targets.len());
+        let mut rows = Vec::with_capacity(targets.len());
+        for target in targets {
+            let meta = RowMeta::touched(
+                self.get_row(target.clone(), key.clone())
+                    .map(|row| row.meta)
+                    .as_ref(),
+            );
+            entries.push(crate::logs::LogEntry::new(
+                operation.to_string(),
+                target.clone(),
+                key.clone(),
+                Some(values.clone()),
+                version,
+                Some(meta),
+                0,
+            ));
+            rows.push((
+                target.clone(),
+                Some(Arc::new(Row {
+                    value: values.clone(),
+                    version,
+                    meta,
+                })),
+            ));
+        }
+
+        Ok(RowWrite {
+            operation: if operation == "update" {
+                "update"
+            } else {
+                "set"
+            },
+            table,
+            key,
+            previous,
+            entries,
+            rows,
+        })
+    }
+
+    fn prepare_remove(&self, targets: &[String], table: &str, key: &str) -> RowWrite {
+        let previous = self
+            .table(table)
+            .and_then(|table_map| table_map.read().unwrap().get(key).cloned());
+        let entries = targets
+            .iter()
+            .map(|target| {
+                crate::logs::LogEntry::new(
+                    "remove".to_string(),
+                    target.clone(),
+                    key.to_string(),
+                    None,
+                    0,
+                    None,
+                    0,
+                )
+            })
+            .collect();
+
+        RowWrite {
+            operation: "delete",
+            table: table.to_string(),
+            key: key.to_string(),
+            previous,
+            entries,
+            rows: targets
+                .iter()
+                .map(|target| (target.clone(), None))
+                .collect(),
+        }
+    }
+
+    // Logs a single-key write before applying it, so no reader ever sees a write the WAL did not
+    // take. The caller holds the key from prepare_write on.
+    async fn commit_row_write(&self, mut write: RowWrite) -> Result<Option<Arc<Row>>, Error> {
+        let (written, _sequenced) = self.append(std::mem::take(&mut write.entries));
+        self.check_logged(written.wait().await).await?;
+        self.apply_row_write(&write);
+        self.fire_row_write(&write);
+        Ok(write.previous)
+    }
+
+    // Returns the row each target held before.
+    fn apply_row_write(&self, write: &RowWrite) -> Vec<Option<Arc<Row>>> {
+        write
+            .rows
+            .iter()
+            .map(|(target, row)| self.put_row(target, &write.key, row.clone()))
+            .collect()
+    }
+
+    // Stores the row, or removes the key for None, and returns the row it replaced.
+    fn put_row(&self, table: &str, key: &str, row: Option<Arc<Row>>) -> Option<Arc<Row>> {
+        let table_map = match row {
+            Some(_) => self.table_or_create(table),
+            None => self.table(table)?,
+        };
+        let mut table_map = table_map.write().unwrap();
+        self.index_row(table, key, row.as_ref().map(|row| &row.value));
+        match row {
+            Some(row) => table_map.insert(key.to_string(), row),
+            None => table_map.remove(key),
+        }
+    }
+
+    fn fire_row_write(&self, write: &RowWrite) {
+        let value = write.rows.first().and_then(|(_, row)| row.as_ref());
+        if value.is_none() && write.previous.is_none() {
+            return;
+        }
+        self.triggers.fire(
+            write.operation,
+            &write.table,
+            &write.key,
+            write.previous.as_ref().map(|row| &row.value),
+            value.map(|row| &row.value),
+        );
+    }
+
+    pub async fn add_row(
+        &self,
+        table: String,
+        key: String,
+        values: serde_json::Value,
+        options: WriteOptions,
+    ) -> Result<Option<Arc<Row>>, Error> {
+        self.write_row("add", table, key, values, options).await
+    }
+
+    // Writes a row under a key the server generates and returns the key. A retry under the same
+    // idempotency key reuses the first attempt's key, so it is answered like any repeated write.
+    pub async fn add_row_auto(
+        &self,
+        table: String,
+        values: serde_json::Value,
+        mut options: WriteOptions,
+    ) -> Result<String, Error> {
+        let recorded = options
+            .idempotency_key
+            .as_ref()
+            .and_then(|idempotency_key| {
+                let idempotent_writes = self.idempotent_writes.lock().unwrap();
+                let write = idempotent_writes.get(idempotency_key)?;
+                let (operation, recorded_table, key) = &write.fingerprint;
+                (write.recorded_at.elapsed() < self.idempotency_window
+                    && operation == "add"
+                    && *recorded_table == table)
+                    .then(|| key.clone())
+            });
+        let key = recorded.unwrap_or_else(|| self.generate_key());
+        options.if_absent = true;
+        self.write_row("add", table, key.clone(), values, options)
+            .await?;
+        Ok(key)
+    }
+
+    // A UUIDv7: 48 bits of Unix milliseconds, then a 12-bit sequence that counts keys made within
+    // one millisecond, then 62 random bits. Keys from one server sort in the order they were
+    // made, even if the clock steps back.
+    pub fn generate_key(&self) -> String {
+        use rand_core::RngCore;
+
+        let now = chrono::Utc::now().timestamp_millis().max(0) as u64;
+        let (millis, sequence) = {
+            let mut clock = self.key_clock.lock().unwrap();
+            let (last, sequence) = *clock;
+            *clock = if now > last {
+                (now, 0)
+            } else if sequence < 0xfff {
+                (last, sequence + 1)
+            } else {
+                (last + 1, 0)
+            };
+            *clock
+        };
+
+        let high = ((millis & 0xffff_ffff_ffff) << 16) | 0x7000 | sequence as u64;
+        let low = (rand_core::OsRng.next_u64() >> 2) | (0b10 << 62);
+        format!(
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            high >> 32,
+            (high >> 16) & 0xffff,
+            high & 0xffff,
+            low >> 48,
+            low & 0xffff_ffff_ffff
+        )
+    }
+
+    pub fn get_row(&self, table: String, key: String) -> Option<Row> {
+        let table_map = self.table(&table)?;
+        table_map
+            .read()
+            .unwrap()
+            .get(&key)
+            .map(|row| Row::clone(row))
+    }
+
+    // Rows matching as `role` sees them, with their stored values: rows its role filter hides
+    // are left out, and `filter` is evaluated against the redacted document.
+    pub fn query_rows(
+        &self,
+        role: &UserRole,
+        table: String,
+        filter: QueryExpr,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<Vec<(String, serde_json::Value)>, Error> {
+        let role_filter = self.role_filter(role, &table);
+        let redactions = self.redactions_for(role, &table);
+        let table_map = match self.table(&table) {
+            Some(table_map) => table_map,
+            None => return Ok(Vec::new()),
+        };
+        let candidates = self.indexed_keys(&table, &filter).map(|(_, keys)| keys);
+        let table_data = table_map.read().unwrap();
+
+        let mut results = Vec::new();
+        for (scanned, (key, row)) in candidate_rows(&table_data, candidates).enumerate() {
+            check_deadline(deadline, scanned)?;
+            if role_filter.as_ref().is_some_and(|role_filter| {
+                self.matches_expr(&table, &row.value, &row.meta, role_filter) != Ok(true)
+            }) {
+                continue;
+            }
+            let matched = if redactions.is_empty() {
+                self.matches_expr(&table, &row.value, &row.meta, &filter)?
+            } else {
+                let value = self.materialize(row.value.clone(), &redactions);
+                self.matches_expr(&table, &value, &row.meta, &filter)?
+            };
+            if matched {
+                results.push((key.clone(), row.value.clone()));
+            }
+        }
+        Ok(results)
+    }
+
+    // The rows a bulk write by `role` acts on. Rows its role filter hides are left alone.
+    async fn scan_rows(
+        self: &Arc<Self>,
+        role: &UserRole,
+        table: String,
+        filter: QueryExpr,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<Vec<(String, serde_json::Value)>, Error> {
+        let engine = self.clone();
+        let role = role.clone();
+        tokio::task::spawn_blocking(move || engine.query_rows(&role, table, filter, deadline))
+            .await?
+    }
+
+    pub fn set_expiry(&self, table: String, age_field: String, max_age: chrono::Duration) {
+        self.expiry
+            .write()
+            .unwrap()
+            .insert(table, (age_field, max_age));
+    }
+
+    // When the table's retention policy will delete the row: its age field plus max_age. None for
+    // rows that never expire, including those without a readable age field.
+    fn expires_at(
+        &self,
+        table: &str,
+        value: &serde_json::Value,
+        meta: &RowMeta,
+    ) -> Option<chrono::DateTime<chrono::Utc>> {
+        if meta.persist {
+            return None;
+        }
+        let expiry = self.expiry.read().unwrap();
+        let (age_field, max_age) = expiry.get(table)?;
+        let age = meta.field(age_field).or_else(|| {
+            self.get_nested_value(value, age_field)
+                .and_then(parse_timestamp)
+        })?;
+        age.checked_add_signed(*max_age)
+    }
+
+    // The timestamps a row carries outside its JSON, readable by name in conditions and ORDER BY.
+    fn meta_field(
+        &self,
+        table: &str,
+        value: &serde_json::Value,
+        meta: &RowMeta,
+        field_path: &str,
+    ) -> Option<chrono::DateTime<chrono::Utc>> {
+        match field_path {
+            EXPIRES_AT_FIELD => self.expires_at(table, value, meta),
+            _ => meta.field(field_path),
+        }
+    }
+
+    fn matches_expr(
+        &self,
+        table: &str,
+        value: &serde_json::Value,
+        meta: &RowMeta,
+        expr: &QueryExpr,
+    ) -> Result<bool, Error> {
+        match expr {
+            QueryExpr::Condition(condition) => {
+                match self.meta_field(table, value, meta, &condition.field_path) {
+                    Some(timestamp) => self.matches_timestamp(timestamp, condition),
+                    None => self.matches_condition(value, condition),
+                }
+            }
+            QueryExpr::Within(within) => {
+                Ok(self
+                    .index_point(value, &within.fields)
+                    .is_some_and(|point| {
+                        within
+                            .bounds
+                            .iter()
+                            .zip(point)
+                            .all(|((min, max), number)| *min <= number.0 && number.0 <= *max)
+                    }))
+            }
+            QueryExpr::Not(inner) => Ok(!self.matches_expr(table, value, meta, inner)?),
+            QueryExpr::And(terms) => {
+                for term in terms {
+                    if !self.matches_expr(table, value, meta, term)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            QueryExpr::Or(terms) => {
+                for term in terms {
+                    if self.matches_expr(table, value, meta, term)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+        }
+    }
+
+    fn matches_timestamp(
+        &self,
+        timestamp: chrono::DateTime<chrono::Utc>,
+        condition: &QueryCondition,
+    ) -> Result<bool, Error> {
+        if !condition.operator.is_comparison() {
+            return Err(Error::InvalidArgument(format!(
+                "{} cannot be used with {}",
+                condition.operator.symbol(),
+                condition.field_path
+            )));
+        }
+        let expected = parse_timestamp(&condition.value).ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "{} must be compared with a timestamp, got {}",
+                condition.field_path, condition.value
+            ))
+        })?;
+
+        Ok(match condition.operator {
+            QueryOperator::Equals => timestamp == expected,
+            QueryOperator::NotEquals => timestamp != expected,
+            QueryOperator::GreaterThan => timestamp > expected,
+            QueryOperator::LessThan => timestamp < expected,
+            QueryOperator::GreaterThanOrEqual => timestamp >= expected,
+            _ => timestamp <= expected,
+        })
+    }
+
+    fn compare_field(
+        &self,
+        table: &str,
+        (a_value, a_meta): (&serde_json::Value, &RowMeta),
+        (b_value, b_meta): (&serde_json::Value, &RowMeta),
+        field_path: &str,
+    ) -> std::cmp::Ordering {
+        use std::cmp::Ordering;
+
+        let a = self.meta_field(table, a_value, a_meta, field_path);
+        let b = self.meta_field(table, b_value, b_meta, field_path);
+        match (a, b) {
+            (Some(a), Some(b)) => return a.cmp(&b),
+            // Rows that never expire sort last, as rows missing a field do.
+            _ if field_path == EXPIRES_AT_FIELD => return a.is_none().cmp(&b.is_none()),
+            _ => {}
+        }
+
+        match (
+            self.get_nested_value(a_value, field_path),
+            self.get_nested_value(b_value, field_path),
+        ) {
+            (Some(a), Some(b)) => {
+                compare_values(a, b).unwrap_or_else(|| type_rank(a).cmp(&type_rank(b)))
+            }
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            _ => Ordering::Equal,
+        }
+    }
+
+    fn matches_condition(
+        &self,
+        value: &serde_json::Value,
+        condition: &QueryCondition,
+    ) -> Result<bool, Error> {
+        let field_value = match self.get_nested_value(value, &condition.field_path) {
+            Some(serde_json::Value::Null) if condition.value != serde_json::Value::Null => {
+                return Ok(condition.operator == QueryOperator::NotEquals);
+            }
+            Some(field_value) => field_value,
+            None => return Ok(false),
+        };
+        let expected = &condition.value;
+
+        match condition.operator {
+            QueryOperator::Equals => Ok(values_equal(field_value, expected)),
+            QueryOperator::NotEquals => Ok(!values_equal(field_value, expected)),
+            QueryOperator::Contains => match (field_value, expected) {
+                (serde_json::Value::String(field_str), serde_json::Value::String(expected_str)) => {
+                    Ok(field_str.contains(expected_str.as_str()))
+                }
+                (serde_json::Value::String(field_str), _) => {
+                    Ok(field_str.contains(text_of(expected).as_str()))
+                }
+                (serde_json::Value::Array(items), _) => {
+                    Ok(items.iter().any(|item| values_equal(item, expected)))
+                }
+                _ => Err(Error::InvalidArgument(format!(
+                    "contains needs a string or array field, but {} is {}",
+                    condition.field_path,
+                    type_name(field_value)
+                ))),
+            },
+            QueryOperator::IContains => match field_value {
+                serde_json::Value::String(field_str) => Ok(field_str
+                    .to_lowercase()
+                    .contains(&text_of(expected).to_lowercase())),
+                serde_json::Value::Array(items) => Ok(items.iter().any(|item| {
+                    item.as_str()
+                        .is_some_and(|item| item.to_lowercase() == text_of(expected).to_lowercase())
+                })),
+                _ => Err(Error::InvalidArgument(format!(
+                    "icontains needs a string or array field, but {} is {}",
+                    condition.field_path,
+                    type_name(field_value)
+                ))),
+            },
+            QueryOperator::StartsWith | QueryOperator::EndsWith | QueryOperator::Regex => {
+                let field_str = field_value.as_str().ok_or_else(|| {
+                    Error::InvalidArgument(format!(
+                        "{} needs a string field, but {} is {}",
+                        condition.operator.symbol(),
+                        condition.field_path,
+                        type_name(field_value)
+                    ))
+                })?;
+                Ok(match condition.operator {
+                    QueryOperator::StartsWith => field_str.starts_with(text_of(expected).as_str()),
+                    QueryOperator::EndsWith => field_str.ends_with(text_of(expected).as_str()),
+                    _ => condition
+                        .pattern
+                        .as_ref()
+                        .is_some_and(|pattern| pattern.is_match(field_str)),
+                })
+            }
+            QueryOperator::GreaterThan
+            | QueryOperator::LessThan
+            | QueryOperator::GreaterThanOrEqual
+            | QueryOperator::LessThanOrEqual => {
+                let ordering = compare_values(field_value, expected).ok_or_else(|| {
+                    Error::InvalidArgument(format!(
+                        "Cannot compare {} ({}) with {} ({}) using {}",
+                        condition.field_path,
+                        type_name(field_value),
+                        expected,
+                        type_name(expected),
+                        condition.operator.symbol()
+                    ))
+                })?;
+                Ok(match condition.operator {
+                    QueryOperator::GreaterThan => ordering.is_gt(),
+                    QueryOperator::LessThan => ordering.is_lt(),
+                    QueryOperator::GreaterThanOrEqual => ordering.is_ge(),
+                    _ => ordering.is_le(),
+                })
+            }
+        }
+    }
+
+    pub fn get_nested_value<'a>(
+        &self,
+        value: &'a serde_json::Value,
+        field_path: &str,
+    ) -> Option<&'a serde_json::Value> {
+        let path_parts: Vec<&str> = field_path.split('.').collect();
+        let mut current = value;
+
+        for part in path_parts {
+            let (name, indexes) = match split_array_indexes(part) {
+                Some(split) => split,
+                None => (part, Vec::new()),
+            };
+            if !name.is_empty() || indexes.is_empty() {
+                match current {
+                    serde_json::Value::Object(obj) => {
+                        current = obj.get(name)?;
+                    }
+                    _ => return None,
+                }
+            }
+            for index in indexes {
+                current = current.as_array()?.get(index)?;
+            }
+        }
+
+        Some(current)
+    }
+
+    // Returns the row that was removed, if there was one.
+    pub async fn remove_row(&self, table: String, key: String) -> Result<Option<Arc<Row>>, Error> {
+        let targets = self.write_targets(&table);
+        let _key_guard = self.lock_key(&targets, &key).await;
+        let write = self.prepare_remove(&targets, &table, &key);
+        self.commit_row_write(write).await
+    }
+
+    // Holds every table an EVAL script declared (and their shadow tables) for the whole script,
+    // so its reads and writes are never interleaved with anyone else's writes.
+    pub async fn lock_for_script(&self, tables: &[String]) -> TableGuards {
+        let targets: Vec<String> = tables
+            .iter()
+            .flat_map(|table| self.write_targets(table))
+            .collect();
+        self.lock_tables(&targets).await
+    }
+
+    // Script writes run under lock_for_script, so they skip locking. They are applied at once so
+    // the script reads them back, and commit_script undoes any the WAL then does not take.
+    pub fn set_locked(
+        &self,
+        table: String,
+        key: String,
+        values: serde_json::Value,
+    ) -> Result<(Option<Arc<Row>>, ScriptWrite), Error> {
+        let targets = self.write_targets(&table);
+        let write = self.prepare_write(
+            "add",
+            &targets,
+            table,
+            key,
+            values,
+            &WriteOptions::default(),
+        )?;
+        Ok(self.apply_locked(write))
+    }
+
+    pub fn remove_locked(&self, table: &str, key: &str) -> (Option<Arc<Row>>, ScriptWrite) {
+        let write = self.prepare_remove(&self.write_targets(table), table, key);
+        self.apply_locked(write)
+    }
+
+    fn apply_locked(&self, mut write: RowWrite) -> (Option<Arc<Row>>, ScriptWrite) {
+        let (written, sequenced) = self.append(std::mem::take(&mut write.entries));
+        let replaced = self.apply_row_write(&write);
+        (
+            write.previous.clone(),
+            ScriptWrite {
+                write,
+                replaced,
+                written,
+                _sequenced: sequenced,
+            },
+        )
+    }
+
+    // Called while the script still holds its tables. Writes the WAL did not take are undone
+    // latest first, giving each row back what the write replaced, unless a later write that
+    // was logged has replaced it since.
+    pub async fn commit_script(&self, writes: Vec<ScriptWrite>) -> Result<(), Error> {
+        let mut logged = Vec::with_capacity(writes.len());
+        for write in writes {
+            let result = write.written.wait().await;
+            logged.push((write.write, write.replaced, result));
+        }
+
+        let mut settled = std::collections::HashSet::new();
+        let mut failed = None;
+        for (write, replaced, result) in logged.iter().rev() {
+            for ((target, _), replaced) in write.rows.iter().zip(replaced) {
+                let row = (target.as_str(), write.key.as_str());
+                if result.is_ok() {
+                    settled.insert(row);
+                } else if !settled.contains(&row) {
+                    self.put_row(target, &write.key, replaced.clone());
+                }
+            }
+            if let Err(e) = result {
+                failed = Some(e.clone());
+            }
+        }
+        for (write, _, result) in &logged {
+            if result.is_ok() {
+                self.fire_row_write(write);
+            }
+        }
+        match failed {
+            Some(e) => self.check_logged(Err(e)).await,
+            None => Ok(()),
+        }
+    }
+
+    // `writable` vets each matching key before anything is written; one refusal fails the whole
+    // write.
+    pub async fn remove_rows_where(
+        self: &Arc<Self>,
+        role: &UserRole,
+        table: String,
+        filter: QueryExpr,
+        writable: &(dyn Fn(&str) -> Result<(), Error> + Sync),
+        deadline: Option<std::time::Instant>,
+    ) -> Result<usize, Error> {
+        let targets = self.write_targets(&table);
+        let _table_guards = self.lock_tables(&targets).await;
+
+        let rows = self
+            .scan_rows(role, table.clone(), filter, deadline)
+            .await?;
+        for (key, _) in &rows {
+            writable(key)?;
+        }
+        let (keys, previous): (Vec<String>, Vec<serde_json::Value>) = rows.into_iter().unzip();
+
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        self.remove_keys(&targets, &keys).await?;
+        for (key, old) in keys.iter().zip(&previous) {
+            self.triggers.fire("delete", &table, key, Some(old), None);
+        }
+        Ok(keys.len())
+    }
+
+    // Deletes rows whose `age_field` is older than `cutoff`, then the oldest rows beyond
+    // `max_rows`. A row without a readable timestamp in `age_field` never counts as expired.
+    pub async fn apply_retention(
+        &self,
+        table: &str,
+        age_field: &str,
+        cutoff: Option<chrono::DateTime<chrono::Utc>>,
+        max_rows: Option<usize>,
+    ) -> Result<(usize, usize), Error> {
+        let targets = self.write_targets(table);
+        let _table_guards = self.lock_tables(&targets).await;
+
+        let mut rows: Vec<(String, Arc<Row>)> = self.iter_table(table).collect();
+        let mut expired = Vec::new();
+        if let Some(cutoff) = cutoff {
+            rows.retain(|(key, row)| {
+                let stale = !row.meta.persist
+                    && row
+                        .meta
+                        .field(age_field)
+                        .or_else(|| {
+                            self.get_nested_value(&row.value, age_field)
+                                .and_then(parse_timestamp)
+                        })
+                        .is_some_and(|timestamp| timestamp < cutoff);
+                if stale {
+                    expired.push(key.clone());
+                }
+                !stale
+            });
+        }
+
+        let mut excess = Vec::new();
+        if let Some(max_rows) = max_rows
+            && rows.len() > max_rows
+        {
+            rows.sort_by(|a, b| {
+                a.1.meta
+                    .created_at
+                    .cmp(&b.1.meta.created_at)
+                    .then_with(|| a.0.cmp(&b.0))
+            });
+            let over = rows.len() - max_rows;
+            excess = rows.drain(..over).map(|(key, _)| key).collect();
+        }
+
+        let removed: Vec<String> = expired.iter().chain(&excess).cloned().collect();
+        if !removed.is_empty() {
+            self.remove_keys(&targets, &removed).await?;
+        }
+        Ok((expired.len(), excess.len()))
+    }
+
+    async fn remove_keys(&self, targets: &[String], keys: &[String]) -> Result<(), Error> {
+        for target in targets {
+            let entries = keys
+                .iter()
+                .map(|key| {
+                    crate::logs::LogEntry::new(
+                        "remove".to_string(),
+                        target.clone(),
+                        key.clone(),
+                        None,
+                        0,
+                        None,
+                        0,
+                    )
+                })
+                .collect();
+            let _logged = self.log_entries(entries).await?;
+
+            if let Some(table_map) = self.table(target) {
+                let mut table_map = table_map.write().unwrap();
+                for key in keys {
+                    table_map.remove(key);
+                    self.index_row(target, key, None);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Vets keys with `writable` as remove_rows_where does.
+    pub async fn update_rows_where(
+        self: &Arc<Self>,
+        role: &UserRole,
+        table: String,
+        filter: QueryExpr,
+        patch: serde_json::Value,
+        writable: &(dyn Fn(&str) -> Result<(), Error> + Sync),
+        deadline: Option<std::time::Instant>,
+    ) -> Result<usize, Error> {
+        let targets = self.write_targets(&table);
+        let _table_guards = self.lock_tables(&targets).await;
+
+        let rows = self
+            .scan_rows(role, table.clone(), filter, deadline)
+            .await?;
+        for (key, _) in &rows {
+            writable(key)?;
+        }
+        let mut previous = Vec::new();
+        let updated: Vec<(String, Row)> = rows
+            .into_iter()
+            .map(|(key, mut value)| {
+                previous.push(value.clone());
+                merge_patch(&mut value, &patch);
+                let version = self.next_version();
+                let meta = RowMeta::touched(
+                    self.get_row(table.clone(), key.clone())
+                        .map(|row| row.meta)
+                        .as_ref(),
+                );
+                (
+                    key,
+                    Row {
+                        value,
+                        version,
+                        meta,
+                    },
+                )
+            })
+            .collect();
+
+        if updated.is_empty() {
+            return Ok(0);
+        }
+
+        for target in targets {
+            let entries = updated
+                .iter()
+                .map(|(key, row)| {
+                    crate::logs::LogEntry::new(
+                        "update".to_string(),
+                        target.clone(),
+                        key.clone(),
+                        Some(row.value.clone()),
+                        row.version,
+                        Some(row.meta),
+                        0,
+                    )
+                })
+                .collect();
+            let _logged = self.log_entries(entries).await?;
+
+            let table_map = self.table_or_create(&target);
+            let mut table_map = table_map.write().unwrap();
+            for (key, row) in &updated {
+                table_map.insert(key.clone(), Arc::new(row.clone()));
+                self.index_row(&target, key, Some(&row.value));
+            }
+        }
+        for ((key, row), old) in updated.iter().zip(&previous) {
+            self.triggers
+                .fire("update", &table, key, Some(old), Some(&row.value));
+        }
+
+        Ok(updated.len())
+    }
+
+    // Exempts a row from its table's retention max_age until it is next written. The row keeps
+    // its value and timestamps under a new version.
+    pub async fn persist_row(&self, table: String, key: String) -> Result<(), Error> {
+        let targets = self.write_targets(&table);
+        let _table_guards = self.lock_tables(&targets).await;
+
+        let row = self
+            .get_row(table.clone(), key.clone())
+            .ok_or_else(|| Error::NotFound(format!("Key {} not found in {}", key, table)))?;
+        if row.meta.persist {
+            return Ok(());
+        }
+        let row = Row {
+            value: row.value,
+            version: self.next_version(),
+            meta: RowMeta {
+                persist: true,
+                ..row.meta
+            },
+        };
+
+        let entries = targets
+            .iter()
+            .map(|target| {
+                crate::logs::LogEntry::new(
+                    "update".to_string(),
+                    target.clone(),
+                    key.clone(),
+                    Some(row.value.clone()),
+                    row.version,
+                    Some(row.meta),
+                    0,
+                )
+            })
+            .collect();
+        let _logged = self.log_entries(entries).await?;
+
+        for target in &targets {
+            let table_map = self.table_or_create(target);
+            let mut table_map = table_map.write().unwrap();
+            table_map.insert(key.clone(), Arc::new(row.clone()));
+            self.index_row(target, &key, Some(&row.value));
+        }
+        Ok(())
+    }
+
+    pub async fn update_row(
+        &self,
+        table: String,
+        key: String,
+        values: serde_json::Value,
+        options: WriteOptions,
+    ) -> Result<Option<Arc<Row>>, Error> {
+        self.write_row("update", table, key, values, options).await
+    }
+
+    pub async fn replay_log(&self) -> Result<(), Error> {
+        let started = std::time::Instant::now();
+        let Some(replay) = self.storage.replay()? else {
+            self.logger
+                .info(&format!(
+                    "Nothing to replay from {}, starting empty",
+                    self.storage.location()
+                ))
+                .await;
+            return Ok(());
+        };
+        self.logger
+            .info(&format!(
+                "Replaying {} ({} bytes)",
+                self.storage.location(),
+                replay.bytes
+            ))
+            .await;
+        if replay.truncated > 0 {
+            self.logger
+                .warning(&format!(
+                    "Dropped {} bytes of an incomplete entry from the end of {}",
+                    replay.truncated,
+                    self.storage.location()
+                ))
+                .await;
+        }
+        // A sequence number at or below one already replayed marks an entry written twice, such
+        // as an archive segment restored again, and replaying it again would undo later writes.
+        let mut last_seq = 0;
+        let mut duplicates = 0;
+        let entries: Vec<crate::logs::LogEntry> = replay
+            .entries
+            .into_iter()
+            .filter(|entry| {
+                if entry.seq == 0 || entry.seq > last_seq {
+                    last_seq = last_seq.max(entry.seq);
+                    true
+                } else {
+                    duplicates += 1;
+                    false
+                }
+            })
+            .collect();
+        if duplicates > 0 {
+            self.logger
+                .info(&format!(
+                    "Skipped {} log entries already replayed",
+                    duplicates
+                ))
+                .await;
+        }
+        let entry_count = entries.len();
+        let legacy_meta = RowMeta::at(replay.modified);
+
+        let index = rebuild_tables(entries, legacy_meta, |entry| {
+            if entry.version == 0 {
+                self.next_version()
+            } else {
+                self.last_version.fetch_max(entry.version, Ordering::SeqCst);
+                entry.version
+            }
+        });
+
+        let mut replayed = Vec::with_capacity(index.len());
+        {
+            let mut tables = self.index.write().unwrap();
+            for (table, table_map) in index {
+                let table_map: HashMap<String, Arc<Row>> = table_map
+                    .into_iter()
+                    .map(|(key, row)| (key, Arc::new(row)))
+                    .collect();
+                replayed.push((table.clone(), table_map.len()));
+                tables.insert(table, Arc::new(RwLock::new(table_map)));
+            }
+        }
+        {
+            let mut sequences = self.sequences.state.lock().unwrap();
+            sequences.issued = self.storage.last_sequence();
+            sequences.applied = sequences.issued;
+        }
+
+        for (table, rows) in &replayed {
+            self.reindex_table(table);
+            self.logger
+                .debug(&format!("Replayed table {} with {} rows", table, rows))
+                .await;
+        }
+        self.logger
+            .info(&format!(
+                "Replayed {} log entries into {} tables in {} ms",
+                entry_count,
+                replayed.len(),
+                started.elapsed().as_millis()
+            ))
+            .await;
+        Ok(())
+    }
+
+    // Every entry in the log once pending writes have landed, with the log's size in bytes.
+    pub async fn wal_entries(&self) -> Result<(Vec<crate::logs::LogEntry>, u64), Error> {
+        let bytes = self.storage.snapshot().await?;
+        tokio::task::spawn_blocking(move || {
+            let entries = crate::logs::LogEntry::decode_all(&bytes).map_err(Error::Parse)?;
+            Ok((entries, bytes.len() as u64))
+        })
+        .await?
+    }
+
+    pub async fn check_consistency(self: &Arc<Self>) -> Result<ConsistencyReport, Error> {
+        let table_names: Vec<String> = self.index.read().unwrap().keys().cloned().collect();
+        let table_guards = self.lock_tables(&table_names).await;
+
+        // Every write to these tables has been submitted; the snapshot waits for them to land.
+        let bytes = self.storage.snapshot().await?;
+        let snapshot: HashMap<String, HashMap<String, Arc<Row>>> = table_names
+            .iter()
+            .filter(|name| self.table(name).is_some())
+            .map(|name| (name.clone(), self.iter_table(name).collect()))
+            .collect();
+        drop(table_guards);
+
+        let engine = self.clone();
+        tokio::task::spawn_blocking(move || engine.compare_with_log(&bytes, snapshot)).await?
+    }
+
+    fn compare_with_log(
+        &self,
+        bytes: &[u8],
+        snapshot: HashMap<String, HashMap<String, Arc<Row>>>,
+    ) -> Result<ConsistencyReport, Error> {
+        let entries = crate::logs::LogEntry::decode_all(bytes).map_err(Error::Parse)?;
+        let checksum_failures = entries
+            .iter()
+            .filter(|entry| entry.verify_checksum() == Some(false))
+            .count();
+        // Legacy entries carry no version or timestamps, so those rows only compare by value.
+        let replayed = rebuild_tables(entries, RowMeta::at(chrono::Utc::now()), |entry| {
+            entry.version
+        });
+
+        let mut report = ConsistencyReport {
+            tables: snapshot.len(),
+            rows: snapshot.values().map(HashMap::len).sum(),
+            checksum_failures,
+            discrepancies: Vec::new(),
+        };
+        let last_version = self.last_version.load(Ordering::SeqCst);
+
+        let mut table_names: Vec<&String> = snapshot.keys().chain(replayed.keys()).collect();
+        table_names.sort();
+        table_names.dedup();
+        let (empty_live, empty_logged) = (HashMap::new(), HashMap::new());
+        for table in table_names {
+            let live = match snapshot.get(table) {
+                Some(live) => live,
+                // Created after the snapshot was taken, so the log is ahead of it.
+                None if self.table(table).is_some() => continue,
+                None => &empty_live,
+            };
+            let logged = replayed.get(table).unwrap_or(&empty_logged);
+
+            let mut keys: Vec<&String> = live.keys().chain(logged.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let problem = match (live.get(key), logged.get(key)) {
+                    (Some(_), None) => Some("in memory but not in the log".to_string()),
+                    (None, Some(_)) => Some("in the log but not in memory".to_string()),
+                    (Some(row), Some(logged_row)) => {
+                        if row.value != logged_row.value {
+                            Some("value differs from the log".to_string())
+                        } else if logged_row.version != 0 && row.version != logged_row.version {
+                            Some(format!(
+                                "version {} differs from logged version {}",
+                                row.version, logged_row.version
+                            ))
+                        } else if logged_row.version != 0 && row.meta != logged_row.meta {
+                            Some("timestamps differ from the log".to_string())
+                        } else if row.version > last_version {
+                            Some(format!(
+                                "version {} is ahead of the version counter {}",
+                                row.version, last_version
+                            ))
+                        } else {
+                            None
+                        }
+                    }
+                    (None, None) => None,
+                };
+                if let Some(problem) = problem {
+                    report
+                        .discrepancies
+                        .push(format!("{}.{}: {}", table, key, problem));
+                }
+            }
+        }
+
+        Ok(report)
+    }
+
+    pub async fn import_table(
+        &self,
+        table: String,
+        path: &std::path::Path,
+        format: crate::import::ImportFormat,
+    ) -> Result<usize, Error> {
+        let contents = tokio::fs::read(path)
+            .await
+            .map_err(|e| Error::Io(format!("{}: {}", path.display(), e)))?;
+        let rows = match format {
+            crate::import::ImportFormat::Redis => crate::import::parse_redis_dump(path, &contents)?,
+            format => {
+                let contents = String::from_utf8(contents).map_err(|_| {
+                    Error::Parse(format!("{}: file is not valid UTF-8", path.display()))
+                })?;
+                if format == crate::import::ImportFormat::Mongo {
+                    crate::import::parse_mongo_export(path, &contents)?
+                } else {
+                    parse_rows_file(path, &contents)?
+                }
+            }
+        };
+
+        let _table_guards = self.lock_tables(std::slice::from_ref(&table)).await;
+
+        let meta = RowMeta::at(chrono::Utc::now());
+        let mut table_map = HashMap::with_capacity(rows.len());
+        let mut entries = vec![crate::logs::LogEntry::new(
+            "truncate".to_string(),
+            table.clone(),
+            String::new(),
+            None,
+            0,
+            None,
+            0,
+        )];
+        for (key, value) in rows {
+            let version = self.next_version();
+            entries.push(crate::logs::LogEntry::new(
+                "add".to_string(),
+                table.clone(),
+                key.clone(),
+                Some(value.clone()),
+                version,
+                Some(meta),
+                0,
+            ));
+            table_map.insert(
+                key,
+                Arc::new(Row {
+                    value,
+                    version,
+                    meta,
+                }),
+            );
+        }
+        let _logged = self.log_entries(entries).await?;
+
+        let count = table_map.len();
+        self.index
+            .write()
+            .unwrap()
+            .insert(table.clone(), Arc::new(RwLock::new(table_map)));
+        self.reindex_table(&table);
+        Ok(count)
+    }
+
+    // Entries at or below `after` were restored on an earlier pass, so restoring the same
+    // segment twice changes nothing.
+    pub async fn apply_log_entries(
+        &self,
+        entries: Vec<crate::logs::LogEntry>,
+        after: u64,
+    ) -> Result<usize, Error> {
+        let mut tables: Vec<String> = entries.iter().map(|entry| entry.table.clone()).collect();
+        tables.extend(
+            entries
+                .iter()
+                .filter(|entry| entry.operation == "swap" || entry.operation == "rename")
+                .map(|entry| entry.key.clone()),
+        );
+        let _table_guards = self.lock_tables(&tables).await;
+
+        let restored_meta = RowMeta::at(chrono::Utc::now());
+        let mut last_seq = after;
+        let mut accepted = Vec::with_capacity(entries.len());
+        for mut entry in entries {
+            if entry.seq != 0 {
+                if entry.seq <= last_seq {
+                    continue;
+                }
+                last_seq = entry.seq;
+            }
+            match entry.operation.as_str() {
+                "add" | "update" => {
+                    if entry.value.is_none() {
+                        continue;
+                    }
+                    entry.meta.get_or_insert(restored_meta);
+                }
+                "remove" | "truncate" | "drop" | "swap" | "rename" | "checkpoint" => {}
+                _ => continue,
+            }
+            entry.version = if entry.version == 0 {
+                self.next_version()
+            } else {
+                self.last_version.fetch_max(entry.version, Ordering::SeqCst);
+                entry.version
+            };
+            entry.checksum = None;
+            accepted.push(entry);
+        }
+
+        // Logged before memory changes, like any other write: entries the local log refused are
+        // neither visible nor lost on a restart, and the caller restores them again.
+        let _logged = self.log_entries(accepted.clone()).await?;
+
+        let mut applied = 0;
+        for entry in accepted {
+            match entry.operation.as_str() {
+                "add" | "update" => {
+                    let (Some(value), Some(meta)) = (entry.value, entry.meta) else {
+                        continue;
+                    };
+                    let table_map = self.table_or_create(&entry.table);
+                    let mut table_map = table_map.write().unwrap();
+                    self.index_row(&entry.table, &entry.key, Some(&value));
+                    table_map.insert(
+                        entry.key,
+                        Arc::new(Row {
+                            value,
+                            version: entry.version,
+                            meta,
+                        }),
+                    );
+                }
+                "remove" => {
+                    if let Some(table_map) = self.table(&entry.table) {
+                        let mut table_map = table_map.write().unwrap();
+                        table_map.remove(&entry.key);
+                        self.index_row(&entry.table, &entry.key, None);
+                    }
+                }
+                "truncate" | "drop" => {
+                    self.index.write().unwrap().remove(&entry.table);
+                    self.reindex_table(&entry.table);
+                }
+                "swap" => {
+                    {
+                        let mut index = self.index.write().unwrap();
+                        let table_map = index.remove(&entry.table).unwrap_or_default();
+                        let other_map = index.remove(&entry.key).unwrap_or_default();
+                        index.insert(entry.table.clone(), other_map);
+                        index.insert(entry.key.clone(), table_map);
+                    }
+                    self.reindex_table(&entry.table);
+                    self.reindex_table(&entry.key);
+                }
+                "rename" => {
+                    {
+                        let mut index = self.index.write().unwrap();
+                        let table_map = index.remove(&entry.table).unwrap_or_default();
+                        index.insert(entry.key.clone(), table_map);
+                    }
+                    self.reindex_table(&entry.table);
+                    self.reindex_table(&entry.key);
+                }
+                _ => continue,
+            }
+            applied += 1;
+        }
+
+        Ok(applied)
+    }
+
+    pub async fn vacuum_table(&self, table: String) -> Result<crate::logs::VacuumStats, Error> {
+        let _table_guards = self.lock_tables(std::slice::from_ref(&table)).await;
+        let started = std::time::Instant::now();
+        match self.storage.compact(table.clone()).await.map_err(Error::Io) {
+            Ok(stats) => {
+                self.logger
+                    .info(&format!(
+                        "Compacted log for {}: removed {} entries, {} -> {} bytes in {} ms",
+                        table,
+                        stats.entries_removed,
+                        stats.bytes_before,
+                        stats.bytes_after,
+                        started.elapsed().as_millis()
+                    ))
+                    .await;
+                Ok(stats)
+            }
+            Err(e) => {
+                self.logger
+                    .error(&format!("Failed to compact log for {}: {}", table, e))
+                    .await;
+                Err(e)
+            }
+        }
+    }
+
+    // TRUNCATE mirrors to the table's shadow table like any other write.
+    pub async fn truncate_table(&self, table: String) -> Result<usize, Error> {
+        let targets = self.write_targets(&table);
+        self.clear_tables("truncate", &table, targets).await
+    }
+
+    pub async fn drop_table(&self, table: String) -> Result<usize, Error> {
+        if self.table(&table).is_none() {
+            return Err(Error::NotFound(format!("Table {} does not exist", table)));
+        }
+        let rows = self
+            .clear_tables("drop", &table, vec![table.clone()])
+            .await?;
+        self.remove_shadow_table(&table);
+        Ok(rows)
+    }
+
+    // One marker entry per table however many rows it held. The table maps leave the index at
+    // once, so their memory goes as soon as in-flight readers finish with them.
+    async fn clear_tables(
+        &self,
+        operation: &str,
+        table: &str,
+        targets: Vec<String>,
+    ) -> Result<usize, Error> {
+        let _table_guards = self.lock_tables(&targets).await;
+
+        let entries = targets
+            .iter()
+            .map(|target| {
+                crate::logs::LogEntry::new(
+                    operation.to_string(),
+                    target.clone(),
+                    String::new(),
+                    None,
+                    0,
+                    None,
+                    0,
+                )
+            })
+            .collect();
+        let _logged = self.log_entries(entries).await?;
+
+        let mut rows = 0;
+        {
+            let mut index = self.index.write().unwrap();
+            for target in &targets {
+                if let Some(table_map) = index.remove(target)
+                    && target == table
+                {
+                    rows = table_map.read().unwrap().len();
+                }
+            }
+        }
+        for target in &targets {
+            self.reindex_table(target);
+        }
+        Ok(rows)
+    }
+
+    // Copies matching rows under new versions, keeping their timestamps. The destination must be
+    // empty, so a copy never merges into live data.
+    pub async fn copy_table(
+        self: &Arc<Self>,
+        table: String,
+        destination: String,
+        filter: QueryExpr,
+        deadline: Option<std::time::Instant>,
+    ) -> Result<usize, Error> {
+        if table == destination {
+            return Err(Error::InvalidArgument(
+                "A table cannot be copied onto itself".to_string(),
+            ));
+        }
+
+        let targets = self.write_targets(&destination);
+        let mut locked = targets.clone();
+        locked.push(table.clone());
+        let _table_guards = self.lock_tables(&locked).await;
+        if self.table(&table).is_none() {
+            return Err(Error::NotFound(format!("Table {} does not exist", table)));
+        }
+        if self.table_len(&destination) > 0 {
+            return Err(Error::Conflict(format!(
+                "Table {} already has rows",
+                destination
+            )));
+        }
+
+        let engine = self.clone();
+        let matched = tokio::task::spawn_blocking(move || {
+            let mut matched = Vec::new();
+            for (scanned, (key, row)) in engine.iter_table(&table).enumerate() {
+                check_deadline(deadline, scanned)?;
+                if engine.matches_expr(&table, &row.value, &row.meta, &filter)? {
+                    matched.push((key, row));
+                }
+            }
+            Ok::<_, Error>(matched)
+        })
+        .await??;
+        let rows: Vec<(String, Row)> = matched
+            .into_iter()
+            .map(|(key, row)| {
+                (
+                    key,
+                    Row {
+                        value: row.value.clone(),
+                        version: self.next_version(),
+                        meta: row.meta,
+                    },
+                )
+            })
+            .collect();
+
+        for target in targets {
+            let entries = rows
+                .iter()
+                .map(|(key, row)| {
+                    crate::logs::LogEntry::new(
+                        "add".to_string(),
+                        target.clone(),
+                        key.clone(),
+                        Some(row.value.clone()),
+                        row.version,
+                        Some(row.meta),
+                        0,
+                    )
+                })
+                .collect();
+            let _logged = self.log_entries(entries).await?;
+
+            let table_map = self.table_or_create(&target);
+            let mut table_map = table_map.write().unwrap();
+            for (key, row) in &rows {
+                table_map.insert(key.clone(), Arc::new(row.clone()));
+                self.index_row(&target, key, Some(&row.value));
+            }
+        }
+        Ok(rows.len())
+    }
+
+    pub async fn swap_tables(&self, table: String, other: String) -> Result<(), Error> {
+        if table == other {
+            return Err(Error::InvalidArgument(
+                "A table cannot be swapped with itself".to_string(),
+            ));
+        }
+
+        let _table_guards = self.lock_tables(&[table.clone(), other.clone()]).await;
+
+        let entry = crate::logs::LogEntry::new(
+            "swap".to_string(),
+            table.clone(),
+            other.clone(),
+            None,
+            0,
+            None,
+            0,
+        );
+        let _logged = self.log_entries(vec![entry]).await?;
+
+        {
+            let mut index = self.index.write().unwrap();
+            let table_map = index.remove(&table).unwrap_or_default();
+            let other_map = index.remove(&other).unwrap_or_default();
+            index.insert(table.clone(), other_map);
+            index.insert(other.clone(), table_map);
+        }
+        self.reindex_table(&table);
+        self.reindex_table(&other);
+        Ok(())
+    }
+
+    // Role filters, redactions, range indexes and retention are keyed by table name, often from
+    // the configuration file, so they would not follow a rename: a renamed table would lose them
+    // and a new one under the old name would pick them up.
+    fn table_policies(&self, table: &str) -> Vec<&'static str> {
+        let mut policies = Vec::new();
+        if self
+            .role_filters
+            .read()
+            .unwrap()
+            .keys()
+            .any(|(_, filtered)| filtered == table)
+        {
+            policies.push("role filters");
+        }
+        if self
+            .redactions
+            .read()
+            .unwrap()
+            .get(table)
+            .is_some_and(|rules| !rules.is_empty())
+        {
+            policies.push("redactions");
+        }
+        if self.range_indexes.read().unwrap().contains_key(table) {
+            policies.push("range indexes");
+        }
+        if self.expiry.read().unwrap().contains_key(table) {
+            policies.push("retention");
+        }
+        policies
+    }
+
+    // Moves the rows under a new name in one log entry. A shadow table follows the rename.
+    pub async fn rename_table(&self, table: String, new_name: String) -> Result<(), Error> {
+        if table == new_name {
+            return Err(Error::InvalidArgument(
+                "A table cannot be renamed to itself".to_string(),
+            ));
+        }
+
+        let _table_guards = self.lock_tables(&[table.clone(), new_name.clone()]).await;
+        if self.table(&table).is_none() {
+            return Err(Error::NotFound(format!("Table {} does not exist", table)));
+        }
+        if self.table_len(&new_name) > 0 {
+            return Err(Error::Conflict(format!(
+                "Table {} already exists",
+                new_name
+            )));
+        }
+        let policies = self.table_policies(&table);
+        if !policies.is_empty() {
+            return Err(Error::Conflict(format!(
+                "Table {} has {}; remove them before renaming it",
+                table,
+                policies.join(", ")
+            )));
+        }
+
+        let entry = crate::logs::LogEntry::new(
+            "rename".to_string(),
+            table.clone(),
+            new_name.clone(),
+            None,
+            0,
+            None,
+            0,
+        );
+        let _logged = self.log_entries(vec![entry]).await?;
+
+        {
+            let mut index = self.index.write().unwrap();
+            let table_map = index.remove(&table).unwrap_or_default();
+            index.insert(new_name.clone(), table_map);
+        }
+        self.reindex_table(&table);
+        self.reindex_table(&new_name);
+        if let Some(shadow_table) = self.remove_shadow_table(&table) {
+            let _ = self.set_shadow_table(new_name, shadow_table);
+        }
+        Ok(())
+    }
+
+    pub fn iter_table(&self, table: &str) -> TableIter {
+        let rows = match self.table(table) {
+            Some(table_map) => table_map
+                .read()
+                .unwrap()
+                .iter()
+                .map(|(key, row)| (key.clone(), row.clone()))
+                .collect(),
+            None => Vec::new(),
+        };
+        TableIter {
+            rows: rows.into_iter(),
+        }
+    }
+
+    pub fn table_len(&self, table: &str) -> usize {
+        self.table(table)
+            .map(|table_map| table_map.read().unwrap().len())
+            .unwrap_or(0)
+    }
+
+    // `seed_path` is a fixture file mapping tables to keys to documents, or a directory of
+    // <table>.jsonl files. Every file is parsed before any table is touched.
+    pub async fn seed_from_path(
+        &self,
+        seed_path: &std::path::Path,
+        mode: SeedMode,
+    ) -> Result<Vec<(String, usize)>, Error> {
+        let tables = if seed_path.is_dir() {
+            let mut seed_files: Vec<std::path::PathBuf> = std::fs::read_dir(seed_path)?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+                .collect();
+            seed_files.sort();
+
+            let mut tables = Vec::new();
+            for path in seed_files {
+                let table = match path.file_stem() {
+                    Some(stem) => stem.to_string_lossy().to_string(),
+                    None => continue,
+                };
+                tables.push((
+                    table,
+                    parse_rows_file(&path, &std::fs::read_to_string(&path)?)?,
+                ));
+            }
+            tables
+        } else {
+            parse_fixture_file(seed_path, &std::fs::read_to_string(seed_path)?)?
+        };
+
+        let mut seeded = Vec::new();
+        for (table, rows) in tables {
+            match mode {
+                SeedMode::Empty if self.table_len(&table) > 0 => continue,
+                SeedMode::Overwrite if self.table_len(&table) > 0 => {
+                    self.truncate_table(table.clone()).await?;
+                }
+                _ => {}
+            }
+
+            let count = rows.len();
+            for (key, value) in rows {
+                self.add_row(table.clone(), key, value, WriteOptions::default())
+                    .await?;
+            }
+            seeded.push((table, count));
+        }
+
+        Ok(seeded)
+    }
+}
+
+fn type_name(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "a boolean",
+        serde_json::Value::Number(_) => "a number",
+        serde_json::Value::String(_) => "a string",
+        serde_json::Value::Array(_) => "an array",
+        serde_json::Value::Object(_) => "an object",
+    }
+}
+
+// Sort order across types: null < booleans < numbers < strings < arrays < objects.
+fn type_rank(value: &serde_json::Value) -> u8 {
+    match value {
+        serde_json::Value::Null => 0,
+        serde_json::Value::Bool(_) => 1,
+        serde_json::Value::Number(_) => 2,
+        serde_json::Value::String(_) => 3,
+        serde_json::Value::Array(_) => 4,
+        serde_json::Value::Object(_) => 5,
+    }
+}
+
+fn compare_values(a: &serde_json::Value, b: &serde_json::Value) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (serde_json::Value::Number(a), serde_json::Value::Number(b)) => {
+            a.as_f64()?.partial_cmp(&b.as_f64()?)
+        }
+        (serde_json::Value::String(a), serde_json::Value::String(b)) => Some(a.cmp(b)),
+        (serde_json::Value::Bool(a), serde_json::Value::Bool(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+// Splits `items[0][2]` into `items` and its indexes. Parts that are not well-formed index
+// syntax return None and are looked up as plain object keys.
+fn split_array_indexes(part: &str) -> Option<(&str, Vec<usize>)> {
+    let open = part.find('[')?;
+    let (name, mut rest) = part.split_at(open);
+    let mut indexes = Vec::new();
+    while !rest.is_empty() {
+        let inner = rest.strip_prefix('[')?;
+        let close = inner.find(']')?;
+        indexes.push(inner[..close].parse().ok()?);
+        rest = &inner[close + 1..];
+    }
+    Some((name, indexes))
+}
+
+// Unquoted literals parse as numbers or booleans; string operators match them as text.
+fn text_of(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn values_equal(a: &serde_json::Value, b: &serde_json::Value) -> bool {
+    match (a, b) {
+        (serde_json::Value::Number(_), serde_json::Value::Number(_)) => {
+            compare_values(a, b) == Some(std::cmp::Ordering::Equal)
+        }
+        _ => a == b,
+    }
+}
+
+fn rebuild_tables(
+    entries: Vec<crate::logs::LogEntry>,
+    legacy_meta: RowMeta,
+    mut version_of: impl FnMut(&crate::logs::LogEntry) -> u64,
+) -> HashMap<String, HashMap<String, Row>> {
+    let mut index: HashMap<String, HashMap<String, Row>> = HashMap::new();
+
+    for entry in entries {
+        let version = version_of(&entry);
+        match entry.operation.as_str() {
+            "truncate" | "drop" => {
+                index.remove(&entry.table);
+                continue;
+            }
+            "checkpoint" => continue,
+            "swap" => {
+                let table = index.remove(&entry.table);
+                let other = index.remove(&entry.key);
+                if let Some(table) = table {
+                    index.insert(entry.key, table);
+                }
+                if let Some(other) = other {
+                    index.insert(entry.table, other);
+                }
+                continue;
+            }
+            "rename" => {
+                let table = index.remove(&entry.table).unwrap_or_default();
+                index.insert(entry.key, table);
+                continue;
+            }
+            _ => {}
+        }
+        let table_map = index.entry(entry.table).or_default();
+
+        match entry.operation.as_str() {
+            "add" | "update" => {
+                if let Some(value) = entry.value {
+                    let meta = entry.meta.unwrap_or(legacy_meta);
+                    table_map.insert(
+                        entry.key,
+                        Row {
+                            value,
+                            version,
+                            meta,
+                        },
+                    );
+                }
+            }
+            "remove" => {
+                table_map.remove(&entry.key);
+            }
+            _ => {}
+        }
+    }
+
+    index
+}
+
+// Each table named in a fixture file with its (key, document) rows.
+type FixtureTables = Vec<(String, Vec<(String, serde_json::Value)>)>;
+
+fn parse_fixture_file(path: &std::path::Path, contents: &str) -> Result<FixtureTables, Error> {
+    let fixture: serde_json::Value = serde_json::from_str(contents)
+        .map_err(|e| Error::Parse(format!("{}: invalid JSON: {}", path.display(), e)))?;
+    let serde_json::Value::Object(tables) = fixture else {
+        return Err(Error::Parse(format!(
+            "{}: fixtures must be an object of tables",
+            path.display()
+        )));
+    };
+
+    tables
+        .into_iter()
+        .map(|(table, rows)| match rows {
+            serde_json::Value::Object(rows) => Ok((table, rows.into_iter().collect())),
+            _ => Err(Error::Parse(format!(
+                "{}: table {} must be an object of keys to documents",
+                path.display(),
+                table
+            ))),
+        })
+        .collect()
+}
+
+fn parse_rows_file(
+    path: &std::path::Path,
+    contents: &str,
+) -> Result<Vec<(String, serde_json::Value)>, Error> {
+    let mut rows = Vec::new();
+    for (line_number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let row: serde_json::Value = serde_json::from_str(line).map_err(|e| {
+            Error::Parse(format!(
+                "{}:{}: invalid JSON: {}",
+                path.display(),
+                line_number + 1,
+                e
+            ))
+        })?;
+        let key = match row.get("key") {
+            Some(serde_json::Value::String(key)) => key.clone(),
+            Some(serde_json::Value::Number(key)) => key.to_string(),
+            _ => {
+                return Err(Error::Parse(format!(
+                    "{}:{}: rows must have a string or number \"key\"",
+                    path.display(),
+                    line_number + 1
+                )));
+            }
+        };
+        let value = row.get("value").cloned().ok_or_else(|| {
+            Error::Parse(format!(
+                "{}:{}: rows must have a \"value\"",
+                path.display(),
+                line_number + 1
+            ))
+        })?;
+        rows.push((key, value));
+    }
+    Ok(rows)
+}
+
+// The rows a query reads: the keys a range index picked out, or else the whole table.
+fn candidate_rows<'a>(
+    table_data: &'a HashMap<String, Arc<Row>>,
+    keys: Option<Vec<String>>,
+) -> Box<dyn Iterator<Item = (&'a String, &'a Arc<Row>)> + 'a> {
+    match keys {
+        Some(keys) => Box::new(
+            keys.into_iter()
+                .filter_map(move |key| table_data.get_key_value(&key)),
+        ),
+        None => Box::new(table_data.iter()),
+    }
+}
+
+fn check_deadline(deadline: Option<std::time::Instant>, scanned: usize) -> Result<(), Error> {
+    match deadline {
+        Some(deadline)
+            if scanned.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+                && std::time::Instant::now() >= deadline =>
+        {
+            Err(Error::Timeout)
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn parse_timestamp(value: &serde_json::Value) -> Option<chrono::DateTime<chrono::Utc>> {
+    match value {
+        serde_json::Value::Number(seconds) => {
+            chrono::DateTime::from_timestamp_millis((seconds.as_f64()? * 1000.0) as i64)
+        }
+        serde_json::Value::String(timestamp) => {
+            if let Ok(parsed) = chrono::DateTime::parse_from_rfc3339(timestamp) {
+                return Some(parsed.with_timezone(&chrono::Utc));
+            }
+            if let Ok(parsed) =
+                chrono::NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S")
+            {
+                return Some(parsed.and_utc());
+            }
+            chrono::NaiveDate::parse_from_str(timestamp, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .map(|parsed| parsed.and_utc())
+        }
+        _ => None,
+    }
+}
+
+fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    let patch_fields = match patch {
+        serde_json::Value::Object(patch_fields) => patch_fields,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    let target_fields = target.as_object_mut().unwrap();
+
+    for (field, patch_value) in patch_fields {
+        if patch_value.is_null() {
+            target_fields.remove(field);
+        } else {
+            merge_patch(
+                target_fields
+                    .entry(field.clone())
+                    .or_insert(serde_json::Value::Null),
+                patch_value,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG_FILE: &str = "test.log";
+
+    async fn open(dir: &std::path::Path) -> Arc<Engine> {
+        open_with(dir, crate::logs::WalOptions::default()).await
+    }
+
+    async fn open_with(dir: &std::path::Path, options: crate::logs::WalOptions) -> Arc<Engine> {
+        let storage = crate::logs::LogStorageSetup::new(dir.join(LOG_FILE), options);
+        let engine = Engine::new(
+            "test".to_string(),
+            dir.to_string_lossy().to_string(),
+            Box::new(storage),
+        );
+        engine.replay_log().await.unwrap();
+        Arc::new(engine)
+    }
+
+    // Drops the engine, so its log writer is gone, and replays the log into a fresh one.
+    async fn reopen(engine: Arc<Engine>, dir: &std::path::Path) -> Arc<Engine> {
+        engine.storage.flush().await.unwrap();
+        drop(engine);
+        open(dir).await
+    }
+
+    fn condition(field_path: &str, operator: QueryOperator, value: serde_json::Value) -> QueryExpr {
+        let pattern = match (&operator, &value) {
+            (QueryOperator::Regex, serde_json::Value::String(pattern)) => {
+                Some(regex::Regex::new(pattern).unwrap())
+            }
+            _ => None,
+        };
+        QueryExpr::Condition(QueryCondition {
+            field_path: field_path.to_string(),
+            operator,
+            value,
+            pattern,
+        })
+    }
+
+    async fn set(engine: &Engine, table: &str, key: &str, value: serde_json::Value) {
+        engine
+            .add_row(
+                table.to_string(),
+                key.to_string(),
+                value,
+                WriteOptions::default(),
+            )
+            .await
+            .unwrap();
+    }
+
+    fn value(engine: &Engine, table: &str, key: &str) -> Option<serde_json::Value> {
+        engine
+            .get_row(table.to_string(), key.to_string())
+            .map(|row| row.value)
+    }
+
+    fn keys(mut rows: Vec<(String, serde_json::Value)>) -> Vec<String> {
+        rows.sort_by(|a, b| a.0.cmp(&b.0));
+        rows.into_iter().map(|(key, _)| key).collect()
+    }
+
+    #[tokio::test]
+    async fn add_get_update_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+
+        set(&engine, "users", "ann", serde_json::json!({ "age": 30 })).await;
+        assert_eq!(
+            value(&engine, "users", "ann"),
+            Some(serde_json::json!({ "age": 30 }))
+        );
+        let first_version = engine
+            .get_row("users".to_string(), "ann".to_string())
+            .unwrap()
+            .version;
+
+        let previous = engine
+            .update_row(
+                "users".to_string(),
+                "ann".to_string(),
+                serde_json::json!({ "age": 31 }),
+                WriteOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(previous.unwrap().value, serde_json::json!({ "age": 30 }));
+        let row = engine
+            .get_row("users".to_string(), "ann".to_string())
+            .unwrap();
+        assert_eq!(row.value, serde_json::json!({ "age": 31 }));
+        assert!(row.version > first_version);
+
+        let removed = engine
+            .remove_row("users".to_string(), "ann".to_string())
+            .await
+            .unwrap();
+        assert_eq!(removed.unwrap().value, serde_json::json!({ "age": 31 }));
+        assert_eq!(value(&engine, "users", "ann"), None);
+
+        let removed = engine
+            .remove_row("users".to_string(), "ann".to_string())
+            .await
+            .unwrap();
+        assert!(removed.is_none());
+    }
+
+    #[tokio::test]
+    async fn update_requires_an_existing_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+
+        let result = engine
+            .update_row(
+                "users".to_string(),
+                "ghost".to_string(),
+                serde_json::json!({}),
+                WriteOptions::default(),
+            )
+            .await;
+        assert!(matches!(result, Err(Error::NotFound(_))));
+        assert_eq!(engine.table_len("users"), 0);
+    }
+
+    #[tokio::test]
+    async fn set_if_absent_and_if_exists() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        let if_absent = WriteOptions {
+            if_absent: true,
+            ..Default::default()
+        };
+        let if_exists = WriteOptions {
+            if_exists: true,
+            ..Default::default()
+        };
+
+        let result = engine
+            .add_row(
+                "t".to_string(),
+                "k".to_string(),
+                serde_json::json!(1),
+                if_exists.clone(),
+            )
+            .await;
+        assert!(matches!(result, Err(Error::NotFound(_))));
+
+        engine
+            .add_row(
+                "t".to_string(),
+                "k".to_string(),
+                serde_json::json!(1),
+                if_absent.clone(),
+            )
+            .await
+            .unwrap();
+        let result = engine
+            .add_row(
+                "t".to_string(),
+                "k".to_string(),
+                serde_json::json!(2),
+                if_absent,
+            )
+            .await;
+        assert!(matches!(result, Err(Error::Conflict(_))));
+
+        engine
+            .add_row(
+                "t".to_string(),
+                "k".to_string(),
+                serde_json::json!(3),
+                if_exists,
+            )
+            .await
+            .unwrap();
+        assert_eq!(value(&engine, "t", "k"), Some(serde_json::json!(3)));
+    }
+
+    #[tokio::test]
+    async fn if_version_rejects_stale_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        set(&engine, "t", "k", serde_json::json!(1)).await;
+        let version = engine
+            .get_row("t".to_string(), "k".to_string())
+            .unwrap()
+            .version;
+
+        let stale = WriteOptions {
+            if_version: Some(version + 1),
+            ..Default::default()
+        };
+        let result = engine
+            .add_row(
+                "t".to_string(),
+                "k".to_string(),
+                serde_json::json!(2),
+                stale,
+            )
+            .await;
+        assert!(matches!(result, Err(Error::Conflict(_))));
+
+        let current = WriteOptions {
+            if_version: Some(version),
+            ..Default::default()
+        };
+        engine
+            .add_row(
+                "t".to_string(),
+                "k".to_string(),
+                serde_json::json!(2),
+                current,
+            )
+            .await
+            .unwrap();
+
+        let create_only = WriteOptions {
+            if_version: Some(0),
+            ..Default::default()
+        };
+        let result = engine
+            .add_row(
+                "t".to_string(),
+                "k".to_string(),
+                serde_json::json!(3),
+                create_only.clone(),
+            )
+            .await;
+        assert!(matches!(result, Err(Error::Conflict(_))));
+        engine
+            .add_row(
+                "t".to_string(),
+                "new".to_string(),
+                serde_json::json!(3),
+                create_only,
+            )
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn idempotent_writes_apply_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        let options = WriteOptions {
+            idempotency_key: Some("retry-1".to_string()),
+            ..Default::default()
+        };
+
+        for attempt in 0..3 {
+            engine
+                .add_row(
+                    "t".to_string(),
+                    "k".to_string(),
+                    serde_json::json!(attempt),
+                    options.clone(),
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(value(&engine, "t", "k"), Some(serde_json::json!(0)));
+
+        let result = engine
+            .add_row(
+                "t".to_string(),
+                "other".to_string(),
+                serde_json::json!(0),
+                options,
+            )
+            .await;
+        assert!(matches!(result, Err(Error::Conflict(_))));
+    }
+
+    #[tokio::test]
+    async fn replay_restores_awkward_keys_and_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        let rows = vec![
+            (
+                "pipe|key",
+                serde_json::json!({ "note": "a|b|c", "list": ["|", "||"] }),
+            ),
+            ("new\nline", serde_json::json!("first\nsecond\r\nthird")),
+            (
+                "quote\"key",
+                serde_json::json!({ "q": "say \"hi\"", "back": "C:\\temp\\" }),
+            ),
+            (
+                "unicodé 🔑",
+                serde_json::json!({ "名前": "ünïcödé", "emoji": "🦈" }),
+            ),
+            ("", serde_json::json!(null)),
+            ("spaces in key", serde_json::json!("  padded  ")),
+            ("numbers", serde_json::json!([0, -1, 1.5, 1e300, u64::MAX])),
+            ("empty", serde_json::json!({})),
+        ];
+        for (key, row) in &rows {
+            set(&engine, "t|able", key, row.clone()).await;
+        }
+
+        let engine = reopen(engine, dir.path()).await;
+        assert_eq!(engine.table_len("t|able"), rows.len());
+        for (key, row) in &rows {
+            assert_eq!(
+                value(&engine, "t|able", key).as_ref(),
+                Some(row),
+                "{:?}",
+                key
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_applies_updates_and_removes_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        set(&engine, "t", "kept", serde_json::json!(1)).await;
+        set(&engine, "t", "removed", serde_json::json!(1)).await;
+        set(&engine, "t", "readded", serde_json::json!(1)).await;
+        engine
+            .update_row(
+                "t".to_string(),
+                "kept".to_string(),
+                serde_json::json!(2),
+                WriteOptions::default(),
+            )
+            .await
+            .unwrap();
+        engine
+            .remove_row("t".to_string(), "removed".to_string())
+            .await
+            .unwrap();
+        engine
+            .remove_row("t".to_string(), "readded".to_string())
+            .await
+            .unwrap();
+        set(&engine, "t", "readded", serde_json::json!(3)).await;
+        let versions: Vec<u64> = ["kept", "readded"]
+            .iter()
+            .map(|key| {
+                engine
+                    .get_row("t".to_string(), key.to_string())
+                    .unwrap()
+                    .version
+            })
+            .collect();
+
+        let engine = reopen(engine, dir.path()).await;
+        assert_eq!(value(&engine, "t", "kept"), Some(serde_json::json!(2)));
+        assert_eq!(value(&engine, "t", "removed"), None);
+        assert_eq!(value(&engine, "t", "readded"), Some(serde_json::json!(3)));
+        for (key, version) in ["kept", "readded"].iter().zip(versions) {
+            let row = engine.get_row("t".to_string(), key.to_string()).unwrap();
+            assert_eq!(row.version, version);
+        }
+
+        // Versions keep increasing after a restart instead of starting over.
+        set(&engine, "t", "later", serde_json::json!(4)).await;
+        let later = engine
+            .get_row("t".to_string(), "later".to_string())
+            .unwrap();
+        assert!(
+            later.version
+                > engine
+                    .get_row("t".to_string(), "readded".to_string())
+                    .unwrap()
+                    .version
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_keeps_row_timestamps() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        set(&engine, "t", "k", serde_json::json!(1)).await;
+        let created = engine
+            .get_row("t".to_string(), "k".to_string())
+            .unwrap()
+            .meta;
+        engine
+            .update_row(
+                "t".to_string(),
+                "k".to_string(),
+                serde_json::json!(2),
+                WriteOptions::default(),
+            )
+            .await
+            .unwrap();
+        let updated = engine
+            .get_row("t".to_string(), "k".to_string())
+            .unwrap()
+            .meta;
+        assert_eq!(updated.created_at, created.created_at);
+        assert!(updated.updated_at >= created.updated_at);
+
+        let engine = reopen(engine, dir.path()).await;
+        let replayed = engine
+            .get_row("t".to_string(), "k".to_string())
+            .unwrap()
+            .meta;
+        assert_eq!(replayed.created_at, updated.created_at);
+        assert_eq!(replayed.updated_at, updated.updated_at);
+    }
+
+    #[tokio::test]
+    async fn replay_with_binary_codecs() {
+        for codec in [crate::logs::ValueCodec::Cbor, crate::logs::ValueCodec::Bson] {
+            let dir = tempfile::tempdir().unwrap();
+            let options = crate::logs::WalOptions {
+                codec,
+                ..Default::default()
+            };
+            let engine = open_with(dir.path(), options).await;
+            set(&engine, "t", "a|b", serde_json::json!({ "text": "x|y\nz" })).await;
+            set(&engine, "t", "gone", serde_json::json!(1)).await;
+            engine
+                .remove_row("t".to_string(), "gone".to_string())
+                .await
+                .unwrap();
+
+            // Reopened with the default codec: each entry records how it was encoded.
+            let engine = reopen(engine, dir.path()).await;
+            assert_eq!(
+                value(&engine, "t", "a|b"),
+                Some(serde_json::json!({ "text": "x|y\nz" })),
+                "{}",
+                codec
+            );
+            assert_eq!(value(&engine, "t", "gone"), None, "{}", codec);
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_reads_legacy_pipe_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(LOG_FILE),
+            "add|t|a|{\"text\":\"left|right\"}\nadd|t|b|{\"n\":1}\nremove|t|b|\n",
+        )
+        .unwrap();
+
+        let engine = open(dir.path()).await;
+        assert_eq!(
+            value(&engine, "t", "a"),
+            Some(serde_json::json!({ "text": "left|right" }))
+        );
+        assert_eq!(value(&engine, "t", "b"), None);
+        assert!(
+            engine
+                .get_row("t".to_string(), "a".to_string())
+                .unwrap()
+                .version
+                > 0
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_of_an_empty_directory_starts_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        assert_eq!(engine.table_count(), 0);
+        assert_eq!(value(&engine, "t", "k"), None);
+    }
+
+    // Acknowledges appends until told to fail them, like a disk that has filled up.
+    struct FlakyStorage {
+        failing: Arc<std::sync::atomic::AtomicBool>,
+        inner: crate::storage::MemoryStorage,
+    }
+
+    impl crate::storage::StorageBackend for FlakyStorage {
+        fn location(&self) -> String {
+            self.inner.location()
+        }
+
+        fn append(&self, entries: Vec<crate::logs::LogEntry>) -> crate::logs::WalTicket {
+            if self.failing.load(Ordering::SeqCst) {
+                return crate::logs::WalTicket::resolved(Err(crate::logs::WalError::Write(
+                    "No space left on device".to_string(),
+                )));
+            }
+            self.inner.append(entries)
+        }
+
+        fn replay(&self) -> Result<Option<crate::storage::Replay>, Error> {
+            self.inner.replay()
+        }
+
+        fn snapshot(&self) -> crate::storage::StorageFuture<'_, Result<Vec<u8>, Error>> {
+            self.inner.snapshot()
+        }
+
+        fn compact(
+            &self,
+            table: String,
+        ) -> crate::storage::StorageFuture<'_, Result<crate::logs::VacuumStats, String>> {
+            self.inner.compact(table)
+        }
+
+        fn last_sequence(&self) -> u64 {
+            self.inner.last_sequence()
+        }
+
+        fn metrics(&self) -> crate::logs::WalMetrics {
+            self.inner.metrics()
+        }
+    }
+
+    #[tokio::test]
+    async fn writes_the_wal_did_not_take_are_never_seen() {
+        let failing = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let engine = Engine::new(
+            "test".to_string(),
+            String::new(),
+            Box::new(FlakyStorage {
+                failing: failing.clone(),
+                inner: crate::storage::MemoryStorage::default(),
+            }),
+        );
+        set(&engine, "t", "k", serde_json::json!(1)).await;
+        let dir = tempfile::tempdir().unwrap();
+        let events = dir.path().join("events.jsonl");
+        let hook = dir.path().join("hook.sh");
+        std::fs::write(&hook, format!("#!/bin/sh\ncat >> {}\n", events.display())).unwrap();
+        std::fs::set_permissions(&hook, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+        engine
+            .triggers()
+            .add(
+                "t",
+                "hook",
+                crate::triggers::TriggerTarget::Command(hook.to_string_lossy().into_owned()),
+            )
+            .unwrap();
+        failing.store(true, Ordering::SeqCst);
+
+        let written = engine
+            .add_row(
+                "t".to_string(),
+                "k".to_string(),
+                serde_json::json!(2),
+                WriteOptions::default(),
+            )
+            .await;
+        assert!(matches!(written, Err(Error::Wal(_))));
+        let removed = engine.remove_row("t".to_string(), "k".to_string()).await;
+        assert!(matches!(removed, Err(Error::Wal(_))));
+        assert_eq!(value(&engine, "t", "k"), Some(serde_json::json!(1)));
+
+        // A script reads its own writes back, and they are undone once the WAL refuses them.
+        let tables = vec!["t".to_string()];
+        let guards = engine.lock_for_script(&tables).await;
+        let (_, first) = engine
+            .set_locked("t".to_string(), "k".to_string(), serde_json::json!(3))
+            .unwrap();
+        let (_, second) = engine.remove_locked("t", "k");
+        let (_, third) = engine
+            .set_locked("t".to_string(), "new".to_string(), serde_json::json!(4))
+            .unwrap();
+        assert_eq!(value(&engine, "t", "new"), Some(serde_json::json!(4)));
+        let committed = engine.commit_script(vec![first, second, third]).await;
+        drop(guards);
+        assert!(matches!(committed, Err(Error::Wal(_))));
+        assert_eq!(value(&engine, "t", "k"), Some(serde_json::json!(1)));
+        assert_eq!(value(&engine, "t", "new"), None);
+
+        // Nor do their triggers fire: events go out in commit order, so once the next logged
+        // write is delivered, nothing came before it.
+        failing.store(false, Ordering::SeqCst);
+        set(&engine, "t", "k", serde_json::json!(5)).await;
+        for _ in 0..500 {
+            if engine.triggers().list()[0].delivered > 0 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let delivered: Vec<serde_json::Value> = std::fs::read_to_string(&events)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(delivered.len(), 1);
+        assert_eq!(delivered[0]["new"], serde_json::json!(5));
+    }
+
+    #[tokio::test]
+    async fn restored_entries_the_wal_did_not_take_are_never_seen() {
+        let failing = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let engine = Engine::new(
+            "test".to_string(),
+            String::new(),
+            Box::new(FlakyStorage {
+                failing: failing.clone(),
+                inner: crate::storage::MemoryStorage::default(),
+            }),
+        );
+        let mut entry = crate::logs::LogEntry::new(
+            "add".to_string(),
+            "t".to_string(),
+            "k".to_string(),
+            Some(serde_json::json!(1)),
+            0,
+            None,
+            0,
+        );
+        entry.seq = 1;
+
+        let restored = engine.apply_log_entries(vec![entry.clone()], 0).await;
+        assert!(matches!(restored, Err(Error::Wal(_))));
+        assert_eq!(value(&engine, "t", "k"), None);
+
+        failing.store(false, Ordering::SeqCst);
+        assert_eq!(
+            engine
+                .apply_log_entries(vec![entry.clone()], 0)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(value(&engine, "t", "k"), Some(serde_json::json!(1)));
+        assert_eq!(engine.apply_log_entries(vec![entry], 1).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn table_operations_survive_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        for table in ["truncated", "dropped", "old_name", "left", "right"] {
+            set(&engine, table, "k", serde_json::json!(table)).await;
+        }
+        assert_eq!(
+            engine
+                .truncate_table("truncated".to_string())
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(engine.drop_table("dropped".to_string()).await.unwrap(), 1);
+        engine
+            .rename_table("old_name".to_string(), "new_name".to_string())
+            .await
+            .unwrap();
+        engine
+            .swap_tables("left".to_string(), "right".to_string())
+            .await
+            .unwrap();
+        set(&engine, "truncated", "after", serde_json::json!(1)).await;
+
+        let engine = reopen(engine, dir.path()).await;
+        assert_eq!(engine.table_len("truncated"), 1);
+        assert_eq!(
+            value(&engine, "truncated", "after"),
+            Some(serde_json::json!(1))
+        );
+        assert_eq!(engine.table_len("dropped"), 0);
+        assert_eq!(engine.table_len("old_name"), 0);
+        assert_eq!(
+            value(&engine, "new_name", "k"),
+            Some(serde_json::json!("old_name"))
+        );
+        assert_eq!(
+            value(&engine, "left", "k"),
+            Some(serde_json::json!("right"))
+        );
+        assert_eq!(
+            value(&engine, "right", "k"),
+            Some(serde_json::json!("left"))
+        );
+    }
+
+    #[tokio::test]
+    async fn table_operations_reject_bad_targets() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        set(&engine, "a", "k", serde_json::json!(1)).await;
+        set(&engine, "b", "k", serde_json::json!(2)).await;
+
+        assert!(matches!(
+            engine.drop_table("missing".to_string()).await,
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            engine.rename_table("a".to_string(), "a".to_string()).await,
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            engine.rename_table("a".to_string(), "b".to_string()).await,
+            Err(Error::Conflict(_))
+        ));
+
+        // Policies keyed by the name would stay behind, so they have to go first.
+        engine.set_role_filter(&UserRole::User, "a".to_string(), QueryExpr::all());
+        engine.set_redaction("a".to_string(), "ssn".to_string(), RedactionAction::Drop);
+        let refused = engine.rename_table("a".to_string(), "c".to_string()).await;
+        assert_eq!(
+            refused,
+            Err(Error::Conflict(
+                "Table a has role filters, redactions; remove them before renaming it".to_string()
+            ))
+        );
+        engine.clear_role_filter(&UserRole::User, "a");
+        engine.clear_redaction("a", "ssn");
+        engine
+            .create_range_index("a".to_string(), ["x".to_string(), "y".to_string()])
+            .await;
+        assert!(matches!(
+            engine.rename_table("a".to_string(), "c".to_string()).await,
+            Err(Error::Conflict(_))
+        ));
+        engine.drop_range_index("a", &["x".to_string(), "y".to_string()]);
+        engine
+            .rename_table("a".to_string(), "c".to_string())
+            .await
+            .unwrap();
+        assert_eq!(value(&engine, "c", "k"), Some(serde_json::json!(1)));
+        set(&engine, "a", "k", serde_json::json!(1)).await;
+        assert!(matches!(
+            engine
+                .copy_table("a".to_string(), "b".to_string(), QueryExpr::all(), None)
+                .await,
+            Err(Error::Conflict(_)) | Err(Error::InvalidArgument(_))
+        ));
+        assert_eq!(value(&engine, "b", "k"), Some(serde_json::json!(2)));
+    }
+
+    #[tokio::test]
+    async fn copy_table_copies_matching_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        for n in 0..10 {
+            set(
+                &engine,
+                "src",
+                &format!("k{}", n),
+                serde_json::json!({ "n": n }),
+            )
+            .await;
+        }
+        let copied = engine
+            .copy_table(
+                "src".to_string(),
+                "dst".to_string(),
+                condition("n", QueryOperator::GreaterThanOrEqual, serde_json::json!(5)),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(copied, 5);
+
+        let engine = reopen(engine, dir.path()).await;
+        assert_eq!(engine.table_len("src"), 10);
+        assert_eq!(engine.table_len("dst"), 5);
+        assert_eq!(
+            value(&engine, "dst", "k7"),
+            Some(serde_json::json!({ "n": 7 }))
+        );
+    }
+
+    #[tokio::test]
+    async fn query_conditions() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        set(&engine, "u", "ann", serde_json::json!({ "age": 30, "name": "Ann", "address": { "city": "Lisbon" }, "tags": ["admin", "ops"] })).await;
+        set(&engine, "u", "bob", serde_json::json!({ "age": 25, "name": "Bob", "address": { "city": "Porto" }, "tags": ["dev"] })).await;
+        set(&engine, "u", "cy", serde_json::json!({ "name": "Cy|Pipe" })).await;
+
+        let query = |filter: QueryExpr| {
+            keys(
+                engine
+                    .query_rows(&UserRole::Admin, "u".to_string(), filter, None)
+                    .unwrap(),
+            )
+        };
+
+        assert_eq!(
+            query(condition(
+                "age",
+                QueryOperator::GreaterThan,
+                serde_json::json!(26)
+            )),
+            vec!["ann"]
+        );
+        assert_eq!(
+            query(condition(
+                "address.city",
+                QueryOperator::Equals,
+                serde_json::json!("Porto")
+            )),
+            vec!["bob"]
+        );
+        assert_eq!(
+            query(condition(
+                "tags[1]",
+                QueryOperator::Equals,
+                serde_json::json!("ops")
+            )),
+            vec!["ann"]
+        );
+        assert_eq!(
+            query(condition(
+                "name",
+                QueryOperator::Contains,
+                serde_json::json!("|")
+            )),
+            vec!["cy"]
+        );
+        assert_eq!(
+            query(condition(
+                "name",
+                QueryOperator::IContains,
+                serde_json::json!("ANN")
+            )),
+            vec!["ann"]
+        );
+        assert_eq!(
+            query(condition(
+                "name",
+                QueryOperator::Regex,
+                serde_json::json!("^[AB]")
+            )),
+            vec!["ann", "bob"]
+        );
+        assert_eq!(
+            query(QueryExpr::Or(vec![
+                condition("age", QueryOperator::LessThan, serde_json::json!(26)),
+                condition("name", QueryOperator::StartsWith, serde_json::json!("Cy")),
+            ])),
+            vec!["bob", "cy"]
+        );
+        assert_eq!(
+            query(QueryExpr::And(vec![
+                condition(
+                    "age",
+                    QueryOperator::GreaterThanOrEqual,
+                    serde_json::json!(25)
+                ),
+                QueryExpr::Not(Box::new(condition(
+                    "name",
+                    QueryOperator::Equals,
+                    serde_json::json!("Bob"),
+                ))),
+            ])),
+            vec!["ann"]
+        );
+        assert!(
+            query(condition(
+                "missing.field",
+                QueryOperator::Equals,
+                serde_json::json!(1)
+            ))
+            .is_empty()
+        );
+        assert_eq!(query(QueryExpr::all()).len(), 3);
+        assert!(
+            engine
+                .query_rows(
+                    &UserRole::Admin,
+                    "no_such_table".to_string(),
+                    QueryExpr::all(),
+                    None
+                )
+                .unwrap()
+                .is_empty()
+        );
+
+        // Comparing a string with a number is an error rather than a silent non-match.
+        set(&engine, "u", "dee", serde_json::json!({ "age": "unknown" })).await;
+        assert!(matches!(
+            engine.query_rows(
+                &UserRole::Admin,
+                "u".to_string(),
+                condition("age", QueryOperator::GreaterThan, serde_json::json!(26)),
+                None,
+            ),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn ordered_and_streamed_queries() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        for n in [3, 1, 2, 5, 4] {
+            set(
+                &engine,
+                "t",
+                &format!("k{}", n),
+                serde_json::json!({ "n": n }),
+            )
+            .await;
+        }
+
+        let order = QueryOrder {
+            field_path: "n".to_string(),
+            descending: true,
+        };
+        let ordered = engine
+            .query_rows_as(
+                &UserRole::Admin,
+                "t".to_string(),
+                QueryExpr::all(),
+                Some(order.clone()),
+                None,
+            )
+            .unwrap();
+        let ordered: Vec<String> = ordered.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(ordered, vec!["k5", "k4", "k3", "k2", "k1"]);
+
+        let mut streamed = Vec::new();
+        let count = engine
+            .stream_rows_as(
+                &UserRole::Admin,
+                "t".to_string(),
+                QueryExpr::all(),
+                Some(order),
+                Some(2),
+                None,
+                |key, _| {
+                    streamed.push(key);
+                    true
+                },
+            )
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(streamed, vec!["k5", "k4"]);
+
+        let mut seen = 0;
+        let count = engine
+            .stream_rows_as(
+                &UserRole::Admin,
+                "t".to_string(),
+                condition("n", QueryOperator::GreaterThan, serde_json::json!(1)),
+                None,
+                None,
+                None,
+                |_, _| {
+                    seen += 1;
+                    seen < 3
+                },
+            )
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[tokio::test]
+    async fn bulk_update_and_delete_survive_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        for n in 0..6 {
+            set(
+                &engine,
+                "t",
+                &format!("k{}", n),
+                serde_json::json!({ "n": n, "drop": "me" }),
+            )
+            .await;
+        }
+        // A single key refused fails the whole write before anything is written.
+        let refused = engine
+            .remove_rows_where(
+                &UserRole::Admin,
+                "t".to_string(),
+                QueryExpr::all(),
+                &|key| match key {
+                    "k5" => Err(Error::Conflict("locked".to_string())),
+                    _ => Ok(()),
+                },
+                None,
+            )
+            .await;
+        assert!(matches!(refused, Err(Error::Conflict(_))));
+        assert_eq!(engine.table_len("t"), 6);
+
+        let updated = engine
+            .update_rows_where(
+                &UserRole::Admin,
+                "t".to_string(),
+                condition("n", QueryOperator::LessThan, serde_json::json!(3)),
+                serde_json::json!({ "low": true, "drop": null }),
+                &|_| Ok(()),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated, 3);
+        let removed = engine
+            .remove_rows_where(
+                &UserRole::Admin,
+                "t".to_string(),
+                condition("n", QueryOperator::GreaterThanOrEqual, serde_json::json!(4)),
+                &|_| Ok(()),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(removed, 2);
+
+        let engine = reopen(engine, dir.path()).await;
+        assert_eq!(engine.table_len("t"), 4);
+        assert_eq!(
+            value(&engine, "t", "k0"),
+            Some(serde_json::json!({ "n": 0, "low": true }))
+        );
+        assert_eq!(
+            value(&engine, "t", "k3"),
+            Some(serde_json::json!({ "n": 3, "drop": "me" }))
+        );
+        assert_eq!(value(&engine, "t", "k5"), None);
+    }
+
+    #[tokio::test]
+    async fn role_filters_and_redactions() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        set(
+            &engine,
+            "u",
+            "ann",
+            serde_json::json!({ "team": "a", "ssn": "123", "email": "ann@x" }),
+        )
+        .await;
+        set(
+            &engine,
+            "u",
+            "bob",
+            serde_json::json!({ "team": "b", "ssn": "456", "email": "bob@x" }),
+        )
+        .await;
+        engine.set_role_filter(
+            &UserRole::User,
+            "u".to_string(),
+            condition("team", QueryOperator::Equals, serde_json::json!("a")),
+        );
+        engine.set_redaction("u".to_string(), "ssn".to_string(), RedactionAction::Drop);
+        engine.set_redaction("u".to_string(), "email".to_string(), RedactionAction::Mask);
+
+        assert!(
+            engine
+                .get_row_as(&UserRole::User, "u".to_string(), "bob".to_string())
+                .is_none()
+        );
+        let ann = engine
+            .get_row_as(&UserRole::User, "u".to_string(), "ann".to_string())
+            .unwrap();
+        assert_eq!(
+            ann.value,
+            serde_json::json!({ "team": "a", "email": REDACTION_MASK })
+        );
+        let rows = engine
+            .query_rows_as(
+                &UserRole::User,
+                "u".to_string(),
+                QueryExpr::all(),
+                None,
+                None,
+            )
+            .unwrap();
+        assert_eq!(keys(rows), vec!["ann"]);
+
+        // Redacted fields cannot be probed through query conditions.
+        let rows = engine
+            .query_rows_as(
+                &UserRole::User,
+                "u".to_string(),
+                condition("ssn", QueryOperator::Equals, serde_json::json!("123")),
+                None,
+                None,
+            )
+            .unwrap();
+        assert!(rows.is_empty());
+
+        // Bulk writes leave the rows a role cannot see alone.
+        let updated = engine
+            .update_rows_where(
+                &UserRole::User,
+                "u".to_string(),
+                QueryExpr::all(),
+                serde_json::json!({ "seen": true }),
+                &|_| Ok(()),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated, 1);
+
+        // Nor can their conditions probe redacted fields.
+        let deleted = engine
+            .remove_rows_where(
+                &UserRole::User,
+                "u".to_string(),
+                condition("ssn", QueryOperator::Equals, serde_json::json!("123")),
+                &|_| Ok(()),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(deleted, 0);
+
+        // Single-key writes cannot reach them by key either, though new keys are fine.
+        assert!(matches!(
+            engine.check_writable_as(&UserRole::User, "u", "bob"),
+            Err(Error::PermissionDenied(_))
+        ));
+        assert!(
+            engine
+                .check_writable_as(&UserRole::User, "u", "ann")
+                .is_ok()
+        );
+        assert!(
+            engine
+                .check_writable_as(&UserRole::User, "u", "new")
+                .is_ok()
+        );
+        assert!(
+            engine
+                .check_writable_as(&UserRole::Admin, "u", "bob")
+                .is_ok()
+        );
+
+        let admin = engine
+            .get_row_as(&UserRole::Admin, "u".to_string(), "bob".to_string())
+            .unwrap();
+        assert_eq!(admin.value["ssn"], serde_json::json!("456"));
+        assert_eq!(admin.value.get("seen"), None);
+        assert!(
+            engine
+                .get_row_as(&UserRole::Admin, "u".to_string(), "ann".to_string())
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn import_replaces_the_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        set(&engine, "staging", "stale", serde_json::json!(1)).await;
+        let file = dir.path().join("rows.jsonl");
+        std::fs::write(
+            &file,
+            "{\"key\":\"a|1\",\"value\":{\"text\":\"line\\nbreak\"}}\n\n{\"key\":\"b\",\"value\":2}\n",
+        )
+        .unwrap();
+
+        let imported = engine
+            .import_table(
+                "staging".to_string(),
+                &file,
+                crate::import::ImportFormat::Jsonl,
+            )
+            .await
+            .unwrap();
+        assert_eq!(imported, 2);
+
+        let engine = reopen(engine, dir.path()).await;
+        assert_eq!(value(&engine, "staging", "stale"), None);
+        assert_eq!(
+            value(&engine, "staging", "a|1"),
+            Some(serde_json::json!({ "text": "line\nbreak" }))
+        );
+
+        std::fs::write(&file, "{\"key\":\"ok\",\"value\":1}\nnot json\n").unwrap();
+        assert!(
+            engine
+                .import_table(
+                    "staging".to_string(),
+                    &file,
+                    crate::import::ImportFormat::Jsonl
+                )
+                .await
+                .is_err()
+        );
+        assert_eq!(engine.table_len("staging"), 2);
+    }
+
+    #[tokio::test]
+    async fn seed_modes_decide_what_happens_to_existing_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        let fixture = dir.path().join("fixture.json");
+        std::fs::write(
+            &fixture,
+            r#"{"users": {"ann": {"n": 1}, "bob": {"n": 2}}, "orders": {"o1": {"total": 5}}}"#,
+        )
+        .unwrap();
+        let seed = |mode| {
+            let (engine, fixture) = (engine.clone(), fixture.clone());
+            async move { engine.seed_from_path(&fixture, mode).await.unwrap() }
+        };
+
+        set(&engine, "users", "ann", serde_json::json!({ "n": 0 })).await;
+        set(&engine, "users", "cat", serde_json::json!({ "n": 3 })).await;
+        assert_eq!(seed(SeedMode::Empty).await, [("orders".to_string(), 1)]);
+        assert_eq!(
+            value(&engine, "users", "ann"),
+            Some(serde_json::json!({ "n": 0 }))
+        );
+
+        seed(SeedMode::Merge).await;
+        assert_eq!(
+            value(&engine, "users", "ann"),
+            Some(serde_json::json!({ "n": 1 }))
+        );
+        assert_eq!(engine.table_len("users"), 3);
+
+        seed(SeedMode::Overwrite).await;
+        let engine = reopen(engine, dir.path()).await;
+        assert_eq!(value(&engine, "users", "cat"), None);
+        assert_eq!(engine.table_len("users"), 2);
+        assert_eq!(engine.table_len("orders"), 1);
+
+        std::fs::write(&fixture, r#"{"users": [1, 2]}"#).unwrap();
+        assert!(
+            engine
+                .seed_from_path(&fixture, SeedMode::Merge)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn vacuum_keeps_the_replayed_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        for n in 0..20 {
+            set(&engine, "t", "hot", serde_json::json!(n)).await;
+        }
+        set(&engine, "t", "gone", serde_json::json!(1)).await;
+        engine
+            .remove_row("t".to_string(), "gone".to_string())
+            .await
+            .unwrap();
+        set(&engine, "other", "k", serde_json::json!("untouched")).await;
+
+        let stats = engine.vacuum_table("t".to_string()).await.unwrap();
+        assert!(stats.entries_removed >= 19);
+        assert!(stats.bytes_after < stats.bytes_before);
+
+        let engine = reopen(engine, dir.path()).await;
+        assert_eq!(value(&engine, "t", "hot"), Some(serde_json::json!(19)));
+        assert_eq!(value(&engine, "t", "gone"), None);
+        assert_eq!(
+            value(&engine, "other", "k"),
+            Some(serde_json::json!("untouched"))
+        );
+    }
+
+    #[tokio::test]
+    async fn consistency_check_matches_after_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        set(&engine, "t", "a", serde_json::json!({ "v": "x|y" })).await;
+        set(&engine, "t", "b", serde_json::json!(2)).await;
+        engine
+            .remove_row("t".to_string(), "b".to_string())
+            .await
+            .unwrap();
+        engine.truncate_table("t".to_string()).await.unwrap();
+        set(&engine, "t", "c", serde_json::json!(3)).await;
+
+        let report = engine.check_consistency().await.unwrap();
+        assert!(
+            report.discrepancies.is_empty(),
+            "{:?}",
+            report.discrepancies
+        );
+        assert_eq!(report.rows, 1);
+    }
+
+    #[tokio::test]
+    async fn memory_storage_keeps_nothing_across_restarts() {
+        let engine = Engine::new(
+            "test".to_string(),
+            String::new(),
+            Box::new(crate::storage::MemoryStorage::default()),
+        );
+        engine.replay_log().await.unwrap();
+        set(&engine, "t", "k", serde_json::json!(1)).await;
+        assert_eq!(value(&engine, "t", "k"), Some(serde_json::json!(1)));
+        assert_eq!(engine.storage.last_sequence(), 1);
+
+        let engine = Engine::new(
+            "test".to_string(),
+            String::new(),
+            Box::new(crate::storage::MemoryStorage::default()),
+        );
+        engine.replay_log().await.unwrap();
+        assert_eq!(value(&engine, "t", "k"), None);
+    }
+
+    #[tokio::test]
+    async fn applied_sequence_waits_for_earlier_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        let entry = |key: &str| {
+            crate::logs::LogEntry::new(
+                "add".to_string(),
+                "t".to_string(),
+                key.to_string(),
+                Some(serde_json::json!(1)),
+                0,
+                None,
+                0,
+            )
+        };
+
+        // Numbered but not yet applied: neither write counts.
+        let (_, first) = engine.append(vec![entry("a")]);
+        let (_, second) = engine.append(vec![entry("b")]);
+        assert_eq!(engine.storage.last_sequence(), 2);
+        assert_eq!(engine.applied_sequence(), 0);
+        drop(second);
+        assert_eq!(engine.applied_sequence(), 0);
+        drop(first);
+        assert_eq!(engine.applied_sequence(), 2);
+
+        set(&engine, "t", "c", serde_json::json!(3)).await;
+        assert_eq!(engine.applied_sequence(), 3);
+        let engine = reopen(engine, dir.path()).await;
+        assert_eq!(engine.applied_sequence(), 3);
+    }
+
+    #[tokio::test]
+    async fn auto_keys_are_ordered_and_idempotent() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+
+        let generated: Vec<String> = (0..5000).map(|_| engine.generate_key()).collect();
+        let uuid = regex::Regex::new(
+            "^[0-9a-f]{8}-[0-9a-f]{4}-7[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$",
+        )
+        .unwrap();
+        assert!(generated.iter().all(|key| uuid.is_match(key)));
+        assert!(generated.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let key = engine
+            .add_row_auto(
+                "events".to_string(),
+                serde_json::json!({ "n": 1 }),
+                WriteOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            value(&engine, "events", &key),
+            Some(serde_json::json!({ "n": 1 }))
+        );
+
+        let retry = || WriteOptions {
+            idempotency_key: Some("batch-1".to_string()),
+            ..WriteOptions::default()
+        };
+        let first = engine
+            .add_row_auto("events".to_string(), serde_json::json!({ "n": 2 }), retry())
+            .await
+            .unwrap();
+        let second = engine
+            .add_row_auto("events".to_string(), serde_json::json!({ "n": 2 }), retry())
+            .await
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(engine.table_len("events"), 2);
+
+        let engine = reopen(engine, dir.path()).await;
+        assert_eq!(
+            value(&engine, "events", &first),
+            Some(serde_json::json!({ "n": 2 }))
+        );
+    }
+
+    #[tokio::test]
+    async fn persisted_rows_do_not_expire() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        engine.set_expiry(
+            "s".to_string(),
+            CREATED_AT_FIELD.to_string(),
+            chrono::Duration::hours(1),
+        );
+        set(&engine, "s", "a", serde_json::json!({})).await;
+        set(&engine, "s", "b", serde_json::json!({})).await;
+        engine
+            .persist_row("s".to_string(), "b".to_string())
+            .await
+            .unwrap();
+        assert!(
+            engine
+                .persist_row("s".to_string(), "missing".to_string())
+                .await
+                .is_err()
+        );
+
+        let expiring = condition(
+            EXPIRES_AT_FIELD,
+            QueryOperator::GreaterThan,
+            serde_json::json!("1970-01-01"),
+        );
+        assert_eq!(
+            keys(
+                engine
+                    .query_rows(&UserRole::Admin, "s".to_string(), expiring.clone(), None)
+                    .unwrap()
+            ),
+            ["a"]
+        );
+
+        let engine = reopen(engine, dir.path()).await;
+        engine.set_expiry(
+            "s".to_string(),
+            CREATED_AT_FIELD.to_string(),
+            chrono::Duration::hours(1),
+        );
+        assert_eq!(
+            keys(
+                engine
+                    .query_rows(&UserRole::Admin, "s".to_string(), expiring, None)
+                    .unwrap()
+            ),
+            ["a"]
+        );
+        let cutoff = chrono::Utc::now() + chrono::Duration::hours(1);
+        assert_eq!(
+            engine
+                .apply_retention("s", CREATED_AT_FIELD, Some(cutoff), None)
+                .await
+                .unwrap(),
+            (1, 0)
+        );
+        assert!(value(&engine, "s", "b").is_some());
+
+        set(&engine, "s", "b", serde_json::json!({ "v": 2 })).await;
+        assert!(
+            engine
+                .get_row("s".to_string(), "b".to_string())
+                .is_some_and(|row| !row.meta.persist)
+        );
+    }
+
+    #[tokio::test]
+    async fn range_index_follows_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = open(dir.path()).await;
+        set(
+            &engine,
+            "p",
+            "nyc",
+            serde_json::json!({ "lat": 40.7, "lng": -74.0 }),
+        )
+        .await;
+        set(
+            &engine,
+            "p",
+            "zero",
+            serde_json::json!({ "lat": -0.0, "lng": 0 }),
+        )
+        .await;
+        set(
+            &engine,
+            "p",
+            "text",
+            serde_json::json!({ "lat": "40.7", "lng": -74.0 }),
+        )
+        .await;
+        let fields = ["lng".to_string(), "lat".to_string()];
+        assert!(
+            engine
+                .create_range_index("p".to_string(), fields.clone())
+                .await
+        );
+        assert!(
+            !engine
+                .create_range_index("p".to_string(), fields.clone())
+                .await
+        );
+
+        let within = |bounds: [(f64, f64); 2]| {
+            QueryExpr::Within(WithinCondition {
+                fields: ["lat".to_string(), "lng".to_string()],
+                bounds,
+            })
+        };
+        let query = |filter: QueryExpr| {
+            let (index, _) = engine.indexed_keys("p", &filter).unwrap();
+            assert_eq!(index, fields);
+            keys(
+                engine
+                    .query_rows(&UserRole::Admin, "p".to_string(), filter, None)
+                    .unwrap(),
+            )
+        };
+        let new_york = within([(40.0, 41.0), (-75.0, -73.0)]);
+        assert_eq!(query(new_york.clone()), vec!["nyc"]);
+        assert_eq!(query(within([(0.0, 0.0), (0.0, 0.0)])), vec!["zero"]);
+        assert_eq!(
+            query(within([(41.0, 40.0), (-75.0, -73.0)])),
+            Vec::<String>::new()
+        );
+
+        set(
+            &engine,
+            "p",
+            "jfk",
+            serde_json::json!({ "lat": 40.6, "lng": -73.8 }),
+        )
+        .await;
+        set(
+            &engine,
+            "p",
+            "nyc",
+            serde_json::json!({ "lat": 51.5, "lng": -0.1 }),
+        )
+        .await;
+        set(
+            &engine,
+            "p",
+            "text",
+            serde_json::json!({ "lat": 40.5, "lng": -74.0 }),
+        )
+        .await;
+        assert_eq!(query(new_york.clone()), vec!["jfk", "text"]);
+        engine
+            .remove_row("p".to_string(), "jfk".to_string())
+            .await
+            .unwrap();
+        assert_eq!(query(new_york.clone()), vec!["text"]);
+
+        // The index belongs to the table name, so it follows the rows swapped in.
+        set(
+            &engine,
+            "q",
+            "ewr",
+            serde_json::json!({ "lat": 40.7, "lng": -74.2 }),
+        )
+        .await;
+        engine
+            .swap_tables("p".to_string(), "q".to_string())
+            .await
+            .unwrap();
+        assert_eq!(query(new_york.clone()), vec!["ewr"]);
+        engine.truncate_table("p".to_string()).await.unwrap();
+        assert_eq!(query(new_york.clone()), Vec::<String>::new());
+        assert_eq!(
+            engine.list_range_indexes(),
+            vec![("p".to_string(), fields.clone(), 0)]
+        );
+
+        // A WITHIN under OR, or over other fields, needs a full scan.
+        let either = QueryExpr::Or(vec![
+            new_york.clone(),
+            condition("lat", QueryOperator::Equals, serde_json::json!(1)),
+        ]);
+        assert!(engine.indexed_keys("p", &either).is_none());
+        assert!(engine.drop_range_index("p", &fields));
+        assert!(!engine.drop_range_index("p", &fields));
+        assert!(engine.indexed_keys("p", &new_york).is_none());
+    }
+}
+
+#[cfg(test)]
+mod benches {
+    use super::*;
+
+    fn bench_engine(name: &str) -> Arc<Engine> {
+        let dir =
+            std::env::temp_dir().join(format!("sharknado-bench-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let storage = crate::logs::LogStorageSetup::new(
+            dir.join(format!("{}.log", name)),
+            crate::logs::WalOptions::default(),
+        );
+        Arc::new(Engine::new(
+            name.to_string(),
+            dir.to_string_lossy().to_string(),
+            Box::new(storage),
+        ))
+    }
+
+    async fn fill_table(engine: &Arc<Engine>, table: &str, rows: usize) {
+        let path = std::env::temp_dir().join(format!(
+            "sharknado-bench-{}-{}.jsonl",
+            table,
+            std::process::id()
+        ));
+        let contents: String = (0..rows)
+            .map(|i| format!("{{\"key\":\"{}\",\"value\":{{\"n\":{}}}}}\n", i, i))
+            .collect();
+        std::fs::write(&path, contents).unwrap();
+        engine
+            .import_table(table.to_string(), &path, crate::import::ImportFormat::Jsonl)
+            .await
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    async fn writes_per_second(engine: &Arc<Engine>, write_table: &str, scan_table: &str) -> f64 {
+        let scanning = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let scanners: Vec<_> = (0..4)
+            .map(|_| {
+                let engine = engine.clone();
+                let scanning = scanning.clone();
+                let scan_table = scan_table.to_string();
+                tokio::task::spawn_blocking(move || {
+                    let filter = QueryExpr::Condition(QueryCondition {
+                        field_path: "n".to_string(),
+                        operator: QueryOperator::LessThan,
+                        value: serde_json::json!(0),
+                        pattern: None,
+                    });
+                    while scanning.load(Ordering::Relaxed) {
+                        engine
+                            .query_rows(&UserRole::Admin, scan_table.clone(), filter.clone(), None)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let started = std::time::Instant::now();
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let engine = engine.clone();
+                let write_table = write_table.to_string();
+                tokio::spawn(async move {
+                    for i in 0..250 {
+                        engine
+                            .add_row(
+                                write_table.clone(),
+                                format!("w{}-{}", writer, i),
+                                serde_json::json!({ "n": -1 }),
+                                WriteOptions::default(),
+                            )
+                            .await
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        let elapsed = started.elapsed();
+
+        scanning.store(false, Ordering::Relaxed);
+        for scanner in scanners {
+            scanner.await.unwrap();
+        }
+        1000.0 / elapsed.as_secs_f64()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore = "benchmark: cargo test --release -- --ignored --nocapture"]
+    async fn bench_writes_during_scans() {
+        let engine = bench_engine("sharded");
+        fill_table(&engine, "scanned", 200_000).await;
+
+        let other_table = writes_per_second(&engine, "writes", "scanned").await;
+        let same_table = writes_per_second(&engine, "scanned", "scanned").await;
+
+        println!(
+            "writes/s while another table is scanned: {:.0}",
+            other_table
+        );
+        println!(
+            "writes/s while the same table is scanned: {:.0}",
+            same_table
+        );
+    }
 }
@@ -0,0 +1,63 @@
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Io(String),
+    #[error("{0}")]
+    Parse(String),
+    #[error("{0}")]
+    Auth(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    PermissionDenied(String),
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    ReadOnly(String),
+    #[error("{0}")]
+    Quota(String),
+    #[error("{0}")]
+    Script(String),
+    #[error("Command timed out")]
+    Timeout,
+    #[error("Write could not be logged: {0}")]
+    Wal(#[from] crate::logs::WalError),
+}
+
+impl Error {
+    // Stable codes sent with every error response, so clients can branch without parsing messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "ERR_IO",
+            Error::Parse(_) => "ERR_SYNTAX",
+            Error::Auth(_) => "ERR_AUTH",
+            Error::NotFound(_) => "ERR_NOT_FOUND",
+            Error::PermissionDenied(_) => "ERR_PERMISSION",
+            Error::InvalidArgument(_) => "ERR_INVALID",
+            Error::Conflict(_) => "ERR_CONFLICT",
+            Error::ReadOnly(_) => "ERR_READ_ONLY",
+            Error::Quota(_) => "ERR_QUOTA",
+            Error::Script(_) => "ERR_SCRIPT",
+            Error::Timeout => "ERR_TIMEOUT",
+            Error::Wal(_) => "ERR_WAL",
+        }
+    }
+}
+
+// io::Error is neither Clone nor PartialEq, and idempotent writes replay their cached result,
+// so only the message is kept.
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e.to_string())
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(e: tokio::task::JoinError) -> Self {
+        Error::Io(e.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,103 @@
+pub type ResultRows = Vec<(String, serde_json::Value)>;
+
+pub fn parse_query_results(response: &str) -> Option<ResultRows> {
+    if response.starts_with(crate::helpers::messages::Messages::QUERY_NO_RESULTS) {
+        return Some(Vec::new());
+    }
+
+    let mut lines = response.lines();
+    let header = lines.next()?;
+    let is_query = header.starts_with("Found ") && header.ends_with(" results:");
+    if !is_query && !header.starts_with("CURSOR ") {
+        return None;
+    }
+
+    let mut rows = Vec::new();
+    for line in lines {
+        if let Some(row) = parse_result_line(line) {
+            rows.push(row);
+        }
+    }
+    Some(rows)
+}
+
+fn parse_result_line(line: &str) -> Option<(String, serde_json::Value)> {
+    // Keys may contain ": ", so take the first split whose remainder is valid JSON.
+    line.match_indices(": ").find_map(|(position, _)| {
+        serde_json::from_str(&line[position + 2..])
+            .ok()
+            .map(|value| (line[..position].to_string(), value))
+    })
+}
+
+pub fn write_results(path: &str, rows: &ResultRows) -> Result<(), String> {
+    let contents = if path.to_lowercase().ends_with(".csv") {
+        to_csv(rows)
+    } else {
+        to_jsonl(rows)
+    };
+    std::fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+fn to_jsonl(rows: &ResultRows) -> String {
+    let mut output = String::new();
+    for (key, value) in rows {
+        output.push_str(&serde_json::json!({ "key": key, "value": value }).to_string());
+        output.push('\n');
+    }
+    output
+}
+
+fn to_csv(rows: &ResultRows) -> String {
+    let mut columns: Vec<String> = Vec::new();
+    let mut has_scalars = false;
+    for (_, value) in rows {
+        match value.as_object() {
+            Some(object) => {
+                for field in object.keys() {
+                    if !columns.contains(field) {
+                        columns.push(field.clone());
+                    }
+                }
+            }
+            None => has_scalars = true,
+        }
+    }
+    if has_scalars && !columns.iter().any(|column| column == "value") {
+        columns.push("value".to_string());
+    }
+
+    let mut output = String::from("key");
+    for column in &columns {
+        output.push(',');
+        output.push_str(&csv_cell(column));
+    }
+    output.push('\n');
+
+    for (key, value) in rows {
+        output.push_str(&csv_cell(key));
+        for column in &columns {
+            output.push(',');
+            let cell = match value.as_object() {
+                Some(object) => object.get(column),
+                None if column == "value" => Some(value),
+                None => None,
+            };
+            match cell {
+                Some(serde_json::Value::String(text)) => output.push_str(&csv_cell(text)),
+                Some(serde_json::Value::Null) | None => {}
+                Some(other) => output.push_str(&csv_cell(&other.to_string())),
+            }
+        }
+        output.push('\n');
+    }
+    output
+}
+
+fn csv_cell(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
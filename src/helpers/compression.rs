@@ -0,0 +1,144 @@
+use std::io::{Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_str(name: &str) -> Option<Compression> {
+        match name.to_lowercase().as_str() {
+            "gzip" => Some(Compression::Gzip),
+            "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Compression::Zstd => zstd::encode_all(data, 0),
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        self.decompress_limited(data, u64::MAX)
+    }
+
+    // Stops decoding past `limit` bytes so a tiny frame cannot expand into unbounded memory.
+    pub fn decompress_limited(&self, data: &[u8], limit: u64) -> std::io::Result<Vec<u8>> {
+        let decoder: Box<dyn Read + '_> = match self {
+            Compression::Gzip => Box::new(flate2::read::GzDecoder::new(data)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(data)?),
+        };
+        let mut decoded = Vec::new();
+        decoder
+            .take(limit.saturating_add(1))
+            .read_to_end(&mut decoded)?;
+        if decoded.len() as u64 > limit {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("decoded frame is larger than {} bytes", limit),
+            ));
+        }
+        Ok(decoded)
+    }
+}
+
+#[derive(Default)]
+pub struct CompressionStats {
+    frames: std::sync::atomic::AtomicU64,
+    raw_bytes: std::sync::atomic::AtomicU64,
+    wire_bytes: std::sync::atomic::AtomicU64,
+}
+
+impl CompressionStats {
+    pub fn record(&self, raw_bytes: usize, wire_bytes: usize) {
+        use std::sync::atomic::Ordering;
+
+        self.frames.fetch_add(1, Ordering::Relaxed);
+        self.raw_bytes
+            .fetch_add(raw_bytes as u64, Ordering::Relaxed);
+        self.wire_bytes
+            .fetch_add(wire_bytes as u64, Ordering::Relaxed);
+    }
+
+    // (frames, raw bytes, wire bytes, raw/wire ratio)
+    pub fn snapshot(&self) -> (u64, u64, u64, f64) {
+        use std::sync::atomic::Ordering;
+
+        let raw_bytes = self.raw_bytes.load(Ordering::Relaxed);
+        let wire_bytes = self.wire_bytes.load(Ordering::Relaxed);
+        let ratio = if wire_bytes == 0 {
+            0.0
+        } else {
+            raw_bytes as f64 / wire_bytes as f64
+        };
+        (
+            self.frames.load(Ordering::Relaxed),
+            raw_bytes,
+            wire_bytes,
+            ratio,
+        )
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+pub fn encode_frame(compression: Compression, min_size: usize, payload: &[u8]) -> Vec<u8> {
+    let (encoding, body) = if payload.len() >= min_size {
+        match compression.compress(payload) {
+            Ok(compressed) if compressed.len() < payload.len() => {
+                (compression.to_string(), compressed)
+            }
+            _ => ("none".to_string(), payload.to_vec()),
+        }
+    } else {
+        ("none".to_string(), payload.to_vec())
+    };
+
+    let mut frame = format!("FRAME {} {}\n", encoding, body.len()).into_bytes();
+    frame.extend_from_slice(&body);
+    frame
+}
+
+// Picks the first of the server's algorithms that the client also offered.
+pub fn negotiate(preferred: &[Compression], offered: &[Compression]) -> Option<Compression> {
+    preferred
+        .iter()
+        .copied()
+        .find(|compression| offered.contains(compression))
+}
+
+pub fn parse_frame_header(header: &str) -> Option<(Option<Compression>, usize)> {
+    let mut parts = header.split_whitespace();
+    if parts.next()? != "FRAME" {
+        return None;
+    }
+    let compression = match parts.next()? {
+        "none" => None,
+        name => Some(Compression::from_str(name)?),
+    };
+    let length = parts.next()?.parse().ok()?;
+    Some((compression, length))
+}
+
+pub fn decode_frame(compression: Option<Compression>, body: &[u8]) -> std::io::Result<Vec<u8>> {
+    match compression {
+        Some(compression) => compression.decompress(body),
+        None => Ok(body.to_vec()),
+    }
+}
@@ -1,11 +1,44 @@
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct ServerConfig {
     #[serde(default = "default_host")]
     pub host: String,
     #[serde(default = "default_port")]
     pub port: u16,
+    // "host:port" pairs; when set, these replace host and port.
+    #[serde(default)]
+    pub listen: Vec<String>,
+    #[serde(default = "default_command_timeout_ms")]
+    pub command_timeout_ms: u64,
+    #[serde(default = "default_login_timeout_ms")]
+    pub login_timeout_ms: u64,
+    #[serde(default = "default_max_unauthenticated_per_ip")]
+    pub max_unauthenticated_per_ip: usize,
+    #[serde(default = "default_max_request_bytes")]
+    pub max_request_bytes: usize,
 }
-#[derive(serde::Deserialize, Debug)]
+impl ServerConfig {
+    pub fn listen_addresses(&self) -> Vec<String> {
+        if self.listen.is_empty() {
+            vec![format!("{}:{}", self.host, self.port)]
+        } else {
+            self.listen.clone()
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default)]
+pub struct HealthConfig {
+    #[serde(default)]
+    pub require_auth: bool,
+}
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct QueryConfig {
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default = "default_query_max_result_rows")]
+    pub max_result_rows: usize,
+}
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct LoggingSetup {
     #[serde(default = "default_log_level")]
     pub levels: Vec<String>,
@@ -13,21 +46,286 @@ pub struct LoggingSetup {
     pub path: String,
     #[serde(default = "default_color")]
     pub color: bool,
+    #[serde(default = "default_log_format")]
+    pub format: String,
+    #[serde(default = "default_log_rotation")]
+    pub rotation: LogRotationConfig,
+    #[serde(default = "default_log_flush_interval_ms")]
+    pub flush_interval_ms: u64,
+    #[serde(default = "default_log_timestamp_format")]
+    pub timestamp_format: String,
+    #[serde(default = "default_log_timezone")]
+    pub timezone: String,
+}
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct LogRotationConfig {
+    #[serde(default)]
+    pub max_bytes: u64,
+    #[serde(default)]
+    pub interval_secs: u64,
+    #[serde(default = "default_log_rotation_keep")]
+    pub keep: usize,
+    #[serde(default)]
+    pub compress: bool,
 }
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct LoggingConfig {
     #[serde(default = "default_main_logging")]
     pub main: LoggingSetup,
     #[serde(default = "default_tcp_logging")]
     pub tcp: LoggingSetup,
+    #[serde(default = "default_engine_logging")]
+    pub engine: LoggingSetup,
+    #[serde(default)]
+    pub modules: std::collections::HashMap<String, Vec<String>>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct StatsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_stats_sample_rate")]
+    pub sample_rate: u64,
+    #[serde(default = "default_stats_max_rows")]
+    pub max_rows: u64,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct StorageConfig {
+    #[serde(default = "default_storage_codec")]
+    pub codec: String,
+    #[serde(default)]
+    pub data_dir: Option<String>,
+    // "wal" or "memory"; memory keeps no log, so data does not survive a restart.
+    #[serde(default = "default_storage_mode")]
+    pub mode: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct AdminConfig {
+    #[serde(default = "default_admin_username")]
+    pub username: String,
+    #[serde(default = "default_admin_password")]
+    pub password: String,
+    #[serde(default = "default_admin_create_default")]
+    pub create_default: bool,
+}
+
+impl AdminConfig {
+    pub fn has_default_password(&self) -> bool {
+        self.password == default_admin_password()
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct CompressionConfig {
+    #[serde(default = "default_compression_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_compression_min_size")]
+    pub min_size: usize,
+    #[serde(default = "default_compression_algorithms")]
+    pub algorithms: Vec<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct WalConfig {
+    #[serde(default = "default_wal_max_batch_delay_us")]
+    pub max_batch_delay_us: u64,
+    #[serde(default = "default_wal_max_batch_entries")]
+    pub max_batch_entries: usize,
+    #[serde(default)]
+    pub sync: bool,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct StandbyConfig {
+    #[serde(default)]
+    pub archive_dir: Option<String>,
+    #[serde(default = "default_standby_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct RowLocksConfig {
+    #[serde(default = "default_row_lock_ttl_ms")]
+    pub ttl_ms: u64,
+    #[serde(default = "default_row_lock_wait_ms")]
+    pub wait_ms: u64,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct CdcKafkaConfig {
+    pub brokers: String,
+    pub topic: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct CdcNatsConfig {
+    pub url: String,
+    pub subject: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct CdcConfig {
+    #[serde(default)]
+    pub kafka: Option<CdcKafkaConfig>,
+    #[serde(default)]
+    pub nats: Option<CdcNatsConfig>,
+    #[serde(default = "default_cdc_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default)]
+pub struct AuthEventsConfig {
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub webhook: Option<String>,
+    #[serde(default)]
+    pub syslog: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct MaintenanceConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_maintenance_notice")]
+    pub notice: String,
 }
 
-#[derive(serde::Deserialize, Debug)]
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct IdempotencyConfig {
+    #[serde(default = "default_idempotency_window_secs")]
+    pub window_secs: u64,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct RetentionPolicyConfig {
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+    #[serde(default = "default_retention_age_field")]
+    pub age_field: String,
+    #[serde(default)]
+    pub max_rows: Option<usize>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct RetentionConfig {
+    #[serde(default = "default_retention_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub tables: std::collections::HashMap<String, RetentionPolicyConfig>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct ClusterNodeConfig {
+    pub address: String,
+    // Inclusive [first, last] ranges of the hash slots this node owns.
+    pub slots: Vec<[u16; 2]>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Default)]
+pub struct ClusterConfig {
+    // This node's address, as listed in nodes.
+    #[serde(default)]
+    pub node: Option<String>,
+    #[serde(default)]
+    pub nodes: Vec<ClusterNodeConfig>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct TriggerConfig {
+    pub name: String,
+    #[serde(default)]
+    pub webhook: Option<String>,
+    // Only settable here, never over TCP, since it runs a program on the server.
+    #[serde(default)]
+    pub command: Option<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct TriggersConfig {
+    #[serde(default = "default_trigger_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_trigger_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    // Defaults to <database>.triggers.dead.jsonl in the data directory.
+    #[serde(default)]
+    pub dead_letter_path: Option<String>,
+    #[serde(default = "default_trigger_queue_size")]
+    pub queue_size: usize,
+    #[serde(default)]
+    pub tables: std::collections::HashMap<String, Vec<TriggerConfig>>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct ScriptingConfig {
+    #[serde(default = "default_scripting_enabled")]
+    pub enabled: bool,
+    #[serde(default = "default_scripting_max_instructions")]
+    pub max_instructions: u64,
+    #[serde(default = "default_scripting_max_memory_bytes")]
+    pub max_memory_bytes: usize,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
+pub struct SeedConfig {
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default = "default_seed_mode")]
+    pub mode: String,
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug)]
 pub struct Config {
     #[serde(default = "default_server")]
     pub server: ServerConfig,
     #[serde(default = "default_logging")]
     pub logging: LoggingConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default = "default_query")]
+    pub query: QueryConfig,
+    #[serde(default = "default_storage")]
+    pub storage: StorageConfig,
+    #[serde(default = "default_admin")]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub auth_events: AuthEventsConfig,
+    #[serde(default = "default_wal")]
+    pub wal: WalConfig,
+    #[serde(default = "default_compression")]
+    pub compression: CompressionConfig,
+    #[serde(default = "default_idempotency")]
+    pub idempotency: IdempotencyConfig,
+    #[serde(default = "default_standby")]
+    pub standby: StandbyConfig,
+    #[serde(default = "default_cdc")]
+    pub cdc: CdcConfig,
+    #[serde(default = "default_row_locks")]
+    pub row_locks: RowLocksConfig,
+    #[serde(default = "default_maintenance")]
+    pub maintenance: MaintenanceConfig,
+    #[serde(default = "default_seed")]
+    pub seed: SeedConfig,
+    #[serde(default)]
+    pub shadow_tables: std::collections::HashMap<String, String>,
+    #[serde(default = "default_stats")]
+    pub stats: StatsConfig,
+    #[serde(default)]
+    pub redactions: std::collections::HashMap<String, std::collections::HashMap<String, String>>,
+    #[serde(default)]
+    pub range_indexes: std::collections::HashMap<String, Vec<[String; 2]>>,
+    #[serde(default)]
+    pub row_filters: std::collections::HashMap<String, std::collections::HashMap<String, String>>,
+    #[serde(default = "default_retention")]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub cluster: ClusterConfig,
+    #[serde(default = "default_scripting")]
+    pub scripting: ScriptingConfig,
+    #[serde(default = "default_triggers")]
+    pub triggers: TriggersConfig,
 }
 
 pub fn log_level_from_strings(levels: &Vec<String>) -> crate::helpers::logging::LogLevel {
@@ -38,6 +336,7 @@ pub fn log_level_from_strings(levels: &Vec<String>) -> crate::helpers::logging::
             "DEBUG" => log_level |= crate::helpers::logging::LogLevel::DEBUG,
             "WARNING" => log_level |= crate::helpers::logging::LogLevel::WARNING,
             "ERROR" => log_level |= crate::helpers::logging::LogLevel::ERROR,
+            "TRACE" => log_level |= crate::helpers::logging::LogLevel::TRACE,
             "ALL" => log_level = crate::helpers::logging::LogLevel::ALL,
             "NONE" => log_level = crate::helpers::logging::LogLevel::NONE,
             _ => continue,
@@ -46,6 +345,23 @@ pub fn log_level_from_strings(levels: &Vec<String>) -> crate::helpers::logging::
     log_level
 }
 
+// Module names may leave off the sharknado:: prefix, so "engine" and "sharknado::engine" match alike.
+pub fn log_module_levels(
+    modules: &std::collections::HashMap<String, Vec<String>>,
+) -> Vec<(String, crate::helpers::logging::LogLevel)> {
+    modules
+        .iter()
+        .map(|(module, levels)| {
+            let module = if module.starts_with("sharknado") {
+                module.clone()
+            } else {
+                format!("sharknado::{}", module)
+            };
+            (module, log_level_from_strings(levels))
+        })
+        .collect()
+}
+
 pub fn log_path_from_string(path: &String) -> crate::helpers::logging::LogPath {
     if path == "console" {
         crate::helpers::logging::LogPath::Console
@@ -54,29 +370,741 @@ pub fn log_path_from_string(path: &String) -> crate::helpers::logging::LogPath {
     }
 }
 
+pub fn log_format_from_string(format: &str) -> crate::helpers::logging::LogFormat {
+    match format.to_lowercase().as_str() {
+        "json" => crate::helpers::logging::LogFormat::Json,
+        "text" => crate::helpers::logging::LogFormat::Text,
+        _ => {
+            eprintln!("Warning: Unknown log format '{}', using text", format);
+            crate::helpers::logging::LogFormat::Text
+        }
+    }
+}
+
+pub fn log_rotation_from_config(
+    config: &LogRotationConfig,
+) -> crate::helpers::logging::LogRotation {
+    crate::helpers::logging::LogRotation {
+        max_bytes: config.max_bytes,
+        interval: (config.interval_secs > 0)
+            .then(|| std::time::Duration::from_secs(config.interval_secs)),
+        keep: config.keep,
+        compress: config.compress,
+    }
+}
+
+pub fn log_timestamp_from_config(setup: &LoggingSetup) -> crate::helpers::logging::LogTimestamp {
+    use crate::helpers::logging::{LogTimestamp, LogTimezone};
+
+    let mut timestamp = LogTimestamp::default();
+    // chrono panics when asked to render a malformed format, so reject it here instead.
+    if chrono::format::StrftimeItems::new(&setup.timestamp_format)
+        .any(|item| matches!(item, chrono::format::Item::Error))
+    {
+        eprintln!(
+            "Warning: Invalid log timestamp format '{}', using {}",
+            setup.timestamp_format, timestamp.format
+        );
+    } else {
+        timestamp.format = setup.timestamp_format.clone();
+    }
+    timestamp.timezone = match setup.timezone.to_lowercase().as_str() {
+        "utc" => LogTimezone::Utc,
+        "local" => LogTimezone::Local,
+        offset => match offset.parse::<chrono::FixedOffset>() {
+            Ok(offset) => LogTimezone::Fixed(offset),
+            Err(_) => {
+                eprintln!(
+                    "Warning: Unknown log timezone '{}', using utc",
+                    setup.timezone
+                );
+                LogTimezone::Utc
+            }
+        },
+    };
+    timestamp
+}
+
+pub fn logger_from_config(
+    name: &str,
+    setup: &LoggingSetup,
+    modules: &std::collections::HashMap<String, Vec<String>>,
+) -> crate::helpers::logging::Logger {
+    crate::helpers::logging::Logger::new(
+        name.to_string(),
+        log_level_from_strings(&setup.levels),
+        log_path_from_string(&setup.path),
+        setup.color,
+        log_format_from_string(&setup.format),
+    )
+    .with_rotation(log_rotation_from_config(&setup.rotation))
+    .with_timestamp(log_timestamp_from_config(setup))
+    .with_flush_interval(std::time::Duration::from_millis(setup.flush_interval_ms))
+    .with_module_levels(log_module_levels(modules))
+}
+
 pub fn load_config() -> Config {
-    if !std::path::Path::new("sharknado.json").exists() {
-        return Config {
-            server: ServerConfig {
-                host: default_host(),
-                port: default_port(),
+    // Logging is configured from this file, so problems with it go straight to stderr.
+    let mut config = if !std::path::Path::new("sharknado.json").exists() {
+        default_config()
+    } else {
+        let parsed = std::fs::read_to_string("sharknado.json")
+            .map_err(|e| e.to_string())
+            .and_then(|contents| parse_config(&contents));
+        match parsed {
+            Ok((config, unknown_keys)) => {
+                for key in unknown_keys {
+                    eprintln!(
+                        "WARNING: sharknado.json: unknown setting '{}' was ignored",
+                        key
+                    );
+                }
+                config
+            }
+            Err(e) => {
+                eprintln!("ERROR: sharknado.json is invalid: {}", e);
+                eprintln!("WARNING: ignoring sharknado.json and starting with default settings");
+                default_config()
+            }
+        }
+    };
+    apply_env_overrides(&mut config);
+    config
+}
+
+fn default_config() -> Config {
+    Config {
+        server: ServerConfig {
+            host: default_host(),
+            port: default_port(),
+            listen: Vec::new(),
+            command_timeout_ms: default_command_timeout_ms(),
+            login_timeout_ms: default_login_timeout_ms(),
+            max_unauthenticated_per_ip: default_max_unauthenticated_per_ip(),
+            max_request_bytes: default_max_request_bytes(),
+        },
+        logging: LoggingConfig {
+            main: LoggingSetup {
+                levels: default_log_level(),
+                path: default_log_path(),
+                color: default_color(),
+                format: default_log_format(),
+                rotation: default_log_rotation(),
+                flush_interval_ms: default_log_flush_interval_ms(),
+                timestamp_format: default_log_timestamp_format(),
+                timezone: default_log_timezone(),
             },
-            logging: LoggingConfig {
-                main: LoggingSetup {
-                    levels: default_log_level(),
-                    path: default_log_path(),
-                    color: default_color(),
-                },
-                tcp: LoggingSetup {
-                    levels: default_log_level(),
-                    path: default_log_path(),
-                    color: default_color(),
-                },
+            tcp: LoggingSetup {
+                levels: default_log_level(),
+                path: default_log_path(),
+                color: default_color(),
+                format: default_log_format(),
+                rotation: default_log_rotation(),
+                flush_interval_ms: default_log_flush_interval_ms(),
+                timestamp_format: default_log_timestamp_format(),
+                timezone: default_log_timezone(),
             },
+            engine: default_engine_logging(),
+            modules: std::collections::HashMap::new(),
+        },
+        health: HealthConfig::default(),
+        query: default_query(),
+        storage: default_storage(),
+        admin: default_admin(),
+        auth_events: AuthEventsConfig::default(),
+        wal: default_wal(),
+        compression: default_compression(),
+        idempotency: default_idempotency(),
+        standby: default_standby(),
+        cdc: default_cdc(),
+        row_locks: default_row_locks(),
+        maintenance: default_maintenance(),
+        seed: default_seed(),
+        shadow_tables: std::collections::HashMap::new(),
+        stats: default_stats(),
+        redactions: std::collections::HashMap::new(),
+        range_indexes: std::collections::HashMap::new(),
+        row_filters: std::collections::HashMap::new(),
+        retention: default_retention(),
+        cluster: ClusterConfig::default(),
+        scripting: default_scripting(),
+        triggers: default_triggers(),
+    }
+}
+
+// `--init-config` writes `//` comments on lines of their own; JSON has no comments, so those lines
+// are blanked before parsing. Blanking rather than removing keeps error line numbers accurate.
+fn strip_comment_lines(contents: &str) -> String {
+    contents
+        .lines()
+        .map(|line| {
+            if line.trim_start().starts_with("//") {
+                ""
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Parses a config file, collecting the paths of keys no setting uses. Errors name the offending
+// field and, from serde_json, its line and column.
+fn parse_config(contents: &str) -> Result<(Config, Vec<String>), String> {
+    let contents = strip_comment_lines(contents);
+    let mut unknown_keys = Vec::new();
+    let mut track = serde_path_to_error::Track::new();
+    let mut deserializer = serde_json::Deserializer::from_str(&contents);
+    let result = serde_ignored::deserialize(
+        serde_path_to_error::Deserializer::new(&mut deserializer, &mut track),
+        |path| unknown_keys.push(path.to_string()),
+    )
+    .and_then(|config| deserializer.end().map(|_| config));
+    match result {
+        Ok(config) => Ok((config, unknown_keys)),
+        Err(e) => {
+            // Syntax errors can stop before a key is read, which the path shows as "?".
+            let path = track
+                .path()
+                .to_string()
+                .split('.')
+                .filter(|segment| !segment.is_empty() && *segment != "?")
+                .collect::<Vec<_>>()
+                .join(".");
+            let line = contents
+                .lines()
+                .nth(e.line().saturating_sub(1))
+                .unwrap_or("");
+            let field = if path.is_empty() {
+                String::new()
+            } else {
+                format!(" (setting '{}')", path)
+            };
+            Err(format!("{}{}\n  {} | {}", e, field, e.line(), line.trim()))
+        }
+    }
+}
+
+// Comments for `--init-config`, keyed by setting path. A `*` segment matches any key.
+const CONFIG_COMMENTS: &[(&str, &str)] = &[
+    ("admin", "Account created when a database has no users"),
+    (
+        "admin.create_default",
+        "false skips creating it, for externally provisioned users",
+    ),
+    (
+        "admin.password",
+        "The shipped default must be changed with PASSWD before writing",
+    ),
+    (
+        "auth_events",
+        "Security sinks for login and account events; null disables one",
+    ),
+    (
+        "auth_events.file",
+        "Append one JSON event per line to this file",
+    ),
+    (
+        "auth_events.syslog",
+        "host:port of a syslog server (UDP, RFC 5424)",
+    ),
+    ("auth_events.webhook", "http:// URL to POST each event to"),
+    (
+        "cdc",
+        "Change data capture: publish every WAL entry to Kafka or NATS",
+    ),
+    (
+        "cdc.kafka",
+        "{brokers, topic}; needs a build with --features kafka",
+    ),
+    (
+        "cdc.nats",
+        "{url, subject} of a JetStream stream; needs a build with --features nats",
+    ),
+    (
+        "cdc.poll_interval_ms",
+        "How often the WAL is checked for new entries",
+    ),
+    (
+        "cluster",
+        "Sharding: tables are split across nodes by key hash; empty nodes turns it off",
+    ),
+    ("cluster.node", "This node's address, as listed in nodes"),
+    (
+        "cluster.nodes",
+        "Every node's client address and the [first, last] hash slots (0-16383) it owns",
+    ),
+    (
+        "compression",
+        "Response compression negotiated with HELLO COMPRESS",
+    ),
+    (
+        "compression.algorithms",
+        "Accepted algorithms, in order of server preference",
+    ),
+    (
+        "compression.min_size",
+        "Smaller responses are sent uncompressed (bytes)",
+    ),
+    ("health", "HEALTH command"),
+    ("health.require_auth", "Require LOGIN before HEALTH"),
+    ("idempotency", "IDEMPOTENT write keys"),
+    (
+        "idempotency.window_secs",
+        "How long a key and its result are remembered",
+    ),
+    (
+        "logging",
+        "Loggers for the main process, client connections and the engine",
+    ),
+    (
+        "logging.*.color",
+        "Colour text lines written to the console",
+    ),
+    (
+        "logging.*.flush_interval_ms",
+        "Longest time a line waits in the buffer",
+    ),
+    ("logging.*.format", "text or json"),
+    (
+        "logging.*.levels",
+        "Any of INFO, DEBUG, WARNING, ERROR, TRACE, or ALL / NONE",
+    ),
+    ("logging.*.path", "console or a file path"),
+    (
+        "logging.*.rotation",
+        "File rotation; max_bytes and interval_secs of 0 turn it off",
+    ),
+    (
+        "logging.*.timestamp_format",
+        "chrono strftime pattern for text lines",
+    ),
+    (
+        "logging.*.timezone",
+        "utc, local or a fixed offset such as +05:30",
+    ),
+    (
+        "logging.modules",
+        "Per-target levels, e.g. { \"engine\": [\"INFO\", \"ERROR\"] }",
+    ),
+    ("maintenance", "Read-only maintenance mode"),
+    ("maintenance.enabled", "Start in maintenance mode"),
+    ("maintenance.notice", "Message returned to rejected writes"),
+    ("query", "QUERY parsing"),
+    (
+        "query.max_result_rows",
+        "A QUERY matching more rows fails instead of sending them all (0 = no limit)",
+    ),
+    ("query.strict", "Use the strict grammar by default"),
+    (
+        "range_indexes",
+        "Numeric field pairs indexed for WITHIN, e.g. { \"places\": [[\"lat\", \"lng\"]] }",
+    ),
+    (
+        "redactions",
+        "Fields hidden from non-admins, e.g. { \"users\": { \"ssn\": \"mask\" } }",
+    ),
+    ("retention", "Background deletion of old or excess rows"),
+    (
+        "retention.interval_secs",
+        "Seconds between retention passes",
+    ),
+    (
+        "retention.tables",
+        "Per-table policies, e.g. { \"events\": { \"max_age_secs\": 86400 } }",
+    ),
+    (
+        "row_filters",
+        "Rows each role may see, e.g. { \"user\": { \"orders\": \"public = true\" } }",
+    ),
+    ("row_locks", "Row locks taken with LOCK"),
+    (
+        "row_locks.ttl_ms",
+        "How long a row lock is held before it expires",
+    ),
+    (
+        "row_locks.wait_ms",
+        "How long LOCK waits for a row another connection holds",
+    ),
+    ("scripting", "Lua scripts run with EVAL"),
+    ("scripting.enabled", "Accept EVAL"),
+    (
+        "scripting.max_instructions",
+        "Lua instructions one script may run (0 = no limit)",
+    ),
+    (
+        "scripting.max_memory_bytes",
+        "Memory one script may allocate",
+    ),
+    ("seed", "Fixtures loaded at startup"),
+    (
+        "seed.mode",
+        "empty (only seed empty tables), merge (write over same keys) or overwrite (truncate first)",
+    ),
+    (
+        "seed.path",
+        "Fixture file of {table: {key: document}}, or directory of <table>.jsonl files",
+    ),
+    ("server", "Network listeners and per-connection limits"),
+    (
+        "server.command_timeout_ms",
+        "Longest a command may run; 0 disables the limit",
+    ),
+    ("server.host", "Address to listen on when listen is empty"),
+    (
+        "server.listen",
+        "host:port pairs to listen on instead of host and port",
+    ),
+    (
+        "server.login_timeout_ms",
+        "Time a connection has to LOGIN; 0 disables the limit",
+    ),
+    (
+        "server.max_request_bytes",
+        "Largest command or request frame a client may send",
+    ),
+    (
+        "server.max_unauthenticated_per_ip",
+        "Connections per IP still waiting to LOGIN; 0 is unlimited",
+    ),
+    ("server.port", "Port to listen on when listen is empty"),
+    (
+        "shadow_tables",
+        "Tables whose writes are mirrored, e.g. { \"orders\": \"orders_v2\" }",
+    ),
+    ("standby", "Warm standby restoring from a WAL archive"),
+    (
+        "standby.archive_dir",
+        "Archive to restore from; null runs as a primary",
+    ),
+    (
+        "standby.poll_interval_ms",
+        "How often the archive is checked for new entries",
+    ),
+    (
+        "stats",
+        "Sampled read statistics in the _sharknado_stats table",
+    ),
+    (
+        "stats.max_rows",
+        "Samples kept before the oldest are dropped",
+    ),
+    ("stats.sample_rate", "Record one in this many reads"),
+    ("storage", "Where and how data is stored"),
+    ("storage.codec", "WAL entry encoding: json, cbor or bson"),
+    (
+        "storage.data_dir",
+        "Data directory; null uses the platform data directory",
+    ),
+    (
+        "storage.mode",
+        "wal, or memory to keep no log and start empty on every restart",
+    ),
+    ("triggers", "Change hooks fired after writes to a table"),
+    (
+        "triggers.dead_letter_path",
+        "Where undeliverable events go; null uses <database>.triggers.dead.jsonl",
+    ),
+    (
+        "triggers.max_attempts",
+        "Deliveries tried before an event is dead-lettered",
+    ),
+    (
+        "triggers.queue_size",
+        "Events waiting per trigger before new ones are dead-lettered",
+    ),
+    (
+        "triggers.retry_backoff_ms",
+        "Wait before the first retry, doubled after each",
+    ),
+    (
+        "triggers.tables",
+        "Table name -> list of {name, webhook} or {name, command}",
+    ),
+    ("wal", "Write-ahead log durability and batching"),
+    (
+        "wal.max_batch_delay_us",
+        "Longest a write waits to share a batch",
+    ),
+    ("wal.max_batch_entries", "Most writes in one batch"),
+    ("wal.sync", "fsync after every batch"),
+];
+
+fn config_comment(path: &str) -> Option<&'static str> {
+    CONFIG_COMMENTS.iter().find_map(|(pattern, comment)| {
+        let mut pattern_segments = pattern.split('.');
+        let mut path_segments = path.split('.');
+        loop {
+            match (pattern_segments.next(), path_segments.next()) {
+                (None, None) => return Some(*comment),
+                (Some(pattern), Some(segment)) if pattern == "*" || pattern == segment => {}
+                _ => return None,
+            }
+        }
+    })
+}
+
+fn render_config_value(out: &mut String, value: &serde_json::Value, path: &str, indent: usize) {
+    let fields = match value {
+        serde_json::Value::Object(fields) => fields,
+        serde_json::Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+            out.push_str(&format!("[{}]", items.join(", ")));
+            return;
+        }
+        _ => {
+            out.push_str(&value.to_string());
+            return;
+        }
+    };
+    if fields.is_empty() {
+        out.push_str("{}");
+        return;
+    }
+
+    out.push_str("{\n");
+    for (index, (key, field)) in fields.iter().enumerate() {
+        let field_path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", path, key)
         };
+        if let Some(comment) = config_comment(&field_path) {
+            out.push_str(&format!(
+                "{:indent$}// {}\n",
+                "",
+                comment,
+                indent = indent + 2
+            ));
+        }
+        out.push_str(&format!(
+            "{:indent$}{}: ",
+            "",
+            serde_json::Value::from(key.as_str()),
+            indent = indent + 2
+        ));
+        render_config_value(out, field, &field_path, indent + 2);
+        if index + 1 < fields.len() {
+            out.push(',');
+        }
+        out.push('\n');
+    }
+    out.push_str(&format!("{:indent$}}}", "", indent = indent));
+}
+
+// Writes every setting with its default value and a comment. Existing files are left alone.
+pub fn init_config(path: &str) -> Result<(), String> {
+    if std::path::Path::new(path).exists() {
+        return Err(format!("{} already exists", path));
+    }
+    let defaults = serde_json::to_value(default_config()).map_err(|e| e.to_string())?;
+    let mut contents = String::from(
+        "// Sharknado configuration. Every setting is shown with its default value; remove any you\n\
+         // do not need to change. Lines starting with // are comments.\n",
+    );
+    render_config_value(&mut contents, &defaults, "", 0);
+    contents.push('\n');
+    std::fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path, e))
+}
+
+fn apply_env_overrides(config: &mut Config) {
+    env_override("SHARKNADO_HOST", &mut config.server.host);
+    env_override("SHARKNADO_PORT", &mut config.server.port);
+    if let Ok(listen) = std::env::var("SHARKNADO_LISTEN") {
+        config.server.listen = listen
+            .split(',')
+            .map(str::trim)
+            .filter(|address| !address.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+    env_override(
+        "SHARKNADO_COMMAND_TIMEOUT_MS",
+        &mut config.server.command_timeout_ms,
+    );
+    env_override(
+        "SHARKNADO_LOGIN_TIMEOUT_MS",
+        &mut config.server.login_timeout_ms,
+    );
+    env_override(
+        "SHARKNADO_MAX_UNAUTHENTICATED_PER_IP",
+        &mut config.server.max_unauthenticated_per_ip,
+    );
+    env_override(
+        "SHARKNADO_MAX_REQUEST_BYTES",
+        &mut config.server.max_request_bytes,
+    );
+
+    env_override_bool(
+        "SHARKNADO_HEALTH_REQUIRE_AUTH",
+        &mut config.health.require_auth,
+    );
+    env_override_bool("SHARKNADO_QUERY_STRICT", &mut config.query.strict);
+    env_override(
+        "SHARKNADO_QUERY_MAX_RESULT_ROWS",
+        &mut config.query.max_result_rows,
+    );
+
+    for setup in [
+        &mut config.logging.main,
+        &mut config.logging.tcp,
+        &mut config.logging.engine,
+    ] {
+        env_override_levels("SHARKNADO_LOG_LEVELS", &mut setup.levels);
+        env_override("SHARKNADO_LOG_PATH", &mut setup.path);
+        env_override_bool("SHARKNADO_LOG_COLOR", &mut setup.color);
+        env_override("SHARKNADO_LOG_FORMAT", &mut setup.format);
+        env_override(
+            "SHARKNADO_LOG_ROTATE_MAX_BYTES",
+            &mut setup.rotation.max_bytes,
+        );
+        env_override(
+            "SHARKNADO_LOG_ROTATE_INTERVAL_SECS",
+            &mut setup.rotation.interval_secs,
+        );
+        env_override("SHARKNADO_LOG_ROTATE_KEEP", &mut setup.rotation.keep);
+        env_override_bool(
+            "SHARKNADO_LOG_ROTATE_COMPRESS",
+            &mut setup.rotation.compress,
+        );
+        env_override(
+            "SHARKNADO_LOG_FLUSH_INTERVAL_MS",
+            &mut setup.flush_interval_ms,
+        );
+        env_override(
+            "SHARKNADO_LOG_TIMESTAMP_FORMAT",
+            &mut setup.timestamp_format,
+        );
+        env_override("SHARKNADO_LOG_TIMEZONE", &mut setup.timezone);
+    }
+    env_override_levels("SHARKNADO_MAIN_LOG_LEVELS", &mut config.logging.main.levels);
+    env_override_levels("SHARKNADO_TCP_LOG_LEVELS", &mut config.logging.tcp.levels);
+    env_override_levels(
+        "SHARKNADO_ENGINE_LOG_LEVELS",
+        &mut config.logging.engine.levels,
+    );
+    env_override("SHARKNADO_MAIN_LOG_PATH", &mut config.logging.main.path);
+    env_override("SHARKNADO_TCP_LOG_PATH", &mut config.logging.tcp.path);
+    env_override("SHARKNADO_ENGINE_LOG_PATH", &mut config.logging.engine.path);
+    env_override("SHARKNADO_MAIN_LOG_FORMAT", &mut config.logging.main.format);
+    env_override("SHARKNADO_TCP_LOG_FORMAT", &mut config.logging.tcp.format);
+    env_override(
+        "SHARKNADO_ENGINE_LOG_FORMAT",
+        &mut config.logging.engine.format,
+    );
+
+    env_override("SHARKNADO_STORAGE_CODEC", &mut config.storage.codec);
+    env_override("SHARKNADO_STORAGE_MODE", &mut config.storage.mode);
+    if let Ok(data_dir) = std::env::var("SHARKNADO_DATA_DIR") {
+        config.storage.data_dir = Some(data_dir);
+    }
+    env_override("SHARKNADO_ADMIN_USERNAME", &mut config.admin.username);
+    env_override("SHARKNADO_ADMIN_PASSWORD", &mut config.admin.password);
+    let mut no_default_admin = !config.admin.create_default;
+    env_override_bool("SHARKNADO_NO_DEFAULT_ADMIN", &mut no_default_admin);
+    config.admin.create_default = !no_default_admin;
+    if let Ok(file) = std::env::var("SHARKNADO_AUTH_EVENTS_FILE") {
+        config.auth_events.file = Some(file);
+    }
+    if let Ok(webhook) = std::env::var("SHARKNADO_AUTH_EVENTS_WEBHOOK") {
+        config.auth_events.webhook = Some(webhook);
+    }
+    if let Ok(syslog) = std::env::var("SHARKNADO_AUTH_EVENTS_SYSLOG") {
+        config.auth_events.syslog = Some(syslog);
+    }
+
+    env_override(
+        "SHARKNADO_WAL_MAX_BATCH_DELAY_US",
+        &mut config.wal.max_batch_delay_us,
+    );
+    env_override(
+        "SHARKNADO_WAL_MAX_BATCH_ENTRIES",
+        &mut config.wal.max_batch_entries,
+    );
+    env_override_bool("SHARKNADO_WAL_SYNC", &mut config.wal.sync);
+
+    env_override_bool(
+        "SHARKNADO_COMPRESSION_ENABLED",
+        &mut config.compression.enabled,
+    );
+    env_override(
+        "SHARKNADO_COMPRESSION_MIN_SIZE",
+        &mut config.compression.min_size,
+    );
+    if let Ok(algorithms) = std::env::var("SHARKNADO_COMPRESSION_ALGORITHMS") {
+        config.compression.algorithms = algorithms
+            .split(',')
+            .map(str::trim)
+            .filter(|algorithm| !algorithm.is_empty())
+            .map(str::to_string)
+            .collect();
+    }
+    env_override(
+        "SHARKNADO_IDEMPOTENCY_WINDOW_SECS",
+        &mut config.idempotency.window_secs,
+    );
+    if let Ok(archive_dir) = std::env::var("SHARKNADO_STANDBY_ARCHIVE_DIR") {
+        config.standby.archive_dir = Some(archive_dir);
+    }
+    env_override(
+        "SHARKNADO_STANDBY_POLL_INTERVAL_MS",
+        &mut config.standby.poll_interval_ms,
+    );
+    env_override_bool(
+        "SHARKNADO_MAINTENANCE_ENABLED",
+        &mut config.maintenance.enabled,
+    );
+    env_override(
+        "SHARKNADO_MAINTENANCE_NOTICE",
+        &mut config.maintenance.notice,
+    );
+    if let Ok(seed_path) = std::env::var("SHARKNADO_SEED_PATH") {
+        config.seed.path = Some(seed_path);
+    }
+    env_override("SHARKNADO_SEED_MODE", &mut config.seed.mode);
+
+    env_override_bool("SHARKNADO_STATS_ENABLED", &mut config.stats.enabled);
+    env_override("SHARKNADO_STATS_SAMPLE_RATE", &mut config.stats.sample_rate);
+    env_override("SHARKNADO_STATS_MAX_ROWS", &mut config.stats.max_rows);
+    env_override(
+        "SHARKNADO_RETENTION_INTERVAL_SECS",
+        &mut config.retention.interval_secs,
+    );
+    if let Ok(node) = std::env::var("SHARKNADO_CLUSTER_NODE") {
+        config.cluster.node = Some(node);
+    }
+    env_override_bool("SHARKNADO_SCRIPTING_ENABLED", &mut config.scripting.enabled);
+}
+
+fn env_override<T: std::str::FromStr>(name: &str, target: &mut T) {
+    let Ok(raw) = std::env::var(name) else {
+        return;
+    };
+    match raw.trim().parse() {
+        Ok(value) => *target = value,
+        Err(_) => eprintln!("Warning: Ignoring invalid value '{}' for {}", raw, name),
+    }
+}
+
+fn env_override_bool(name: &str, target: &mut bool) {
+    let Ok(raw) = std::env::var(name) else {
+        return;
+    };
+    match raw.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => *target = true,
+        "0" | "false" | "no" | "off" => *target = false,
+        _ => eprintln!("Warning: Ignoring invalid value '{}' for {}", raw, name),
+    }
+}
+
+fn env_override_levels(name: &str, target: &mut Vec<String>) {
+    if let Ok(raw) = std::env::var(name) {
+        *target = raw
+            .split(',')
+            .map(|level| level.trim().to_uppercase())
+            .filter(|level| !level.is_empty())
+            .collect();
     }
-    let config = serde_json::from_str(std::fs::read_to_string("sharknado.json").unwrap().as_str());
-    return config.unwrap();
 }
 
 fn default_main_logging() -> LoggingSetup {
@@ -84,6 +1112,11 @@ fn default_main_logging() -> LoggingSetup {
         levels: default_log_level(),
         path: default_log_path(),
         color: default_color(),
+        format: default_log_format(),
+        rotation: default_log_rotation(),
+        flush_interval_ms: default_log_flush_interval_ms(),
+        timestamp_format: default_log_timestamp_format(),
+        timezone: default_log_timezone(),
     }
 }
 
@@ -92,6 +1125,24 @@ fn default_tcp_logging() -> LoggingSetup {
         levels: default_log_level(),
         path: default_log_path(),
         color: default_color(),
+        format: default_log_format(),
+        rotation: default_log_rotation(),
+        flush_interval_ms: default_log_flush_interval_ms(),
+        timestamp_format: default_log_timestamp_format(),
+        timezone: default_log_timezone(),
+    }
+}
+
+fn default_engine_logging() -> LoggingSetup {
+    LoggingSetup {
+        levels: default_log_level(),
+        path: default_log_path(),
+        color: default_color(),
+        format: default_log_format(),
+        rotation: default_log_rotation(),
+        flush_interval_ms: default_log_flush_interval_ms(),
+        timestamp_format: default_log_timestamp_format(),
+        timezone: default_log_timezone(),
     }
 }
 
@@ -99,6 +1150,8 @@ fn default_logging() -> LoggingConfig {
     LoggingConfig {
         main: default_main_logging(),
         tcp: default_tcp_logging(),
+        engine: default_engine_logging(),
+        modules: std::collections::HashMap::new(),
     }
 }
 
@@ -106,15 +1159,257 @@ fn default_server() -> ServerConfig {
     ServerConfig {
         host: default_host(),
         port: default_port(),
+        listen: Vec::new(),
+        command_timeout_ms: default_command_timeout_ms(),
+        login_timeout_ms: default_login_timeout_ms(),
+        max_unauthenticated_per_ip: default_max_unauthenticated_per_ip(),
+        max_request_bytes: default_max_request_bytes(),
+    }
+}
+
+fn default_storage() -> StorageConfig {
+    StorageConfig {
+        codec: default_storage_codec(),
+        data_dir: None,
+        mode: default_storage_mode(),
+    }
+}
+
+fn default_admin() -> AdminConfig {
+    AdminConfig {
+        username: default_admin_username(),
+        password: default_admin_password(),
+        create_default: default_admin_create_default(),
+    }
+}
+
+fn default_admin_username() -> String {
+    "admin".to_string()
+}
+
+fn default_admin_password() -> String {
+    "admin123".to_string()
+}
+
+fn default_admin_create_default() -> bool {
+    true
+}
+
+fn default_storage_codec() -> String {
+    "json".to_string()
+}
+
+fn default_storage_mode() -> String {
+    "wal".to_string()
+}
+
+fn default_compression() -> CompressionConfig {
+    CompressionConfig {
+        enabled: default_compression_enabled(),
+        min_size: default_compression_min_size(),
+        algorithms: default_compression_algorithms(),
+    }
+}
+
+fn default_compression_enabled() -> bool {
+    true
+}
+
+fn default_compression_min_size() -> usize {
+    1024
+}
+
+fn default_compression_algorithms() -> Vec<String> {
+    vec!["zstd".to_string(), "gzip".to_string()]
+}
+
+fn default_wal() -> WalConfig {
+    WalConfig {
+        max_batch_delay_us: default_wal_max_batch_delay_us(),
+        max_batch_entries: default_wal_max_batch_entries(),
+        sync: false,
+    }
+}
+
+fn default_wal_max_batch_delay_us() -> u64 {
+    2000
+}
+
+fn default_wal_max_batch_entries() -> usize {
+    512
+}
+
+fn default_query() -> QueryConfig {
+    QueryConfig {
+        strict: false,
+        max_result_rows: default_query_max_result_rows(),
+    }
+}
+
+fn default_query_max_result_rows() -> usize {
+    10000
+}
+
+fn default_idempotency() -> IdempotencyConfig {
+    IdempotencyConfig {
+        window_secs: default_idempotency_window_secs(),
+    }
+}
+
+fn default_idempotency_window_secs() -> u64 {
+    300
+}
+
+fn default_standby() -> StandbyConfig {
+    StandbyConfig {
+        archive_dir: None,
+        poll_interval_ms: default_standby_poll_interval_ms(),
+    }
+}
+
+fn default_standby_poll_interval_ms() -> u64 {
+    1000
+}
+
+fn default_seed() -> SeedConfig {
+    SeedConfig {
+        path: None,
+        mode: default_seed_mode(),
+    }
+}
+
+fn default_seed_mode() -> String {
+    "empty".to_string()
+}
+
+fn default_row_locks() -> RowLocksConfig {
+    RowLocksConfig {
+        ttl_ms: default_row_lock_ttl_ms(),
+        wait_ms: default_row_lock_wait_ms(),
+    }
+}
+
+fn default_row_lock_ttl_ms() -> u64 {
+    30000
+}
+
+fn default_row_lock_wait_ms() -> u64 {
+    5000
+}
+
+fn default_cdc() -> CdcConfig {
+    CdcConfig {
+        kafka: None,
+        nats: None,
+        poll_interval_ms: default_cdc_poll_interval_ms(),
+    }
+}
+
+fn default_cdc_poll_interval_ms() -> u64 {
+    200
+}
+
+fn default_maintenance() -> MaintenanceConfig {
+    MaintenanceConfig {
+        enabled: false,
+        notice: default_maintenance_notice(),
+    }
+}
+
+fn default_maintenance_notice() -> String {
+    "Server is in maintenance mode and is read-only".to_string()
+}
+
+fn default_retention() -> RetentionConfig {
+    RetentionConfig {
+        interval_secs: default_retention_interval_secs(),
+        tables: std::collections::HashMap::new(),
+    }
+}
+
+fn default_retention_interval_secs() -> u64 {
+    60
+}
+
+fn default_triggers() -> TriggersConfig {
+    TriggersConfig {
+        max_attempts: default_trigger_max_attempts(),
+        retry_backoff_ms: default_trigger_retry_backoff_ms(),
+        dead_letter_path: None,
+        queue_size: default_trigger_queue_size(),
+        tables: std::collections::HashMap::new(),
+    }
+}
+
+fn default_trigger_max_attempts() -> u32 {
+    5
+}
+
+fn default_trigger_retry_backoff_ms() -> u64 {
+    1000
+}
+
+fn default_trigger_queue_size() -> usize {
+    10_000
+}
+
+fn default_scripting() -> ScriptingConfig {
+    ScriptingConfig {
+        enabled: default_scripting_enabled(),
+        max_instructions: default_scripting_max_instructions(),
+        max_memory_bytes: default_scripting_max_memory_bytes(),
+    }
+}
+
+fn default_scripting_enabled() -> bool {
+    true
+}
+
+fn default_scripting_max_instructions() -> u64 {
+    10_000_000
+}
+
+fn default_scripting_max_memory_bytes() -> usize {
+    16 * 1024 * 1024
+}
+
+fn default_retention_age_field() -> String {
+    crate::engine::CREATED_AT_FIELD.to_string()
+}
+
+fn default_stats() -> StatsConfig {
+    StatsConfig {
+        enabled: false,
+        sample_rate: default_stats_sample_rate(),
+        max_rows: default_stats_max_rows(),
     }
 }
 
+fn default_stats_sample_rate() -> u64 {
+    10
+}
+fn default_stats_max_rows() -> u64 {
+    1000
+}
+
 fn default_host() -> String {
     "127.0.0.1".to_string()
 }
 fn default_port() -> u16 {
     8080
 }
+fn default_command_timeout_ms() -> u64 {
+    30000
+}
+fn default_login_timeout_ms() -> u64 {
+    10000
+}
+fn default_max_unauthenticated_per_ip() -> usize {
+    16
+}
+fn default_max_request_bytes() -> usize {
+    16 * 1024 * 1024
+}
 fn default_log_level() -> Vec<String> {
     vec!["INFO".to_string(), "DEBUG".to_string()]
 }
@@ -124,6 +1419,29 @@ fn default_log_path() -> String {
 fn default_color() -> bool {
     true
 }
+fn default_log_format() -> String {
+    "text".to_string()
+}
+fn default_log_rotation() -> LogRotationConfig {
+    LogRotationConfig {
+        max_bytes: 0,
+        interval_secs: 0,
+        keep: default_log_rotation_keep(),
+        compress: false,
+    }
+}
+fn default_log_rotation_keep() -> usize {
+    5
+}
+fn default_log_flush_interval_ms() -> u64 {
+    200
+}
+fn default_log_timestamp_format() -> String {
+    crate::helpers::logging::DEFAULT_TIMESTAMP_FORMAT.to_string()
+}
+fn default_log_timezone() -> String {
+    "utc".to_string()
+}
 
 pub fn create_protocol_registery() {
     register_sharknado_protocol();
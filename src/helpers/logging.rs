@@ -5,160 +5,489 @@ bitflags::bitflags! {
         const DEBUG   = 0b0010;
         const WARNING = 0b0100;
         const ERROR   = 0b1000;
-        const ALL     = Self::INFO.bits() | Self::DEBUG.bits() | Self::WARNING.bits() | Self::ERROR.bits();
+        const TRACE   = 0b10000;
+        const ALL     = Self::INFO.bits() | Self::DEBUG.bits() | Self::WARNING.bits() | Self::ERROR.bits() | Self::TRACE.bits();
         const NONE    = 0b0000;
    }
 }
 
+tokio::task_local! {
+    // Set around each TCP command so every line it logs carries the command's trace id.
+    pub static TRACE_ID: String;
+}
+
 #[derive(Clone)]
 pub enum LogPath {
     Console,
     File(String),
 }
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct LogRotation {
+    pub max_bytes: u64,
+    pub interval: Option<std::time::Duration>,
+    pub keep: usize,
+    pub compress: bool,
+}
+
+impl LogRotation {
+    fn enabled(&self) -> bool {
+        self.max_bytes > 0 || self.interval.is_some()
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum LogTimezone {
+    Utc,
+    Local,
+    Fixed(chrono::FixedOffset),
+}
+
+pub const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Clone)]
+pub struct LogTimestamp {
+    pub format: String,
+    pub timezone: LogTimezone,
+}
+
+impl Default for LogTimestamp {
+    fn default() -> Self {
+        LogTimestamp {
+            format: DEFAULT_TIMESTAMP_FORMAT.to_string(),
+            timezone: LogTimezone::Utc,
+        }
+    }
+}
+
+impl LogTimestamp {
+    fn text(&self, now: chrono::DateTime<chrono::Utc>) -> String {
+        match self.timezone {
+            LogTimezone::Utc => now.format(&self.format).to_string(),
+            LogTimezone::Local => now
+                .with_timezone(&chrono::Local)
+                .format(&self.format)
+                .to_string(),
+            LogTimezone::Fixed(offset) => {
+                now.with_timezone(&offset).format(&self.format).to_string()
+            }
+        }
+    }
+
+    fn rfc3339(&self, now: chrono::DateTime<chrono::Utc>) -> String {
+        use chrono::SecondsFormat::Millis;
+
+        match self.timezone {
+            LogTimezone::Utc => now.to_rfc3339_opts(Millis, true),
+            LogTimezone::Local => now
+                .with_timezone(&chrono::Local)
+                .to_rfc3339_opts(Millis, true),
+            LogTimezone::Fixed(offset) => now.with_timezone(&offset).to_rfc3339_opts(Millis, true),
+        }
+    }
+}
+
+const DEFAULT_FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+// One writer per destination, so the main and tcp loggers sharing a file also share its rotation.
+static WRITERS: std::sync::LazyLock<
+    std::sync::Mutex<std::collections::HashMap<String, std::sync::mpsc::Sender<LogCommand>>>,
+> = std::sync::LazyLock::new(Default::default);
+
+enum LogCommand {
+    Line {
+        text: String,
+        color_code: Option<&'static str>,
+    },
+    Flush(std::sync::mpsc::Sender<()>),
+}
+
 #[derive(Clone)]
 pub struct Logger {
     pub name: String,
     pub level: LogLevel,
     pub path: LogPath,
     pub color: bool,
+    pub format: LogFormat,
+    pub rotation: LogRotation,
+    pub timestamp: LogTimestamp,
+    pub flush_interval: std::time::Duration,
+    fields: Vec<(String, String)>,
+    module_levels: std::sync::Arc<Vec<(String, LogLevel)>>,
+    // Started on the first line, and shared by clones so per-connection loggers feed one writer.
+    writer: std::sync::Arc<std::sync::OnceLock<std::sync::mpsc::Sender<LogCommand>>>,
 }
 
 impl Logger {
-    pub fn new(name: String, level: LogLevel, path: LogPath, color: bool) -> Self {
+    pub fn new(
+        name: String,
+        level: LogLevel,
+        path: LogPath,
+        color: bool,
+        format: LogFormat,
+    ) -> Self {
         Logger {
             name,
             level,
             path,
             color,
+            format,
+            rotation: LogRotation::default(),
+            timestamp: LogTimestamp::default(),
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            fields: Vec::new(),
+            module_levels: Default::default(),
+            writer: Default::default(),
         }
     }
-    pub async fn log(&self, level: LogLevel, message: &str) {
-        if self.level.contains(level) {
-            if level == LogLevel::INFO {
-                self.info(message).await;
-            } else if level == LogLevel::DEBUG {
-                self.debug(message).await;
-            } else if level == LogLevel::WARNING {
-                self.warning(message).await;
-            } else if level == LogLevel::ERROR {
-                self.error(message).await;
-            }
-        }
+
+    pub fn with_rotation(mut self, rotation: LogRotation) -> Logger {
+        self.rotation = rotation;
+        self
     }
 
-    async fn log_in_file(&self, formatted_message: &str) {
-        if let LogPath::File(ref path) = self.path {
-            use tokio::fs::OpenOptions;
-            use tokio::io::AsyncWriteExt;
+    pub fn with_timestamp(mut self, timestamp: LogTimestamp) -> Logger {
+        self.timestamp = timestamp;
+        self
+    }
 
-            let mut file = OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open(path)
-                .await
-                .unwrap();
-            file.write_all(formatted_message.as_bytes()).await.unwrap();
-            file.write_all(b"\n").await.unwrap();
-            file.flush().await.unwrap();
-        }
+    pub fn with_flush_interval(mut self, flush_interval: std::time::Duration) -> Logger {
+        self.flush_interval = flush_interval;
+        self
     }
 
+    // Levels keyed by target name. They apply to this logger and to every module logger made from it.
+    pub fn with_module_levels(mut self, module_levels: Vec<(String, LogLevel)>) -> Logger {
+        self.module_levels = std::sync::Arc::new(module_levels);
+        self.level = self.module_level(&self.name).unwrap_or(self.level);
+        self
+    }
+
+    // A logger for a part of the server, sharing this one's output but with its own target name.
+    pub fn module(&self, name: &str) -> Logger {
+        let mut logger = self.clone();
+        logger.level = self.module_level(name).unwrap_or(self.level);
+        logger.name = name.to_string();
+        logger
+    }
+
+    // The most specific override wins, so sharknado::tcp also covers sharknado::tcp::anything.
+    fn module_level(&self, name: &str) -> Option<LogLevel> {
+        self.module_levels
+            .iter()
+            .filter(|(module, _)| {
+                name == module
+                    || name
+                        .strip_prefix(module.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"))
+            })
+            .max_by_key(|(module, _)| module.len())
+            .map(|(_, level)| *level)
+    }
+
+    pub fn with_fields(&self, fields: &[(&str, &str)]) -> Logger {
+        let mut logger = self.clone();
+        logger.fields.extend(
+            fields
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string())),
+        );
+        logger
+    }
     pub async fn info(&self, message: &str) {
-        if !self.level.contains(LogLevel::INFO) {
-            return;
+        if self.level.contains(LogLevel::INFO) {
+            self.write("INFO", "32", message);
         }
-        let timestamp = Self::get_timestamp();
-        let formatted_message = format!("[{}] [INFO] [{}] {}", timestamp, self.name, message);
-        if self.color {
-            println!("\x1b[32m{}\x1b[0m", formatted_message);
-        } else {
-            println!("{}", formatted_message);
+    }
+
+    pub async fn trace(&self, message: &str) {
+        if self.level.contains(LogLevel::TRACE) {
+            self.write("TRACE", "90", message);
         }
-        self.log_in_file(&formatted_message).await;
     }
 
     pub async fn debug(&self, message: &str) {
-        if !self.level.contains(LogLevel::DEBUG) {
-            return;
-        }
-        let timestamp = Self::get_timestamp();
-        let formatted_message = format!("[{}] [DEBUG] [{}] {}", timestamp, self.name, message);
-        if self.color {
-            println!("\x1b[34m{}\x1b[0m", formatted_message);
-        } else {
-            println!("{}", formatted_message);
+        if self.level.contains(LogLevel::DEBUG) {
+            self.write("DEBUG", "34", message);
         }
-        self.log_in_file(&formatted_message).await;
     }
 
     pub async fn warning(&self, message: &str) {
-        if !self.level.contains(LogLevel::WARNING) {
-            return;
-        }
-        let timestamp = Self::get_timestamp();
-        let formatted_message = format!("[{}] [WARNING] [{}] {}", timestamp, self.name, message);
-        if self.color {
-            println!("\x1b[33m{}\x1b[0m", formatted_message);
-        } else {
-            println!("{}", formatted_message);
+        if self.level.contains(LogLevel::WARNING) {
+            self.write("WARNING", "33", message);
         }
-        self.log_in_file(&formatted_message).await;
     }
 
     pub async fn error(&self, message: &str) {
-        if !self.level.contains(LogLevel::ERROR) {
+        if self.level.contains(LogLevel::ERROR) {
+            self.write("ERROR", "31", message);
+        }
+    }
+
+    // Waits until every line logged so far has reached the console and the log file.
+    pub fn flush(&self) {
+        let Some(writer) = self.writer.get() else {
             return;
+        };
+        let (done, flushed) = std::sync::mpsc::channel();
+        if writer.send(LogCommand::Flush(done)).is_ok() {
+            let _ = flushed.recv();
         }
-        let timestamp = Self::get_timestamp();
-        let formatted_message = format!("[{}] [ERROR] [{}] {}", timestamp, self.name, message);
-        if self.color {
-            println!("\x1b[31m{}\x1b[0m", formatted_message);
-        } else {
-            println!("{}", formatted_message);
+    }
+
+    fn write(&self, level: &str, color_code: &'static str, message: &str) {
+        let now = chrono::Utc::now();
+        let text = match self.format {
+            LogFormat::Text => format!(
+                "[{}] [{}] [{}]{} {}",
+                self.timestamp.text(now),
+                level,
+                self.name,
+                self.context_tag(),
+                message
+            ),
+            LogFormat::Json => self.json_line(now, level, message),
+        };
+        let color_code = (self.color && self.format == LogFormat::Text).then_some(color_code);
+        let _ = self.writer().send(LogCommand::Line { text, color_code });
+    }
+
+    fn writer(&self) -> &std::sync::mpsc::Sender<LogCommand> {
+        self.writer.get_or_init(|| {
+            let key = match &self.path {
+                LogPath::File(path) => path.clone(),
+                LogPath::Console => String::new(),
+            };
+            let mut writers = WRITERS.lock().unwrap();
+            if let Some(sender) = writers.get(&key) {
+                return sender.clone();
+            }
+
+            let (sender, receiver) = std::sync::mpsc::channel();
+            let mut output = LogOutput {
+                path: self.path.clone(),
+                rotation: self.rotation,
+                console: std::io::BufWriter::new(std::io::stdout()),
+                file: None,
+            };
+            let flush_interval = self.flush_interval;
+            std::thread::Builder::new()
+                .name("sharknado-log".to_string())
+                .spawn(move || output.run(receiver, flush_interval))
+                .unwrap();
+            writers.insert(key, sender.clone());
+            sender
+        })
+    }
+
+    fn json_line(&self, now: chrono::DateTime<chrono::Utc>, level: &str, message: &str) -> String {
+        let mut fields: serde_json::Map<String, serde_json::Value> = self
+            .fields
+            .iter()
+            .map(|(key, value)| (key.clone(), serde_json::Value::from(value.as_str())))
+            .collect();
+        if let Ok(trace_id) = TRACE_ID.try_with(|trace_id| trace_id.clone()) {
+            fields.insert("trace".to_string(), serde_json::Value::from(trace_id));
         }
-        self.log_in_file(&formatted_message).await;
+        serde_json::json!({
+            "timestamp": self.timestamp.rfc3339(now),
+            "level": level,
+            "target": self.name,
+            "message": message,
+            "fields": fields,
+        })
+        .to_string()
     }
 
-    fn get_timestamp() -> String {
-        use std::time::{SystemTime, UNIX_EPOCH};
+    fn context_tag(&self) -> String {
+        let mut tag: String = self
+            .fields
+            .iter()
+            .map(|(key, value)| format!(" [{}={}]", key, value))
+            .collect();
+        if let Ok(trace) = TRACE_ID.try_with(|trace_id| format!(" [trace={}]", trace_id)) {
+            tag.push_str(&trace);
+        }
+        tag
+    }
+}
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+struct LogFile {
+    writer: std::io::BufWriter<std::fs::File>,
+    size: u64,
+    opened_at: std::time::SystemTime,
+}
 
-        const SECONDS_PER_DAY: u64 = 86400;
-        const SECONDS_PER_HOUR: u64 = 3600;
-        const SECONDS_PER_MINUTE: u64 = 60;
+struct LogOutput {
+    path: LogPath,
+    rotation: LogRotation,
+    console: std::io::BufWriter<std::io::Stdout>,
+    file: Option<LogFile>,
+}
 
-        let days_since_epoch = now / SECONDS_PER_DAY;
+impl LogOutput {
+    // Lines are written as they arrive but only flushed once per interval, so a burst of logging
+    // costs one write to the console and the file instead of one per line.
+    fn run(
+        &mut self,
+        receiver: std::sync::mpsc::Receiver<LogCommand>,
+        flush_interval: std::time::Duration,
+    ) {
+        use std::sync::mpsc::RecvTimeoutError;
 
-        let mut year = 1970;
-        let mut remaining_days = days_since_epoch;
-        while remaining_days >= 365 {
-            let days_in_year = if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) {
-                366
+        let mut last_flush = std::time::Instant::now();
+        let mut dirty = false;
+        loop {
+            let command = if dirty {
+                match receiver.recv_timeout(flush_interval.saturating_sub(last_flush.elapsed())) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
             } else {
-                365
+                match receiver.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => break,
+                }
             };
-            if remaining_days >= days_in_year {
-                remaining_days -= days_in_year;
-                year += 1;
-            } else {
-                break;
+
+            match command {
+                Some(LogCommand::Line { text, color_code }) => {
+                    self.write_line(&text, color_code);
+                    dirty = true;
+                }
+                Some(LogCommand::Flush(done)) => {
+                    self.flush();
+                    dirty = false;
+                    last_flush = std::time::Instant::now();
+                    let _ = done.send(());
+                    continue;
+                }
+                None => {}
+            }
+
+            if dirty && last_flush.elapsed() >= flush_interval {
+                self.flush();
+                dirty = false;
+                last_flush = std::time::Instant::now();
+            }
+        }
+        self.flush();
+    }
+
+    fn write_line(&mut self, text: &str, color_code: Option<&str>) {
+        use std::io::Write;
+
+        let _ = match color_code {
+            Some(color_code) => writeln!(self.console, "\x1b[{}m{}\x1b[0m", color_code, text),
+            None => writeln!(self.console, "{}", text),
+        };
+
+        let LogPath::File(path) = &self.path else {
+            return;
+        };
+        let incoming = text.len() as u64 + 1;
+        if self.rotation.enabled()
+            && let Some(file) = &mut self.file
+            && Self::rotation_due(&self.rotation, file, incoming)
+        {
+            let _ = file.writer.flush();
+            self.file = None;
+            if let Err(e) = rotate_files(path, &self.rotation) {
+                eprintln!("Failed to rotate log file {}: {}", path, e);
+            }
+        }
+        if self.file.is_none() {
+            match Self::open(path) {
+                Ok(file) => self.file = Some(file),
+                Err(e) => {
+                    eprintln!("Failed to open log file {}: {}", path, e);
+                    return;
+                }
             }
         }
-        let month = (remaining_days / 30) + 1;
-        let day = (remaining_days % 30) + 1;
-        let seconds_today = now % SECONDS_PER_DAY;
-        let hours = seconds_today / SECONDS_PER_HOUR;
-        let minutes = (seconds_today % SECONDS_PER_HOUR) / SECONDS_PER_MINUTE;
-        let seconds = seconds_today % SECONDS_PER_MINUTE;
-
-        format!(
-            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
-            year, month, day, hours, minutes, seconds
-        )
+        if let Some(file) = &mut self.file
+            && writeln!(file.writer, "{}", text).is_ok()
+        {
+            file.size += incoming;
+        }
+    }
+
+    fn rotation_due(rotation: &LogRotation, file: &LogFile, incoming: u64) -> bool {
+        if file.size == 0 {
+            return false;
+        }
+        let too_large = rotation.max_bytes > 0 && file.size + incoming > rotation.max_bytes;
+        let too_old = rotation
+            .interval
+            .is_some_and(|interval| file.opened_at.elapsed().unwrap_or_default() >= interval);
+        too_large || too_old
+    }
+
+    fn open(path: &str) -> std::io::Result<LogFile> {
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let metadata = file.metadata()?;
+        let opened_at = if metadata.len() > 0 {
+            metadata
+                .created()
+                .or_else(|_| metadata.modified())
+                .unwrap_or_else(|_| std::time::SystemTime::now())
+        } else {
+            std::time::SystemTime::now()
+        };
+        Ok(LogFile {
+            writer: std::io::BufWriter::new(file),
+            size: metadata.len(),
+            opened_at,
+        })
+    }
+
+    fn flush(&mut self) {
+        use std::io::Write;
+
+        let _ = self.console.flush();
+        if let Some(file) = &mut self.file {
+            let _ = file.writer.flush();
+        }
+    }
+}
+
+// Shifts app.log.1 to app.log.2 and so on, dropping whatever falls past `keep`, then moves the
+// live file to app.log.1 (app.log.1.gz when compressing).
+fn rotate_files(path: &str, rotation: &LogRotation) -> std::io::Result<()> {
+    let suffix = if rotation.compress { ".gz" } else { "" };
+    let rotated = |index: usize| format!("{}.{}{}", path, index, suffix);
+
+    if rotation.keep == 0 {
+        return std::fs::remove_file(path);
+    }
+    match std::fs::remove_file(rotated(rotation.keep)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    for index in (1..rotation.keep).rev() {
+        if std::path::Path::new(&rotated(index)).exists() {
+            std::fs::rename(rotated(index), rotated(index + 1))?;
+        }
+    }
+
+    if rotation.compress {
+        let mut input = std::fs::File::open(path)?;
+        let output = std::fs::File::create(rotated(1))?;
+        let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+        std::io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?;
+        std::fs::remove_file(path)
+    } else {
+        std::fs::rename(path, rotated(1))
     }
 }
@@ -1,48 +1,83 @@
 pub struct Messages;
 
 impl Messages {
-    pub const HELP_TEXT: &'static str = "Available commands:\n\
-        SET <table> <key> <json_value> - Insert or update a record\n\
-        GET <table> <key> - Retrieve a record\n\
-        UPDATE <table> <key> <json_value> - Update a record\n\
-        DELETE <table> <key> - Delete a record\n\
-        QUERY <table> <field>=<value> [<field2>><value2>...] - Query records by field values\n\
-        \n\
-        User Management (CLI only):\n\
-        USER CREATE <username> <password> <role> - Create a new user\n\
-        USER LIST - List all users\n\
-        USER DELETE <username> - Delete a user\n\
-        USER UPDATE <username> <field> <value> - Update user field (password, role)\n\
-        USER LOGIN <username> <password> - Authenticate user\n\
-        USER LOGOUT - Log out current user\n\
-        USER WHOAMI - Show current logged in user\n\
-        \n\
-        HELP - Show this help message\n\
-        \n\
-        Query operators: = != > < >= <= contains\n\
-        Examples:\n\
-          QUERY users name=\"John\"\n\
-          QUERY products price>100\n\
-          QUERY users age>=18 status=\"active\"\n\
-          QUERY posts title contains \"database\"\n\
-          USER CREATE admin admin123 admin\n\
-          USER LOGIN admin admin123\n";
-
     pub const TCP_HELP_TEXT: &'static str = "Available commands:\n\
         LOGIN <username> <password> - Authenticate to access database\n\
-        SET <table> <key> <json_value> - Insert or update a record (requires login)\n\
-        GET <table> <key> - Retrieve a record (requires login)\n\
-        UPDATE <table> <key> <json_value> - Update a record (requires login)\n\
-        DELETE <table> <key> - Delete a record (requires login)\n\
-        QUERY <table> <field>=<value> [<field2>><value2>...] - Query records (requires login)\n\
+        LOGIN <api_token> - Authenticate with an API token\n\
+        SET <table> <key> <json_value> [NX|XX] [IF VERSION <n>] [IDEMPOTENT <key>] [RETURNOLD] - Insert or update a record; NX only creates, XX only replaces (requires login)\n\
+        SET <table> AUTO <json_value> [IDEMPOTENT <key>] - Insert a record under a generated key, which is returned (requires login)\n\
+        GET <table> <key> [<field_path>] - Retrieve a record, or one field of it, and its version (requires login)\n\
+        UPDATE <table> <key> <json_value> [IF VERSION <n>] [IDEMPOTENT <key>] [RETURNOLD] - Update an existing record (requires login)\n\
+        UPDATE <table> SET <json_patch> WHERE <condition> - Merge a patch into all matching records (requires login)\n\
+        DELETE <table> <key> [RETURNOLD] - Delete a record; RETURNOLD also returns it (requires login)\n\
+        PERSIST <table> <key> - Exempt a record from its table's retention max_age until it is next written (requires login)\n\
+        DELETE FROM <table> WHERE <condition> - Delete all matching records (requires login)\n\
+        QUERY <table> <condition> [AND|OR <condition>...] - Query records; NOT and parentheses nest conditions (requires login)\n\
+        QUERY <table> [<condition>] ORDER BY <field> [ASC|DESC] - Query records in order (requires login)\n\
+        QUERY <table> [<condition>] [ORDER BY <field>] LIMIT <n> - Return at most n records (requires login)\n\
+        QUERY <table> {\"<field>\": {\"$gt\": <value>}, ...} - Query with conditions written as JSON (requires login)\n\
+        PREPARE <name> QUERY <table> <condition> - Parse a query once; ? stands for a value (requires login)\n\
+        EXECUTE <name> [<value>...] - Run a prepared query with JSON values for its ? placeholders (requires login)\n\
+        EVAL <table>[,<table>...] <lua> - Run a Lua script atomically against the listed tables (requires login)\n\
+        SCAN <table> <cursor> [COUNT <n>] - Page through a table in key order, starting at cursor 0 (requires login)\n\
+        FILTER role=<role> ON <table>: <condition> - Restrict rows visible to a role (admin)\n\
+        FILTER CLEAR role=<role> ON <table> - Remove a role filter (admin)\n\
+        FILTER LIST - List role filters (admin)\n\
+        REDACT <table> <field> MASK|DROP|OFF - Hide a field from non-admin roles (admin)\n\
+        REDACT LIST - List redaction rules (admin)\n\
+        INDEX <table> <field1> <field2> [OFF] - Add or remove a numeric range index for WITHIN (admin)\n\
+        INDEX LIST - List range indexes (admin)\n\
+        SHADOW <table> <shadow_table> - Mirror writes on a table to a shadow table (admin)\n\
+        SHADOW <table> OFF - Stop mirroring writes (admin)\n\
+        SHADOW LIST - List shadowed tables (admin)\n\
+        TRIGGER ADD <table> <name> <http-url> - POST every change to a table to a webhook (admin)\n\
+        TRIGGER REMOVE <table> <name> - Remove a trigger (admin)\n\
+        TRIGGER LIST - List triggers with delivered and dead-lettered counts (admin)\n\
+        IMPORT <file> INTO <staging_table> [FORMAT jsonl|redis|mongo] - Replace a table with rows from a file (admin)\n\
+        SWAP TABLE <staging_table> <table> - Atomically swap the contents of two tables (admin)\n\
+        COPY TABLE <table> <destination> [WHERE <conditions>] - Copy rows into an empty table on the server (admin)\n\
+        RENAME TABLE <table> <new_name> - Rename a table without copying its rows (admin)\n\
+        TRUNCATE <table> - Remove every row from a table (admin or lease holder)\n\
+        DROP TABLE <table> - Remove a table and stop mirroring it to a shadow table (admin or lease holder)\n\
+        LEASE ACQUIRE <table> <owner> <ttl_secs> - Take or renew exclusive write access to a table (requires login)\n\
+        LEASE RELEASE <table> - Give up a table lease; admins may break any lease (requires login)\n\
+        LOCK <table> <key> - Lock a row against writes from other connections, waiting if it is locked (requires login)\n\
+        UNLOCK [<table> <key>] - Release a row lock, or every row lock this connection holds (requires login)\n\
+        VACUUM <table> - Drop superseded and deleted entries for a table from the log (admin)\n\
+        PROMOTE - Stop restoring from the archive and start accepting writes (standby, admin)\n\
+        SEQUENCE - Show the WAL sequence number this node has reached\n\
+        WAIT <sequence> [timeout_ms] - Wait until this node has reached a WAL sequence number\n\
+        READ AFTER <sequence> <command> - Run a read once this node has reached a WAL sequence number\n\
+        CONSISTENCY CHECK - Compare in-memory tables against a replay of the log (admin)\n\
+        CLUSTER SLOTS - List which node owns each range of hash slots when sharding (requires login)\n\
+        WAL TAIL [n] | WAL STATS - Show the last n log entries (default 20) or a summary of the log (admin)\n\
+        METRICS - Show server metrics (admin)\n\
+        SESSIONS - List open connections with their user and last command (admin)\n\
+        KILL <connection_id> - Disconnect a client (admin)\n\
+        TOP [COMMANDS|TABLES] [<minutes>] - Show the busiest commands and tables recently (admin)\n\
+        EXPLAIN QUERY <table> <conditions> - Show how a QUERY would run (requires login)\n\
+        MAINTENANCE [ON [<notice>] | OFF] - Show or switch read-only maintenance mode (admin)\n\
         LOGOUT - Log out from current session\n\
         WHOAMI - Show current logged in user\n\
+        PASSWD <old_password> <new_password> - Change your own password\n\
+        TOKEN CREATE <name> | TOKEN LIST | TOKEN REVOKE <name> - Manage your API tokens (requires login)\n\
+        USER LIST | USER CREATE <user> <password> <role> | USER DELETE <user> - Manage users (admin)\n\
+        USER UPDATE <user> <field> <value> - Change a user's password, role, namespace or quota (admin)\n\
+        PING - Check that the server is responding\n\
+        HEALTH - Show server status, uptime, database and role\n\
+        HELLO [COMPRESS zstd,gzip] - Negotiate compressed, length-prefixed frames\n\
+        TRACE <id> <command> - Run a command under a trace id shown in logs and errors\n\
+        TRACE [ON|OFF] - Show, or end every response with, its trace id on this connection\n\
+        SYNTAX [STRICT [<version>]|LENIENT] - Show or set strict query parsing for this connection\n\
+        FORMAT [TEXT|JSON] - Show or set the response format for this connection\n\
+        STREAM [ON|OFF] - Show or set streamed QUERY results for this connection\n\
         HELP - Show this help message\n\
         \n\
         Note: You must login before using database commands.\n\
         Default admin user: username='admin', password='admin123'\n\
         \n\
-        Query operators: = != > < >= <= contains\n\
+        Query operators: = != > < >= <= contains icontains startswith endswith regex\n\
+        Row timestamps: __created_at__ __updated_at__\n\
         Examples:\n\
           LOGIN admin admin123\n\
           QUERY users name=\"John\"\n\
@@ -51,71 +86,316 @@ impl Messages {
     pub const SUCCESS_OK: &'static str = "OK\n";
     pub const SUCCESS_NULL: &'static str = "NULL\n";
     pub const SUCCESS_GOODBYE: &'static str = "Goodbye!\n";
+    pub const PONG: &'static str = "PONG\n";
 
-    pub const ERROR_EMPTY_COMMAND: &'static str = "ERROR: Empty command\n";
+    pub const ERROR_EMPTY_COMMAND: &'static str = "ERROR ERR_SYNTAX: Empty command\n";
     pub const ERROR_SET_ARGS: &'static str =
-        "ERROR: SET requires 3 arguments: SET <table> <key> <value>\n";
-    pub const ERROR_GET_ARGS: &'static str = "ERROR: GET requires 2 arguments: GET <table> <key>\n";
+        "ERROR ERR_SYNTAX: SET requires 3 arguments: SET <table> <key|AUTO> <value>\n";
+    pub const ERROR_AUTO_OPTIONS: &'static str = "ERROR ERR_SYNTAX: SET AUTO always creates a new row; XX, IF VERSION and RETURNOLD do not apply\n";
+    pub const ERROR_GET_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: GET requires 2 arguments: GET <table> <key> [<field_path>]\n";
     pub const ERROR_UPDATE_ARGS: &'static str =
-        "ERROR: UPDATE requires 3 arguments: UPDATE <table> <key> <value>\n";
+        "ERROR ERR_SYNTAX: UPDATE requires 3 arguments: UPDATE <table> <key> <value>\n";
     pub const ERROR_DELETE_ARGS: &'static str =
-        "ERROR: DELETE requires 2 arguments: DELETE <table> <key>\n";
+        "ERROR ERR_SYNTAX: DELETE requires 2 arguments: DELETE <table> <key> [RETURNOLD]\n";
+    pub const ERROR_PERSIST_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: PERSIST requires 2 arguments: PERSIST <table> <key>\n";
+    pub const ERROR_DELETE_WHERE_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: Bulk delete syntax: DELETE FROM <table> WHERE <condition>\n";
     pub const ERROR_QUERY_ARGS: &'static str =
-        "ERROR: QUERY requires at least 2 arguments: QUERY <table> <conditions...>\n";
+        "ERROR ERR_SYNTAX: QUERY requires at least 2 arguments: QUERY <table> <conditions...>\n";
+    pub const ERROR_ORDER_BY_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: ORDER BY syntax: ORDER BY <field> [ASC|DESC]\n";
+    pub const ERROR_LIMIT_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: LIMIT requires a row count: LIMIT <n>\n";
+
+    pub const ERROR_SCAN_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: SCAN syntax: SCAN <table> <cursor> [COUNT <n>]\n";
+    pub const ERROR_SCAN_COUNT: &'static str =
+        "ERROR ERR_INVALID: SCAN COUNT must be between 1 and 10000\n";
+    pub const ERROR_SCAN_CURSOR: &'static str =
+        "ERROR ERR_INVALID: Invalid SCAN cursor. Start with 0 and pass back the returned cursor\n";
 
-    pub const ERROR_INVALID_JSON: &'static str = "ERROR: Invalid JSON value\n";
+    pub const ERROR_INVALID_JSON: &'static str = "ERROR ERR_SYNTAX: Invalid JSON value\n";
+    pub const ERROR_IF_VERSION_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: IF VERSION requires a version number: IF VERSION <n>\n";
+    pub const ERROR_IDEMPOTENT_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: IDEMPOTENT requires a key: IDEMPOTENT <key>\n";
+    pub const ERROR_NX_XX: &'static str = "ERROR ERR_SYNTAX: NX and XX cannot be combined: NX only creates a key, XX only replaces one\n";
 
     pub const ERROR_LOGIN_ARGS: &'static str =
-        "ERROR: LOGIN requires 2 arguments: LOGIN <username> <password>\n";
+        "ERROR ERR_SYNTAX: LOGIN syntax: LOGIN <username> <password> | LOGIN <api_token>\n";
+    pub const ERROR_TOKEN_ARGS: &'static str = "ERROR ERR_SYNTAX: TOKEN syntax: TOKEN CREATE <name> | TOKEN LIST [<user>] | TOKEN REVOKE <name> [<user>]\n";
+    pub const TOKEN_REVOKED: &'static str = "Token revoked\n";
+    pub const TOKENS_NONE: &'static str = "No tokens\n";
     pub const AUTH_REQUIRED: &'static str =
         "Authentication required. Please use: LOGIN <username> <password>\n";
     pub const LOGIN_SUCCESS: &'static str = "Login successful\n";
+    pub const LOGIN_SUCCESS_CHANGE_PASSWORD: &'static str = "Login successful. This account still has its default password: run PASSWD <old_password> <new_password> before making changes\n";
+    pub const ERROR_PASSWORD_CHANGE_REQUIRED: &'static str = "ERROR ERR_AUTH: Password change required. Run PASSWD <old_password> <new_password> first\n";
     pub const LOGOUT_SUCCESS: &'static str = "Logged out\n";
+    pub const PASSWORD_CHANGED: &'static str = "Password changed\n";
+    pub const ERROR_PASSWD_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: PASSWD requires 2 arguments: PASSWD <old_password> <new_password>\n";
+
+    pub const ERROR_FILTER_ARGS: &'static str = "ERROR ERR_SYNTAX: FILTER syntax: FILTER role=<role> ON <table>: <condition> | FILTER CLEAR role=<role> ON <table> | FILTER LIST\n";
+    pub const ERROR_NO_FILTER: &'static str =
+        "ERROR ERR_NOT_FOUND: No filter for that role and table\n";
+    pub const FILTER_NONE: &'static str = "No role filters configured\n";
+
+    pub const ERROR_REDACT_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: REDACT syntax: REDACT <table> <field> MASK|DROP|OFF | REDACT LIST\n";
+    pub const ERROR_NO_REDACTION: &'static str =
+        "ERROR ERR_NOT_FOUND: No redaction rule for that field\n";
+    pub const REDACT_NONE: &'static str = "No redaction rules configured\n";
+
+    pub const ERROR_INDEX_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: INDEX syntax: INDEX <table> <field1> <field2> [OFF] | INDEX LIST\n";
+    pub const ERROR_INDEX_EXISTS: &'static str =
+        "ERROR ERR_CONFLICT: That range index already exists\n";
+    pub const ERROR_NO_INDEX: &'static str =
+        "ERROR ERR_NOT_FOUND: No range index on those fields\n";
+    pub const INDEX_NONE: &'static str = "No range indexes configured\n";
+    pub const WITHIN_ARGS: &'static str =
+        "WITHIN requires 6 arguments: WITHIN <field1> <min1> <max1> <field2> <min2> <max2>";
+
+    pub const ERROR_SHADOW_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: SHADOW requires 2 arguments: SHADOW <table> <shadow_table|OFF>\n";
+    pub const ERROR_NO_SHADOW_TABLE: &'static str =
+        "ERROR ERR_NOT_FOUND: Table has no shadow table\n";
+    pub const SHADOW_NONE: &'static str = "No shadow tables configured\n";
+    pub const ERROR_SEQUENCE_ARGS: &'static str = "ERROR ERR_SYNTAX: SEQUENCE takes no arguments\n";
+    pub const ERROR_WAIT_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: WAIT requires WAIT <sequence> [timeout_ms]\n";
+    pub const ERROR_READ_AFTER_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: READ AFTER requires READ AFTER <sequence> <command>\n";
+    pub const ERROR_READ_AFTER_WRITE: &'static str =
+        "ERROR ERR_INVALID: READ AFTER only runs reads\n";
+    pub const ERROR_TRIGGER_ARGS: &'static str = "ERROR ERR_SYNTAX: TRIGGER requires TRIGGER ADD <table> <name> <http-url>, TRIGGER REMOVE <table> <name> or TRIGGER LIST\n";
+    pub const ERROR_NO_TRIGGER: &'static str = "ERROR ERR_NOT_FOUND: No such trigger\n";
+    pub const TRIGGER_NONE: &'static str = "No triggers configured\n";
+
+    pub const ERROR_IMPORT_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: IMPORT syntax: IMPORT <file> INTO <table> [FORMAT jsonl|redis|mongo]\n";
+    pub const ERROR_VACUUM_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: VACUUM requires 1 argument: VACUUM <table>\n";
+    pub const ERROR_STANDBY_READ_ONLY: &'static str =
+        "ERROR ERR_READ_ONLY: Server is a read-only standby. Use PROMOTE to accept writes\n";
+    pub const ERROR_LOGIN_TIMEOUT: &'static str =
+        "ERROR ERR_AUTH: Login timed out. Connection closed\n";
+    pub const ERROR_TOO_MANY_UNAUTHENTICATED: &'static str =
+        "ERROR ERR_LIMIT: Too many unauthenticated connections from this address\n";
+    pub const ERROR_NOT_STANDBY: &'static str = "ERROR ERR_INVALID: Server is not a standby\n";
+    pub const ERROR_MAINTENANCE_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: MAINTENANCE syntax: MAINTENANCE [ON [<notice>] | OFF]\n";
+    pub const ERROR_EXPLAIN_ARGS: &'static str = "ERROR ERR_SYNTAX: EXPLAIN syntax: EXPLAIN QUERY <table> <conditions> [ORDER BY <field> [ASC|DESC]] [LIMIT <n>]\n";
+    pub const EXPLAIN_CONDITIONS: &'static str = "conditions:\n";
+    pub const MAINTENANCE_OFF: &'static str = "Maintenance mode: off\n";
+    pub const ERROR_CONSISTENCY_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: CONSISTENCY syntax: CONSISTENCY CHECK\n";
+    pub const ERROR_CLUSTER_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: CLUSTER syntax: CLUSTER SLOTS\n";
+    pub const ERROR_CLUSTER_DISABLED: &'static str =
+        "ERROR ERR_INVALID: Sharding is not enabled on this server\n";
+    pub const ERROR_CLUSTER_AUTO: &'static str = "ERROR ERR_INVALID: SET AUTO is not available with sharding; choose the key on the client\n";
+    pub const ERROR_PREPARE_ARGS: &'static str = "ERROR ERR_SYNTAX: PREPARE syntax: PREPARE <name> QUERY <table> <condition with ? placeholders>\n";
+    pub const ERROR_EXECUTE_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: EXECUTE syntax: EXECUTE <name> [<value>...]\n";
+    pub const ERROR_EVAL_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: EVAL syntax: EVAL <table>[,<table>...] <lua script>\n";
+    pub const ERROR_SCRIPTING_DISABLED: &'static str =
+        "ERROR ERR_INVALID: Scripting is not enabled on this server\n";
+    pub const ERROR_CLUSTER_EVAL: &'static str = "ERROR ERR_INVALID: EVAL is not available with sharding; a script could touch keys held by other nodes\n";
+    pub const JSON_FILTER_OBJECT: &'static str =
+        "JSON conditions must be an object of fields and $and, $or or $not";
+    pub const PLACEHOLDER_POSITION: &'static str =
+        "? placeholders may only stand for a condition value";
+    pub const ERROR_WAL_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: WAL syntax: WAL TAIL [n] | WAL STATS\n";
+    pub const CONSISTENCY_OK: &'static str = "OK: no discrepancies found\n";
+    pub const ERROR_SWAP_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: SWAP syntax: SWAP TABLE <table> <table>\n";
+    pub const ERROR_COPY_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: COPY syntax: COPY TABLE <table> <destination> [WHERE <conditions>]\n";
+    pub const ERROR_RENAME_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: RENAME syntax: RENAME TABLE <table> <new_name>\n";
+    pub const ERROR_TRUNCATE_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: TRUNCATE requires 1 argument: TRUNCATE <table>\n";
+    pub const ERROR_DROP_ARGS: &'static str = "ERROR ERR_SYNTAX: DROP syntax: DROP TABLE <table>\n";
+
+    pub const ERROR_TRACE_ARGS: &'static str = "ERROR ERR_SYNTAX: TRACE syntax: TRACE <id> <command> (id: up to 64 letters, digits, '-' or '_') | TRACE [ON|OFF]\n";
+
+    pub const ERROR_HELLO_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: HELLO syntax: HELLO [COMPRESS <gzip|zstd>[,<gzip|zstd>...]]\n";
+    pub const ERROR_INVALID_REQUEST_FRAME: &'static str =
+        "ERROR ERR_SYNTAX: Invalid request frame\n";
 
-    pub const ERROR_INVALID_CONTAINS: &'static str =
-        "Invalid contains condition format. Use: field contains \"value\"";
+    pub const ERROR_UNBALANCED_PARENTHESES: &'static str = "Unbalanced parentheses in condition";
+    pub const ERROR_TOP_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: TOP syntax: TOP [COMMANDS|TABLES] [<minutes, 1-15>]\n";
+    pub const ERROR_LEASE_ARGS: &'static str = "ERROR ERR_SYNTAX: LEASE syntax: LEASE ACQUIRE <table> <owner> <ttl_secs> | LEASE RELEASE <table>\n";
+    pub const ERROR_LOCK_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: LOCK requires 2 arguments: LOCK <table> <key>\n";
+    pub const ERROR_UNLOCK_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: UNLOCK syntax: UNLOCK <table> <key> | UNLOCK\n";
+    pub const ERROR_SYNTAX_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: SYNTAX syntax: SYNTAX [STRICT [<grammar version>]|LENIENT]\n";
+    pub const ERROR_KILL_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: KILL requires 1 argument: KILL <connection_id>\n";
+    pub const SESSION_KILLED: &'static str = "Connection closed by an administrator\n";
+    pub const ERROR_FORMAT_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: FORMAT syntax: FORMAT [TEXT|JSON]\n";
+    pub const ERROR_STREAM_ARGS: &'static str =
+        "ERROR ERR_SYNTAX: STREAM syntax: STREAM [ON|OFF]\n";
+    pub const STRICT_MIXED_AND_OR: &'static str =
+        "AND and OR mixed without parentheses; group them explicitly";
+    pub const STRICT_EXPECTED_FIELD: &'static str = "expected a field path";
+    pub const STRICT_EXPECTED_OPERATOR: &'static str = "expected an operator";
+    pub const STRICT_MISSING_VALUE: &'static str = "missing value";
+    pub const STRICT_UNQUOTED_VALUE: &'static str =
+        "expected a number, true, false, null or a double-quoted string";
 
     pub const QUERY_NO_RESULTS: &'static str = "No results found\n";
+    pub const QUERY_STREAM_HEADER: &'static str = "Streaming results:\n";
 
     pub const USER_CREATED: &'static str = "User created successfully\n";
     pub const USER_DELETED: &'static str = "User deleted successfully\n";
     pub const USER_UPDATED: &'static str = "User updated successfully\n";
+    pub const USERS_NONE: &'static str = "No users found\n";
 
-    pub const ERROR_USER_EXISTS: &'static str = "ERROR: User already exists\n";
-    pub const ERROR_USER_NOT_FOUND: &'static str = "ERROR: User not found\n";
-    pub const ERROR_INVALID_CREDENTIALS: &'static str = "ERROR: Invalid username or password\n";
+    pub const ERROR_USER_EXISTS: &'static str = "ERROR ERR_CONFLICT: User already exists\n";
+    pub const ERROR_USER_NOT_FOUND: &'static str = "ERROR ERR_NOT_FOUND: User not found\n";
+    pub const ERROR_INVALID_CREDENTIALS: &'static str =
+        "ERROR ERR_AUTH: Invalid username or password\n";
     pub const ERROR_NOT_AUTHENTICATED: &'static str =
-        "ERROR: Not authenticated. Please login first\n";
-    pub const ERROR_INSUFFICIENT_PERMISSIONS: &'static str = "ERROR: Insufficient permissions\n";
-    pub const ERROR_USER_CREATE_ARGS: &'static str =
-        "ERROR: USER CREATE requires 3 arguments: USER CREATE <username> <password> <role>\n";
+        "ERROR ERR_AUTH: Not authenticated. Please login first\n";
+    pub const ERROR_INSUFFICIENT_PERMISSIONS: &'static str =
+        "ERROR ERR_PERMISSION: Insufficient permissions\n";
+    pub const ERROR_USER_CREATE_ARGS: &'static str = "ERROR ERR_SYNTAX: USER CREATE requires 3 arguments: USER CREATE <username> <password> <role>\n";
+    pub const ERROR_USER_ARGS: &'static str = "ERROR ERR_SYNTAX: USER syntax: USER LIST | USER CREATE <username> <password> <role> | USER DELETE <username> | USER UPDATE <username> <field> <value>\n";
     pub const ERROR_USER_DELETE_ARGS: &'static str =
-        "ERROR: USER DELETE requires 1 argument: USER DELETE <username>\n";
-    pub const ERROR_USER_UPDATE_ARGS: &'static str =
-        "ERROR: USER UPDATE requires 3 arguments: USER UPDATE <username> <field> <value>\n";
-    pub const ERROR_USER_LOGIN_ARGS: &'static str =
-        "ERROR: USER LOGIN requires 2 arguments: USER LOGIN <username> <password>\n";
-    pub const ERROR_INVALID_USER_COMMAND: &'static str =
-        "ERROR: Invalid USER command. Use: CREATE, LIST, DELETE, UPDATE, LOGIN, LOGOUT, WHOAMI\n";
-    pub const ERROR_INVALID_ROLE: &'static str = "ERROR: Invalid role. Valid roles: admin, user\n";
-    pub const ERROR_INVALID_UPDATE_FIELD: &'static str =
-        "ERROR: Invalid field. Valid fields: password, role\n";
+        "ERROR ERR_SYNTAX: USER DELETE requires 1 argument: USER DELETE <username>\n";
+    pub const ERROR_USER_UPDATE_ARGS: &'static str = "ERROR ERR_SYNTAX: USER UPDATE requires 3 arguments: USER UPDATE <username> <field> <value>\n";
+    pub const ERROR_INVALID_USER_COMMAND: &'static str = "ERROR ERR_SYNTAX: Invalid USER command. Use: CREATE, LIST, DELETE, UPDATE, LOGIN, LOGOUT, WHOAMI\n";
+    pub const ERROR_INVALID_ROLE: &'static str =
+        "ERROR ERR_INVALID: Invalid role. Valid roles: admin, user\n";
 
     pub fn unknown_command(cmd: &str) -> String {
         format!(
-            "ERROR: Unknown command '{}'. Type HELP for available commands.\n",
+            "ERROR ERR_SYNTAX: Unknown command '{}'. Type HELP for available commands.\n",
             cmd
         )
     }
 
-    pub fn query_error(err: &str) -> String {
-        format!("ERROR: {}\n", err)
+    pub fn syntax_error(err: &impl std::fmt::Display) -> String {
+        format!("ERROR ERR_SYNTAX: Syntax error: {}\n", err)
+    }
+
+    pub fn with_trace(response: &str, trace_id: &str) -> String {
+        format!("{} [trace={}]\n", response.trim_end_matches('\n'), trace_id)
+    }
+
+    pub fn trace_line(trace_id: &str) -> String {
+        format!("[trace={}]\n", trace_id)
+    }
+
+    pub fn trace_mode(tracing: bool) -> String {
+        format!("trace {}\n", if tracing { "on" } else { "off" })
+    }
+
+    pub fn command_duration(command: &str, elapsed: std::time::Duration) -> String {
+        format!(
+            "Completed {} in {:.3} ms",
+            command.to_uppercase(),
+            elapsed.as_secs_f64() * 1000.0
+        )
+    }
+
+    pub fn error(err: &crate::error::Error) -> String {
+        format!("ERROR {}: {}\n", err.code(), err)
     }
 
     pub fn invalid_condition(condition: &str) -> String {
         format!("Invalid condition format: {}", condition)
     }
 
+    pub fn strict_error(reason: &str, position: usize) -> String {
+        format!("Strict syntax: {} at position {}", reason, position + 1)
+    }
+
+    pub fn syntax_mode(grammar_version: u32, strict: bool) -> String {
+        format!(
+            "grammar_version {}\nmode {}\n",
+            grammar_version,
+            if strict { "strict" } else { "lenient" }
+        )
+    }
+
+    pub fn response_format(json: bool) -> String {
+        format!("format {}\n", if json { "json" } else { "text" })
+    }
+
+    pub fn request_too_large(max_bytes: usize) -> String {
+        format!(
+            "ERROR ERR_LIMIT: Request is larger than the server limit of {} bytes (server.max_request_bytes)\n",
+            max_bytes
+        )
+    }
+
+    pub fn stream_mode(streaming: bool) -> String {
+        format!("stream {}\n", if streaming { "on" } else { "off" })
+    }
+
+    // Ends a streamed QUERY; a stream that fails part way ends with an ERROR line instead.
+    pub fn stream_end(count: usize) -> String {
+        format!("END {}\n", count)
+    }
+
+    // A streamed row on a JSON connection, one object per line between the header and END.
+    pub fn stream_json_row(key: &str, value: &serde_json::Value) -> String {
+        format!("{}\n", serde_json::json!({ "key": key, "value": value }))
+    }
+
+    // One JSON object per response. Error responses read `ERROR <code>: <message>`.
+    pub fn json_response(response: &str, trace_id: Option<&str>) -> String {
+        let response = response.trim_end_matches('\n');
+        let mut body = match response
+            .strip_prefix("ERROR ")
+            .and_then(|error| error.split_once(": "))
+        {
+            Some((code, message)) => {
+                serde_json::json!({ "ok": false, "code": code, "message": message })
+            }
+            None => serde_json::json!({ "ok": true, "response": response }),
+        };
+        if let Some(trace_id) = trace_id {
+            body["trace"] = serde_json::json!(trace_id);
+        }
+        format!("{}\n", body)
+    }
+
+    pub fn unsupported_grammar_version(requested: &str, supported: u32) -> String {
+        format!(
+            "ERROR ERR_INVALID: Unsupported query grammar version {} (this server speaks {})\n",
+            requested, supported
+        )
+    }
+
+    pub fn expected_condition(found: &str) -> String {
+        format!("Expected a condition, found {}", found)
+    }
+
+    pub fn invalid_word_condition(operator: &str) -> String {
+        format!(
+            "Invalid {} condition format. Use: field {} \"value\"",
+            operator, operator
+        )
+    }
+
+    pub fn invalid_regex(pattern: &str, err: &str) -> String {
+        format!("Invalid regex \"{}\": {}", pattern, err)
+    }
+
     pub fn unsupported_operator(op: &str) -> String {
         format!("Unsupported operator: {}", op)
     }
@@ -124,16 +404,387 @@ impl Messages {
         format!("Found {} results:\n", count)
     }
 
+    pub fn scan_cursor(cursor: &str, count: usize) -> String {
+        format!("CURSOR {} {}\n", cursor, count)
+    }
+
     pub fn query_result_item(key: &str, value: &str) -> String {
         format!("{}: {}\n", key, value)
     }
 
+    pub fn filter_list_item(role: &str, table: &str, condition: &str) -> String {
+        format!("role={} ON {}: {}\n", role, table, condition)
+    }
+
+    pub fn range_index(first: &str, second: &str) -> String {
+        format!("range ({}, {})", first, second)
+    }
+
+    pub fn range_index_item(table: &str, first: &str, second: &str, rows: usize) -> String {
+        format!(
+            "{}: {}, {} rows\n",
+            table,
+            Self::range_index(first, second),
+            rows
+        )
+    }
+
+    pub fn within_bound(bound: &str) -> String {
+        format!("WITHIN bounds must be numbers, got '{}'", bound)
+    }
+
+    pub fn redaction_list_item(table: &str, field_path: &str, action: &str) -> String {
+        format!("{}.{}: {}\n", table, field_path, action)
+    }
+
+    pub fn trigger_list_item(trigger: &crate::triggers::TriggerInfo) -> String {
+        format!(
+            "{} {} -> {} (delivered: {}, dead: {})\n",
+            trigger.table, trigger.name, trigger.target, trigger.delivered, trigger.dead
+        )
+    }
+
+    pub fn shadow_list_item(table: &str, shadow_table: &str) -> String {
+        format!("{} -> {}\n", table, shadow_table)
+    }
+
+    pub fn generated_key(key: &str) -> String {
+        format!("OK\nKEY {}\n", key)
+    }
+
+    pub fn old_value(value: Option<&str>) -> String {
+        format!("OK\nOLD {}\n", value.unwrap_or("NULL"))
+    }
+
+    pub fn row_with_version(value: &str, version: u64) -> String {
+        format!("{}\nVERSION {}\n", value, version)
+    }
+
+    pub fn unexpected_write_option(option: &str) -> String {
+        format!(
+            "ERROR ERR_SYNTAX: Unexpected '{}' after JSON value\n",
+            option
+        )
+    }
+
+    pub fn rows_updated(count: usize) -> String {
+        format!("Updated {} rows\n", count)
+    }
+
+    pub fn hello_response(compression: &str) -> String {
+        format!("HELLO sharknado COMPRESS {}\n", compression)
+    }
+
+    pub fn top_window(window_secs: u64, resolution_secs: u64) -> String {
+        format!(
+            "window: last {}s at {}s resolution\n",
+            window_secs, resolution_secs
+        )
+    }
+
+    pub fn top_section(name: &str) -> String {
+        format!("{}:\n", name)
+    }
+
+    pub fn top_row(
+        name: &str,
+        count: u64,
+        rate: f64,
+        avg_ms: f64,
+        max_ms: f64,
+        errors: u64,
+    ) -> String {
+        format!(
+            "  {:<16} count={} rate={:.2}/s avg={:.2}ms max={:.2}ms errors={}\n",
+            name, count, rate, avg_ms, max_ms, errors
+        )
+    }
+
+    pub fn metric_item(name: &str, value: &str) -> String {
+        format!("{} {}\n", name, value)
+    }
+
+    pub fn table_truncated(table: &str, rows: usize) -> String {
+        format!("Truncated {}: removed {} rows\n", table, rows)
+    }
+
+    pub fn table_dropped(table: &str, rows: usize) -> String {
+        format!("Dropped table {} ({} rows)\n", table, rows)
+    }
+
+    pub fn vacuum_result(table: &str, entries_removed: usize, bytes_reclaimed: u64) -> String {
+        format!(
+            "Vacuumed {}: removed {} entries, reclaimed {} bytes\n",
+            table, entries_removed, bytes_reclaimed
+        )
+    }
+
+    pub fn explain_item(name: &str, value: &str) -> String {
+        format!("{}: {}\n", name, value)
+    }
+
+    pub fn explain_tree_line(line: &str) -> String {
+        format!("  {}\n", line)
+    }
+
+    pub fn maintenance_status(notice: &str) -> String {
+        format!("Maintenance mode: on ({})\n", notice)
+    }
+
+    pub fn wal_read_only(reason: &str) -> String {
+        format!(
+            "ERROR ERR_READ_ONLY: Read-only: the write-ahead log is unavailable ({})\n",
+            reason
+        )
+    }
+
+    pub fn lease_acquired(table: &str, owner: &str, ttl_secs: u64) -> String {
+        format!("Leased {} to {} for {}s\n", table, owner, ttl_secs)
+    }
+
+    pub fn lease_released(table: &str, owner: &str) -> String {
+        format!("Released lease on {} held by {}\n", table, owner)
+    }
+
+    pub fn lease_held(table: &str, owner: &str, remaining_secs: u64) -> String {
+        format!(
+            "ERROR ERR_CONFLICT: Table {} is leased to {} for another {}s\n",
+            table, owner, remaining_secs
+        )
+    }
+
+    pub fn lease_missing(table: &str) -> String {
+        format!("ERROR ERR_NOT_FOUND: No active lease on {}\n", table)
+    }
+
+    pub fn row_locked(table: &str, key: &str, ttl_secs: u64) -> String {
+        format!("Locked {} {} for {}s\n", table, key, ttl_secs)
+    }
+
+    pub fn row_locks_released(count: usize) -> String {
+        format!("Released {} row locks\n", count)
+    }
+
+    pub fn row_lock_held(table: &str, key: &str, remaining_secs: u64) -> String {
+        format!(
+            "ERROR ERR_CONFLICT: Row {} {} is locked by another connection for another {}s\n",
+            table, key, remaining_secs
+        )
+    }
+
+    pub fn row_lock_missing(table: &str, key: &str) -> String {
+        format!(
+            "ERROR ERR_NOT_FOUND: Row {} {} is not locked by this connection\n",
+            table, key
+        )
+    }
+
+    pub fn quota_ops(max_ops_per_sec: u32) -> String {
+        format!(
+            "ERROR ERR_QUOTA: Rate limit of {} commands per second exceeded\n",
+            max_ops_per_sec
+        )
+    }
+
+    pub fn quota_rows(rows: usize, max_rows: usize) -> String {
+        format!(
+            "ERROR ERR_QUOTA: {} rows requested, your limit is {}; use LIMIT or a smaller COUNT\n",
+            rows, max_rows
+        )
+    }
+
+    pub fn result_too_large(rows: usize, max_rows: usize) -> String {
+        format!(
+            "ERROR ERR_QUOTA: QUERY matched {} rows, more than the server's limit of {}; add LIMIT, page with SCAN or use STREAM ON\n",
+            rows, max_rows
+        )
+    }
+
+    pub fn maintenance_read_only(notice: &str) -> String {
+        format!("ERROR ERR_READ_ONLY: Read-only: {}\n", notice)
+    }
+
+    pub fn sequence(sequence: u64) -> String {
+        format!("Sequence {}\n", sequence)
+    }
+
+    pub fn wait_timeout(sequence: u64, reached: u64) -> String {
+        format!(
+            "ERROR ERR_TIMEOUT: Sequence {} not reached; this node is at {}\n",
+            sequence, reached
+        )
+    }
+
+    pub fn promoted(applied: usize) -> String {
+        format!(
+            "Promoted: restored {} final entries, now accepting writes\n",
+            applied
+        )
+    }
+
+    pub fn moved(slot: u16, address: &str) -> String {
+        format!("ERROR ERR_MOVED: MOVED {} {}\n", slot, address)
+    }
+
+    pub fn cluster_slots(first: u16, last: u16, address: &str, this_node: bool) -> String {
+        if this_node {
+            format!("{} {} {} myself\n", first, last, address)
+        } else {
+            format!("{} {} {}\n", first, last, address)
+        }
+    }
+
+    pub fn invalid_json_filter(reason: &str) -> String {
+        format!("Invalid JSON conditions: {}", reason)
+    }
+
+    pub fn json_filter_trailing(rest: &str) -> String {
+        format!(
+            "Unexpected '{}' after JSON conditions; only ORDER BY and LIMIT may follow",
+            rest
+        )
+    }
+
+    pub fn json_filter_list(operator: &str) -> String {
+        format!("{} takes a list of JSON conditions", operator)
+    }
+
+    pub fn json_filter_operator(operator: &str) -> String {
+        format!(
+            "Unknown JSON condition operator {}; use $eq, $ne, $gt, $gte, $lt, $lte, $contains, $icontains, $startswith, $endswith or $regex",
+            operator
+        )
+    }
+
+    pub fn script_result(value: &serde_json::Value) -> String {
+        match value {
+            serde_json::Value::Null => Self::SUCCESS_NULL.to_string(),
+            value => format!("{}\n", value),
+        }
+    }
+
+    pub fn prepared(name: &str, parameters: usize) -> String {
+        format!("Prepared {} with {} parameters\n", name, parameters)
+    }
+
+    pub fn prepared_limit(limit: usize) -> String {
+        format!(
+            "ERROR ERR_QUOTA: A connection can hold at most {} prepared queries\n",
+            limit
+        )
+    }
+
+    pub fn no_prepared_query(name: &str) -> String {
+        format!("No prepared query named {}", name)
+    }
+
+    pub fn invalid_parameter(reason: &str) -> String {
+        format!("Parameters must be JSON values: {}", reason)
+    }
+
+    pub fn parameter_count(name: &str, expected: usize, given: usize) -> String {
+        format!("{} takes {} parameters, got {}", name, expected, given)
+    }
+
+    pub fn wal_tail_header(shown: usize, total: usize) -> String {
+        format!("Last {} of {} WAL entries\n", shown, total)
+    }
+
+    pub fn wal_entry(entry: &str) -> String {
+        format!("{}\n", entry)
+    }
+
+    pub fn consistency_header(tables: usize, rows: usize) -> String {
+        format!("Checked {} tables, {} rows\n", tables, rows)
+    }
+
+    pub fn consistency_checksum_failures(count: usize) -> String {
+        format!("log: {} entries fail their checksum\n", count)
+    }
+
+    pub fn consistency_item(discrepancy: &str) -> String {
+        format!("{}\n", discrepancy)
+    }
+
+    pub fn consistency_truncated(count: usize) -> String {
+        format!("... and {} more\n", count)
+    }
+
+    pub fn consistency_problems(count: usize) -> String {
+        format!("FAILED: {} problems found\n", count)
+    }
+
+    pub fn rows_imported(count: usize) -> String {
+        format!("Imported {} rows\n", count)
+    }
+
+    pub fn rows_copied(count: usize) -> String {
+        format!("Copied {} rows\n", count)
+    }
+
+    pub fn rows_deleted(count: usize) -> String {
+        format!("Deleted {} rows\n", count)
+    }
+
     pub fn user_list_header(count: usize) -> String {
         format!("Found {} users:\n", count)
     }
 
-    pub fn user_list_item(username: &str, role: &str, created_at: &str) -> String {
-        format!("  {} (role: {}, created: {})\n", username, role, created_at)
+    pub fn user_list_item(
+        username: &str,
+        role: &str,
+        created_at: &str,
+        namespace: Option<&str>,
+        quotas: &str,
+    ) -> String {
+        let mut details = format!("role: {}, created: {}", role, created_at);
+        if let Some(namespace) = namespace {
+            details.push_str(&format!(", namespace: {}", namespace));
+        }
+        if !quotas.is_empty() {
+            details.push_str(&format!(", quotas: {}", quotas));
+        }
+        format!("  {} ({})\n", username, details)
+    }
+
+    pub fn session_list_header(count: usize) -> String {
+        format!("Found {} sessions:\n", count)
+    }
+
+    pub fn session_list_item(
+        connection_id: &str,
+        username: Option<&str>,
+        connected_at: &str,
+        connected_secs: i64,
+        last_command: Option<&str>,
+    ) -> String {
+        format!(
+            "  {} (user: {}, connected: {}, {}s ago, last command: {})\n",
+            connection_id,
+            username.unwrap_or("-"),
+            connected_at,
+            connected_secs,
+            last_command.unwrap_or("-")
+        )
+    }
+
+    pub fn session_killed(connection_id: &str) -> String {
+        format!("Killed session {}\n", connection_id)
+    }
+
+    pub fn session_missing(connection_id: &str) -> String {
+        format!("ERROR ERR_NOT_FOUND: No session {}\n", connection_id)
+    }
+
+    pub fn token_created(name: &str, token: &str) -> String {
+        format!(
+            "Token {} created: {}\nStore it now, it cannot be shown again\n",
+            name, token
+        )
+    }
+
+    pub fn token_list_item(name: &str, created_at: &str) -> String {
+        format!("  {} (created: {})\n", name, created_at)
     }
 
     pub fn user_whoami_response(username: &str, role: &str) -> String {
@@ -1,3 +1,8 @@
+pub mod compression;
 pub mod configs;
 pub mod logging;
 pub mod messages;
+pub mod service;
+pub mod tokenizer;
+pub mod uri;
+pub mod webhook;
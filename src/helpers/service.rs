@@ -0,0 +1,98 @@
+pub const DEFAULT_UNIT_PATH: &str = "/etc/systemd/system/sharknado.service";
+
+pub struct ServiceSpec {
+    pub database_name: String,
+    pub data_dir: String,
+    pub standby_dir: Option<String>,
+}
+
+// The server already shuts down cleanly on SIGTERM, so systemd's default stop signal is enough;
+// TimeoutStopSec only bounds how long the final log flush may take before a SIGKILL.
+pub fn systemd_unit(spec: &ServiceSpec) -> Result<String, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Failed to find executable: {}", e))?;
+    let working_dir =
+        std::env::current_dir().map_err(|e| format!("Failed to read current directory: {}", e))?;
+    let mut exec_start = vec![quote(&exe.to_string_lossy()), quote(&spec.database_name)];
+    if let Some(standby_dir) = &spec.standby_dir {
+        exec_start.push("--standby".to_string());
+        exec_start.push(quote(standby_dir));
+    }
+    // Under sudo, run as the invoking user so the service keeps using their files.
+    let user = std::env::var("SUDO_USER")
+        .ok()
+        .or_else(|| std::env::var("USER").ok().filter(|user| user != "root"))
+        .map(|user| format!("User={}\n", user))
+        .unwrap_or_default();
+
+    Ok(format!(
+        "[Unit]\n\
+         Description=Sharknado database ({database})\n\
+         After=network-online.target\n\
+         Wants=network-online.target\n\
+         \n\
+         [Service]\n\
+         Type=simple\n\
+         {user}\
+         WorkingDirectory={working_dir}\n\
+         Environment={data_dir}\n\
+         ExecStart={exec_start}\n\
+         KillSignal=SIGTERM\n\
+         TimeoutStopSec=30\n\
+         Restart=on-failure\n\
+         RestartSec=2\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        database = spec.database_name,
+        working_dir = quote(&working_dir.to_string_lossy()),
+        data_dir = quote(&format!("SHARKNADO_DATA_DIR={}", spec.data_dir)),
+        exec_start = exec_start.join(" "),
+    ))
+}
+
+// Writes the unit and returns the name to enable it under.
+#[cfg(target_os = "linux")]
+pub fn install_service(spec: &ServiceSpec, path: &str) -> Result<String, String> {
+    let unit = systemd_unit(spec)?;
+    std::fs::write(path, unit).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    Ok(std::path::Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "sharknado.service".to_string()))
+}
+
+// A Windows service has to answer the service control manager, which the server does not do
+// yet, so registering it with sc.exe would only produce a service that fails to start.
+#[cfg(not(target_os = "linux"))]
+pub fn install_service(_spec: &ServiceSpec, _path: &str) -> Result<String, String> {
+    Err(
+        "--install-service only supports systemd on Linux; on Windows run sharknado under a \
+         service wrapper such as NSSM"
+            .to_string(),
+    )
+}
+
+// systemd splits ExecStart on whitespace, so anything with spaces or quotes is double-quoted;
+// a literal % would otherwise start a unit specifier.
+fn quote(value: &str) -> String {
+    let value = value.replace('%', "%%");
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_only_what_systemd_would_split() {
+        assert_eq!(quote("/usr/bin/sharknado"), "/usr/bin/sharknado");
+        assert_eq!(quote("/opt/my db"), "\"/opt/my db\"");
+        assert_eq!(quote(r#"a"b\c"#), r#""a\"b\\c""#);
+        assert_eq!(quote("50%"), "50%%");
+        assert_eq!(quote(""), "\"\"");
+    }
+}
@@ -0,0 +1,433 @@
+// A malformed command, with the character offset (from 0) where the problem starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub message: &'static str,
+    pub position: usize,
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position + 1)
+    }
+}
+
+const UNTERMINATED_ESCAPE: &str = "Unterminated escape sequence";
+const UNTERMINATED_DOUBLE_QUOTE: &str = "Unterminated double quote";
+const UNTERMINATED_SINGLE_QUOTE: &str = "Unterminated single quote";
+
+pub fn tokenize(input: &str) -> Result<Vec<String>, SyntaxError> {
+    tokenize_n(input, usize::MAX)
+}
+
+pub fn tokenize_n(input: &str, max_tokens: usize) -> Result<Vec<String>, SyntaxError> {
+    let leading = input.len() - input.trim_start().len();
+    let trimmed = input.trim();
+    let error = |message, offset: usize| SyntaxError {
+        message,
+        position: input[..leading + offset].chars().count(),
+    };
+    let mut tokens = Vec::new();
+    let mut chars = trimmed.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        if tokens.len() + 1 == max_tokens {
+            tokens.push(trimmed[start..].to_string());
+            break;
+        }
+
+        let mut token = String::new();
+        while let Some(&(offset, c)) = chars.peek() {
+            if c.is_whitespace() {
+                break;
+            }
+            chars.next();
+            match c {
+                '"' => loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((escape, '\\')) => match chars.next() {
+                            Some((_, 'n')) => token.push('\n'),
+                            Some((_, 't')) => token.push('\t'),
+                            Some((_, escaped)) => token.push(escaped),
+                            None => return Err(error(UNTERMINATED_ESCAPE, escape)),
+                        },
+                        Some((_, c)) => token.push(c),
+                        None => return Err(error(UNTERMINATED_DOUBLE_QUOTE, offset)),
+                    }
+                },
+                '\'' => loop {
+                    match chars.next() {
+                        Some((_, '\'')) => break,
+                        Some((_, c)) => token.push(c),
+                        None => return Err(error(UNTERMINATED_SINGLE_QUOTE, offset)),
+                    }
+                },
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => token.push(escaped),
+                    None => return Err(error(UNTERMINATED_ESCAPE, offset)),
+                },
+                c => token.push(c),
+            }
+        }
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ExpressionToken {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Condition(String),
+}
+
+impl std::fmt::Display for ExpressionToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpressionToken::Open => write!(f, "("),
+            ExpressionToken::Close => write!(f, ")"),
+            ExpressionToken::And => write!(f, "AND"),
+            ExpressionToken::Or => write!(f, "OR"),
+            ExpressionToken::Not => write!(f, "NOT"),
+            ExpressionToken::Condition(condition) => write!(f, "{}", condition),
+        }
+    }
+}
+
+// Splits a boolean filter into keywords, parentheses and the raw text of each condition.
+// Text inside double quotes is never split, so quoted values may contain AND/OR/NOT or parens.
+// Each token carries the character offset where it starts, for error positions.
+pub fn tokenize_expression(input: &str) -> Result<Vec<(ExpressionToken, usize)>, SyntaxError> {
+    let mut tokens = Vec::new();
+    let mut condition: Vec<Word> = Vec::new();
+    let mut word = Word::default();
+    let mut chars = input.char_indices().enumerate();
+
+    while let Some((position, (offset, c))) = chars.next() {
+        if word.text.is_empty() {
+            word.start = offset;
+            word.position = position;
+        }
+        match c {
+            '"' => {
+                word.text.push(c);
+                loop {
+                    match chars.next() {
+                        Some((_, (_, '"'))) => {
+                            word.text.push('"');
+                            break;
+                        }
+                        Some((escape, (_, '\\'))) => {
+                            word.text.push('\\');
+                            match chars.next() {
+                                Some((_, (_, escaped))) => word.text.push(escaped),
+                                None => {
+                                    return Err(SyntaxError {
+                                        message: UNTERMINATED_ESCAPE,
+                                        position: escape,
+                                    });
+                                }
+                            }
+                        }
+                        Some((_, (_, c))) => word.text.push(c),
+                        None => {
+                            return Err(SyntaxError {
+                                message: UNTERMINATED_DOUBLE_QUOTE,
+                                position,
+                            });
+                        }
+                    }
+                }
+            }
+            '(' if keyword(&word.text).is_some()
+                || (word.text.is_empty() && condition.is_empty()) =>
+            {
+                end_word(input, &mut word, &mut condition, &mut tokens);
+                tokens.push((ExpressionToken::Open, position));
+            }
+            ')' => {
+                end_word(input, &mut word, &mut condition, &mut tokens);
+                end_condition(input, &mut condition, &mut tokens);
+                tokens.push((ExpressionToken::Close, position));
+            }
+            c if c.is_whitespace() => end_word(input, &mut word, &mut condition, &mut tokens),
+            c => word.text.push(c),
+        }
+    }
+    end_word(input, &mut word, &mut condition, &mut tokens);
+    end_condition(input, &mut condition, &mut tokens);
+
+    Ok(tokens)
+}
+
+#[derive(Default)]
+struct Word {
+    text: String,
+    start: usize,
+    position: usize,
+}
+
+fn end_word(
+    input: &str,
+    word: &mut Word,
+    condition: &mut Vec<Word>,
+    tokens: &mut Vec<(ExpressionToken, usize)>,
+) {
+    if word.text.is_empty() {
+        return;
+    }
+    match keyword(&word.text) {
+        Some(keyword) => {
+            end_condition(input, condition, tokens);
+            tokens.push((keyword, word.position));
+            word.text.clear();
+        }
+        None => condition.push(std::mem::take(word)),
+    }
+}
+
+fn keyword(word: &str) -> Option<ExpressionToken> {
+    match word.to_ascii_lowercase().as_str() {
+        "and" => Some(ExpressionToken::And),
+        "or" => Some(ExpressionToken::Or),
+        "not" => Some(ExpressionToken::Not),
+        _ => None,
+    }
+}
+
+// A condition keeps its original text, spacing included, so offsets inside it still line up.
+fn end_condition(
+    input: &str,
+    condition: &mut Vec<Word>,
+    tokens: &mut Vec<(ExpressionToken, usize)>,
+) {
+    if let (Some(first), Some(last)) = (condition.first(), condition.last()) {
+        let end = last.start + last.text.len();
+        tokens.push((
+            ExpressionToken::Condition(input[first.start..end].to_string()),
+            first.position,
+        ));
+        condition.clear();
+    }
+}
+
+const OPERATORS: [&str; 6] = [">=", "<=", "!=", "=", ">", "<"];
+
+// Finds the first comparison operator outside double quotes, as its byte offset and symbol, so
+// a quoted value may itself contain `=` or `>`.
+pub fn find_operator(condition: &str) -> Option<(usize, &'static str)> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (offset, c) in condition.char_indices() {
+        if quoted {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = false,
+                _ => {}
+            }
+            continue;
+        }
+        if c == '"' {
+            quoted = true;
+            continue;
+        }
+        if let Some(symbol) = OPERATORS
+            .into_iter()
+            .find(|symbol| condition[offset..].starts_with(symbol))
+        {
+            return Some((offset, symbol));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(input: &str) -> (&'static str, usize) {
+        let err = tokenize(input).unwrap_err();
+        (err.message, err.position)
+    }
+
+    fn conditions(input: &str) -> Vec<(String, usize)> {
+        tokenize_expression(input)
+            .unwrap()
+            .into_iter()
+            .filter_map(|(token, position)| match token {
+                ExpressionToken::Condition(condition) => Some((condition, position)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Small xorshift generator, so the fuzz cases are the same on every run.
+    fn random_input(seed: &mut u64, alphabet: &[char], max_len: usize) -> String {
+        let mut next = || {
+            *seed ^= *seed << 13;
+            *seed ^= *seed >> 7;
+            *seed ^= *seed << 17;
+            *seed as usize
+        };
+        let len = next() % max_len;
+        (0..len)
+            .map(|_| alphabet[next() % alphabet.len()])
+            .collect()
+    }
+
+    #[test]
+    fn splits_words_and_quoted_text() {
+        assert_eq!(
+            tokenize(r#"  SET users "al ice" 'x y' {"a":1}  "#).unwrap(),
+            ["SET", "users", "al ice", "x y", r#"{a:1}"#]
+        );
+        assert_eq!(tokenize("").unwrap(), Vec::<String>::new());
+        assert_eq!(tokenize(" \t\r\n ").unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn keeps_embedded_quotes_and_escapes() {
+        assert_eq!(
+            tokenize(r#""say \"hi\"" 'it"s' a\ b "tab\there""#).unwrap(),
+            [r#"say "hi""#, r#"it"s"#, "a b", "tab\there"]
+        );
+        assert_eq!(tokenize(r#"pre"mid dle"post"#).unwrap(), ["premid dlepost"]);
+        assert_eq!(tokenize(r#"'\'"#).unwrap(), ["\\"]);
+    }
+
+    #[test]
+    fn reports_where_malformed_input_starts() {
+        assert_eq!(error(r#"GET "users"#), (UNTERMINATED_DOUBLE_QUOTE, 4));
+        assert_eq!(error("GET 'users"), (UNTERMINATED_SINGLE_QUOTE, 4));
+        assert_eq!(error(r#"GET "users\"#), (UNTERMINATED_ESCAPE, 10));
+        assert_eq!(error("GET users\\"), (UNTERMINATED_ESCAPE, 9));
+        // Leading whitespace still counts towards the position.
+        assert_eq!(error("   \"x"), (UNTERMINATED_DOUBLE_QUOTE, 3));
+        assert_eq!(
+            tokenize("\"x").unwrap_err().to_string(),
+            "Unterminated double quote at position 1"
+        );
+    }
+
+    #[test]
+    fn counts_positions_in_characters() {
+        assert_eq!(
+            tokenize("SET café \"日本語").unwrap_err().position,
+            9,
+            "multi-byte characters count once"
+        );
+        assert_eq!(
+            tokenize("名前 \"値 値\" 🦈").unwrap(),
+            ["名前", "値 値", "🦈"]
+        );
+        // Unicode whitespace separates tokens like ASCII whitespace does.
+        assert_eq!(tokenize("a\u{3000}b\u{a0}c").unwrap(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn tokenize_n_keeps_the_rest_verbatim() {
+        assert_eq!(
+            tokenize_n(r#"SET users k {"a": "b c"}"#, 4).unwrap(),
+            ["SET", "users", "k", r#"{"a": "b c"}"#]
+        );
+        // A bad quote in the untouched rest is not an error.
+        assert_eq!(tokenize_n(r#"a b "c"#, 2).unwrap(), ["a", r#"b "c"#]);
+        assert_eq!(tokenize_n("a b", 0).unwrap(), ["a", "b"]);
+    }
+
+    #[test]
+    fn handles_over_long_commands() {
+        let value = "x".repeat(1 << 20);
+        let tokens = tokenize(&format!("SET t k \"{}\"", value)).unwrap();
+        assert_eq!(tokens[3].len(), value.len());
+
+        let many = "a ".repeat(100_000);
+        assert_eq!(tokenize(&many).unwrap().len(), 100_000);
+
+        let unterminated = format!("{}\"{}", "a ".repeat(50_000), "é".repeat(50_000));
+        assert_eq!(tokenize(&unterminated).unwrap_err().position, 100_000);
+
+        let nested = "(".repeat(10_000) + "a = 1" + &")".repeat(10_000);
+        assert_eq!(tokenize_expression(&nested).unwrap().len(), 20_001);
+    }
+
+    #[test]
+    fn splits_expressions_with_positions() {
+        let tokens = tokenize_expression(r#"NOT (age > 1 or name = "a AND (b)") and x=1"#).unwrap();
+        let kinds: Vec<String> = tokens.iter().map(|(token, _)| token.to_string()).collect();
+        assert_eq!(
+            kinds,
+            [
+                "NOT",
+                "(",
+                "age > 1",
+                "OR",
+                r#"name = "a AND (b)""#,
+                ")",
+                "AND",
+                "x=1"
+            ]
+        );
+        let positions: Vec<usize> = tokens.iter().map(|(_, position)| *position).collect();
+        assert_eq!(positions, [0, 4, 5, 13, 16, 34, 36, 40]);
+
+        assert_eq!(
+            conditions("ñame = \"é\" or b=2")[1],
+            ("b=2".to_string(), 14)
+        );
+        assert_eq!(
+            tokenize_expression(r#"a = 1 and b = "x"#).unwrap_err(),
+            SyntaxError {
+                message: UNTERMINATED_DOUBLE_QUOTE,
+                position: 14
+            }
+        );
+        assert_eq!(
+            tokenize_expression(r#"b = "x\"#).unwrap_err(),
+            SyntaxError {
+                message: UNTERMINATED_ESCAPE,
+                position: 6
+            }
+        );
+    }
+
+    #[test]
+    fn finds_operators_outside_quotes() {
+        assert_eq!(find_operator("age>=18"), Some((3, ">=")));
+        assert_eq!(find_operator("a != 1"), Some((2, "!=")));
+        assert_eq!(find_operator("a<b"), Some((1, "<")));
+        assert_eq!(find_operator(r#"name = "x>=y""#), Some((5, "=")));
+        assert_eq!(find_operator(r#""a=b" = 1"#), Some((6, "=")));
+        assert_eq!(find_operator(r#""a\"=b" > 1"#), Some((8, ">")));
+        assert_eq!(find_operator("città ≥ 3"), None);
+        assert_eq!(find_operator(r#"a "= 1"#), None);
+        assert_eq!(find_operator(""), None);
+    }
+
+    #[test]
+    fn random_input_never_panics() {
+        let alphabet: Vec<char> = "ab =!<>()\"'\\\t\n,{}[]:0é日🦈\u{3000}".chars().collect();
+        let mut seed = 0x5eed_u64;
+        for _ in 0..20_000 {
+            let input = random_input(&mut seed, &alphabet, 48);
+            let _ = tokenize(&input);
+            let _ = tokenize_n(&input, 3);
+            if let Err(err) = tokenize_expression(&input) {
+                assert!(err.position < input.chars().count(), "{:?}", input);
+            }
+            if let Some((offset, symbol)) = find_operator(&input) {
+                assert!(input[offset..].starts_with(symbol));
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::io::{Read, Write};
+
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+// A plain-HTTP endpoint that JSON bodies are POSTed to.
+pub struct Webhook {
+    pub url: String,
+    address: String,
+    host: String,
+    path: String,
+}
+
+impl Webhook {
+    pub fn parse(url: &str) -> Result<Self, String> {
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or_else(|| format!("Unsupported webhook (only http:// is supported): {}", url))?;
+        let (host, path) = match rest.find('/') {
+            Some(position) => (&rest[..position], &rest[position..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(format!("Invalid webhook: {}", url));
+        }
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:80", host)
+        };
+
+        Ok(Webhook {
+            url: url.to_string(),
+            address,
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+
+    // Succeeds only on a 2xx response.
+    pub fn post(&self, body: &str) -> Result<(), String> {
+        use std::net::ToSocketAddrs;
+
+        let address = &self.address;
+        let target = address
+            .to_socket_addrs()
+            .map_err(|e| format!("webhook {}: {}", address, e))?
+            .next()
+            .ok_or_else(|| format!("webhook {}: no address", address))?;
+        let mut stream = std::net::TcpStream::connect_timeout(&target, TIMEOUT)
+            .map_err(|e| format!("webhook {}: {}", address, e))?;
+        let _ = stream.set_read_timeout(Some(TIMEOUT));
+        let _ = stream.set_write_timeout(Some(TIMEOUT));
+
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            body.len(),
+            body
+        );
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|e| format!("webhook {}: {}", address, e))?;
+
+        let mut status = [0u8; 12];
+        stream
+            .read_exact(&mut status)
+            .map_err(|e| format!("webhook {}: {}", address, e))?;
+        let status = String::from_utf8_lossy(&status);
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(format!(
+                "webhook {}: unexpected response {}",
+                address,
+                status.trim()
+            )),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use crate::error::Error;
+
+pub type ImportRows = Vec<(String, serde_json::Value)>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    Jsonl,
+    Redis,
+    Mongo,
+}
+
+impl ImportFormat {
+    pub fn from_str(format: &str) -> Option<ImportFormat> {
+        match format.to_lowercase().as_str() {
+            "jsonl" => Some(ImportFormat::Jsonl),
+            "redis" => Some(ImportFormat::Redis),
+            "mongo" => Some(ImportFormat::Mongo),
+            _ => None,
+        }
+    }
+}
+
+// Rows in the order their keys first appear; later commands for a key replace or extend it.
+#[derive(Default)]
+struct RedisKeys {
+    order: Vec<String>,
+    values: std::collections::HashMap<String, serde_json::Value>,
+}
+
+impl RedisKeys {
+    fn entry(&mut self, key: &str, empty: serde_json::Value) -> &mut serde_json::Value {
+        if !self.values.contains_key(key) {
+            self.order.push(key.to_string());
+        }
+        let value = self.values.entry(key.to_string()).or_insert(empty.clone());
+        // A command for another type replaces the key, as it would after a DEL in Redis.
+        if std::mem::discriminant(value) != std::mem::discriminant(&empty) {
+            *value = empty;
+        }
+        value
+    }
+
+    fn into_rows(mut self) -> ImportRows {
+        self.order
+            .into_iter()
+            .filter_map(|key| self.values.remove(&key).map(|value| (key, value)))
+            .collect()
+    }
+}
+
+// Reads a stream of RESP commands, as written by `redis-cli --pipe` tooling or an AOF file.
+// Strings, hashes, lists, sets and sorted sets become JSON strings, objects, arrays, arrays and
+// member -> score objects. A string holding a JSON object or array is stored as that JSON.
+// Commands that do not write data (SELECT, EXPIRE, MULTI, ...) are skipped.
+pub fn parse_redis_dump(path: &std::path::Path, bytes: &[u8]) -> Result<ImportRows, Error> {
+    let mut keys = RedisKeys::default();
+    let mut position = 0;
+    while position < bytes.len() {
+        if bytes[position].is_ascii_whitespace() {
+            position += 1;
+            continue;
+        }
+        let offset = position;
+        let command = read_resp_command(bytes, &mut position)
+            .map_err(|e| Error::Parse(format!("{}: byte {}: {}", path.display(), offset, e)))?;
+        apply_redis_command(&mut keys, &command)
+            .map_err(|e| Error::Parse(format!("{}: byte {}: {}", path.display(), offset, e)))?;
+    }
+    Ok(keys.into_rows())
+}
+
+fn read_resp_line<'a>(bytes: &'a [u8], position: &mut usize) -> Result<&'a [u8], String> {
+    let start = *position;
+    let end = bytes[start..]
+        .windows(2)
+        .position(|window| window == b"\r\n")
+        .map(|offset| start + offset)
+        .ok_or("unterminated line")?;
+    *position = end + 2;
+    Ok(&bytes[start..end])
+}
+
+fn read_resp_length(line: &[u8], marker: u8) -> Result<usize, String> {
+    match line.split_first() {
+        Some((first, digits)) if *first == marker => std::str::from_utf8(digits)
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(|| format!("invalid length '{}'", String::from_utf8_lossy(line))),
+        _ => Err(format!(
+            "expected '{}', found '{}'",
+            marker as char,
+            String::from_utf8_lossy(line)
+        )),
+    }
+}
+
+fn read_resp_command(bytes: &[u8], position: &mut usize) -> Result<Vec<String>, String> {
+    let count = read_resp_length(read_resp_line(bytes, position)?, b'*')?;
+    let mut arguments = Vec::with_capacity(count);
+    for _ in 0..count {
+        let length = read_resp_length(read_resp_line(bytes, position)?, b'$')?;
+        let end = *position + length;
+        if bytes.get(end..end + 2) != Some(b"\r\n") {
+            return Err("truncated bulk string".to_string());
+        }
+        arguments.push(String::from_utf8_lossy(&bytes[*position..end]).into_owned());
+        *position = end + 2;
+    }
+    Ok(arguments)
+}
+
+fn apply_redis_command(keys: &mut RedisKeys, command: &[String]) -> Result<(), String> {
+    let Some((name, arguments)) = command.split_first() else {
+        return Ok(());
+    };
+    let name = name.to_uppercase();
+    let Some((key, values)) = arguments.split_first() else {
+        return Ok(());
+    };
+    let wrong_arguments = || format!("wrong number of arguments for {}", name);
+
+    match name.as_str() {
+        "SET" => {
+            let value = values.first().ok_or_else(wrong_arguments)?;
+            // Null only marks the key as a string; it is overwritten straight away.
+            *keys.entry(key, serde_json::Value::Null) = redis_string(value);
+        }
+        "HSET" | "HMSET" => {
+            if values.is_empty() || values.len() % 2 != 0 {
+                return Err(wrong_arguments());
+            }
+            let hash = keys.entry(key, serde_json::json!({}));
+            for pair in values.chunks(2) {
+                hash[&pair[0]] = serde_json::Value::String(pair[1].clone());
+            }
+        }
+        "RPUSH" | "LPUSH" => {
+            let list = keys.entry(key, serde_json::json!([]));
+            for value in values {
+                let value = serde_json::Value::String(value.clone());
+                if let Some(list) = list.as_array_mut() {
+                    if name == "LPUSH" {
+                        list.insert(0, value);
+                    } else {
+                        list.push(value);
+                    }
+                }
+            }
+        }
+        "SADD" => {
+            if let Some(set) = keys.entry(key, serde_json::json!([])).as_array_mut() {
+                for member in values {
+                    let member = serde_json::Value::String(member.clone());
+                    if !set.contains(&member) {
+                        set.push(member);
+                    }
+                }
+            }
+        }
+        "ZADD" => {
+            if values.is_empty() || values.len() % 2 != 0 {
+                return Err(wrong_arguments());
+            }
+            let sorted_set = keys.entry(key, serde_json::json!({}));
+            for pair in values.chunks(2) {
+                let score: f64 = pair[0]
+                    .parse()
+                    .map_err(|_| format!("invalid ZADD score '{}'", pair[0]))?;
+                sorted_set[&pair[1]] = serde_json::json!(score);
+            }
+        }
+        "DEL" | "UNLINK" => {
+            for key in arguments {
+                keys.values.remove(key);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn redis_string(value: &str) -> serde_json::Value {
+    match serde_json::from_str::<serde_json::Value>(value) {
+        Ok(json @ (serde_json::Value::Object(_) | serde_json::Value::Array(_))) => json,
+        _ => serde_json::Value::String(value.to_string()),
+    }
+}
+
+// Reads `mongoexport` output, either one document per line or a `--jsonArray` array. `_id`
+// becomes the row key (an ObjectId by its hex string) and is left out of the value. Extended
+// JSON wrappers are unwrapped: numbers become JSON numbers and dates RFC 3339 strings.
+pub fn parse_mongo_export(path: &std::path::Path, contents: &str) -> Result<ImportRows, Error> {
+    let documents: Vec<(usize, serde_json::Value)> = if contents.trim_start().starts_with('[') {
+        let documents: Vec<serde_json::Value> = serde_json::from_str(contents)
+            .map_err(|e| Error::Parse(format!("{}: invalid JSON: {}", path.display(), e)))?;
+        documents
+            .into_iter()
+            .enumerate()
+            .map(|(index, document)| (index + 1, document))
+            .collect()
+    } else {
+        let mut documents = Vec::new();
+        for (line_number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let document = serde_json::from_str(line).map_err(|e| {
+                Error::Parse(format!(
+                    "{}:{}: invalid JSON: {}",
+                    path.display(),
+                    line_number + 1,
+                    e
+                ))
+            })?;
+            documents.push((line_number + 1, document));
+        }
+        documents
+    };
+
+    let mut rows = Vec::with_capacity(documents.len());
+    for (position, document) in documents {
+        let serde_json::Value::Object(mut document) = document else {
+            return Err(Error::Parse(format!(
+                "{}:{}: documents must be JSON objects",
+                path.display(),
+                position
+            )));
+        };
+        let key = match document.remove("_id").map(from_extended_json) {
+            Some(serde_json::Value::String(id)) => id,
+            Some(serde_json::Value::Number(id)) => id.to_string(),
+            Some(id @ (serde_json::Value::Object(_) | serde_json::Value::Array(_))) => {
+                id.to_string()
+            }
+            _ => {
+                return Err(Error::Parse(format!(
+                    "{}:{}: documents must have an \"_id\"",
+                    path.display(),
+                    position
+                )));
+            }
+        };
+        rows.push((key, from_extended_json(serde_json::Value::Object(document))));
+    }
+    Ok(rows)
+}
+
+fn from_extended_json(value: serde_json::Value) -> serde_json::Value {
+    let object = match value {
+        serde_json::Value::Object(object) => object,
+        serde_json::Value::Array(items) => {
+            return serde_json::Value::Array(items.into_iter().map(from_extended_json).collect());
+        }
+        other => return other,
+    };
+
+    if object.len() == 1 {
+        let (wrapper, inner) = object.iter().next().unwrap();
+        match (wrapper.as_str(), inner) {
+            ("$oid", serde_json::Value::String(id)) => {
+                return serde_json::Value::String(id.clone());
+            }
+            ("$numberInt" | "$numberLong", serde_json::Value::String(number)) => {
+                if let Ok(number) = number.parse::<i64>() {
+                    return serde_json::json!(number);
+                }
+            }
+            ("$numberDouble", serde_json::Value::String(number)) => {
+                if let Some(number) = number
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                {
+                    return serde_json::Value::Number(number);
+                }
+            }
+            // Decimal128 keeps its digits as a string rather than rounding to a double.
+            ("$numberDecimal", serde_json::Value::String(number)) => {
+                return serde_json::Value::String(number.clone());
+            }
+            ("$date", date) => {
+                let millis = match from_extended_json(date.clone()) {
+                    serde_json::Value::Number(millis) => millis.as_i64(),
+                    serde_json::Value::String(date) => return serde_json::Value::String(date),
+                    _ => None,
+                };
+                if let Some(date) = millis.and_then(chrono::DateTime::from_timestamp_millis) {
+                    return serde_json::Value::String(date.to_rfc3339());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    serde_json::Value::Object(
+        object
+            .into_iter()
+            .map(|(field, value)| (field, from_extended_json(value)))
+            .collect(),
+    )
+}
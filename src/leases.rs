@@ -0,0 +1,129 @@
+struct Lease {
+    owner: String,
+    expires_at: std::time::Instant,
+}
+
+pub enum LeaseError {
+    Held { owner: String, remaining_secs: u64 },
+    NotLeased,
+}
+
+#[derive(Default)]
+struct LeaseState {
+    leases: std::collections::HashMap<String, Lease>,
+    // connection id -> table -> owner the connection acquired the table as
+    holders: std::collections::HashMap<String, std::collections::HashMap<String, String>>,
+}
+
+// Per-table write leases. A lease outlives the connections that took it until its TTL runs out,
+// so a worker that dies mid-task keeps others fenced off until the lease expires.
+#[derive(Default)]
+pub struct TableLeases {
+    state: std::sync::Mutex<LeaseState>,
+}
+
+impl TableLeases {
+    pub fn new() -> Self {
+        TableLeases::default()
+    }
+
+    // Takes or renews the lease. Any connection acquiring as the current owner joins it.
+    pub fn acquire(
+        &self,
+        table: &str,
+        owner: &str,
+        ttl: std::time::Duration,
+        connection_id: &str,
+    ) -> Result<(), LeaseError> {
+        let now = std::time::Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if let Some(lease) = state.leases.get(table)
+            && lease.expires_at > now
+            && lease.owner != owner
+        {
+            return Err(Self::held(lease, now));
+        }
+
+        state.leases.insert(
+            table.to_string(),
+            Lease {
+                owner: owner.to_string(),
+                expires_at: now + ttl,
+            },
+        );
+        state
+            .holders
+            .entry(connection_id.to_string())
+            .or_default()
+            .insert(table.to_string(), owner.to_string());
+        Ok(())
+    }
+
+    // Returns the owner whose lease was released. `force` lets an admin break another owner's lease.
+    pub fn release(
+        &self,
+        table: &str,
+        connection_id: &str,
+        force: bool,
+    ) -> Result<String, LeaseError> {
+        let now = std::time::Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let Some(lease) = state
+            .leases
+            .get(table)
+            .filter(|lease| lease.expires_at > now)
+        else {
+            state.leases.remove(table);
+            return Err(LeaseError::NotLeased);
+        };
+        if !force && !Self::holds(&state, table, &lease.owner, connection_id) {
+            return Err(Self::held(lease, now));
+        }
+
+        let owner = lease.owner.clone();
+        state.leases.remove(table);
+        Ok(owner)
+    }
+
+    pub fn check_write(&self, table: &str, connection_id: &str) -> Result<(), LeaseError> {
+        let now = std::time::Instant::now();
+        let state = self.state.lock().unwrap();
+        match state.leases.get(table) {
+            Some(lease)
+                if lease.expires_at > now
+                    && !Self::holds(&state, table, &lease.owner, connection_id) =>
+            {
+                Err(Self::held(lease, now))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Whether this connection holds a live lease on the table.
+    pub fn is_held_by(&self, table: &str, connection_id: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        state.leases.get(table).is_some_and(|lease| {
+            lease.expires_at > std::time::Instant::now()
+                && Self::holds(&state, table, &lease.owner, connection_id)
+        })
+    }
+
+    pub fn end_connection(&self, connection_id: &str) {
+        self.state.lock().unwrap().holders.remove(connection_id);
+    }
+
+    fn holds(state: &LeaseState, table: &str, owner: &str, connection_id: &str) -> bool {
+        state
+            .holders
+            .get(connection_id)
+            .and_then(|tables| tables.get(table))
+            .is_some_and(|held_as| held_as == owner)
+    }
+
+    fn held(lease: &Lease, now: std::time::Instant) -> LeaseError {
+        LeaseError::Held {
+            owner: lease.owner.clone(),
+            remaining_secs: lease.expires_at.duration_since(now).as_secs_f64().ceil() as u64,
+        }
+    }
+}
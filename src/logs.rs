@@ -1,9 +1,58 @@
+const CBOR_FRAME: u8 = 0x01;
+const BSON_FRAME: u8 = 0x02;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueCodec {
+    Json,
+    Cbor,
+    Bson,
+}
+
+impl ValueCodec {
+    pub fn from_str(codec: &str) -> Option<ValueCodec> {
+        match codec.to_lowercase().as_str() {
+            "json" => Some(ValueCodec::Json),
+            "cbor" => Some(ValueCodec::Cbor),
+            "bson" => Some(ValueCodec::Bson),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ValueCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValueCodec::Json => write!(f, "json"),
+            ValueCodec::Cbor => write!(f, "cbor"),
+            ValueCodec::Bson => write!(f, "bson"),
+        }
+    }
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct LogEntry {
-    operation: String,
-    table: String,
-    key: String,
-    value: Option<String>,
-    offset: u64,
+    #[serde(rename = "op")]
+    pub operation: String,
+    pub table: String,
+    pub key: String,
+    // A JSON null is a stored value; only a missing field means the entry carries none.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "present_value"
+    )]
+    pub value: Option<serde_json::Value>,
+    #[serde(default)]
+    pub version: u64,
+    // Position in the order entries were appended, starting at 1. Legacy entries have none (0).
+    #[serde(default, skip_serializing_if = "is_unsequenced")]
+    pub seq: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<crate::engine::RowMeta>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<u32>,
+    #[serde(skip)]
+    pub offset: u64,
 }
 
 impl LogEntry {
@@ -11,7 +60,9 @@ impl LogEntry {
         operation: String,
         table: String,
         key: String,
-        value: Option<String>,
+        value: Option<serde_json::Value>,
+        version: u64,
+        meta: Option<crate::engine::RowMeta>,
         offset: u64,
     ) -> Self {
         LogEntry {
@@ -19,44 +70,1024 @@ impl LogEntry {
             table,
             key,
             value,
+            version,
+            seq: 0,
+            meta,
+            checksum: None,
             offset,
         }
     }
+
+    // Carries the last sequence number through a compaction that dropped the entry holding it,
+    // so numbering continues from there after a restart.
+    pub fn checkpoint(seq: u64) -> Self {
+        let mut entry = LogEntry::new(
+            "checkpoint".to_string(),
+            String::new(),
+            String::new(),
+            None,
+            0,
+            None,
+            0,
+        );
+        entry.seq = seq;
+        entry
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        let mut unsealed = self.clone();
+        unsealed.checksum = None;
+        crc32fast::hash(unsealed.encode().as_bytes())
+    }
+
+    pub fn seal(&mut self) {
+        self.checksum = Some(self.compute_checksum());
+    }
+
+    pub fn verify_checksum(&self) -> Option<bool> {
+        self.checksum
+            .map(|checksum| checksum == self.compute_checksum())
+    }
+
+    pub fn parse(line: &str) -> Option<LogEntry> {
+        if line.starts_with('{') {
+            return serde_json::from_str(line).ok();
+        }
+
+        let parts: Vec<&str> = line.splitn(4, '|').collect();
+        if parts.len() < 3 {
+            return None;
+        }
+        let value = match parts.get(3) {
+            Some(value) if !value.is_empty() => serde_json::from_str(value).ok(),
+            _ => None,
+        };
+        Some(LogEntry::new(
+            parts[0].to_string(),
+            parts[1].to_string(),
+            parts[2].to_string(),
+            value,
+            0,
+            None,
+            0,
+        ))
+    }
+
+    pub fn encode(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    // One line per entry for people reading the log: `sharknado wal inspect` and WAL TAIL.
+    pub fn describe(&self) -> String {
+        let checksum = match self.verify_checksum() {
+            Some(true) => "ok",
+            Some(false) => "MISMATCH",
+            None => "none",
+        };
+        let updated_at = self
+            .meta
+            .map(|meta| meta.updated_at.to_rfc3339())
+            .unwrap_or_else(|| "-".to_string());
+        let value = self
+            .value
+            .as_ref()
+            .map(|value| value.to_string())
+            .unwrap_or_else(|| "-".to_string());
+        format!(
+            "@{} seq={} {} {} {} version={} updated_at={} checksum={} value={}",
+            self.offset,
+            self.seq,
+            self.operation,
+            self.table,
+            self.key,
+            self.version,
+            updated_at,
+            checksum,
+            value
+        )
+    }
+
+    pub fn encode_with(&self, codec: ValueCodec) -> Result<Vec<u8>, String> {
+        let (marker, payload) = match codec {
+            ValueCodec::Json => {
+                let mut line = self.encode().into_bytes();
+                line.push(b'\n');
+                return Ok(line);
+            }
+            ValueCodec::Cbor => {
+                let mut payload = Vec::new();
+                ciborium::into_writer(self, &mut payload).map_err(|e| e.to_string())?;
+                (CBOR_FRAME, payload)
+            }
+            ValueCodec::Bson => (BSON_FRAME, bson::to_vec(self).map_err(|e| e.to_string())?),
+        };
+
+        let mut frame = Vec::with_capacity(payload.len() + 5);
+        frame.push(marker);
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        Ok(frame)
+    }
+
+    pub fn decode_all(bytes: &[u8]) -> Result<Vec<LogEntry>, String> {
+        Self::decode(bytes, false).map(|(entries, _)| entries)
+    }
+
+    // Stops before an entry that is still being written and returns how many bytes were used.
+    pub fn decode_available(bytes: &[u8]) -> Result<(Vec<LogEntry>, usize), String> {
+        Self::decode(bytes, true)
+    }
+
+    fn decode(bytes: &[u8], stop_at_partial: bool) -> Result<(Vec<LogEntry>, usize), String> {
+        let mut entries = Vec::new();
+        let mut position = 0;
+
+        while position < bytes.len() {
+            let marker = bytes[position];
+            if marker == CBOR_FRAME || marker == BSON_FRAME {
+                let header_end = position + 5;
+                let frame_end = bytes.get(position + 1..header_end).map(|length| {
+                    header_end + u32::from_be_bytes(length.try_into().unwrap()) as usize
+                });
+                let frame_end = match frame_end {
+                    Some(frame_end) if frame_end <= bytes.len() => frame_end,
+                    _ if stop_at_partial => break,
+                    _ => return Err(format!("Truncated log frame at byte {}", position)),
+                };
+
+                let payload = &bytes[header_end..frame_end];
+                let entry: Result<LogEntry, String> = if marker == CBOR_FRAME {
+                    ciborium::from_reader(payload).map_err(|e| e.to_string())
+                } else {
+                    bson::from_slice(payload).map_err(|e| e.to_string())
+                }
+                .map_err(|e| format!("Invalid log frame at byte {}: {}", position, e));
+                let mut entry = entry?;
+                entry.offset = position as u64;
+                entries.push(entry);
+                position = frame_end;
+                continue;
+            }
+
+            let (line_end, terminated) =
+                match bytes[position..].iter().position(|byte| *byte == b'\n') {
+                    Some(offset) => (position + offset, true),
+                    None if stop_at_partial => break,
+                    None => (bytes.len(), false),
+                };
+            let line = String::from_utf8_lossy(&bytes[position..line_end]);
+            let line = line.trim_end_matches('\r');
+            if !line.trim().is_empty() {
+                match LogEntry::parse(line) {
+                    Some(mut entry) => {
+                        entry.offset = position as u64;
+                        entries.push(entry);
+                    }
+                    // A last line without its newline is a write cut short, not corruption.
+                    None if !terminated => break,
+                    None => return Err(format!("Invalid log entry at byte {}", position)),
+                }
+            }
+            position = line_end + 1;
+        }
+
+        Ok((entries, position.min(bytes.len())))
+    }
+}
+
+fn present_value<'de, D>(deserializer: D) -> Result<Option<serde_json::Value>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    serde::Deserialize::deserialize(deserializer).map(Some)
+}
+
+fn is_unsequenced(seq: &u64) -> bool {
+    *seq == 0
+}
+
+pub struct VacuumStats {
+    pub entries_removed: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+// Truncates and swaps split a table's history into segments. Within a segment only the last
+// entry per key matters, and a trailing remove can go too when the segment started empty.
+pub fn vacuum_table_entries(
+    bytes: &[u8],
+    table: &str,
+    codec: ValueCodec,
+) -> Result<(Vec<u8>, usize), String> {
+    let entries = LogEntry::decode_all(bytes)?;
+    let mut keep: Vec<bool> = entries
+        .iter()
+        .map(|entry| entry.operation != "checkpoint")
+        .collect();
+    let mut segment: Vec<usize> = Vec::new();
+    let mut starts_empty = true;
+
+    let close_segment = |segment: &mut Vec<usize>, keep: &mut Vec<bool>, starts_empty: bool| {
+        let mut last: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+        for &index in segment.iter() {
+            last.insert(entries[index].key.as_str(), index);
+        }
+        for &index in segment.iter() {
+            let entry = &entries[index];
+            if last.get(entry.key.as_str()) != Some(&index)
+                || (entry.operation == "remove" && starts_empty)
+            {
+                keep[index] = false;
+            }
+        }
+        segment.clear();
+    };
+
+    for (index, entry) in entries.iter().enumerate() {
+        match entry.operation.as_str() {
+            "add" | "update" if entry.table == table => {
+                if entry.value.is_some() {
+                    segment.push(index);
+                } else {
+                    keep[index] = false;
+                }
+            }
+            "remove" if entry.table == table => segment.push(index),
+            "truncate" | "drop" if entry.table == table => {
+                for &dropped in &segment {
+                    keep[dropped] = false;
+                }
+                segment.clear();
+                if starts_empty {
+                    keep[index] = false;
+                }
+                starts_empty = true;
+            }
+            "swap" | "rename" if entry.table == table || entry.key == table => {
+                close_segment(&mut segment, &mut keep, starts_empty);
+                starts_empty = false;
+            }
+            _ => {}
+        }
+    }
+    close_segment(&mut segment, &mut keep, starts_empty);
+
+    let mut output = Vec::with_capacity(bytes.len());
+    if let Some(first) = entries.first() {
+        output.extend_from_slice(&bytes[..first.offset as usize]);
+    }
+    let mut removed: usize = 0;
+    for (index, entry) in entries.iter().enumerate() {
+        let end = entries
+            .get(index + 1)
+            .map(|next| next.offset as usize)
+            .unwrap_or(bytes.len());
+        if keep[index] {
+            output.extend_from_slice(&bytes[entry.offset as usize..end]);
+        } else {
+            removed += 1;
+        }
+    }
+    if entries.is_empty() {
+        output.extend_from_slice(bytes);
+    }
+
+    let last_seq = entries.iter().map(|entry| entry.seq).max().unwrap_or(0);
+    let kept_seq = entries
+        .iter()
+        .zip(&keep)
+        .filter(|(_, keep)| **keep)
+        .map(|(entry, _)| entry.seq)
+        .max()
+        .unwrap_or(0);
+    if kept_seq < last_seq {
+        let mut checkpoint = LogEntry::checkpoint(last_seq);
+        checkpoint.seal();
+        output.extend(checkpoint.encode_with(codec)?);
+        // The checkpoint replaces whatever entries it stands in for.
+        removed = removed.saturating_sub(1);
+    }
+
+    Ok((output, removed))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct WalOptions {
+    pub codec: ValueCodec,
+    pub max_batch_delay: std::time::Duration,
+    pub max_batch_entries: usize,
+    pub sync: bool,
+}
+
+impl Default for WalOptions {
+    fn default() -> Self {
+        WalOptions {
+            codec: ValueCodec::Json,
+            max_batch_delay: std::time::Duration::from_micros(2000),
+            max_batch_entries: 512,
+            sync: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum WalError {
+    #[error("cannot open log: {0}")]
+    Open(String),
+    #[error("cannot write log: {0}")]
+    Write(String),
+    #[error("cannot encode log entry: {0}")]
+    Encode(String),
+    #[error("log writer stopped")]
+    Stopped,
+}
+
+const BATCH_WINDOW_STEP: std::time::Duration = std::time::Duration::from_micros(50);
+
+pub struct WalTicket(tokio::sync::oneshot::Receiver<Result<(), WalError>>);
+
+impl WalTicket {
+    pub fn resolved(result: Result<(), WalError>) -> Self {
+        let (done, written) = tokio::sync::oneshot::channel();
+        let _ = done.send(result);
+        WalTicket(written)
+    }
+
+    pub async fn wait(self) -> Result<(), WalError> {
+        self.0.await.map_err(|_| WalError::Stopped)?
+    }
+}
+
+enum WalRequest {
+    Append {
+        bytes: Vec<u8>,
+        done: tokio::sync::oneshot::Sender<Result<(), WalError>>,
+    },
+    Vacuum {
+        table: String,
+        done: tokio::sync::oneshot::Sender<Result<VacuumStats, String>>,
+    },
+}
+
+// What the writer needs from the log file, so tests can stand in one that fails part way.
+trait LogFile: std::io::Write {
+    fn length(&self) -> std::io::Result<u64>;
+    fn truncate(&self, length: u64) -> std::io::Result<()>;
+    fn sync(&self) -> std::io::Result<()>;
+}
+
+impl LogFile for std::fs::File {
+    fn length(&self) -> std::io::Result<u64> {
+        self.metadata().map(|metadata| metadata.len())
+    }
+
+    fn truncate(&self, length: u64) -> std::io::Result<()> {
+        self.set_len(length)
+    }
+
+    fn sync(&self) -> std::io::Result<()> {
+        self.sync_data()
+    }
+}
+
+// Clients are told a batch failed when this errors, so none of it may stay behind: a partial
+// entry would break the ones appended after it, and a whole one would come back on replay.
+fn write_batch(file: &mut impl LogFile, bytes: &[u8], sync: bool) -> std::io::Result<()> {
+    let start = file.length()?;
+    let written = file
+        .write_all(bytes)
+        .and_then(|_| file.flush())
+        .and_then(|_| if sync { file.sync() } else { Ok(()) });
+    if written.is_err() {
+        let _ = file.truncate(start);
+    }
+    written
+}
+
+#[derive(Default)]
+struct WalCounters {
+    batches: std::sync::atomic::AtomicU64,
+    requests: std::sync::atomic::AtomicU64,
+    bytes: std::sync::atomic::AtomicU64,
+    write_us: std::sync::atomic::AtomicU64,
+    window_us: std::sync::atomic::AtomicU64,
+}
+
+pub struct WalMetrics {
+    pub batches: u64,
+    pub requests: u64,
+    pub bytes: u64,
+    pub avg_batch_size: f64,
+    pub avg_write_us: u64,
+    pub window_us: u64,
 }
 
 pub struct LogStorageSetup {
-    pub database_name: String,
     pub log_file_path: std::path::PathBuf,
+    pub codec: ValueCodec,
+    sender: std::sync::mpsc::Sender<WalRequest>,
+    counters: std::sync::Arc<WalCounters>,
+    // Held while entries are numbered and queued, so the log is written in sequence order.
+    sequence: std::sync::Mutex<u64>,
 }
 
 impl LogStorageSetup {
-    pub fn new(database_name: String, log_file_path: std::path::PathBuf) -> Self {
+    pub fn new(log_file_path: std::path::PathBuf, options: WalOptions) -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let counters = std::sync::Arc::new(WalCounters::default());
+
+        let writer_path = log_file_path.clone();
+        let writer_counters = counters.clone();
+        std::thread::Builder::new()
+            .name("sharknado-wal".to_string())
+            .spawn(move || Self::run_writer(writer_path, receiver, options, writer_counters))
+            .unwrap();
+
         LogStorageSetup {
-            database_name,
             log_file_path,
+            codec: options.codec,
+            sender,
+            counters,
+            sequence: std::sync::Mutex::new(0),
+        }
+    }
+
+    fn run_writer(
+        log_file_path: std::path::PathBuf,
+        receiver: std::sync::mpsc::Receiver<WalRequest>,
+        options: WalOptions,
+        counters: std::sync::Arc<WalCounters>,
+    ) {
+        use std::sync::atomic::Ordering;
+        use std::sync::mpsc::RecvTimeoutError;
+
+        let mut file: Option<std::fs::File> = None;
+        let mut open_error = String::new();
+        let mut window = std::time::Duration::ZERO;
+        let mut write_cost = std::time::Duration::ZERO;
+
+        let mut pending = None;
+        loop {
+            let first = match pending.take() {
+                Some(request) => request,
+                None => match receiver.recv() {
+                    Ok(request) => request,
+                    Err(_) => break,
+                },
+            };
+            let (bytes, done) = match first {
+                WalRequest::Append { bytes, done } => (bytes, done),
+                WalRequest::Vacuum { table, done } => {
+                    // The writer reopens the log after the rewrite replaced it.
+                    file = None;
+                    let _ = done.send(Self::vacuum_file(
+                        &log_file_path,
+                        &table,
+                        options.codec,
+                        options.sync,
+                    ));
+                    continue;
+                }
+            };
+
+            let mut batch = vec![(bytes, done)];
+            let deadline = std::time::Instant::now() + window;
+            while batch.len() < options.max_batch_entries.max(1) {
+                let request = match receiver.try_recv() {
+                    Ok(request) => request,
+                    Err(_) => {
+                        let now = std::time::Instant::now();
+                        if now >= deadline {
+                            break;
+                        }
+                        match receiver.recv_timeout(deadline - now) {
+                            Ok(request) => request,
+                            Err(RecvTimeoutError::Timeout)
+                            | Err(RecvTimeoutError::Disconnected) => break,
+                        }
+                    }
+                };
+                match request {
+                    WalRequest::Append { bytes, done } => batch.push((bytes, done)),
+                    vacuum => {
+                        pending = Some(vacuum);
+                        break;
+                    }
+                }
+            }
+
+            let started = std::time::Instant::now();
+            let mut bytes = Vec::new();
+            for (request_bytes, _) in &batch {
+                bytes.extend_from_slice(request_bytes);
+            }
+
+            if file.is_none() {
+                match std::fs::OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .open(&log_file_path)
+                {
+                    Ok(opened) => file = Some(opened),
+                    Err(e) => open_error = format!("{}: {}", log_file_path.display(), e),
+                }
+            }
+            let result = match file.as_mut() {
+                Some(file) => write_batch(file, &bytes, options.sync)
+                    .map_err(|e| WalError::Write(e.to_string())),
+                None => Err(WalError::Open(open_error.clone())),
+            };
+            if result.is_err() {
+                file = None;
+            }
+
+            let elapsed = started.elapsed();
+            write_cost = (write_cost * 7 + elapsed) / 8;
+
+            counters.batches.fetch_add(1, Ordering::Relaxed);
+            counters
+                .requests
+                .fetch_add(batch.len() as u64, Ordering::Relaxed);
+            counters
+                .bytes
+                .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+            counters
+                .write_us
+                .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+
+            let batched = batch.len() > 1;
+            for (_, done) in batch {
+                let _ = done.send(result.clone());
+            }
+
+            // Waiting longer than a write takes would cost more latency than batching saves.
+            window = if batched {
+                (window * 2)
+                    .max(BATCH_WINDOW_STEP)
+                    .min(write_cost.max(BATCH_WINDOW_STEP))
+                    .min(options.max_batch_delay)
+            } else if window > BATCH_WINDOW_STEP {
+                window / 2
+            } else {
+                std::time::Duration::ZERO
+            };
+            counters
+                .window_us
+                .store(window.as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn vacuum_file(
+        log_file_path: &std::path::Path,
+        table: &str,
+        codec: ValueCodec,
+        sync: bool,
+    ) -> Result<VacuumStats, String> {
+        let bytes = match std::fs::read(log_file_path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.to_string()),
+        };
+        let (vacuumed, entries_removed) = vacuum_table_entries(&bytes, table, codec)?;
+        if entries_removed > 0 {
+            let temp_path = log_file_path.with_extension("log.vacuum");
+            let file = std::fs::File::create(&temp_path).map_err(|e| e.to_string())?;
+            std::io::Write::write_all(&mut &file, &vacuumed).map_err(|e| e.to_string())?;
+            if sync {
+                file.sync_all().map_err(|e| e.to_string())?;
+            }
+            std::fs::rename(&temp_path, log_file_path).map_err(|e| e.to_string())?;
+        }
+
+        Ok(VacuumStats {
+            entries_removed,
+            bytes_before: bytes.len() as u64,
+            bytes_after: if entries_removed > 0 {
+                vacuumed.len() as u64
+            } else {
+                bytes.len() as u64
+            },
+        })
+    }
+}
+
+impl crate::storage::StorageBackend for LogStorageSetup {
+    fn location(&self) -> String {
+        self.log_file_path.display().to_string()
+    }
+
+    fn append(&self, entries: Vec<LogEntry>) -> WalTicket {
+        let (done, written) = tokio::sync::oneshot::channel();
+        let mut bytes = Vec::new();
+        let mut sequence = self.sequence.lock().unwrap();
+        for mut entry in entries {
+            entry.seq = crate::storage::next_sequence(&mut sequence, entry.seq);
+            entry.seal();
+            match entry.encode_with(self.codec) {
+                Ok(encoded) => bytes.extend(encoded),
+                Err(e) => {
+                    let _ = done.send(Err(WalError::Encode(e)));
+                    return WalTicket(written);
+                }
+            }
         }
+
+        // A send only fails once the writer thread is gone, which wait() then reports.
+        let _ = self.sender.send(WalRequest::Append { bytes, done });
+        WalTicket(written)
     }
 
-    pub async fn log_entry(&self, entry: LogEntry) {
-        use tokio::fs::OpenOptions;
-        use tokio::io::AsyncWriteExt;
+    fn replay(&self) -> Result<Option<crate::storage::Replay>, crate::error::Error> {
+        let bytes = match std::fs::read(&self.log_file_path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let modified =
+            std::fs::metadata(&self.log_file_path).and_then(|metadata| metadata.modified())?;
+        // A crash mid-write leaves the last entry cut short. It was never acknowledged, so it is
+        // cut off before anything is appended after it; a broken entry earlier on is an error.
+        let (entries, used) = LogEntry::decode_available(&bytes).map_err(|e| {
+            crate::error::Error::Parse(format!("{}: {}", self.log_file_path.display(), e))
+        })?;
+        if let Some(entry) = entries
+            .iter()
+            .find(|entry| entry.verify_checksum() == Some(false))
+        {
+            return Err(crate::error::Error::Parse(format!(
+                "{}: checksum mismatch in log entry at byte {}",
+                self.log_file_path.display(),
+                entry.offset
+            )));
+        }
+        if used < bytes.len() {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(&self.log_file_path)
+                .and_then(|file| file.set_len(used as u64))?;
+        }
+        let last_seq = entries.iter().map(|entry| entry.seq).max().unwrap_or(0);
+        let mut sequence = self.sequence.lock().unwrap();
+        *sequence = (*sequence).max(last_seq);
+        Ok(Some(crate::storage::Replay {
+            entries,
+            bytes: used as u64,
+            truncated: (bytes.len() - used) as u64,
+            modified: modified.into(),
+        }))
+    }
 
-        let mut file = OpenOptions::new()
-            .append(true)
-            .create(true)
-            .open(&self.log_file_path)
+    fn snapshot(&self) -> crate::storage::StorageFuture<'_, Result<Vec<u8>, crate::error::Error>> {
+        Box::pin(async move {
+            self.append(Vec::new()).wait().await?;
+            match std::fs::read(&self.log_file_path) {
+                Ok(bytes) => Ok(bytes),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+                Err(e) => Err(e.into()),
+            }
+        })
+    }
+
+    fn compact(
+        &self,
+        table: String,
+    ) -> crate::storage::StorageFuture<'_, Result<VacuumStats, String>> {
+        Box::pin(async move {
+            let (done, vacuumed) = tokio::sync::oneshot::channel();
+            self.sender
+                .send(WalRequest::Vacuum { table, done })
+                .map_err(|e| e.to_string())?;
+            vacuumed.await.map_err(|e| e.to_string())?
+        })
+    }
+
+    fn last_sequence(&self) -> u64 {
+        *self.sequence.lock().unwrap()
+    }
+
+    fn metrics(&self) -> WalMetrics {
+        use std::sync::atomic::Ordering;
+
+        let batches = self.counters.batches.load(Ordering::Relaxed);
+        let requests = self.counters.requests.load(Ordering::Relaxed);
+        WalMetrics {
+            batches,
+            requests,
+            bytes: self.counters.bytes.load(Ordering::Relaxed),
+            avg_batch_size: if batches == 0 {
+                0.0
+            } else {
+                requests as f64 / batches as f64
+            },
+            avg_write_us: self
+                .counters
+                .write_us
+                .load(Ordering::Relaxed)
+                .checked_div(batches)
+                .unwrap_or(0),
+            window_us: self.counters.window_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::StorageBackend;
+
+    fn entry(operation: &str, key: &str, value: Option<serde_json::Value>) -> LogEntry {
+        LogEntry::new(
+            operation.to_string(),
+            "t|able".to_string(),
+            key.to_string(),
+            value,
+            7,
+            None,
+            0,
+        )
+    }
+
+    #[test]
+    fn entries_round_trip_through_every_codec() {
+        let entries = vec![
+            entry(
+                "add",
+                "a|b",
+                Some(serde_json::json!({ "text": "x|y\nz\r\n" })),
+            ),
+            entry("add", "null", Some(serde_json::Value::Null)),
+            entry("add", "line\nbreak", Some(serde_json::json!("🦈"))),
+            entry("remove", "a|b", None),
+        ];
+        for codec in [ValueCodec::Json, ValueCodec::Cbor, ValueCodec::Bson] {
+            let mut bytes = Vec::new();
+            for entry in &entries {
+                bytes.extend(entry.encode_with(codec).unwrap());
+            }
+            let decoded = LogEntry::decode_all(&bytes).unwrap();
+            assert_eq!(decoded.len(), entries.len(), "{}", codec);
+            for (decoded, entry) in decoded.iter().zip(&entries) {
+                assert_eq!(decoded.operation, entry.operation, "{}", codec);
+                assert_eq!(decoded.table, entry.table, "{}", codec);
+                assert_eq!(decoded.key, entry.key, "{}", codec);
+                assert_eq!(decoded.value, entry.value, "{}", codec);
+                assert_eq!(decoded.version, entry.version, "{}", codec);
+            }
+        }
+    }
+
+    #[test]
+    fn sealed_entries_verify_after_decoding() {
+        let mut sealed = entry("add", "k", Some(serde_json::Value::Null));
+        sealed.seal();
+        let decoded = LogEntry::decode_all(&sealed.encode_with(ValueCodec::Json).unwrap()).unwrap();
+        assert_eq!(decoded[0].verify_checksum(), Some(true));
+
+        let mut tampered = sealed.clone();
+        tampered.value = Some(serde_json::json!("changed"));
+        assert_eq!(tampered.verify_checksum(), Some(false));
+    }
+
+    #[test]
+    fn decode_available_stops_before_a_partial_entry() {
+        let mut bytes = entry("add", "a", Some(serde_json::json!(1)))
+            .encode_with(ValueCodec::Json)
+            .unwrap();
+        let complete = bytes.len();
+        let frame = entry("add", "b", Some(serde_json::json!(2)))
+            .encode_with(ValueCodec::Cbor)
+            .unwrap();
+        bytes.extend_from_slice(&frame[..frame.len() - 1]);
+
+        let (entries, used) = LogEntry::decode_available(&bytes).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(used, complete);
+        assert!(LogEntry::decode_all(&bytes).is_err());
+    }
+
+    #[test]
+    fn vacuum_keeps_the_last_entry_per_key() {
+        let mut bytes = Vec::new();
+        for n in 0..5 {
+            bytes.extend(
+                entry("add", "k", Some(serde_json::json!(n)))
+                    .encode_with(ValueCodec::Json)
+                    .unwrap(),
+            );
+        }
+        let (vacuumed, removed) = vacuum_table_entries(&bytes, "t|able", ValueCodec::Json).unwrap();
+        assert_eq!(removed, 4);
+        let entries = LogEntry::decode_all(&vacuumed).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].value, Some(serde_json::json!(4)));
+    }
+
+    #[tokio::test]
+    async fn log_storage_appends_and_replays_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested").join("test.log");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let storage = LogStorageSetup::new(path.clone(), WalOptions::default());
+        assert!(storage.replay().unwrap().is_none());
+
+        storage
+            .append(vec![
+                entry("add", "a", Some(serde_json::json!(1))),
+                entry("add", "b", Some(serde_json::json!(2))),
+            ])
+            .wait()
             .await
             .unwrap();
+        storage
+            .append(vec![entry("remove", "a", None)])
+            .wait()
+            .await
+            .unwrap();
+        assert_eq!(storage.last_sequence(), 3);
+        drop(storage);
 
-        let entry_str = format!(
-            "{}|{}|{}|{}\n",
-            entry.operation,
-            entry.table,
-            entry.key,
-            entry.value.unwrap_or_default()
-        );
+        let storage = LogStorageSetup::new(path, WalOptions::default());
+        let replay = storage.replay().unwrap().unwrap();
+        let seqs: Vec<u64> = replay.entries.iter().map(|entry| entry.seq).collect();
+        assert_eq!(seqs, vec![1, 2, 3]);
+        assert_eq!(storage.last_sequence(), 3);
+        storage
+            .append(vec![entry("add", "c", Some(serde_json::json!(3)))])
+            .wait()
+            .await
+            .unwrap();
+        assert_eq!(storage.last_sequence(), 4);
+    }
+
+    #[tokio::test]
+    async fn replay_drops_a_frame_cut_short_by_a_crash() {
+        for codec in [ValueCodec::Json, ValueCodec::Cbor, ValueCodec::Bson] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("test.log");
+            let options = WalOptions {
+                codec,
+                ..Default::default()
+            };
+            let storage = LogStorageSetup::new(path.clone(), options);
+            for key in ["a", "b"] {
+                storage
+                    .append(vec![entry("add", key, Some(serde_json::json!(key)))])
+                    .wait()
+                    .await
+                    .unwrap();
+            }
+            drop(storage);
+            let bytes = std::fs::read(&path).unwrap();
+            std::fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+
+            let storage = LogStorageSetup::new(path.clone(), options);
+            let replay = storage.replay().unwrap().unwrap();
+            assert_eq!(replay.entries.len(), 1, "{}", codec);
+            assert_eq!(
+                replay.bytes + replay.truncated,
+                bytes.len() as u64 - 3,
+                "{}",
+                codec
+            );
+            storage
+                .append(vec![entry("add", "c", Some(serde_json::json!("c")))])
+                .wait()
+                .await
+                .unwrap();
+            drop(storage);
+
+            let storage = LogStorageSetup::new(path.clone(), options);
+            let replay = storage.replay().unwrap().unwrap();
+            let keys: Vec<&str> = replay
+                .entries
+                .iter()
+                .map(|entry| entry.key.as_str())
+                .collect();
+            assert_eq!(keys, ["a", "c"], "{}", codec);
+            assert_eq!(replay.truncated, 0, "{}", codec);
+
+            // A broken entry before the end is not a torn write, and replay refuses it.
+            let mut bytes = std::fs::read(&path).unwrap();
+            bytes[5..9].copy_from_slice(&[0xff; 4]);
+            std::fs::write(&path, &bytes).unwrap();
+            assert!(storage.replay().is_err(), "{}", codec);
+        }
+    }
+
+    #[tokio::test]
+    async fn replay_refuses_a_broken_or_altered_entry_before_the_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        let storage = LogStorageSetup::new(path.clone(), WalOptions::default());
+        for (key, value) in [("a", 1), ("b", 2), ("c", 3)] {
+            storage
+                .append(vec![entry("add", key, Some(serde_json::json!(value)))])
+                .wait()
+                .await
+                .unwrap();
+        }
+        drop(storage);
+        let original = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = original.lines().collect();
+
+        let broken = format!("{}\n{}\n{}\n", lines[0], &lines[1][..20], lines[2]);
+        std::fs::write(&path, broken).unwrap();
+        let storage = LogStorageSetup::new(path.clone(), WalOptions::default());
+        assert!(storage.replay().is_err());
 
-        file.write_all(entry_str.as_bytes()).await.unwrap();
-        file.flush().await.unwrap();
+        let altered = original.replace("\"value\":2", "\"value\":5");
+        assert_ne!(altered, original);
+        std::fs::write(&path, altered).unwrap();
+        let error = storage.replay().err().unwrap();
+        assert!(error.to_string().contains("checksum"), "{}", error);
+
+        std::fs::write(&path, &original).unwrap();
+        assert_eq!(storage.replay().unwrap().unwrap().entries.len(), 3);
+    }
+
+    struct FailingFile {
+        file: std::fs::File,
+        room: usize,
+        fail_sync: bool,
+    }
+
+    impl std::io::Write for FailingFile {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.room == 0 {
+                return Err(std::io::Error::other("no space left"));
+            }
+            let accepted = buf.len().min(self.room);
+            self.room -= accepted;
+            self.file.write(&buf[..accepted])
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.file.flush()
+        }
+    }
+
+    impl LogFile for FailingFile {
+        fn length(&self) -> std::io::Result<u64> {
+            self.file.length()
+        }
+
+        fn truncate(&self, length: u64) -> std::io::Result<()> {
+            self.file.truncate(length)
+        }
+
+        fn sync(&self) -> std::io::Result<()> {
+            if self.fail_sync {
+                return Err(std::io::Error::other("sync failed"));
+            }
+            self.file.sync()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failed_batch_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.log");
+        let storage = LogStorageSetup::new(path.clone(), WalOptions::default());
+        storage
+            .append(vec![entry("add", "a", Some(serde_json::json!(1)))])
+            .wait()
+            .await
+            .unwrap();
+        drop(storage);
+        let before = std::fs::read(&path).unwrap();
+
+        let mut failed = entry("add", "b", Some(serde_json::json!(2)));
+        failed.seq = 2;
+        let batch = failed.encode_with(ValueCodec::Json).unwrap();
+        // Cut off part way through the entry, and written in full but never made durable.
+        for (room, fail_sync) in [(batch.len() / 2, false), (usize::MAX, true)] {
+            let mut file = FailingFile {
+                file: std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .unwrap(),
+                room,
+                fail_sync,
+            };
+            assert!(write_batch(&mut file, &batch, true).is_err());
+            assert_eq!(std::fs::read(&path).unwrap(), before);
+        }
+
+        let storage = LogStorageSetup::new(path.clone(), WalOptions::default());
+        assert_eq!(storage.replay().unwrap().unwrap().entries.len(), 1);
+        storage
+            .append(vec![entry("add", "c", Some(serde_json::json!(3)))])
+            .wait()
+            .await
+            .unwrap();
+        let keys: Vec<String> = storage
+            .replay()
+            .unwrap()
+            .unwrap()
+            .entries
+            .into_iter()
+            .map(|entry| entry.key)
+            .collect();
+        assert_eq!(keys, ["a", "c"]);
     }
 }
@@ -0,0 +1,27 @@
+// Counts commands per user in one-second windows shared by all of the user's connections. A
+// fixed window lets a burst straddle two seconds, which is close enough for a fairness limit.
+#[derive(Default)]
+pub struct OpsLimiter {
+    windows: std::sync::Mutex<std::collections::HashMap<String, (u64, u32)>>,
+}
+
+impl OpsLimiter {
+    pub fn new() -> Self {
+        OpsLimiter::default()
+    }
+
+    pub fn admit(&self, username: &str, max_ops_per_sec: u32) -> bool {
+        let second = chrono::Utc::now().timestamp() as u64;
+        let mut windows = self.windows.lock().unwrap();
+        let (window, count) = windows.entry(username.to_string()).or_insert((second, 0));
+        if *window != second {
+            *window = second;
+            *count = 0;
+        }
+        if *count >= max_ops_per_sec {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
@@ -0,0 +1,97 @@
+pub struct RetentionPolicy {
+    table: String,
+    max_age: Option<chrono::Duration>,
+    age_field: String,
+    max_rows: Option<usize>,
+}
+
+impl RetentionPolicy {
+    pub fn from_config(
+        table: &str,
+        config: &crate::helpers::configs::RetentionPolicyConfig,
+    ) -> Result<Self, String> {
+        if config.max_age_secs.is_none() && config.max_rows.is_none() {
+            return Err("set max_age_secs, max_rows or both".to_string());
+        }
+        let max_age = match config.max_age_secs {
+            Some(0) => return Err("max_age_secs must be greater than 0".to_string()),
+            Some(secs) => Some(
+                chrono::Duration::try_seconds(secs as i64)
+                    .ok_or_else(|| format!("max_age_secs {} is too large", secs))?,
+            ),
+            None => None,
+        };
+
+        Ok(RetentionPolicy {
+            table: table.to_string(),
+            max_age,
+            age_field: config.age_field.clone(),
+            max_rows: config.max_rows,
+        })
+    }
+
+    pub fn expiry(&self) -> Option<(&str, chrono::Duration)> {
+        self.max_age
+            .map(|max_age| (self.age_field.as_str(), max_age))
+    }
+}
+
+impl std::fmt::Display for RetentionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut rules = Vec::new();
+        if let Some(max_age) = self.max_age {
+            rules.push(format!(
+                "delete rows with {} older than {}s",
+                self.age_field,
+                max_age.num_seconds()
+            ));
+        }
+        if let Some(max_rows) = self.max_rows {
+            rules.push(format!("keep the newest {} rows", max_rows));
+        }
+        write!(f, "{}", rules.join(", "))
+    }
+}
+
+// Applies every policy once per interval. Passes are skipped while the server is read-only, so a
+// standby never drifts from its primary and maintenance windows or WAL outages see no deletes.
+pub async fn run(
+    policies: Vec<RetentionPolicy>,
+    engine: std::sync::Arc<crate::engine::Engine>,
+    standby_active: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    maintenance_notice: std::sync::Arc<std::sync::RwLock<Option<String>>>,
+    logger: crate::helpers::logging::Logger,
+    interval: std::time::Duration,
+) {
+    loop {
+        let read_only = standby_active.load(std::sync::atomic::Ordering::SeqCst)
+            || maintenance_notice.read().unwrap().is_some()
+            || engine.wal_degraded().is_some();
+        if !read_only {
+            for policy in &policies {
+                let cutoff = policy.max_age.map(|max_age| chrono::Utc::now() - max_age);
+                match engine
+                    .apply_retention(&policy.table, &policy.age_field, cutoff, policy.max_rows)
+                    .await
+                {
+                    Ok((expired, excess)) if expired + excess > 0 => {
+                        logger
+                            .info(&format!(
+                                "Retention on {}: deleted {} expired and {} excess rows",
+                                policy.table, expired, excess
+                            ))
+                            .await;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        logger
+                            .error(&format!("Retention on {} failed: {}", policy.table, e))
+                            .await;
+                    }
+                }
+            }
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,200 @@
+struct RowLock {
+    connection_id: String,
+    expires_at: std::time::Instant,
+}
+
+pub enum LockError {
+    Held { remaining_secs: u64 },
+    NotLocked,
+}
+
+impl LockError {
+    // For writes that report through the engine's errors rather than a response line.
+    pub fn into_error(self, table: &str, key: &str) -> crate::error::Error {
+        match self {
+            LockError::Held { remaining_secs } => crate::error::Error::Conflict(format!(
+                "Row {} {} is locked by another connection for another {}s",
+                table, key, remaining_secs
+            )),
+            LockError::NotLocked => crate::error::Error::NotFound(format!(
+                "Row {} {} is not locked by this connection",
+                table, key
+            )),
+        }
+    }
+}
+
+// Locks on single rows, held by the connection that took them until it unlocks, disconnects or
+// the lock's TTL runs out. Writes to a locked row from other connections are held back, as are
+// commands that replace its whole table; reads never wait.
+#[derive(Default)]
+pub struct RowLocks {
+    locks: std::sync::Mutex<std::collections::HashMap<(String, String), RowLock>>,
+    released: tokio::sync::Notify,
+}
+
+impl RowLocks {
+    pub fn new() -> Self {
+        RowLocks::default()
+    }
+
+    // Takes the lock, or renews it for the connection already holding it. While another
+    // connection holds it, waits for it to be released or to expire until `deadline`.
+    pub async fn lock(
+        &self,
+        table: &str,
+        key: &str,
+        connection_id: &str,
+        ttl: std::time::Duration,
+        deadline: std::time::Instant,
+    ) -> Result<(), LockError> {
+        let row = (table.to_string(), key.to_string());
+        loop {
+            let released = self.released.notified();
+            let now = std::time::Instant::now();
+            let expires_at = {
+                let mut locks = self.locks.lock().unwrap();
+                // Taking a lock is the only way the map grows, so expired ones go here.
+                locks.retain(|_, lock| lock.expires_at > now);
+                match locks.get(&row) {
+                    Some(lock) if lock.expires_at > now && lock.connection_id != connection_id => {
+                        lock.expires_at
+                    }
+                    _ => {
+                        locks.insert(
+                            row,
+                            RowLock {
+                                connection_id: connection_id.to_string(),
+                                expires_at: now + ttl,
+                            },
+                        );
+                        return Ok(());
+                    }
+                }
+            };
+            if now >= deadline {
+                return Err(Self::held(expires_at, now));
+            }
+            let _ = tokio::time::timeout_at(deadline.min(expires_at).into(), released).await;
+        }
+    }
+
+    pub fn unlock(&self, table: &str, key: &str, connection_id: &str) -> Result<(), LockError> {
+        let row = (table.to_string(), key.to_string());
+        let mut locks = self.locks.lock().unwrap();
+        match locks.get(&row) {
+            Some(lock)
+                if lock.expires_at > std::time::Instant::now()
+                    && lock.connection_id == connection_id =>
+            {
+                locks.remove(&row);
+                self.released.notify_waiters();
+                Ok(())
+            }
+            _ => Err(LockError::NotLocked),
+        }
+    }
+
+    pub fn check_write(
+        &self,
+        table: &str,
+        key: &str,
+        connection_id: &str,
+    ) -> Result<(), LockError> {
+        let now = std::time::Instant::now();
+        let locks = self.locks.lock().unwrap();
+        match locks.get(&(table.to_string(), key.to_string())) {
+            Some(lock) if lock.expires_at > now && lock.connection_id != connection_id => {
+                Err(Self::held(lock.expires_at, now))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Fails while another connection holds a live lock on any row of the table, naming that row.
+    pub fn check_table(&self, table: &str, connection_id: &str) -> Result<(), (String, LockError)> {
+        let now = std::time::Instant::now();
+        let locks = self.locks.lock().unwrap();
+        let held = locks.iter().find(|((locked_table, _), lock)| {
+            locked_table == table && lock.expires_at > now && lock.connection_id != connection_id
+        });
+        match held {
+            Some(((_, key), lock)) => Err((key.clone(), Self::held(lock.expires_at, now))),
+            None => Ok(()),
+        }
+    }
+
+    // Releases every lock the connection holds, returning how many were still live. Expired
+    // locks are dropped along the way.
+    pub fn unlock_all(&self, connection_id: &str) -> usize {
+        let now = std::time::Instant::now();
+        let mut locks = self.locks.lock().unwrap();
+        let before = locks.len();
+        let mut expired = 0;
+        locks.retain(|_, lock| {
+            if lock.expires_at <= now {
+                expired += 1;
+                return false;
+            }
+            lock.connection_id != connection_id
+        });
+        let released = before - locks.len() - expired;
+        if released > 0 {
+            self.released.notify_waiters();
+        }
+        released
+    }
+
+    fn held(expires_at: std::time::Instant, now: std::time::Instant) -> LockError {
+        LockError::Held {
+            remaining_secs: expires_at.duration_since(now).as_secs_f64().ceil() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waiters_take_the_lock_once_it_is_released() {
+        let locks = std::sync::Arc::new(RowLocks::new());
+        let ttl = std::time::Duration::from_secs(30);
+        let soon = || std::time::Instant::now() + std::time::Duration::from_millis(20);
+        let later = || std::time::Instant::now() + std::time::Duration::from_secs(5);
+
+        assert!(locks.lock("orders", "o1", "a", ttl, soon()).await.is_ok());
+        assert!(locks.lock("orders", "o1", "a", ttl, soon()).await.is_ok());
+        assert!(locks.lock("orders", "o2", "b", ttl, soon()).await.is_ok());
+        assert!(matches!(
+            locks.lock("orders", "o1", "b", ttl, soon()).await,
+            Err(LockError::Held { .. })
+        ));
+        assert!(locks.check_write("orders", "o1", "a").is_ok());
+        assert!(locks.check_write("orders", "o1", "b").is_err());
+        assert!(locks.check_table("orders", "a").is_err());
+        assert!(locks.check_table("customers", "b").is_ok());
+        assert!(matches!(
+            locks.unlock("orders", "o1", "b"),
+            Err(LockError::NotLocked)
+        ));
+
+        let waiter = tokio::spawn({
+            let locks = locks.clone();
+            async move { locks.lock("orders", "o1", "b", ttl, later()).await.is_ok() }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        assert_eq!(locks.unlock_all("a"), 1);
+        assert!(waiter.await.unwrap());
+        assert!(locks.check_write("orders", "o1", "a").is_err());
+
+        // An expired lock no longer holds anyone back, and is dropped by the next LOCK.
+        let short = std::time::Duration::from_millis(10);
+        assert!(locks.lock("orders", "o3", "a", short, soon()).await.is_ok());
+        assert!(locks.lock("orders", "o3", "b", ttl, later()).await.is_ok());
+        assert!(locks.lock("orders", "o4", "a", short, soon()).await.is_ok());
+        tokio::time::sleep(short).await;
+        assert!(locks.lock("orders", "o5", "b", ttl, soon()).await.is_ok());
+        assert_eq!(locks.locks.lock().unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,323 @@
+use crate::engine::Engine;
+use crate::error::Error;
+use crate::user_manager::UserRole;
+use mlua::LuaSerdeExt;
+use std::collections::HashMap;
+
+// How often, in Lua VM instructions, the budget and deadline are checked.
+const HOOK_INTERVAL: u32 = 1000;
+
+pub struct ScriptRun {
+    pub role: UserRole,
+    // Each declared table, by the name the script uses for it and the name it is stored under.
+    pub tables: HashMap<String, String>,
+    pub strict: bool,
+    pub max_instructions: u64,
+    pub max_memory_bytes: usize,
+    pub deadline: Option<std::time::Instant>,
+    // Rows locked by other connections cannot be written.
+    pub row_locks: std::sync::Arc<crate::row_locks::RowLocks>,
+    pub connection_id: String,
+}
+
+// Runs a script against tables the caller has already locked with Engine::lock_for_script. Its
+// writes come back even when it fails: writes made before an error are kept, and the caller
+// still has to commit them with Engine::commit_script before releasing the tables.
+pub fn run(
+    engine: &Engine,
+    script: &ScriptRun,
+    source: &str,
+) -> (
+    Result<serde_json::Value, Error>,
+    Vec<crate::engine::ScriptWrite>,
+) {
+    let written = std::cell::RefCell::new(Vec::new());
+    let result = evaluate(engine, script, source, &written).map_err(script_error);
+    (result, written.into_inner())
+}
+
+fn evaluate(
+    engine: &Engine,
+    script: &ScriptRun,
+    source: &str,
+    written: &std::cell::RefCell<Vec<crate::engine::ScriptWrite>>,
+) -> mlua::Result<serde_json::Value> {
+    // No io, os, package or debug library: a script can reach the database and nothing else.
+    let lua = mlua::Lua::new_with(
+        mlua::StdLib::TABLE | mlua::StdLib::STRING | mlua::StdLib::MATH | mlua::StdLib::UTF8,
+        mlua::LuaOptions::new(),
+    )?;
+    for name in ["dofile", "loadfile", "print"] {
+        lua.globals().set(name, mlua::Value::Nil)?;
+    }
+    // The hook below only runs between VM instructions, and string searches run entirely in C,
+    // where a backtracking pattern can hold the tables locked for as long as it takes.
+    let string: mlua::Table = lua.globals().get("string")?;
+    for name in ["find", "match", "gmatch", "gsub"] {
+        string.set(name, mlua::Value::Nil)?;
+    }
+    lua.set_memory_limit(script.max_memory_bytes)?;
+
+    let max_instructions = script.max_instructions;
+    let deadline = script.deadline;
+    let executed = std::cell::Cell::new(0u64);
+    lua.set_hook(
+        mlua::HookTriggers::new().every_nth_instruction(HOOK_INTERVAL),
+        move |_, _| {
+            executed.set(executed.get() + HOOK_INTERVAL as u64);
+            if deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline) {
+                return Err(mlua::Error::external(Error::Timeout));
+            }
+            if max_instructions > 0 && executed.get() > max_instructions {
+                return Err(mlua::Error::external(Error::Script(format!(
+                    "Script ran past its budget of {} instructions",
+                    max_instructions
+                ))));
+            }
+            Ok(())
+        },
+    );
+
+    let table = |name: &str| {
+        script.tables.get(name).cloned().ok_or_else(|| {
+            mlua::Error::external(Error::PermissionDenied(format!(
+                "Table {} was not declared in EVAL",
+                name
+            )))
+        })
+    };
+
+    // Writes act as the script's role: rows its filter hides cannot be written, and the row a
+    // write replaced comes back as the role may read it.
+    let writable = |table: &str, key: &str| {
+        script
+            .row_locks
+            .check_write(table, key, &script.connection_id)
+            .map_err(|err| mlua::Error::external(err.into_error(table, key)))?;
+        engine
+            .check_writable_as(&script.role, table, key)
+            .map_err(mlua::Error::external)
+    };
+    let readable = |table: &str, previous: Option<std::sync::Arc<crate::engine::Row>>| {
+        previous
+            .and_then(|row| engine.row_as(&script.role, table, &row))
+            .map(|row| row.value)
+    };
+
+    lua.scope(|scope| {
+        let db = lua.create_table()?;
+        db.set(
+            "get",
+            scope.create_function(|lua, (name, key): (String, String)| {
+                let row = engine.get_row_as(&script.role, table(&name)?, key);
+                row_value(lua, row.map(|row| row.value))
+            })?,
+        )?;
+        db.set(
+            "set",
+            scope.create_function(|lua, (name, key, value): (String, String, mlua::Value)| {
+                let value: serde_json::Value = lua.from_value(value)?;
+                let table = table(&name)?;
+                writable(&table, &key)?;
+                let (previous, write) = engine
+                    .set_locked(table.clone(), key, value)
+                    .map_err(mlua::Error::external)?;
+                written.borrow_mut().push(write);
+                row_value(lua, readable(&table, previous))
+            })?,
+        )?;
+        db.set(
+            "delete",
+            scope.create_function(|lua, (name, key): (String, String)| {
+                let table = table(&name)?;
+                writable(&table, &key)?;
+                let (previous, write) = engine.remove_locked(&table, &key);
+                written.borrow_mut().push(write);
+                row_value(lua, readable(&table, previous))
+            })?,
+        )?;
+        db.set(
+            "query",
+            scope.create_function(|lua, (name, filter): (String, String)| {
+                let filter =
+                    crate::connection::TCPServer::parse_query_expression(&filter, script.strict)
+                        .map_err(mlua::Error::external)?;
+                let rows = engine
+                    .query_rows_as(&script.role, table(&name)?, filter, None, script.deadline)
+                    .map_err(mlua::Error::external)?;
+                let rows: Vec<serde_json::Value> = rows
+                    .into_iter()
+                    .map(|(key, value)| serde_json::json!({ "key": key, "value": value }))
+                    .collect();
+                lua.to_value(&rows)
+            })?,
+        )?;
+        lua.globals().set("db", db)?;
+
+        let result: mlua::Value = lua.load(source).set_name("=EVAL").eval()?;
+        lua.from_value(result)
+    })
+}
+
+// A missing row is nil, so `db.get(...) or default` works; JSON null inside a document stays
+// distinct from it.
+fn row_value(lua: &mlua::Lua, value: Option<serde_json::Value>) -> mlua::Result<mlua::Value<'_>> {
+    match value {
+        Some(value) => lua.to_value(&value),
+        None => Ok(mlua::Value::Nil),
+    }
+}
+
+// Errors raised by db.* calls keep their own code; anything else Lua reports is a script error,
+// cut to its first line since responses are line-based.
+fn script_error(err: mlua::Error) -> Error {
+    let mut cause = &err;
+    loop {
+        match cause {
+            mlua::Error::CallbackError { cause: inner, .. } => cause = inner,
+            mlua::Error::ExternalError(external) => {
+                if let Some(err) = external.downcast_ref::<Error>() {
+                    return err.clone();
+                }
+                break;
+            }
+            _ => break,
+        }
+    }
+    let message = match cause {
+        mlua::Error::RuntimeError(message) | mlua::Error::SyntaxError { message, .. } => {
+            message.clone()
+        }
+        cause => cause.to_string(),
+    };
+    Error::Script(message.lines().next().unwrap_or_default().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn engine() -> Engine {
+        Engine::new(
+            "test".to_string(),
+            String::new(),
+            Box::new(crate::storage::MemoryStorage::default()),
+        )
+    }
+
+    fn run_script(source: &str) -> Result<serde_json::Value, Error> {
+        run_on(&engine(), source)
+    }
+
+    fn run_on(engine: &Engine, source: &str) -> Result<serde_json::Value, Error> {
+        let script = ScriptRun {
+            role: UserRole::User,
+            tables: HashMap::from([
+                ("counters".to_string(), "counters".to_string()),
+                ("people".to_string(), "people".to_string()),
+            ]),
+            strict: false,
+            max_instructions: 100_000,
+            max_memory_bytes: 1 << 20,
+            deadline: None,
+            row_locks: std::sync::Arc::new(crate::row_locks::RowLocks::new()),
+            connection_id: "test".to_string(),
+        };
+        run(engine, &script, source).0
+    }
+
+    #[test]
+    fn reads_and_writes_declared_tables() {
+        assert_eq!(
+            run_script(
+                r#"
+                db.set("counters", "hits", { n = 1 })
+                local row = db.get("counters", "hits")
+                db.set("counters", "hits", { n = row.n + 1 })
+                return { db.get("counters", "hits").n, #db.query("counters", "n > 0") }
+                "#
+            ),
+            Ok(serde_json::json!([2, 1]))
+        );
+        assert_eq!(
+            run_script(r#"return db.get("counters", "missing")"#),
+            Ok(serde_json::Value::Null)
+        );
+    }
+
+    #[test]
+    fn stays_inside_its_sandbox() {
+        assert_eq!(
+            run_script(r#"return db.get("users", "u1")"#),
+            Err(Error::PermissionDenied(
+                "Table users was not declared in EVAL".to_string()
+            ))
+        );
+        assert!(matches!(
+            run_script("return io.open('/etc/passwd')"),
+            Err(Error::Script(_))
+        ));
+        assert!(matches!(
+            run_script("return os.time()"),
+            Err(Error::Script(_))
+        ));
+        assert!(matches!(
+            run_script("dofile('/etc/passwd')"),
+            Err(Error::Script(_))
+        ));
+        assert!(matches!(
+            run_script("return (string.rep('a', 30)):match('(a*)*b')"),
+            Err(Error::Script(_))
+        ));
+        assert_eq!(
+            run_script("return string.upper('ok')"),
+            Ok(serde_json::json!("OK"))
+        );
+        assert_eq!(
+            run_script("while true do end"),
+            Err(Error::Script(
+                "Script ran past its budget of 100000 instructions".to_string()
+            ))
+        );
+        assert!(matches!(run_script("return ("), Err(Error::Script(_))));
+    }
+
+    #[test]
+    fn writes_see_rows_as_the_role_does() {
+        let engine = engine();
+        engine.set_redaction(
+            "people".to_string(),
+            "ssn".to_string(),
+            crate::engine::RedactionAction::Drop,
+        );
+        let filter =
+            crate::connection::TCPServer::parse_query_expression("public = true", false).unwrap();
+        engine.set_role_filter(&UserRole::User, "people".to_string(), filter);
+        for (key, public) in [("p1", true), ("p2", false)] {
+            engine
+                .set_locked(
+                    "people".to_string(),
+                    key.to_string(),
+                    serde_json::json!({ "public": public, "ssn": "123-45-6789" }),
+                )
+                .unwrap();
+        }
+
+        assert_eq!(
+            run_on(
+                &engine,
+                r#"return { db.set("people", "p1", { public = true, ssn = "x" }), db.delete("people", "p1") }"#
+            ),
+            Ok(serde_json::json!([{ "public": true }, { "public": true }]))
+        );
+        assert!(matches!(
+            run_on(&engine, r#"return db.delete("people", "p2")"#),
+            Err(Error::PermissionDenied(_))
+        ));
+        assert!(
+            engine
+                .get_row("people".to_string(), "p2".to_string())
+                .is_some()
+        );
+    }
+}
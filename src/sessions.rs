@@ -0,0 +1,75 @@
+struct Session {
+    connected_at: chrono::DateTime<chrono::Utc>,
+    last_command: Option<String>,
+    kill: std::sync::Arc<tokio::sync::Notify>,
+}
+
+pub struct SessionInfo {
+    pub connection_id: String,
+    pub connected_at: chrono::DateTime<chrono::Utc>,
+    pub last_command: Option<String>,
+}
+
+// Open connections, keyed by connection id. Each session holds a Notify its connection task waits
+// on alongside the socket, so KILL reaches a client that is idle between commands.
+#[derive(Default)]
+pub struct Sessions {
+    sessions: std::sync::Mutex<std::collections::HashMap<String, Session>>,
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Sessions::default()
+    }
+
+    pub fn register(&self, connection_id: &str) -> std::sync::Arc<tokio::sync::Notify> {
+        let kill = std::sync::Arc::new(tokio::sync::Notify::new());
+        self.sessions.lock().unwrap().insert(
+            connection_id.to_string(),
+            Session {
+                connected_at: chrono::Utc::now(),
+                last_command: None,
+                kill: kill.clone(),
+            },
+        );
+        kill
+    }
+
+    pub fn record_command(&self, connection_id: &str, command: &str) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(connection_id) {
+            session.last_command = Some(command.to_string());
+        }
+    }
+
+    pub fn end(&self, connection_id: &str) {
+        self.sessions.lock().unwrap().remove(connection_id);
+    }
+
+    // notify_one keeps a permit when the connection is busy running a command, so the kill is
+    // seen as soon as it next waits for input.
+    pub fn kill(&self, connection_id: &str) -> bool {
+        match self.sessions.lock().unwrap().get(connection_id) {
+            Some(session) => {
+                session.kill.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> = self
+            .sessions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(connection_id, session)| SessionInfo {
+                connection_id: connection_id.clone(),
+                connected_at: session.connected_at,
+                last_command: session.last_command.clone(),
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.connected_at);
+        sessions
+    }
+}
@@ -0,0 +1,122 @@
+pub struct StandbyRestore {
+    archive_dir: std::path::PathBuf,
+    state_path: std::path::PathBuf,
+    offsets: std::collections::BTreeMap<String, u64>,
+    // The last sequence number restored into the local log. The log's own counter also moves
+    // past entries it failed to write, which have to be restored again.
+    restored: Option<u64>,
+}
+
+impl StandbyRestore {
+    pub fn new(archive_dir: std::path::PathBuf, state_path: std::path::PathBuf) -> Self {
+        let offsets = std::fs::read_to_string(&state_path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        StandbyRestore {
+            archive_dir,
+            state_path,
+            offsets,
+            restored: None,
+        }
+    }
+
+    pub fn archive_dir(&self) -> &std::path::Path {
+        &self.archive_dir
+    }
+
+    // Segments are applied in file name order; each one is remembered by how far it was read,
+    // so a segment that is still growing is picked up again on the next pass.
+    pub async fn catch_up(
+        &mut self,
+        engine: &crate::engine::Engine,
+    ) -> Result<usize, crate::error::Error> {
+        let mut segments: Vec<std::path::PathBuf> = std::fs::read_dir(&self.archive_dir)
+            .map_err(|e| crate::error::Error::Io(format!("{}: {}", self.archive_dir.display(), e)))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        segments.sort();
+
+        let mut restored = *self
+            .restored
+            .get_or_insert_with(|| engine.storage.last_sequence());
+        let mut applied = 0;
+        for path in segments {
+            let name = match path.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => continue,
+            };
+            let offset = self.offsets.get(&name).copied().unwrap_or(0);
+            let bytes = std::fs::read(&path)
+                .map_err(|e| crate::error::Error::Io(format!("{}: {}", path.display(), e)))?;
+            if (bytes.len() as u64) < offset {
+                return Err(crate::error::Error::Conflict(format!(
+                    "Archived segment {} is shorter than the {} bytes already restored",
+                    name, offset
+                )));
+            }
+
+            let (entries, consumed) =
+                crate::logs::LogEntry::decode_available(&bytes[offset as usize..])
+                    .map_err(|e| crate::error::Error::Parse(format!("{}: {}", name, e)))?;
+            if consumed == 0 {
+                continue;
+            }
+            let last_seq = entries.iter().map(|entry| entry.seq).max().unwrap_or(0);
+            applied += engine.apply_log_entries(entries, restored).await?;
+            restored = restored.max(last_seq);
+            self.restored = Some(restored);
+            self.offsets.insert(name, offset + consumed as u64);
+            self.save()?;
+        }
+
+        Ok(applied)
+    }
+
+    fn save(&self) -> Result<(), crate::error::Error> {
+        let contents = serde_json::to_string(&self.offsets)
+            .map_err(|e| crate::error::Error::Parse(e.to_string()))?;
+        std::fs::write(&self.state_path, contents)
+            .map_err(|e| crate::error::Error::Io(format!("{}: {}", self.state_path.display(), e)))
+    }
+}
+
+pub async fn run(
+    restore: std::sync::Arc<tokio::sync::Mutex<StandbyRestore>>,
+    engine: std::sync::Arc<crate::engine::Engine>,
+    active: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    logger: crate::helpers::logging::Logger,
+    poll_interval: std::time::Duration,
+) {
+    let mut last_error: Option<crate::error::Error> = None;
+    loop {
+        let mut restore = restore.lock().await;
+        if !active.load(std::sync::atomic::Ordering::SeqCst) {
+            break;
+        }
+
+        match restore.catch_up(&engine).await {
+            Ok(applied) => {
+                if applied > 0 {
+                    logger
+                        .debug(&format!("Standby restored {} log entries", applied))
+                        .await;
+                }
+                last_error = None;
+            }
+            Err(e) => {
+                if last_error.as_ref() != Some(&e) {
+                    logger
+                        .error(&format!("Standby restore failed: {}", e))
+                        .await;
+                }
+                last_error = Some(e);
+            }
+        }
+        drop(restore);
+
+        tokio::time::sleep(poll_interval).await;
+    }
+}
@@ -0,0 +1,116 @@
+use crate::error::Error;
+use crate::logs::{LogEntry, VacuumStats, WalError, WalMetrics, WalTicket};
+
+pub type StorageFuture<'a, T> = std::pin::Pin<Box<dyn std::future::Future<Output = T> + Send + 'a>>;
+
+pub struct Replay {
+    pub entries: Vec<LogEntry>,
+    pub bytes: u64,
+    // Bytes of an entry left incomplete by a crash, dropped from the end of the log.
+    pub truncated: u64,
+    // Stamped on rows from legacy entries, which carry no timestamps of their own.
+    pub modified: chrono::DateTime<chrono::Utc>,
+}
+
+// Where the engine keeps its history of writes. The engine holds one as a trait object, so a
+// backend only has to order entries and hand them back; tables are always rebuilt from replay.
+pub trait StorageBackend: Send + Sync {
+    // Names where entries are kept, for log messages.
+    fn location(&self) -> String;
+
+    // Queues entries to be stored after everything appended before them. The ticket resolves
+    // once they are durable, or with the error that stopped them. An empty append resolves once
+    // earlier appends have.
+    fn append(&self, entries: Vec<LogEntry>) -> WalTicket;
+
+    // Every stored entry in order, or None when nothing has been stored yet. Fails rather than
+    // returning a partial history when stored entries cannot be read back.
+    fn replay(&self) -> Result<Option<Replay>, Error>;
+
+    // The stored entries as encoded bytes, once earlier appends are durable.
+    fn snapshot(&self) -> StorageFuture<'_, Result<Vec<u8>, Error>>;
+
+    // Drops entries for a table that replay no longer needs.
+    fn compact(&self, table: String) -> StorageFuture<'_, Result<VacuumStats, String>>;
+
+    // The highest sequence number appended or replayed so far.
+    fn last_sequence(&self) -> u64;
+
+    fn metrics(&self) -> WalMetrics;
+}
+
+// Numbers an entry being appended. Entries copied from another log (a standby restoring an
+// archive) keep their sequence number, so both logs number the same write alike.
+pub fn next_sequence(last: &mut u64, seq: u64) -> u64 {
+    if seq == 0 {
+        *last += 1;
+        *last
+    } else {
+        *last = (*last).max(seq);
+        seq
+    }
+}
+
+impl dyn StorageBackend {
+    pub async fn flush(&self) -> Result<(), WalError> {
+        self.append(Vec::new()).wait().await
+    }
+}
+
+// Keeps nothing: writes are acknowledged at once and every restart starts empty. For caches and
+// tests that have no use for durability.
+#[derive(Default)]
+pub struct MemoryStorage {
+    sequence: std::sync::Mutex<u64>,
+}
+
+impl StorageBackend for MemoryStorage {
+    fn location(&self) -> String {
+        "memory".to_string()
+    }
+
+    fn append(&self, entries: Vec<LogEntry>) -> WalTicket {
+        let mut sequence = self.sequence.lock().unwrap();
+        for entry in entries {
+            next_sequence(&mut sequence, entry.seq);
+        }
+        WalTicket::resolved(Ok(()))
+    }
+
+    fn replay(&self) -> Result<Option<Replay>, Error> {
+        Ok(None)
+    }
+
+    fn snapshot(&self) -> StorageFuture<'_, Result<Vec<u8>, Error>> {
+        Box::pin(async {
+            Err(Error::InvalidArgument(
+                "In-memory storage keeps no log".to_string(),
+            ))
+        })
+    }
+
+    fn compact(&self, _table: String) -> StorageFuture<'_, Result<VacuumStats, String>> {
+        Box::pin(async {
+            Ok(VacuumStats {
+                entries_removed: 0,
+                bytes_before: 0,
+                bytes_after: 0,
+            })
+        })
+    }
+
+    fn last_sequence(&self) -> u64 {
+        *self.sequence.lock().unwrap()
+    }
+
+    fn metrics(&self) -> WalMetrics {
+        WalMetrics {
+            batches: 0,
+            requests: 0,
+            bytes: 0,
+            avg_batch_size: 0.0,
+            avg_write_us: 0,
+            window_us: 0,
+        }
+    }
+}
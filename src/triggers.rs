@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+pub enum TriggerTarget {
+    Webhook(crate::helpers::webhook::Webhook),
+    // An executable given the event as one JSON line on stdin; a non-zero exit is a failure.
+    Command(String),
+}
+
+impl TriggerTarget {
+    pub fn from_config(config: &crate::helpers::configs::TriggerConfig) -> Result<Self, String> {
+        match (&config.webhook, &config.command) {
+            (Some(url), None) => Ok(TriggerTarget::Webhook(
+                crate::helpers::webhook::Webhook::parse(url)?,
+            )),
+            (None, Some(command)) => Ok(TriggerTarget::Command(command.clone())),
+            _ => Err("set exactly one of webhook or command".to_string()),
+        }
+    }
+
+    fn deliver(&self, line: &str) -> Result<(), String> {
+        match self {
+            TriggerTarget::Webhook(webhook) => webhook.post(line),
+            TriggerTarget::Command(command) => {
+                let mut child = std::process::Command::new(command)
+                    .stdin(std::process::Stdio::piped())
+                    .stdout(std::process::Stdio::null())
+                    .spawn()
+                    .map_err(|e| format!("{}: {}", command, e))?;
+                if let Some(mut stdin) = child.stdin.take() {
+                    writeln!(stdin, "{}", line).map_err(|e| format!("{}: {}", command, e))?;
+                }
+                let status = child.wait().map_err(|e| format!("{}: {}", command, e))?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(format!("{} exited with {}", command, status))
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for TriggerTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TriggerTarget::Webhook(webhook) => write!(f, "webhook {}", webhook.url),
+            TriggerTarget::Command(command) => write!(f, "command {}", command),
+        }
+    }
+}
+
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    // Doubled after every failed attempt.
+    pub backoff: std::time::Duration,
+    // Events that exhausted their attempts are appended here as JSON lines.
+    pub dead_letter_path: Option<std::path::PathBuf>,
+    // Events waiting per hook. Once a hook falls this far behind, new events go straight to the
+    // dead-letter log instead of piling up in memory.
+    pub queue_size: usize,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            backoff: std::time::Duration::from_secs(1),
+            dead_letter_path: None,
+            queue_size: 10_000,
+        }
+    }
+}
+
+#[derive(Default)]
+struct HookStats {
+    delivered: AtomicU64,
+    dead: AtomicU64,
+}
+
+struct Hook {
+    name: String,
+    target: String,
+    sender: std::sync::mpsc::SyncSender<serde_json::Value>,
+    stats: Arc<HookStats>,
+}
+
+pub struct TriggerInfo {
+    pub table: String,
+    pub name: String,
+    pub target: String,
+    pub delivered: u64,
+    pub dead: u64,
+}
+
+// Change hooks per table. Each hook delivers on its own thread, one event at a time and in
+// commit order, so a failing endpoint delays only its own events while it is retried.
+#[derive(Default)]
+pub struct Triggers {
+    hooks: RwLock<HashMap<String, Vec<Hook>>>,
+    policy: Arc<RetryPolicy>,
+}
+
+impl Triggers {
+    pub fn set_policy(&mut self, policy: RetryPolicy) {
+        self.policy = Arc::new(policy);
+    }
+
+    pub fn add(&self, table: &str, name: &str, target: TriggerTarget) -> Result<(), String> {
+        let mut hooks = self.hooks.write().unwrap();
+        let table_hooks = hooks.entry(table.to_string()).or_default();
+        if table_hooks.iter().any(|hook| hook.name == name) {
+            return Err(format!("Trigger {} already exists on {}", name, table));
+        }
+
+        let (sender, receiver) =
+            std::sync::mpsc::sync_channel::<serde_json::Value>(self.policy.queue_size.max(1));
+        let stats = Arc::new(HookStats::default());
+        let label = target.to_string();
+        let policy = self.policy.clone();
+        let thread_stats = stats.clone();
+        std::thread::Builder::new()
+            .name(format!("sharknado-trigger-{}", name))
+            .spawn(move || {
+                for event in receiver {
+                    deliver_with_retry(&target, &policy, &thread_stats, event);
+                }
+            })
+            .map_err(|e| e.to_string())?;
+
+        table_hooks.push(Hook {
+            name: name.to_string(),
+            target: label,
+            sender,
+            stats,
+        });
+        Ok(())
+    }
+
+    // Events already queued for the hook are still delivered.
+    pub fn remove(&self, table: &str, name: &str) -> bool {
+        let mut hooks = self.hooks.write().unwrap();
+        let Some(table_hooks) = hooks.get_mut(table) else {
+            return false;
+        };
+        let before = table_hooks.len();
+        table_hooks.retain(|hook| hook.name != name);
+        let removed = table_hooks.len() < before;
+        if table_hooks.is_empty() {
+            hooks.remove(table);
+        }
+        removed
+    }
+
+    pub fn list(&self) -> Vec<TriggerInfo> {
+        let hooks = self.hooks.read().unwrap();
+        let mut triggers: Vec<TriggerInfo> = hooks
+            .iter()
+            .flat_map(|(table, table_hooks)| {
+                table_hooks.iter().map(|hook| TriggerInfo {
+                    table: table.clone(),
+                    name: hook.name.clone(),
+                    target: hook.target.clone(),
+                    delivered: hook.stats.delivered.load(Ordering::Relaxed),
+                    dead: hook.stats.dead.load(Ordering::Relaxed),
+                })
+            })
+            .collect();
+        triggers.sort_by(|a, b| (&a.table, &a.name).cmp(&(&b.table, &b.name)));
+        triggers
+    }
+
+    // `operation` is set, update or delete; `old` is None for a new key and `new` for a delete.
+    pub fn fire(
+        &self,
+        operation: &str,
+        table: &str,
+        key: &str,
+        old: Option<&serde_json::Value>,
+        new: Option<&serde_json::Value>,
+    ) {
+        let hooks = self.hooks.read().unwrap();
+        let Some(table_hooks) = hooks.get(table) else {
+            return;
+        };
+        let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+        for hook in table_hooks {
+            let event = serde_json::json!({
+                "timestamp": timestamp,
+                "trigger": hook.name,
+                "table": table,
+                "key": key,
+                "operation": operation,
+                "old": old,
+                "new": new,
+            });
+            if let Err(std::sync::mpsc::TrySendError::Full(event)) = hook.sender.try_send(event) {
+                dead_letter(&self.policy, &hook.stats, event, "delivery queue full", 0);
+            }
+        }
+    }
+}
+
+fn deliver_with_retry(
+    target: &TriggerTarget,
+    policy: &RetryPolicy,
+    stats: &HookStats,
+    event: serde_json::Value,
+) {
+    let line = event.to_string();
+    let mut backoff = policy.backoff;
+    let mut attempt = 1;
+    let error = loop {
+        match target.deliver(&line) {
+            Ok(()) => {
+                stats.delivered.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            Err(e) if attempt >= policy.max_attempts => break e,
+            Err(_) => {
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    };
+    dead_letter(policy, stats, event, &error, attempt);
+}
+
+fn dead_letter(
+    policy: &RetryPolicy,
+    stats: &HookStats,
+    mut event: serde_json::Value,
+    error: &str,
+    attempts: u32,
+) {
+    stats.dead.fetch_add(1, Ordering::Relaxed);
+    event["error"] = serde_json::Value::String(error.to_string());
+    event["attempts"] = serde_json::Value::from(attempts);
+    let written = policy.dead_letter_path.as_ref().map(|path| {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", event))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    });
+    match written {
+        Some(Ok(())) => {}
+        Some(Err(e)) => eprintln!("trigger dead-letter write failed: {}; event: {}", e, event),
+        None => eprintln!("trigger delivery failed: {}; event: {}", error, event),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_events_go_to_the_dead_letter_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let dead_letter_path = dir.path().join("dead.jsonl");
+        let mut triggers = Triggers::default();
+        triggers.set_policy(RetryPolicy {
+            max_attempts: 2,
+            backoff: std::time::Duration::from_millis(1),
+            dead_letter_path: Some(dead_letter_path.clone()),
+            queue_size: 16,
+        });
+        triggers
+            .add("orders", "ok", TriggerTarget::Command("true".to_string()))
+            .unwrap();
+        triggers
+            .add(
+                "orders",
+                "broken",
+                TriggerTarget::Command("false".to_string()),
+            )
+            .unwrap();
+        assert!(
+            triggers
+                .add("orders", "ok", TriggerTarget::Command("true".to_string()))
+                .is_err()
+        );
+
+        triggers.fire(
+            "set",
+            "orders",
+            "o1",
+            None,
+            Some(&serde_json::json!({"n": 1})),
+        );
+        triggers.fire(
+            "set",
+            "other",
+            "o1",
+            None,
+            Some(&serde_json::json!({"n": 1})),
+        );
+        for _ in 0..500 {
+            if triggers
+                .list()
+                .iter()
+                .all(|info| info.delivered + info.dead == 1)
+            {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        let counts: Vec<(String, u64, u64)> = triggers
+            .list()
+            .into_iter()
+            .map(|info| (info.name, info.delivered, info.dead))
+            .collect();
+        assert_eq!(
+            counts,
+            [("broken".to_string(), 0, 1), ("ok".to_string(), 1, 0)]
+        );
+        let dead: serde_json::Value = serde_json::from_str(
+            std::fs::read_to_string(&dead_letter_path)
+                .unwrap()
+                .trim_end(),
+        )
+        .unwrap();
+        assert_eq!(dead["trigger"], "broken");
+        assert_eq!(dead["key"], "o1");
+        assert_eq!(dead["old"], serde_json::Value::Null);
+        assert_eq!(dead["new"], serde_json::json!({"n": 1}));
+        assert_eq!(dead["attempts"], 2);
+
+        assert!(triggers.remove("orders", "broken"));
+        assert!(!triggers.remove("orders", "broken"));
+    }
+
+    #[test]
+    fn events_beyond_the_queue_are_dead_lettered() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let dead_letter_path = dir.path().join("dead.jsonl");
+        let slow = dir.path().join("slow.sh");
+        std::fs::write(&slow, "#!/bin/sh\nsleep 1\n").unwrap();
+        std::fs::set_permissions(&slow, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mut triggers = Triggers::default();
+        triggers.set_policy(RetryPolicy {
+            max_attempts: 1,
+            backoff: std::time::Duration::from_millis(1),
+            dead_letter_path: Some(dead_letter_path.clone()),
+            queue_size: 1,
+        });
+        triggers
+            .add(
+                "orders",
+                "slow",
+                TriggerTarget::Command(slow.to_string_lossy().into_owned()),
+            )
+            .unwrap();
+
+        for n in 0..5 {
+            triggers.fire(
+                "set",
+                "orders",
+                &format!("o{}", n),
+                None,
+                Some(&serde_json::json!({"n": n})),
+            );
+        }
+
+        // One event can be in delivery and one queued; the rest are turned away.
+        let dead = triggers.list()[0].dead;
+        assert!(dead >= 3, "{} dead-lettered", dead);
+        let lines = std::fs::read_to_string(&dead_letter_path).unwrap();
+        assert_eq!(lines.lines().count() as u64, dead);
+        for line in lines.lines() {
+            let event: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(event["error"], "delivery queue full");
+            assert_eq!(event["attempts"], 0);
+        }
+    }
+}
@@ -2,12 +2,130 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::RwLock;
 
+use crate::error::Error;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub username: String,
     pub password_hash: String,
     pub role: UserRole,
     pub created_at: String,
+    #[serde(default)]
+    pub quotas: UserQuotas,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tokens: Vec<ApiToken>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub must_change_password: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+impl User {
+    // Prepended to every table a namespaced user names. Admins manage the whole database, so a
+    // namespace never confines them.
+    pub fn table_prefix(&self) -> Option<String> {
+        match (&self.namespace, &self.role) {
+            (Some(namespace), UserRole::User) => Some(format!("{}__", namespace)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub name: String,
+    pub token_hash: String,
+    pub created_at: String,
+}
+
+const TOKEN_PREFIX: &str = "snk_";
+
+// Tokens are random, so a fast unsalted hash is enough; the tag leaves room to change it.
+const TOKEN_HASH_PREFIX: &str = "sha256:";
+
+fn hash_token(token: &str) -> String {
+    use sha2::Digest;
+
+    format!(
+        "{}{}",
+        TOKEN_HASH_PREFIX,
+        hex(&sha2::Sha256::digest(token.as_bytes()))
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// How passwords and tokens were hashed before Argon2 and SHA-256: std's SipHash with fixed keys,
+// unsalted. Only used to recognise hashes written by earlier versions.
+fn legacy_hash(secret: &str) -> String {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    secret.hash(&mut hasher);
+    format!("{:x}", hasher.finish())
+}
+
+fn is_legacy_hash(hash: &str) -> bool {
+    argon2::PasswordHash::new(hash).is_err()
+}
+
+// Per-user limits set by an admin; None means unlimited.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct UserQuotas {
+    pub max_connections: Option<usize>,
+    pub max_ops_per_sec: Option<u32>,
+    pub max_result_rows: Option<usize>,
+}
+
+impl UserQuotas {
+    fn set(&mut self, field: &str, value: &str) -> Result<(), Error> {
+        let limit = if value.eq_ignore_ascii_case("none") {
+            None
+        } else {
+            match value.parse::<u32>() {
+                Ok(limit) if limit > 0 => Some(limit),
+                _ => {
+                    return Err(Error::InvalidArgument(
+                        "Quota must be a positive integer or none".to_string(),
+                    ));
+                }
+            }
+        };
+        match field {
+            "max_connections" => self.max_connections = limit.map(|limit| limit as usize),
+            "max_ops_per_sec" => self.max_ops_per_sec = limit,
+            "max_result_rows" => self.max_result_rows = limit.map(|limit| limit as usize),
+            _ => {
+                return Err(Error::InvalidArgument(
+                    "Invalid field. Valid fields: password, role, namespace, max_connections, max_ops_per_sec, max_result_rows".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for UserQuotas {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let limits = [
+            (
+                "max_connections",
+                self.max_connections.map(|limit| limit as u64),
+            ),
+            ("max_ops_per_sec", self.max_ops_per_sec.map(u64::from)),
+            (
+                "max_result_rows",
+                self.max_result_rows.map(|limit| limit as u64),
+            ),
+        ];
+        let limits: Vec<String> = limits
+            .iter()
+            .filter_map(|(name, limit)| limit.map(|limit| format!("{}={}", name, limit)))
+            .collect();
+        write!(f, "{}", limits.join(" "))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -24,11 +142,13 @@ impl UserRole {
             _ => None,
         }
     }
+}
 
-    pub fn to_string(&self) -> String {
+impl std::fmt::Display for UserRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            UserRole::Admin => "admin".to_string(),
-            UserRole::User => "user".to_string(),
+            UserRole::Admin => write!(f, "admin"),
+            UserRole::User => write!(f, "user"),
         }
     }
 }
@@ -37,6 +157,8 @@ pub struct UserManager {
     users: RwLock<HashMap<String, User>>,
     current_user: RwLock<Option<String>>, // For CLI mode
     authenticated_connections: RwLock<HashMap<String, String>>, // connection_id -> username for TCP
+    auth_events: crate::auth_events::AuthEvents,
+    storage_path: Option<std::path::PathBuf>,
 }
 
 impl UserManager {
@@ -45,7 +167,58 @@ impl UserManager {
             users: RwLock::new(HashMap::new()),
             current_user: RwLock::new(None),
             authenticated_connections: RwLock::new(HashMap::new()),
+            auth_events: crate::auth_events::AuthEvents::default(),
+            storage_path: None,
+        }
+    }
+
+    // Users live in a JSON file next to the database's WAL and it is rewritten on every change.
+    // Without a file, as in a fresh install, users start empty.
+    pub fn load_from(&mut self, path: std::path::PathBuf) -> Result<usize, Error> {
+        let users: HashMap<String, User> = match std::fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents)
+                .map_err(|e| Error::Parse(format!("{}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(Error::Io(format!("{}: {}", path.display(), e))),
+        };
+        let count = users.len();
+        *self.users.get_mut().unwrap() = users;
+        self.storage_path = Some(path);
+        Ok(count)
+    }
+
+    // Applies `change` to a copy and only swaps it in once the copy is saved, so memory and the
+    // file never disagree.
+    fn commit(
+        &self,
+        users: &mut HashMap<String, User>,
+        change: impl FnOnce(&mut HashMap<String, User>),
+    ) -> Result<(), Error> {
+        let mut updated = users.clone();
+        change(&mut updated);
+        if let Some(path) = &self.storage_path {
+            let contents =
+                serde_json::to_string_pretty(&updated).map_err(|e| Error::Parse(e.to_string()))?;
+            let temp_path = path.with_extension("json.tmp");
+            std::fs::write(&temp_path, contents)
+                .and_then(|()| std::fs::rename(&temp_path, path))
+                .map_err(|e| Error::Io(format!("{}: {}", path.display(), e)))?;
         }
+        *users = updated;
+        Ok(())
+    }
+
+    pub fn set_auth_events(&mut self, auth_events: crate::auth_events::AuthEvents) {
+        self.auth_events = auth_events;
+    }
+
+    // CLI-side changes have no connection, so the acting CLI user is recorded instead.
+    fn cli_actor(&self) -> String {
+        self.current_user
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| "-".to_string())
     }
 
     pub fn create_user(
@@ -53,14 +226,27 @@ impl UserManager {
         username: String,
         password: String,
         role: UserRole,
-    ) -> Result<(), String> {
+    ) -> Result<(), Error> {
+        self.create_user_as(&self.cli_actor(), "cli", username, password, role)
+    }
+
+    // The `_as` methods skip permission checks and record `actor` and `source` in auth events:
+    // the CLI user and "cli", or a TCP user and their connection id.
+    pub fn create_user_as(
+        &self,
+        actor: &str,
+        source: &str,
+        username: String,
+        password: String,
+        role: UserRole,
+    ) -> Result<(), Error> {
+        let password_hash = self.hash_password(&password);
         let mut users = self.users.write().unwrap();
 
         if users.contains_key(&username) {
-            return Err("User already exists".to_string());
+            return Err(Error::Conflict("User already exists".to_string()));
         }
 
-        let password_hash = self.hash_password(&password);
         let user = User {
             username: username.clone(),
             password_hash,
@@ -68,28 +254,25 @@ impl UserManager {
             created_at: chrono::Utc::now()
                 .format("%Y-%m-%d %H:%M:%S UTC")
                 .to_string(),
+            quotas: UserQuotas::default(),
+            tokens: Vec::new(),
+            must_change_password: false,
+            namespace: None,
         };
 
-        users.insert(username, user);
+        let role = user.role.to_string();
+        self.commit(&mut users, |users| {
+            users.insert(username.clone(), user);
+        })?;
+        self.auth_events.emit(
+            "user_created",
+            &username,
+            source,
+            serde_json::json!({ "actor": actor, "role": role }),
+        );
         Ok(())
     }
 
-    pub fn authenticate(&self, username: &str, password: &str) -> Result<(), String> {
-        let users = self.users.read().unwrap();
-
-        if let Some(user) = users.get(username) {
-            if self.verify_password(password, &user.password_hash) {
-                let mut current_user = self.current_user.write().unwrap();
-                *current_user = Some(username.to_string());
-                Ok(())
-            } else {
-                Err("Invalid credentials".to_string())
-            }
-        } else {
-            Err("User not found".to_string())
-        }
-    }
-
     pub fn logout(&self) {
         let mut current_user = self.current_user.write().unwrap();
         *current_user = None;
@@ -105,11 +288,6 @@ impl UserManager {
         }
     }
 
-    pub fn is_authenticated(&self) -> bool {
-        let current_user = self.current_user.read().unwrap();
-        current_user.is_some()
-    }
-
     pub fn is_admin(&self) -> bool {
         if let Some(user) = self.get_current_user() {
             user.role == UserRole::Admin
@@ -118,82 +296,266 @@ impl UserManager {
         }
     }
 
-    pub fn delete_user(&self, username: &str) -> Result<(), String> {
+    pub fn delete_user(&self, username: &str) -> Result<(), Error> {
         if !self.is_admin() {
-            return Err("Insufficient permissions".to_string());
+            return Err(Error::PermissionDenied(
+                "Insufficient permissions".to_string(),
+            ));
         }
+        self.delete_user_as(&self.cli_actor(), "cli", username)
+    }
 
+    // Connections logged in as the deleted user are logged out with it.
+    pub fn delete_user_as(&self, actor: &str, source: &str, username: &str) -> Result<(), Error> {
         let mut users = self.users.write().unwrap();
+        if !users.contains_key(username) {
+            return Err(Error::NotFound("User not found".to_string()));
+        }
 
-        if users.remove(username).is_some() {
-            let current_user = self.current_user.read().unwrap();
-            if let Some(current) = current_user.as_ref() {
-                if current == username {
-                    drop(current_user);
-                    self.logout();
-                }
-            }
-            Ok(())
-        } else {
-            Err("User not found".to_string())
+        self.commit(&mut users, |users| {
+            users.remove(username);
+        })?;
+        drop(users);
+        self.auth_events.emit(
+            "user_deleted",
+            username,
+            source,
+            serde_json::json!({ "actor": actor }),
+        );
+        self.authenticated_connections
+            .write()
+            .unwrap()
+            .retain(|_, logged_in| logged_in != username);
+        let current_user = self.current_user.read().unwrap();
+        if current_user.as_deref() == Some(username) {
+            drop(current_user);
+            self.logout();
         }
+        Ok(())
     }
 
-    pub fn update_user(&self, username: &str, field: &str, value: &str) -> Result<(), String> {
-        if !self.is_admin()
-            && self.get_current_user().map(|u| u.username) != Some(username.to_string())
-        {
-            return Err("Insufficient permissions".to_string());
+    pub fn update_user(&self, username: &str, field: &str, value: &str) -> Result<(), Error> {
+        let is_admin = self.is_admin();
+        if !is_admin && self.get_current_user().map(|u| u.username) != Some(username.to_string()) {
+            return Err(Error::PermissionDenied(
+                "Insufficient permissions".to_string(),
+            ));
         }
+        if field != "password" && !is_admin {
+            return Err(Error::PermissionDenied(
+                "Only admins can change roles, namespaces and quotas".to_string(),
+            ));
+        }
+        self.update_user_as(&self.cli_actor(), "cli", username, field, value)
+    }
 
+    pub fn update_user_as(
+        &self,
+        actor: &str,
+        source: &str,
+        username: &str,
+        field: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        let password_hash = (field == "password").then(|| self.hash_password(value));
         let mut users = self.users.write().unwrap();
-
-        if let Some(user) = users.get_mut(username) {
-            match field {
-                "password" => {
-                    user.password_hash = self.hash_password(value);
-                }
-                "role" => {
-                    if !self.is_admin() {
-                        return Err("Only admins can change roles".to_string());
-                    }
-                    if let Some(role) = UserRole::from_str(value) {
-                        user.role = role;
-                    } else {
-                        return Err("Invalid role".to_string());
-                    }
-                }
-                _ => return Err("Invalid field".to_string()),
+        let Some(user) = users.get(username) else {
+            return Err(Error::NotFound("User not found".to_string()));
+        };
+        let mut user = user.clone();
+        let (event, fields) = match field {
+            "password" => {
+                user.password_hash = password_hash.expect("hashed above for a password change");
+                user.must_change_password = false;
+                ("password_change", serde_json::json!({ "actor": actor }))
             }
-            Ok(())
-        } else {
-            Err("User not found".to_string())
+            "role" => {
+                let Some(role) = UserRole::from_str(value) else {
+                    return Err(Error::InvalidArgument("Invalid role".to_string()));
+                };
+                let fields = serde_json::json!({
+                    "actor": actor,
+                    "old_role": user.role.to_string(),
+                    "role": role.to_string(),
+                });
+                user.role = role;
+                ("role_change", fields)
+            }
+            "namespace" => {
+                // Letters, digits and dashes only: with an underscore one namespace's prefix
+                // could end inside another's, and its tables would overlap.
+                let namespace = if value.eq_ignore_ascii_case("none") {
+                    None
+                } else if !value.is_empty()
+                    && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                {
+                    Some(value.to_string())
+                } else {
+                    return Err(Error::InvalidArgument(
+                        "Namespace must be letters, digits and dashes, or none".to_string(),
+                    ));
+                };
+                let fields = serde_json::json!({
+                    "actor": actor,
+                    "old_namespace": user.namespace,
+                    "namespace": namespace,
+                });
+                user.namespace = namespace;
+                ("namespace_change", fields)
+            }
+            quota => {
+                user.quotas.set(quota, value)?;
+                let fields = serde_json::json!({
+                    "actor": actor,
+                    "quota": quota,
+                    "limit": value.to_lowercase(),
+                });
+                ("quota_change", fields)
+            }
+        };
+
+        self.commit(&mut users, |users| {
+            users.insert(username.to_string(), user);
+        })?;
+        self.auth_events.emit(event, username, source, fields);
+        Ok(())
+    }
+
+    // Lets a logged-in TCP user rotate their own password; the current one must be given.
+    pub fn change_password(
+        &self,
+        connection_id: &str,
+        old_password: &str,
+        new_password: &str,
+    ) -> Result<(), Error> {
+        let username = self
+            .authenticated_connections
+            .read()
+            .unwrap()
+            .get(connection_id)
+            .cloned()
+            .ok_or_else(|| Error::Auth("Not authenticated".to_string()))?;
+
+        let stored_hash = self
+            .users
+            .read()
+            .unwrap()
+            .get(&username)
+            .map(|user| user.password_hash.clone())
+            .ok_or_else(|| Error::NotFound("User not found".to_string()))?;
+        if !self.verify_password(old_password, &stored_hash) {
+            self.auth_events.emit(
+                "password_change_failure",
+                &username,
+                connection_id,
+                serde_json::json!({ "reason": "invalid_credentials" }),
+            );
+            return Err(Error::Auth("Current password is incorrect".to_string()));
+        }
+
+        let password_hash = self.hash_password(new_password);
+        let mut users = self.users.write().unwrap();
+        if users.get(&username).map(|user| &user.password_hash) != Some(&stored_hash) {
+            return Err(Error::Conflict(
+                "Password was changed meanwhile; try again".to_string(),
+            ));
         }
+        self.commit(&mut users, |users| {
+            if let Some(user) = users.get_mut(&username) {
+                user.password_hash = password_hash;
+                user.must_change_password = false;
+            }
+        })?;
+        self.auth_events.emit(
+            "password_change",
+            &username,
+            connection_id,
+            serde_json::json!({ "actor": username }),
+        );
+        Ok(())
+    }
+
+    pub fn get_user(&self, username: &str) -> Option<User> {
+        self.users.read().unwrap().get(username).cloned()
     }
 
     pub fn list_users(&self) -> Vec<User> {
         let users = self.users.read().unwrap();
-        users.values().cloned().collect()
+        let mut users: Vec<User> = users.values().cloned().collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        users
     }
 
+    // Argon2id with a random salt, in PHC string format so the salt and parameters are stored
+    // alongside the hash. It is slow on purpose, so it runs without the users lock, which every
+    // request's user lookup waits on.
     fn hash_password(&self, password: &str) -> String {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-
-        let mut hasher = DefaultHasher::new();
-        password.hash(&mut hasher);
-        format!("{:x}", hasher.finish())
+        use argon2::PasswordHasher;
+        use argon2::password_hash::SaltString;
+
+        let salt = SaltString::generate(&mut rand_core::OsRng);
+        argon2::Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("default Argon2 parameters accept any password")
+            .to_string()
     }
 
+    // Hashes from before Argon2 are still accepted, so they can be upgraded at the next login.
     fn verify_password(&self, password: &str, hash: &str) -> bool {
-        self.hash_password(password) == hash
+        use argon2::PasswordVerifier;
+
+        match argon2::PasswordHash::new(hash) {
+            Ok(parsed) => argon2::Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok(),
+            Err(_) => legacy_hash(password) == hash,
+        }
+    }
+
+    // Checked against when a username is unknown, so that login takes as long as for a wrong
+    // password and its timing does not tell which usernames exist. No password matches it.
+    fn unknown_user_hash(&self) -> &'static str {
+        use rand_core::RngCore;
+
+        static HASH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+        HASH.get_or_init(|| {
+            let mut random = [0u8; 16];
+            rand_core::OsRng.fill_bytes(&mut random);
+            self.hash_password(&hex(&random))
+        })
     }
 
-    pub fn ensure_default_admin(&self) {
+    // Rehashes a password still stored in the legacy format once it has been verified, unless
+    // the stored hash changed meanwhile.
+    fn upgrade_password_hash(&self, username: &str, password: &str, stored_hash: &str) {
+        if !is_legacy_hash(stored_hash) {
+            return;
+        }
+        let password_hash = self.hash_password(password);
+        let mut users = self.users.write().unwrap();
+        if users.get(username).map(|user| user.password_hash.as_str()) != Some(stored_hash) {
+            return;
+        }
+        let _ = self.commit(&mut users, |users| {
+            if let Some(user) = users.get_mut(username) {
+                user.password_hash = password_hash;
+            }
+        });
+    }
+
+    pub fn ensure_default_admin(&self, username: &str, password: &str, must_change_password: bool) {
         let users = self.users.read().unwrap();
         if users.is_empty() {
             drop(users);
-            let _ = self.create_user("admin".to_string(), "admin123".to_string(), UserRole::Admin);
+            let _ = self.create_user(username.to_string(), password.to_string(), UserRole::Admin);
+            if must_change_password {
+                let mut users = self.users.write().unwrap();
+                let _ = self.commit(&mut users, |users| {
+                    if let Some(user) = users.get_mut(username) {
+                        user.must_change_password = true;
+                    }
+                });
+            }
         }
     }
 
@@ -202,25 +564,203 @@ impl UserManager {
         connection_id: &str,
         username: &str,
         password: &str,
-    ) -> Result<(), String> {
-        let users = self.users.read().unwrap();
+    ) -> Result<(), Error> {
+        let user = self.users.read().unwrap().get(username).cloned();
 
-        if let Some(user) = users.get(username) {
+        if let Some(user) = user {
             if self.verify_password(password, &user.password_hash) {
-                let mut connections = self.authenticated_connections.write().unwrap();
-                connections.insert(connection_id.to_string(), username.to_string());
+                self.log_in(
+                    connection_id,
+                    &user,
+                    serde_json::json!({ "role": user.role.to_string() }),
+                )?;
+                self.upgrade_password_hash(username, password, &user.password_hash);
                 Ok(())
             } else {
-                Err("Invalid credentials".to_string())
+                self.auth_events.emit(
+                    "login_failure",
+                    username,
+                    connection_id,
+                    serde_json::json!({ "reason": "invalid_credentials" }),
+                );
+                Err(Error::Auth("Invalid credentials".to_string()))
             }
         } else {
-            Err("User not found".to_string())
+            self.verify_password(password, self.unknown_user_hash());
+            self.auth_events.emit(
+                "login_failure",
+                username,
+                connection_id,
+                serde_json::json!({ "reason": "user_not_found" }),
+            );
+            Err(Error::NotFound("User not found".to_string()))
         }
     }
 
+    // Returns the token's owner. Tokens are only stored hashed, so every user's are checked.
+    // A token hashed in the legacy format is rehashed once it has logged in.
+    pub fn authenticate_connection_token(
+        &self,
+        connection_id: &str,
+        token: &str,
+    ) -> Result<String, Error> {
+        let token_hash = hash_token(token);
+        let legacy_token_hash = legacy_hash(token);
+        let users = self.users.read().unwrap();
+        let found = users.values().find_map(|user| {
+            user.tokens
+                .iter()
+                .find(|candidate| {
+                    candidate.token_hash == token_hash || candidate.token_hash == legacy_token_hash
+                })
+                .map(|candidate| (user, candidate))
+        });
+
+        let Some((user, api_token)) = found else {
+            self.auth_events.emit(
+                "login_failure",
+                "-",
+                connection_id,
+                serde_json::json!({ "reason": "invalid_token" }),
+            );
+            return Err(Error::Auth("Invalid credentials".to_string()));
+        };
+        self.log_in(
+            connection_id,
+            user,
+            serde_json::json!({ "role": user.role.to_string(), "token": api_token.name }),
+        )?;
+        let username = user.username.clone();
+        if api_token.token_hash == legacy_token_hash {
+            let name = api_token.name.clone();
+            drop(users);
+            let mut users = self.users.write().unwrap();
+            let _ = self.commit(&mut users, |users| {
+                let token = users
+                    .get_mut(&username)
+                    .and_then(|user| user.tokens.iter_mut().find(|token| token.name == name));
+                if let Some(token) = token {
+                    token.token_hash = token_hash;
+                }
+            });
+        }
+        Ok(username)
+    }
+
+    fn log_in(
+        &self,
+        connection_id: &str,
+        user: &User,
+        fields: serde_json::Value,
+    ) -> Result<(), Error> {
+        let username = user.username.as_str();
+        let mut connections = self.authenticated_connections.write().unwrap();
+        if let Some(max_connections) = user.quotas.max_connections
+            && connections.get(connection_id).map(String::as_str) != Some(username)
+            && connections
+                .values()
+                .filter(|name| *name == username)
+                .count()
+                >= max_connections
+        {
+            self.auth_events.emit(
+                "login_failure",
+                username,
+                connection_id,
+                serde_json::json!({ "reason": "quota_exceeded" }),
+            );
+            return Err(Error::Quota(format!(
+                "User {} is limited to {} connections",
+                username, max_connections
+            )));
+        }
+        connections.insert(connection_id.to_string(), username.to_string());
+        self.auth_events
+            .emit("login_success", username, connection_id, fields);
+        Ok(())
+    }
+
+    // The token is returned once and only its hash is kept. Its 128 random bits come from the
+    // operating system.
+    pub fn create_token(
+        &self,
+        connection_id: &str,
+        username: &str,
+        name: &str,
+    ) -> Result<String, Error> {
+        use rand_core::RngCore;
+
+        let mut users = self.users.write().unwrap();
+        let Some(user) = users.get(username) else {
+            return Err(Error::NotFound("User not found".to_string()));
+        };
+        if user.tokens.iter().any(|token| token.name == name) {
+            return Err(Error::Conflict(format!("Token {} already exists", name)));
+        }
+
+        let mut random = [0u8; 16];
+        rand_core::OsRng.fill_bytes(&mut random);
+        let token = format!("{}{}", TOKEN_PREFIX, hex(&random));
+        let api_token = ApiToken {
+            name: name.to_string(),
+            token_hash: hash_token(&token),
+            created_at: chrono::Utc::now()
+                .format("%Y-%m-%d %H:%M:%S UTC")
+                .to_string(),
+        };
+
+        self.commit(&mut users, |users| {
+            if let Some(user) = users.get_mut(username) {
+                user.tokens.push(api_token);
+            }
+        })?;
+        self.auth_events.emit(
+            "token_created",
+            username,
+            connection_id,
+            serde_json::json!({ "token": name }),
+        );
+        Ok(token)
+    }
+
+    pub fn revoke_token(
+        &self,
+        actor: &str,
+        connection_id: &str,
+        username: &str,
+        name: &str,
+    ) -> Result<(), Error> {
+        let mut users = self.users.write().unwrap();
+        let Some(user) = users.get(username) else {
+            return Err(Error::NotFound("User not found".to_string()));
+        };
+        if !user.tokens.iter().any(|token| token.name == name) {
+            return Err(Error::NotFound(format!(
+                "No token {} for {}",
+                name, username
+            )));
+        }
+
+        self.commit(&mut users, |users| {
+            if let Some(user) = users.get_mut(username) {
+                user.tokens.retain(|token| token.name != name);
+            }
+        })?;
+        self.auth_events.emit(
+            "token_revoked",
+            username,
+            connection_id,
+            serde_json::json!({ "actor": actor, "token": name }),
+        );
+        Ok(())
+    }
+
     pub fn logout_connection(&self, connection_id: &str) {
         let mut connections = self.authenticated_connections.write().unwrap();
-        connections.remove(connection_id);
+        if let Some(username) = connections.remove(connection_id) {
+            self.auth_events
+                .emit("logout", &username, connection_id, serde_json::json!({}));
+        }
     }
 
     pub fn is_connection_authenticated(&self, connection_id: &str) -> bool {
@@ -238,7 +778,80 @@ impl UserManager {
         }
     }
 
+    pub fn is_connection_admin(&self, connection_id: &str) -> bool {
+        self.get_connection_user(connection_id)
+            .is_some_and(|user| user.role == UserRole::Admin)
+    }
+
     pub fn cleanup_connection(&self, connection_id: &str) {
         self.logout_connection(connection_id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_hashes_are_upgraded_at_login() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("db.users.json");
+        let user = serde_json::json!({
+            "ann": {
+                "username": "ann",
+                "password_hash": legacy_hash("secret"),
+                "role": "User",
+                "created_at": "2024-01-01 00:00:00 UTC",
+                "tokens": [{
+                    "name": "ci",
+                    "token_hash": legacy_hash("snk_old"),
+                    "created_at": "2024-01-01 00:00:00 UTC"
+                }]
+            }
+        });
+        std::fs::write(&path, user.to_string()).unwrap();
+        let mut users = UserManager::new();
+        users.load_from(path.clone()).unwrap();
+
+        assert!(users.authenticate_connection("c1", "ann", "wrong").is_err());
+        users
+            .authenticate_connection("c1", "ann", "secret")
+            .unwrap();
+        assert_eq!(
+            users
+                .authenticate_connection_token("c2", "snk_old")
+                .unwrap(),
+            "ann"
+        );
+
+        let mut reloaded = UserManager::new();
+        reloaded.load_from(path).unwrap();
+        let ann = reloaded.get_user("ann").unwrap();
+        assert!(ann.password_hash.starts_with("$argon2id$"));
+        assert_eq!(ann.tokens[0].token_hash, hash_token("snk_old"));
+        reloaded
+            .authenticate_connection("c3", "ann", "secret")
+            .unwrap();
+        reloaded
+            .authenticate_connection_token("c4", "snk_old")
+            .unwrap();
+    }
+
+    #[test]
+    fn passwords_are_salted() {
+        let users = UserManager::new();
+        let first = users.hash_password("secret");
+        assert_ne!(first, users.hash_password("secret"));
+        assert!(users.verify_password("secret", &first));
+        assert!(!users.verify_password("Secret", &first));
+
+        // Unknown usernames cost a full verification too.
+        assert!(users.unknown_user_hash().starts_with("$argon2id$"));
+        assert!(!users.verify_password("secret", users.unknown_user_hash()));
+        assert!(
+            users
+                .authenticate_connection("c1", "nobody", "secret")
+                .is_err()
+        );
+    }
+}
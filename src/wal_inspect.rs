@@ -0,0 +1,128 @@
+#[derive(Default)]
+struct InspectFilter {
+    table: Option<String>,
+    key: Option<String>,
+    operation: Option<String>,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    until: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl InspectFilter {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut filter = InspectFilter::default();
+        let mut args = args.iter();
+
+        while let Some(flag) = args.next() {
+            if !["--table", "--key", "--op", "--since", "--until"].contains(&flag.as_str()) {
+                return Err(format!("Unknown option {}", flag));
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| format!("{} requires a value", flag))?;
+            match flag.as_str() {
+                "--table" => filter.table = Some(value.clone()),
+                "--key" => filter.key = Some(value.clone()),
+                "--op" => filter.operation = Some(value.to_lowercase()),
+                "--since" => filter.since = Some(parse_time(value)?),
+                "--until" => filter.until = Some(parse_time(value)?),
+                _ => unreachable!(),
+            }
+        }
+
+        Ok(filter)
+    }
+
+    fn matches(&self, entry: &crate::logs::LogEntry) -> bool {
+        if self
+            .table
+            .as_ref()
+            .is_some_and(|table| *table != entry.table)
+            || self.key.as_ref().is_some_and(|key| *key != entry.key)
+            || self
+                .operation
+                .as_ref()
+                .is_some_and(|operation| *operation != entry.operation)
+        {
+            return false;
+        }
+
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        match entry.meta {
+            Some(meta) => {
+                self.since.is_none_or(|since| meta.updated_at >= since)
+                    && self.until.is_none_or(|until| meta.updated_at <= until)
+            }
+            None => false,
+        }
+    }
+}
+
+fn parse_time(value: &str) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    crate::engine::parse_timestamp(&serde_json::Value::String(value.to_string()))
+        .ok_or_else(|| format!("Invalid time '{}'", value))
+}
+
+fn print_usage(program: &str) {
+    eprintln!(
+        "Usage: {} wal inspect <file> [--table <table>] [--key <key>] [--op <op>] [--since <time>] [--until <time>]",
+        program
+    );
+}
+
+pub fn run(program: &str, args: &[String]) -> i32 {
+    let path = match args {
+        [command, path, ..] if command == "inspect" => path,
+        _ => {
+            print_usage(program);
+            return 2;
+        }
+    };
+
+    let filter = match InspectFilter::parse(&args[2..]) {
+        Ok(filter) => filter,
+        Err(err) => {
+            eprintln!("{}", err);
+            print_usage(program);
+            return 2;
+        }
+    };
+
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Failed to read {}: {}", path, err);
+            return 1;
+        }
+    };
+    let entries = match crate::logs::LogEntry::decode_all(&bytes) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("Failed to decode {}: {}", path, err);
+            return 1;
+        }
+    };
+
+    let mut shown = 0;
+    let mut failures = 0;
+    for entry in &entries {
+        if entry.verify_checksum() == Some(false) {
+            failures += 1;
+        }
+        if !filter.matches(entry) {
+            continue;
+        }
+        shown += 1;
+        println!("{}", entry.describe());
+    }
+
+    println!(
+        "{} entries, {} shown, {} checksum failures",
+        entries.len(),
+        shown,
+        failures
+    );
+
+    if failures > 0 { 1 } else { 0 }
+}